@@ -0,0 +1,302 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::client::Client;
+use crate::resp::RespValue;
+
+const USAGE: &str = "Usage: dasrc bench [-h host] [-p port] [-c clients] [-n requests] \
+                     [-d size] [-r keyspace] [-t tests]";
+
+/// Settings for a benchmark run, named after the redis-benchmark flags.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchOptions {
+    pub host: String,
+    pub port: u16,
+    /// Number of parallel connections.
+    pub clients: usize,
+    /// Total requests per test, split across the connections.
+    pub requests: usize,
+    /// Size in bytes of the values SET writes.
+    pub data_size: usize,
+    /// Number of distinct keys the requests cycle through.
+    pub keyspace: usize,
+    pub tests: Vec<BenchTest>,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 6379,
+            clients: 50,
+            requests: 100_000,
+            data_size: 3,
+            keyspace: 10_000,
+            tests: vec![BenchTest::Set, BenchTest::Get],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BenchTest {
+    Ping,
+    Set,
+    Get,
+}
+
+impl BenchTest {
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "ping" => Some(Self::Ping),
+            "set" => Some(Self::Set),
+            "get" => Some(Self::Get),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Ping => "PING",
+            Self::Set => "SET",
+            Self::Get => "GET",
+        }
+    }
+}
+
+impl BenchOptions {
+    /// Parses the arguments following `bench` on the command line.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("missing value for {}", flag))?;
+            let invalid = || format!("invalid value for {}: {}", flag, value);
+            match flag.as_str() {
+                "-h" => options.host = value.clone(),
+                "-p" => options.port = value.parse().map_err(|_| invalid())?,
+                "-c" => options.clients = value.parse().map_err(|_| invalid())?,
+                "-n" => options.requests = value.parse().map_err(|_| invalid())?,
+                "-d" => options.data_size = value.parse().map_err(|_| invalid())?,
+                "-r" => options.keyspace = value.parse().map_err(|_| invalid())?,
+                "-t" => {
+                    options.tests = value
+                        .split(',')
+                        .map(|name| BenchTest::parse(name).ok_or_else(invalid))
+                        .collect::<Result<_, _>>()?
+                }
+                _ => return Err(format!("unknown option {}", flag)),
+            }
+        }
+        if options.clients == 0 || options.keyspace == 0 {
+            return Err("clients and keyspace must be at least 1".to_string());
+        }
+        Ok(options)
+    }
+}
+
+/// Request latencies bucketed by whole microseconds.
+#[derive(Debug, Default)]
+pub struct Histogram {
+    counts: BTreeMap<u64, u64>,
+    total: u64,
+}
+
+impl Histogram {
+    pub fn record(&mut self, latency: Duration) {
+        *self.counts.entry(latency.as_micros() as u64).or_default() += 1;
+        self.total += 1;
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        for (micros, count) in &other.counts {
+            *self.counts.entry(*micros).or_default() += count;
+        }
+        self.total += other.total;
+    }
+
+    pub fn len(&self) -> u64 {
+        self.total
+    }
+
+    /// The latency at or below which `percentile` percent of requests
+    /// completed.
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        let target = ((percentile / 100.0) * self.total as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (micros, count) in &self.counts {
+            seen += count;
+            if seen >= target {
+                return Duration::from_micros(*micros);
+            }
+        }
+        Duration::ZERO
+    }
+}
+
+/// The outcome of running one test.
+pub struct BenchReport {
+    pub test: BenchTest,
+    pub elapsed: Duration,
+    pub latencies: Histogram,
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let millis = |percentile| self.latencies.percentile(percentile).as_secs_f64() * 1000.0;
+        write!(
+            f,
+            "{}: {} requests in {:.2}s, {:.0} requests/s, p50 {:.3}ms, p99 {:.3}ms, p99.9 {:.3}ms",
+            self.test.name(),
+            self.latencies.len(),
+            self.elapsed.as_secs_f64(),
+            self.latencies.len() as f64 / self.elapsed.as_secs_f64(),
+            millis(50.0),
+            millis(99.0),
+            millis(99.9),
+        )
+    }
+}
+
+/// Runs each configured test in turn and reports on it. Stops at the first
+/// connection failure or error reply.
+pub fn run(options: &BenchOptions) -> Result<Vec<BenchReport>, String> {
+    options
+        .tests
+        .iter()
+        .map(|test| run_test(options, *test))
+        .collect()
+}
+
+fn run_test(options: &BenchOptions, test: BenchTest) -> Result<BenchReport, String> {
+    let value = "x".repeat(options.data_size);
+    let start = Instant::now();
+    let latencies = thread::scope(|scope| {
+        let workers: Vec<_> = (0..options.clients)
+            .map(|worker| {
+                // Connection `worker` sends every `clients`-th request
+                let requests = (worker..options.requests).step_by(options.clients);
+                let value = value.as_str();
+                scope.spawn(move || -> Result<Histogram, String> {
+                    let mut client = Client::connect((options.host.as_str(), options.port))
+                        .map_err(|e| e.to_string())?;
+                    let mut latencies = Histogram::default();
+                    for request in requests {
+                        let key = format!("key:{:012}", request % options.keyspace);
+                        let args: &[&str] = match test {
+                            BenchTest::Ping => &["PING"],
+                            BenchTest::Set => &["SET", &key, value],
+                            BenchTest::Get => &["GET", &key],
+                        };
+                        let sent = Instant::now();
+                        match client.command(args) {
+                            Ok(RespValue::Error(e)) => {
+                                return Err(format!("{} failed: {}", test.name(), e))
+                            }
+                            Ok(_) => {}
+                            Err(e) => return Err(e.to_string()),
+                        }
+                        latencies.record(sent.elapsed());
+                    }
+                    Ok(latencies)
+                })
+            })
+            .collect();
+
+        let mut latencies = Histogram::default();
+        for worker in workers {
+            latencies.merge(&worker.join().unwrap()?);
+        }
+        Ok::<_, String>(latencies)
+    })?;
+
+    Ok(BenchReport {
+        test,
+        elapsed: start.elapsed(),
+        latencies,
+    })
+}
+
+/// Entry point for `dasrc bench`: runs the benchmark and prints a line per
+/// test.
+pub fn main(args: impl IntoIterator<Item = String>) -> Result<(), String> {
+    let options = BenchOptions::parse(args).map_err(|e| format!("{}\n{}", e, USAGE))?;
+    for report in run(&options)? {
+        println!("{}", report);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::logger::Logger;
+    use crate::storage::{Databases, Storage};
+    use std::net::TcpListener;
+    use std::sync::{Arc, RwLock};
+
+    #[test]
+    fn test_parse_options() {
+        let args = ["-c", "4", "-n", "100", "-t", "set,GET,ping"].map(String::from);
+
+        let options = BenchOptions::parse(args).unwrap();
+
+        assert_eq!(options.clients, 4);
+        assert_eq!(options.requests, 100);
+        assert_eq!(
+            options.tests,
+            [BenchTest::Set, BenchTest::Get, BenchTest::Ping]
+        );
+        assert!(BenchOptions::parse(["-t", "del"].map(String::from)).is_err());
+        assert!(BenchOptions::parse(["-c"].map(String::from)).is_err());
+    }
+
+    #[test]
+    fn test_histogram_percentiles() {
+        let mut latencies = Histogram::default();
+        for micros in 1..=100 {
+            latencies.record(Duration::from_micros(micros));
+        }
+
+        assert_eq!(latencies.percentile(50.0), Duration::from_micros(50));
+        assert_eq!(latencies.percentile(99.0), Duration::from_micros(99));
+        assert_eq!(latencies.percentile(100.0), Duration::from_micros(100));
+    }
+
+    #[test]
+    fn test_benchmark_against_local_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let databases = Arc::new(Databases::new(Storage::new(), 1));
+        let log_file = std::env::temp_dir().join(format!("rrrr-test-{}.log", std::process::id()));
+        let logger = Arc::new(Logger::new(log_file.to_string_lossy().into_owned()));
+        let config = Arc::new(RwLock::new(Config::default()));
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let (databases, logger, config) =
+                    (databases.clone(), logger.clone(), config.clone());
+                thread::spawn(move || {
+                    crate::handle_stream(stream.unwrap(), databases, logger, config)
+                });
+            }
+        });
+        let options = BenchOptions {
+            port,
+            clients: 3,
+            requests: 20,
+            keyspace: 5,
+            ..BenchOptions::default()
+        };
+
+        let reports = run(&options).unwrap();
+
+        assert_eq!(reports.len(), 2);
+        for report in reports {
+            assert_eq!(report.latencies.len(), 20);
+        }
+    }
+}
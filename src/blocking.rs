@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::sync::{Condvar, LazyLock, Mutex};
+use std::time::Instant;
+
+#[derive(Default)]
+struct KeyWaiters {
+    waiting: usize,
+    pushes: u64,
+}
+
+/// Wakes clients blocked in BLPOP/BRPOP when a list they wait on is pushed
+/// to. Only keys with at least one waiter are tracked, each with a counter
+/// bumped on every push so a waiter can tell whether it missed one.
+#[derive(Default)]
+pub struct ListWaiters {
+    keys: Mutex<HashMap<Vec<u8>, KeyWaiters>>,
+    pushed: Condvar,
+}
+
+pub static LIST_WAITERS: LazyLock<ListWaiters> = LazyLock::new(ListWaiters::default);
+
+/// A registration of interest in some keys, removed again on drop.
+pub struct Watch<'a> {
+    waiters: &'a ListWaiters,
+    keys: Vec<Vec<u8>>,
+}
+
+impl ListWaiters {
+    pub fn notify(&self, key: &[u8]) {
+        if let Some(waiters) = self.keys.lock().unwrap().get_mut(key) {
+            waiters.pushes += 1;
+            self.pushed.notify_all();
+        }
+    }
+
+    pub fn watch(&self, keys: &[Vec<u8>]) -> Watch<'_> {
+        let mut tracked = self.keys.lock().unwrap();
+        for key in keys {
+            tracked.entry(key.clone()).or_default().waiting += 1;
+        }
+        Watch {
+            waiters: self,
+            keys: keys.to_vec(),
+        }
+    }
+}
+
+impl Watch<'_> {
+    /// Total pushes seen so far on the watched keys. Read it while holding
+    /// the storage lock after finding the lists empty, then pass it to
+    /// `wait`, so a push in between cannot be missed.
+    pub fn pushes(&self) -> u64 {
+        let tracked = self.waiters.keys.lock().unwrap();
+        self.count(&tracked)
+    }
+
+    /// Blocks until a watched key is pushed to after `seen`, returning false
+    /// if `deadline` passes first. No deadline waits forever.
+    pub fn wait(&self, seen: u64, deadline: Option<Instant>) -> bool {
+        let mut tracked = self.waiters.keys.lock().unwrap();
+        while self.count(&tracked) == seen {
+            tracked = match deadline {
+                None => self.waiters.pushed.wait(tracked).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return false;
+                    }
+                    self.waiters
+                        .pushed
+                        .wait_timeout(tracked, deadline - now)
+                        .unwrap()
+                        .0
+                }
+            };
+        }
+        true
+    }
+
+    fn count(&self, tracked: &HashMap<Vec<u8>, KeyWaiters>) -> u64 {
+        self.keys
+            .iter()
+            .map(|key| tracked.get(key).map_or(0, |waiters| waiters.pushes))
+            .sum()
+    }
+}
+
+impl Drop for Watch<'_> {
+    fn drop(&mut self) {
+        let mut tracked = self.waiters.keys.lock().unwrap();
+        for key in &self.keys {
+            if let Some(waiters) = tracked.get_mut(key) {
+                waiters.waiting -= 1;
+                if waiters.waiting == 0 {
+                    tracked.remove(key);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_wait_times_out_without_push() {
+        let waiters = ListWaiters::default();
+        let watch = waiters.watch(&["k".into()]);
+
+        let seen = watch.pushes();
+        let deadline = Instant::now() + Duration::from_millis(20);
+
+        assert!(!watch.wait(seen, Some(deadline)));
+    }
+
+    #[test]
+    fn test_wait_returns_after_push_to_watched_key() {
+        let waiters = ListWaiters::default();
+        let watch = waiters.watch(&["a".into(), "b".into()]);
+
+        let seen = watch.pushes();
+        waiters.notify(b"other");
+        waiters.notify(b"b");
+
+        assert!(watch.wait(seen, Some(Instant::now())));
+    }
+
+    #[test]
+    fn test_unwatched_keys_are_forgotten() {
+        let waiters = ListWaiters::default();
+        drop(waiters.watch(&["k".into()]));
+
+        assert!(waiters.keys.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,34 @@
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+
+use crate::resp::{read_resp_from_stream, write_resp, RespError, RespValue};
+
+/// A blocking connection to a RESP server, one command in flight at a time.
+pub struct Client {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+}
+
+impl Client {
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+        })
+    }
+
+    /// Sends `args` as a command and waits for its reply. Error replies are
+    /// returned as values; only I/O and protocol failures are errors.
+    pub fn command(&mut self, args: &[&str]) -> Result<RespValue, RespError> {
+        let request = RespValue::Array(Some(
+            args.iter()
+                .map(|arg| RespValue::BulkString(Some(arg.as_bytes().to_vec())))
+                .collect(),
+        ));
+        write_resp(&request, &mut self.writer)?;
+        self.writer.flush()?;
+        read_resp_from_stream(&mut self.reader)
+    }
+}
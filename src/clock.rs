@@ -0,0 +1,75 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The source of the current time for key expiry, swappable so tests can
+/// move time forward instead of sleeping.
+pub trait Clock: Send + Sync {
+    /// Seconds since the Unix epoch.
+    fn now_secs(&self) -> u64;
+
+    /// Milliseconds since the Unix epoch.
+    fn now_millis(&self) -> u64;
+}
+
+/// The real wall clock.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_secs(&self) -> u64 {
+        secs_since_epoch(SystemTime::now())
+    }
+
+    fn now_millis(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64)
+    }
+}
+
+/// Whole seconds from the Unix epoch to `time`, saturating to 0 for times
+/// before the epoch rather than panicking on a misconfigured system clock.
+fn secs_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// A clock that only moves when told to.
+#[cfg(test)]
+pub struct MockClock(std::sync::atomic::AtomicU64);
+
+#[cfg(test)]
+impl MockClock {
+    pub fn new(now_secs: u64) -> Self {
+        Self(std::sync::atomic::AtomicU64::new(now_secs))
+    }
+
+    pub fn advance(&self, secs: u64) {
+        self.0.fetch_add(secs, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now_secs(&self) -> u64 {
+        self.0.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    fn now_millis(&self) -> u64 {
+        self.now_secs() * 1000
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_secs_since_epoch_saturates_before_the_epoch() {
+        assert_eq!(secs_since_epoch(UNIX_EPOCH - Duration::from_secs(1)), 0);
+        assert_eq!(secs_since_epoch(UNIX_EPOCH), 0);
+        assert_eq!(
+            secs_since_epoch(UNIX_EPOCH + Duration::from_millis(1_500)),
+            1
+        );
+    }
+}
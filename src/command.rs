@@ -1,31 +1,442 @@
 use log::debug;
 
 use crate::resp::RespValue;
+use crate::sorted_set::{LexBound, ScoreBound};
+use std::collections::HashMap;
 use std::string::ToString;
 
+#[allow(clippy::enum_variant_names)]
+#[rustfmt::skip]
 #[derive(Debug, PartialEq)]
 pub enum Command {
-    Get { key: String },
-    MGet { keys: Vec<String> },
-    Set { key: String, value: String },
-    Del { keys: Vec<String> },
-    IncrBy { key: String, value: String },
-    Incr { key: String },
-    DecrBy { key: String, value: String },
-    Decr { key: String },
-    Exists { keys: Vec<String> },
-    Expire { key: String, expire: String },
-    TTL { key: String },
-    Persist { key: String },
+    Get { key: Vec<u8> },
+    MGet { keys: Vec<Vec<u8>> },
+    Set { key: Vec<u8>, value: Vec<u8>, condition: Option<SetCondition>, get: bool },
+    Del { keys: Vec<Vec<u8>> },
+    IncrBy { key: Vec<u8>, value: String },
+    Incr { key: Vec<u8> },
+    DecrBy { key: Vec<u8>, value: String },
+    Decr { key: Vec<u8> },
+    Exists { keys: Vec<Vec<u8>> },
+    Expire { key: Vec<u8>, expire: String },
+    Ttl { key: Vec<u8> },
+    Persist { key: Vec<u8> },
     Ping,
-    CommandDocs,
-    FlushAll,
+    CommandList,
+    CommandCount,
+    CommandDocs { names: Vec<String> },
+    CommandInfo { names: Vec<String> },
+    FlushAll { asynchronous: bool },
     Keys { pattern: String },
-    /*
-     * TODO:
-     * SCAN
-     * INFO
-     */
+    Time,
+    Save,
+    BgSave,
+    LastSave,
+    SetBit { key: Vec<u8>, offset: String, value: String },
+    GetBit { key: Vec<u8>, offset: String },
+    BitCount { key: Vec<u8>, range: Option<(i64, i64)> },
+    SAdd { key: Vec<u8>, members: Vec<Vec<u8>> },
+    SMembers { key: Vec<u8> },
+    SInter { keys: Vec<Vec<u8>> },
+    SUnion { keys: Vec<Vec<u8>> },
+    SDiff { keys: Vec<Vec<u8>> },
+    SInterStore { destination: Vec<u8>, keys: Vec<Vec<u8>> },
+    SUnionStore { destination: Vec<u8>, keys: Vec<Vec<u8>> },
+    SDiffStore { destination: Vec<u8>, keys: Vec<Vec<u8>> },
+    LPush { key: Vec<u8>, values: Vec<Vec<u8>> },
+    RPush { key: Vec<u8>, values: Vec<Vec<u8>> },
+    LRange { key: Vec<u8>, start: i64, stop: i64 },
+    LLen { key: Vec<u8> },
+    GetEx { key: Vec<u8>, expiry: Option<Expiry> },
+    Sort { key: Vec<u8>, alpha: bool, descending: bool, limit: Option<(i64, i64)> },
+    Dump { key: Vec<u8> },
+    Restore { key: Vec<u8>, ttl: i64, payload: Vec<u8>, replace: bool },
+    Eval { script: String, keys: Vec<Vec<u8>>, args: Vec<Vec<u8>> },
+    EvalSha { sha: String, keys: Vec<Vec<u8>>, args: Vec<Vec<u8>> },
+    ScriptLoad { script: String },
+    Info { section: Option<String> },
+    SlowLogGet { count: Option<i64> },
+    SlowLogLen,
+    SlowLogReset,
+    DebugSleep { seconds: f64 },
+    Monitor,
+    Append { key: Vec<u8>, value: Vec<u8> },
+    StrLen { key: Vec<u8> },
+    SetRange { key: Vec<u8>, offset: i64, value: Vec<u8> },
+    Scan { cursor: u64, pattern: Option<String>, count: usize, value_type: Option<String> },
+    SScan { key: Vec<u8>, cursor: u64, pattern: Option<String>, count: usize },
+    HScan { key: Vec<u8>, cursor: u64, pattern: Option<String>, count: usize },
+    HSet { key: Vec<u8>, pairs: Vec<(Vec<u8>, Vec<u8>)> },
+    HGet { key: Vec<u8>, field: Vec<u8> },
+    ObjectRefCount { key: Vec<u8> },
+    ObjectFreq { key: Vec<u8> },
+    ConfigGet { pattern: String },
+    ConfigSet { parameter: String, value: String },
+    ConfigRewrite,
+    RPopLPush { source: Vec<u8>, destination: Vec<u8> },
+    LMove { source: Vec<u8>, destination: Vec<u8>, from: ListEnd, to: ListEnd },
+    BLPop { keys: Vec<Vec<u8>>, timeout: f64 },
+    BRPop { keys: Vec<Vec<u8>>, timeout: f64 },
+    Select { index: i64 },
+    Move { key: Vec<u8>, db: i64 },
+    FlushDb { asynchronous: bool },
+    MSet { pairs: Vec<(Vec<u8>, Vec<u8>)> },
+    CommandGetKeys { args: Vec<String> },
+    Help { command: &'static str },
+    LSet { key: Vec<u8>, index: i64, value: Vec<u8> },
+    LInsert { key: Vec<u8>, before: bool, pivot: Vec<u8>, value: Vec<u8> },
+    LRem { key: Vec<u8>, count: i64, value: Vec<u8> },
+    LTrim { key: Vec<u8>, start: i64, stop: i64 },
+    HIncrBy { key: Vec<u8>, field: Vec<u8>, increment: i64 },
+    HIncrByFloat { key: Vec<u8>, field: Vec<u8>, increment: f64 },
+    HSetNx { key: Vec<u8>, field: Vec<u8>, value: Vec<u8> },
+    HMGet { key: Vec<u8>, fields: Vec<Vec<u8>> },
+    ZAdd { key: Vec<u8>, pairs: Vec<(f64, Vec<u8>)> },
+    ZScore { key: Vec<u8>, member: Vec<u8> },
+    ZRange { key: Vec<u8>, start: i64, stop: i64, withscores: bool },
+    ZRangeByScore { key: Vec<u8>, min: ScoreBound, max: ScoreBound, withscores: bool, limit: Option<(i64, i64)> },
+    ZIncrBy { key: Vec<u8>, increment: f64, member: Vec<u8> },
+    ZRank { key: Vec<u8>, member: Vec<u8> },
+    ZCard { key: Vec<u8> },
+    ZRem { key: Vec<u8>, members: Vec<Vec<u8>> },
+    ZRevRange { key: Vec<u8>, start: i64, stop: i64, withscores: bool },
+    ZRevRangeByScore { key: Vec<u8>, max: ScoreBound, min: ScoreBound, withscores: bool, limit: Option<(i64, i64)> },
+    ZCount { key: Vec<u8>, min: ScoreBound, max: ScoreBound },
+    ZRangeByLex { key: Vec<u8>, min: LexBound, max: LexBound },
+    Multi,
+    Exec,
+    Discard,
+    ClientSetInfo { attribute: String, value: String },
+    ClientNoEvict { enabled: bool },
+    ClientNoTouch { enabled: bool },
+    GetRange { key: Vec<u8>, start: i64, end: i64 },
+    Hello {
+        protover: Option<i64>,
+        auth: Option<(String, String)>,
+        setname: Option<String>,
+    },
+    ExpireTime { key: Vec<u8> },
+    PExpireTime { key: Vec<u8> },
+    DebugObject { key: Vec<u8> },
+    Auth { username: Option<String>, password: String },
+    ClientSetName { name: String },
+    ClientGetName,
+    Subscribe { channels: Vec<String> },
+    Unsubscribe { channels: Vec<String> },
+    PSubscribe { patterns: Vec<String> },
+    PUnsubscribe { patterns: Vec<String> },
+    Publish { channel: String, message: String },
+    PubSubChannels { pattern: Option<String> },
+    PubSubNumSub { channels: Vec<String> },
+    PubSubNumPat,
+    SInterCard { keys: Vec<Vec<u8>>, limit: usize },
+    MemoryUsage { key: Vec<u8>, samples: Option<usize> },
+    DebugReload,
+    Role,
+    ObjectEncoding { key: Vec<u8> },
+    Copy {
+        source: Vec<u8>,
+        destination: Vec<u8>,
+        db: Option<i64>,
+        replace: bool,
+    },
+    SMove {
+        source: Vec<u8>,
+        destination: Vec<u8>,
+        member: Vec<u8>,
+    },
+    ZPopMin { key: Vec<u8>, count: Option<usize> },
+    ZPopMax { key: Vec<u8>, count: Option<usize> },
+    BitPos {
+        key: Vec<u8>,
+        bit: bool,
+        range: Option<(i64, Option<i64>)>,
+        unit: RangeUnit,
+    },
+}
+
+impl Command {
+    /// The lowercase name used to report this command in INFO commandstats.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Get { .. } => "get",
+            Command::MGet { .. } => "mget",
+            Command::Set { .. } => "set",
+            Command::Del { .. } => "del",
+            Command::IncrBy { .. } => "incrby",
+            Command::Incr { .. } => "incr",
+            Command::DecrBy { .. } => "decrby",
+            Command::Decr { .. } => "decr",
+            Command::Exists { .. } => "exists",
+            Command::Expire { .. } => "expire",
+            Command::Ttl { .. } => "ttl",
+            Command::Persist { .. } => "persist",
+            Command::Ping => "ping",
+            Command::CommandList => "command",
+            Command::CommandCount => "command|count",
+            Command::CommandDocs { .. } => "command|docs",
+            Command::CommandInfo { .. } => "command|info",
+            Command::FlushAll { .. } => "flushall",
+            Command::Keys { .. } => "keys",
+            Command::Time => "time",
+            Command::Save => "save",
+            Command::BgSave => "bgsave",
+            Command::LastSave => "lastsave",
+            Command::SetBit { .. } => "setbit",
+            Command::GetBit { .. } => "getbit",
+            Command::BitCount { .. } => "bitcount",
+            Command::SAdd { .. } => "sadd",
+            Command::SMembers { .. } => "smembers",
+            Command::SInter { .. } => "sinter",
+            Command::SUnion { .. } => "sunion",
+            Command::SDiff { .. } => "sdiff",
+            Command::SInterStore { .. } => "sinterstore",
+            Command::SUnionStore { .. } => "sunionstore",
+            Command::SDiffStore { .. } => "sdiffstore",
+            Command::LPush { .. } => "lpush",
+            Command::RPush { .. } => "rpush",
+            Command::LRange { .. } => "lrange",
+            Command::LLen { .. } => "llen",
+            Command::GetEx { .. } => "getex",
+            Command::Sort { .. } => "sort",
+            Command::Dump { .. } => "dump",
+            Command::Restore { .. } => "restore",
+            Command::Eval { .. } => "eval",
+            Command::EvalSha { .. } => "evalsha",
+            Command::ScriptLoad { .. } => "script|load",
+            Command::Info { .. } => "info",
+            Command::SlowLogGet { .. } => "slowlog|get",
+            Command::SlowLogLen => "slowlog|len",
+            Command::SlowLogReset => "slowlog|reset",
+            Command::DebugSleep { .. } => "debug|sleep",
+            Command::Monitor => "monitor",
+            Command::Append { .. } => "append",
+            Command::StrLen { .. } => "strlen",
+            Command::SetRange { .. } => "setrange",
+            Command::Scan { .. } => "scan",
+            Command::SScan { .. } => "sscan",
+            Command::HScan { .. } => "hscan",
+            Command::HSet { .. } => "hset",
+            Command::HGet { .. } => "hget",
+            Command::ObjectRefCount { .. } => "object|refcount",
+            Command::ObjectFreq { .. } => "object|freq",
+            Command::ConfigGet { .. } => "config|get",
+            Command::ConfigSet { .. } => "config|set",
+            Command::ConfigRewrite => "config|rewrite",
+            Command::RPopLPush { .. } => "rpoplpush",
+            Command::LMove { .. } => "lmove",
+            Command::BLPop { .. } => "blpop",
+            Command::BRPop { .. } => "brpop",
+            Command::Select { .. } => "select",
+            Command::Move { .. } => "move",
+            Command::FlushDb { .. } => "flushdb",
+            Command::MSet { .. } => "mset",
+            Command::CommandGetKeys { .. } => "command|getkeys",
+            Command::LSet { .. } => "lset",
+            Command::LInsert { .. } => "linsert",
+            Command::LRem { .. } => "lrem",
+            Command::LTrim { .. } => "ltrim",
+            Command::HIncrBy { .. } => "hincrby",
+            Command::HIncrByFloat { .. } => "hincrbyfloat",
+            Command::HSetNx { .. } => "hsetnx",
+            Command::HMGet { .. } => "hmget",
+            Command::ZAdd { .. } => "zadd",
+            Command::ZScore { .. } => "zscore",
+            Command::ZRange { .. } => "zrange",
+            Command::ZRangeByScore { .. } => "zrangebyscore",
+            Command::ZIncrBy { .. } => "zincrby",
+            Command::ZRank { .. } => "zrank",
+            Command::ZCard { .. } => "zcard",
+            Command::ZRem { .. } => "zrem",
+            Command::ZRevRange { .. } => "zrevrange",
+            Command::ZRevRangeByScore { .. } => "zrevrangebyscore",
+            Command::ZCount { .. } => "zcount",
+            Command::ZRangeByLex { .. } => "zrangebylex",
+            Command::Multi => "multi",
+            Command::Exec => "exec",
+            Command::Discard => "discard",
+            Command::ClientSetInfo { .. } => "client|setinfo",
+            Command::ClientNoEvict { .. } => "client|no-evict",
+            Command::ClientNoTouch { .. } => "client|no-touch",
+            Command::GetRange { .. } => "getrange",
+            Command::Hello { .. } => "hello",
+            Command::ExpireTime { .. } => "expiretime",
+            Command::PExpireTime { .. } => "pexpiretime",
+            Command::DebugObject { .. } => "debug|object",
+            Command::Auth { .. } => "auth",
+            Command::ClientSetName { .. } => "client|setname",
+            Command::ClientGetName => "client|getname",
+            Command::Subscribe { .. } => "subscribe",
+            Command::Unsubscribe { .. } => "unsubscribe",
+            Command::PSubscribe { .. } => "psubscribe",
+            Command::PUnsubscribe { .. } => "punsubscribe",
+            Command::Publish { .. } => "publish",
+            Command::PubSubChannels { .. } => "pubsub|channels",
+            Command::PubSubNumSub { .. } => "pubsub|numsub",
+            Command::PubSubNumPat => "pubsub|numpat",
+            Command::SInterCard { .. } => "sintercard",
+            Command::MemoryUsage { .. } => "memory|usage",
+            Command::DebugReload => "debug|reload",
+            Command::Role => "role",
+            Command::ObjectEncoding { .. } => "object|encoding",
+            Command::Copy { .. } => "copy",
+            Command::SMove { .. } => "smove",
+            Command::ZPopMin { .. } => "zpopmin",
+            Command::ZPopMax { .. } => "zpopmax",
+            Command::BitPos { .. } => "bitpos",
+            Command::Help { command } => match *command {
+                "OBJECT" => "object|help",
+                "COMMAND" => "command|help",
+                "CONFIG" => "config|help",
+                "DEBUG" => "debug|help",
+                "PUBSUB" => "pubsub|help",
+                "MEMORY" => "memory|help",
+                "SLOWLOG" => "slowlog|help",
+                "SCRIPT" => "script|help",
+                "CLIENT" => "client|help",
+                _ => "help",
+            },
+        }
+    }
+
+    /// The keys the command works on, in argument order.
+    pub fn keys(&self) -> Vec<&[u8]> {
+        match self {
+            Command::Get { key, .. }
+            | Command::Set { key, .. }
+            | Command::IncrBy { key, .. }
+            | Command::Incr { key, .. }
+            | Command::DecrBy { key, .. }
+            | Command::Decr { key, .. }
+            | Command::Expire { key, .. }
+            | Command::Ttl { key, .. }
+            | Command::Persist { key, .. }
+            | Command::SetBit { key, .. }
+            | Command::GetBit { key, .. }
+            | Command::BitCount { key, .. }
+            | Command::BitPos { key, .. }
+            | Command::SAdd { key, .. }
+            | Command::SMembers { key, .. }
+            | Command::LPush { key, .. }
+            | Command::RPush { key, .. }
+            | Command::LRange { key, .. }
+            | Command::LLen { key, .. }
+            | Command::GetEx { key, .. }
+            | Command::Sort { key, .. }
+            | Command::Dump { key, .. }
+            | Command::Restore { key, .. }
+            | Command::Append { key, .. }
+            | Command::StrLen { key, .. }
+            | Command::SetRange { key, .. }
+            | Command::GetRange { key, .. }
+            | Command::SScan { key, .. }
+            | Command::HScan { key, .. }
+            | Command::HSet { key, .. }
+            | Command::HGet { key, .. }
+            | Command::HSetNx { key, .. }
+            | Command::HMGet { key, .. }
+            | Command::HIncrBy { key, .. }
+            | Command::HIncrByFloat { key, .. }
+            | Command::LSet { key, .. }
+            | Command::LInsert { key, .. }
+            | Command::LRem { key, .. }
+            | Command::LTrim { key, .. }
+            | Command::ZAdd { key, .. }
+            | Command::ZScore { key, .. }
+            | Command::ZRange { key, .. }
+            | Command::ZRangeByScore { key, .. }
+            | Command::ZIncrBy { key, .. }
+            | Command::ZRank { key, .. }
+            | Command::ZCard { key, .. }
+            | Command::ZRem { key, .. }
+            | Command::ZPopMin { key, .. }
+            | Command::ZPopMax { key, .. }
+            | Command::ZRevRange { key, .. }
+            | Command::ZRevRangeByScore { key, .. }
+            | Command::ZCount { key, .. }
+            | Command::ZRangeByLex { key, .. }
+            | Command::ObjectRefCount { key, .. }
+            | Command::ObjectFreq { key, .. }
+            | Command::ObjectEncoding { key, .. }
+            | Command::MemoryUsage { key, .. }
+            | Command::DebugObject { key, .. }
+            | Command::ExpireTime { key, .. }
+            | Command::PExpireTime { key, .. }
+            | Command::Move { key, .. } => vec![key],
+            Command::MGet { keys, .. }
+            | Command::Del { keys, .. }
+            | Command::Exists { keys, .. }
+            | Command::SInter { keys, .. }
+            | Command::SInterCard { keys, .. }
+            | Command::SUnion { keys, .. }
+            | Command::SDiff { keys, .. }
+            | Command::BLPop { keys, .. }
+            | Command::BRPop { keys, .. }
+            | Command::Eval { keys, .. }
+            | Command::EvalSha { keys, .. } => keys.iter().map(Vec::as_slice).collect(),
+            Command::SInterStore { destination, keys }
+            | Command::SUnionStore { destination, keys }
+            | Command::SDiffStore { destination, keys } => std::iter::once(destination)
+                .chain(keys)
+                .map(Vec::as_slice)
+                .collect(),
+            Command::MSet { pairs } => pairs.iter().map(|(key, _)| key.as_slice()).collect(),
+            Command::RPopLPush {
+                source,
+                destination,
+            }
+            | Command::LMove {
+                source,
+                destination,
+                ..
+            }
+            | Command::SMove {
+                source,
+                destination,
+                ..
+            }
+            | Command::Copy {
+                source,
+                destination,
+                ..
+            } => vec![source, destination],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// An expiry change requested alongside a read or write. Amounts are always
+/// positive; absolute forms are Unix timestamps.
+#[derive(Debug, PartialEq)]
+pub enum Expiry {
+    Ex(u64),
+    Px(u64),
+    ExAt(u64),
+    PxAt(u64),
+    Persist,
+}
+
+/// The NX/XX precondition of a SET.
+#[derive(Debug, PartialEq)]
+pub enum SetCondition {
+    /// Only set the key if it does not already exist.
+    Nx,
+    /// Only set the key if it already exists.
+    Xx,
+}
+
+/// Whether a bit command's range counts bytes or bits.
+#[derive(Debug, PartialEq)]
+pub enum RangeUnit {
+    Byte,
+    Bit,
+}
+
+/// Which end of a list an element is taken from or added to.
+#[derive(Debug, PartialEq)]
+pub enum ListEnd {
+    Left,
+    Right,
 }
 
 #[derive(Debug)]
@@ -68,8 +479,14 @@ impl TryFrom<RespValue> for Command {
                 }
 
                 // Get the command name from the first argument
+                // Only the name has to be text; it's matched against the
+                // command table
                 let command_name = match &array[0] {
-                    RespValue::BulkString(Some(s)) => s.to_uppercase(),
+                    RespValue::BulkString(Some(name)) => std::str::from_utf8(name)
+                        .map_err(|_| {
+                            CommandError::ParseError("command name must be valid UTF-8".to_string())
+                        })?
+                        .to_uppercase(),
                     _ => {
                         return Err(CommandError::ParseError(
                             "command name must be a bulk string".to_string(),
@@ -87,7 +504,7 @@ impl TryFrom<RespValue> for Command {
                             });
                         }
 
-                        let key = extract_string(&array[1])?;
+                        let key = extract_bytes(&array[1])?;
                         Ok(Command::Get { key })
                     }
 
@@ -101,13 +518,13 @@ impl TryFrom<RespValue> for Command {
                         }
                         let keys = array[1..]
                             .iter()
-                            .map(|v| extract_string(v))
-                            .collect::<Result<Vec<String>, _>>()?;
+                            .map(extract_bytes)
+                            .collect::<Result<Vec<Vec<u8>>, _>>()?;
                         Ok(Command::MGet { keys })
                     }
 
                     "SET" => {
-                        if array.len() != 3 {
+                        if array.len() < 3 {
                             return Err(CommandError::WrongNumberOfArguments {
                                 cmd: "SET".to_string(),
                                 expected: 3,
@@ -115,9 +532,32 @@ impl TryFrom<RespValue> for Command {
                             });
                         }
 
-                        let key = extract_string(&array[1])?;
-                        let value = extract_string(&array[2])?;
-                        Ok(Command::Set { key, value })
+                        let key = extract_bytes(&array[1])?;
+                        let value = extract_bytes(&array[2])?;
+                        let mut condition = None;
+                        let mut get = false;
+                        for option in &array[3..] {
+                            match extract_string(option)?.to_uppercase().as_str() {
+                                "NX" if condition != Some(SetCondition::Xx) => {
+                                    condition = Some(SetCondition::Nx)
+                                }
+                                "XX" if condition != Some(SetCondition::Nx) => {
+                                    condition = Some(SetCondition::Xx)
+                                }
+                                "GET" => get = true,
+                                _ => {
+                                    return Err(CommandError::ParseError(
+                                        "syntax error".to_string(),
+                                    ))
+                                }
+                            }
+                        }
+                        Ok(Command::Set {
+                            key,
+                            value,
+                            condition,
+                            get,
+                        })
                     }
 
                     "INCRBY" => {
@@ -129,7 +569,7 @@ impl TryFrom<RespValue> for Command {
                             });
                         }
 
-                        let key = extract_string(&array[1])?;
+                        let key = extract_bytes(&array[1])?;
                         let value = extract_string(&array[2])?;
                         Ok(Command::IncrBy { key, value })
                     }
@@ -142,7 +582,7 @@ impl TryFrom<RespValue> for Command {
                                 got: array.len(),
                             });
                         }
-                        let key = extract_string(&array[1])?;
+                        let key = extract_bytes(&array[1])?;
                         Ok(Command::Incr { key })
                     }
 
@@ -155,7 +595,7 @@ impl TryFrom<RespValue> for Command {
                             });
                         }
 
-                        let key = extract_string(&array[1])?;
+                        let key = extract_bytes(&array[1])?;
                         let value = extract_string(&array[2])?;
                         Ok(Command::DecrBy { key, value })
                     }
@@ -168,7 +608,7 @@ impl TryFrom<RespValue> for Command {
                                 got: array.len(),
                             });
                         }
-                        let key = extract_string(&array[1])?;
+                        let key = extract_bytes(&array[1])?;
                         Ok(Command::Decr { key })
                     }
 
@@ -183,7 +623,7 @@ impl TryFrom<RespValue> for Command {
 
                         let mut keys = Vec::with_capacity(array.len() - 1);
                         for arg in &array[1..] {
-                            keys.push(extract_string(arg)?);
+                            keys.push(extract_bytes(arg)?);
                         }
                         Ok(Command::Del { keys })
                     }
@@ -200,15 +640,39 @@ impl TryFrom<RespValue> for Command {
                     }
 
                     "COMMAND" => {
-                        if array.len() != 2 {
-                            return Err(CommandError::WrongNumberOfArguments {
-                                cmd: "COMMAND".to_string(),
+                        let Some(subcommand) = array.get(1) else {
+                            return Ok(Command::CommandList);
+                        };
+                        let names = || {
+                            array[2..]
+                                .iter()
+                                .map(extract_string)
+                                .collect::<Result<Vec<String>, _>>()
+                        };
+
+                        match extract_string(subcommand)?.to_uppercase().as_str() {
+                            "COUNT" if array.len() == 2 => Ok(Command::CommandCount),
+                            "COUNT" => Err(CommandError::WrongNumberOfArguments {
+                                cmd: "COMMAND COUNT".to_string(),
                                 expected: 2,
                                 got: array.len(),
-                            });
+                            }),
+                            "DOCS" => Ok(Command::CommandDocs { names: names()? }),
+                            "INFO" => Ok(Command::CommandInfo { names: names()? }),
+                            "GETKEYS" if array.len() >= 3 => {
+                                Ok(Command::CommandGetKeys { args: names()? })
+                            }
+                            "GETKEYS" => Err(CommandError::WrongNumberOfArguments {
+                                cmd: "COMMAND GETKEYS".to_string(),
+                                expected: 3,
+                                got: array.len(),
+                            }),
+                            "HELP" if array.len() == 2 => Ok(Command::Help { command: "COMMAND" }),
+                            subcommand => Err(CommandError::UnknownCommand(format!(
+                                "COMMAND {}",
+                                subcommand
+                            ))),
                         }
-
-                        Ok(Command::CommandDocs)
                     }
 
                     "EXISTS" => {
@@ -222,8 +686,8 @@ impl TryFrom<RespValue> for Command {
 
                         let keys = array[1..]
                             .iter()
-                            .map(|v| extract_string(v))
-                            .collect::<Result<Vec<String>, _>>()?;
+                            .map(extract_bytes)
+                            .collect::<Result<Vec<Vec<u8>>, _>>()?;
                         Ok(Command::Exists { keys })
                     }
 
@@ -236,7 +700,7 @@ impl TryFrom<RespValue> for Command {
                             });
                         }
 
-                        let key = extract_string(&array[1])?;
+                        let key = extract_bytes(&array[1])?;
                         let expire = extract_string(&array[2])?;
                         Ok(Command::Expire { key, expire })
                     }
@@ -249,7 +713,7 @@ impl TryFrom<RespValue> for Command {
                                 got: array.len(),
                             });
                         }
-                        let key = extract_string(&array[1])?;
+                        let key = extract_bytes(&array[1])?;
                         Ok(Command::Persist { key })
                     }
 
@@ -261,19 +725,51 @@ impl TryFrom<RespValue> for Command {
                                 got: array.len(),
                             });
                         }
-                        let key = extract_string(&array[1])?;
-                        Ok(Command::TTL { key })
+                        let key = extract_bytes(&array[1])?;
+                        Ok(Command::Ttl { key })
                     }
 
-                    "FLUSHALL" => {
-                        if array.len() != 1 {
+                    "EXPIRETIME" | "PEXPIRETIME" => {
+                        if array.len() != 2 {
                             return Err(CommandError::WrongNumberOfArguments {
-                                cmd: "FLUSHALL".to_string(),
-                                expected: 1,
+                                cmd: command_name,
+                                expected: 2,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        if command_name == "PEXPIRETIME" {
+                            return Ok(Command::PExpireTime { key });
+                        }
+                        Ok(Command::ExpireTime { key })
+                    }
+
+                    "FLUSHALL" | "FLUSHDB" => {
+                        if array.len() > 2 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: command_name,
+                                expected: 2,
                                 got: array.len(),
                             });
                         }
-                        Ok(Command::FlushAll)
+
+                        let asynchronous = match array.get(1).map(extract_string).transpose()? {
+                            None => false,
+                            Some(mode) => match mode.to_uppercase().as_str() {
+                                "ASYNC" => true,
+                                "SYNC" => false,
+                                _ => {
+                                    return Err(CommandError::ParseError(
+                                        "syntax error".to_string(),
+                                    ))
+                                }
+                            },
+                        };
+                        if command_name == "FLUSHALL" {
+                            Ok(Command::FlushAll { asynchronous })
+                        } else {
+                            Ok(Command::FlushDb { asynchronous })
+                        }
                     }
 
                     "KEYS" => {
@@ -295,100 +791,2388 @@ impl TryFrom<RespValue> for Command {
                         })
                     }
 
-                    _ => Err(CommandError::UnknownCommand(command_name)),
-                }
-            }
-            _ => Err(CommandError::ParseError("expected array".to_string())),
-        }
-    }
-}
+                    "TIME" => {
+                        if array.len() != 1 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "TIME".to_string(),
+                                expected: 1,
+                                got: array.len(),
+                            });
+                        }
+                        Ok(Command::Time)
+                    }
 
-fn extract_string(value: &RespValue) -> Result<String, CommandError> {
-    match value {
-        RespValue::BulkString(Some(s)) => Ok(s.clone()),
-        RespValue::SimpleString(s) => Ok(s.clone()),
-        _ => Err(CommandError::ParseError("expected string".to_string())),
-    }
-}
+                    "SAVE" => {
+                        if array.len() != 1 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "SAVE".to_string(),
+                                expected: 1,
+                                got: array.len(),
+                            });
+                        }
+                        Ok(Command::Save)
+                    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+                    "BGSAVE" => {
+                        if array.len() != 1 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "BGSAVE".to_string(),
+                                expected: 1,
+                                got: array.len(),
+                            });
+                        }
+                        Ok(Command::BgSave)
+                    }
 
-    #[test]
-    fn test_parse_get() {
-        let input = RespValue::Array(Some(vec![
-            RespValue::BulkString(Some("GET".to_string())),
-            RespValue::BulkString(Some("mykey".to_string())),
-        ]));
+                    "LASTSAVE" => {
+                        if array.len() != 1 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "LASTSAVE".to_string(),
+                                expected: 1,
+                                got: array.len(),
+                            });
+                        }
+                        Ok(Command::LastSave)
+                    }
 
-        assert_eq!(
-            Command::try_from(input).unwrap(),
-            Command::Get {
-                key: "mykey".to_string()
-            }
-        );
-    }
+                    "ROLE" => {
+                        if array.len() != 1 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "ROLE".to_string(),
+                                expected: 1,
+                                got: array.len(),
+                            });
+                        }
+                        Ok(Command::Role)
+                    }
 
-    #[test]
-    fn test_parse_set() {
-        let input = RespValue::Array(Some(vec![
-            RespValue::BulkString(Some("SET".to_string())),
-            RespValue::BulkString(Some("mykey".to_string())),
-            RespValue::BulkString(Some("myvalue".to_string())),
-        ]));
+                    "SETBIT" => {
+                        if array.len() != 4 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "SETBIT".to_string(),
+                                expected: 4,
+                                got: array.len(),
+                            });
+                        }
 
-        assert_eq!(
-            Command::try_from(input).unwrap(),
-            Command::Set {
-                key: "mykey".to_string(),
-                value: "myvalue".to_string(),
-            }
-        );
-    }
+                        let key = extract_bytes(&array[1])?;
+                        let offset = extract_string(&array[2])?;
+                        let value = extract_string(&array[3])?;
+                        Ok(Command::SetBit { key, offset, value })
+                    }
 
-    #[test]
-    fn test_parse_del() {
-        let input = RespValue::Array(Some(vec![
-            RespValue::BulkString(Some("DEL".to_string())),
-            RespValue::BulkString(Some("key1".to_string())),
-            RespValue::BulkString(Some("key2".to_string())),
-        ]));
+                    "GETBIT" => {
+                        if array.len() != 3 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "GETBIT".to_string(),
+                                expected: 3,
+                                got: array.len(),
+                            });
+                        }
 
-        assert_eq!(
-            Command::try_from(input).unwrap(),
-            Command::Del {
-                keys: vec!["key1".to_string(), "key2".to_string()],
-            }
-        );
-    }
+                        let key = extract_bytes(&array[1])?;
+                        let offset = extract_string(&array[2])?;
+                        Ok(Command::GetBit { key, offset })
+                    }
 
-    #[test]
-    fn test_parse_ping() {
-        let input = RespValue::Array(Some(vec![RespValue::BulkString(Some("PING".to_string()))]));
+                    "BITCOUNT" => {
+                        if array.len() != 2 && array.len() != 4 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "BITCOUNT".to_string(),
+                                expected: 4,
+                                got: array.len(),
+                            });
+                        }
 
-        assert_eq!(Command::try_from(input).unwrap(), Command::Ping);
-    }
+                        let key = extract_bytes(&array[1])?;
+                        let range = if array.len() == 4 {
+                            Some((extract_integer(&array[2])?, extract_integer(&array[3])?))
+                        } else {
+                            None
+                        };
+                        Ok(Command::BitCount { key, range })
+                    }
 
-    #[test]
-    fn test_unknown_command() {
-        let input = RespValue::Array(Some(vec![RespValue::BulkString(Some(
-            "UNKNOWN".to_string(),
-        ))]));
+                    "BITPOS" => {
+                        if !(3..=6).contains(&array.len()) {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "BITPOS".to_string(),
+                                expected: 3,
+                                got: array.len(),
+                            });
+                        }
 
-        assert!(matches!(
-            Command::try_from(input),
-            Err(CommandError::UnknownCommand(_))
-        ));
-    }
+                        let key = extract_bytes(&array[1])?;
+                        let bit = match extract_string(&array[2])?.as_str() {
+                            "0" => false,
+                            "1" => true,
+                            _ => {
+                                return Err(CommandError::ParseError(
+                                    "The bit argument must be 1 or 0.".to_string(),
+                                ))
+                            }
+                        };
+                        let range = match array.get(3) {
+                            Some(start) => Some((
+                                extract_integer(start)?,
+                                array.get(4).map(extract_integer).transpose()?,
+                            )),
+                            None => None,
+                        };
+                        let unit = match array.get(5) {
+                            None => RangeUnit::Byte,
+                            Some(unit) => match extract_string(unit)?.to_uppercase().as_str() {
+                                "BYTE" => RangeUnit::Byte,
+                                "BIT" => RangeUnit::Bit,
+                                _ => {
+                                    return Err(CommandError::ParseError(
+                                        "syntax error".to_string(),
+                                    ))
+                                }
+                            },
+                        };
+                        Ok(Command::BitPos {
+                            key,
+                            bit,
+                            range,
+                            unit,
+                        })
+                    }
 
-    #[test]
-    fn test_wrong_number_of_arguments() {
-        let input = RespValue::Array(Some(vec![RespValue::BulkString(Some("GET".to_string()))]));
+                    "SADD" => {
+                        if array.len() < 3 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "SADD".to_string(),
+                                expected: 3,
+                                got: array.len(),
+                            });
+                        }
 
-        assert!(matches!(
-            Command::try_from(input),
-            Err(CommandError::WrongNumberOfArguments { .. })
+                        let key = extract_bytes(&array[1])?;
+                        let members = array[2..].iter().map(extract_bytes).collect::<Result<
+                            Vec<Vec<u8>>,
+                            _,
+                        >>(
+                        )?;
+                        Ok(Command::SAdd { key, members })
+                    }
+
+                    "SMOVE" => {
+                        if array.len() != 4 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "SMOVE".to_string(),
+                                expected: 4,
+                                got: array.len(),
+                            });
+                        }
+                        let source = extract_bytes(&array[1])?;
+                        let destination = extract_bytes(&array[2])?;
+                        let member = extract_bytes(&array[3])?;
+                        Ok(Command::SMove {
+                            source,
+                            destination,
+                            member,
+                        })
+                    }
+
+                    "SMEMBERS" => {
+                        if array.len() != 2 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "SMEMBERS".to_string(),
+                                expected: 2,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        Ok(Command::SMembers { key })
+                    }
+
+                    "SINTER" => {
+                        if array.len() < 2 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "SINTER".to_string(),
+                                expected: 2,
+                                got: array.len(),
+                            });
+                        }
+                        let keys = array[1..]
+                            .iter()
+                            .map(extract_bytes)
+                            .collect::<Result<Vec<Vec<u8>>, _>>()?;
+                        Ok(Command::SInter { keys })
+                    }
+
+                    "SINTERCARD" => {
+                        if array.len() < 3 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "SINTERCARD".to_string(),
+                                expected: 3,
+                                got: array.len(),
+                            });
+                        }
+                        let numkeys = extract_integer(&array[1])?;
+                        if numkeys <= 0 {
+                            return Err(CommandError::ParseError(
+                                "numkeys should be greater than 0".to_string(),
+                            ));
+                        }
+                        if numkeys as usize > array.len() - 2 {
+                            return Err(CommandError::ParseError(
+                                "Number of keys can't be greater than number of args".to_string(),
+                            ));
+                        }
+                        let split = 2 + numkeys as usize;
+                        let keys = array[2..split].iter().map(extract_bytes).collect::<Result<
+                            Vec<Vec<u8>>,
+                            _,
+                        >>(
+                        )?;
+                        let limit = match &array[split..] {
+                            [] => 0,
+                            [option, limit]
+                                if extract_string(option)?.eq_ignore_ascii_case("LIMIT") =>
+                            {
+                                usize::try_from(extract_integer(limit)?).map_err(|_| {
+                                    CommandError::ParseError("LIMIT can't be negative".to_string())
+                                })?
+                            }
+                            _ => return Err(CommandError::ParseError("syntax error".to_string())),
+                        };
+                        Ok(Command::SInterCard { keys, limit })
+                    }
+
+                    "SUNION" => {
+                        if array.len() < 2 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "SUNION".to_string(),
+                                expected: 2,
+                                got: array.len(),
+                            });
+                        }
+                        let keys = array[1..]
+                            .iter()
+                            .map(extract_bytes)
+                            .collect::<Result<Vec<Vec<u8>>, _>>()?;
+                        Ok(Command::SUnion { keys })
+                    }
+
+                    "SDIFF" => {
+                        if array.len() < 2 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "SDIFF".to_string(),
+                                expected: 2,
+                                got: array.len(),
+                            });
+                        }
+                        let keys = array[1..]
+                            .iter()
+                            .map(extract_bytes)
+                            .collect::<Result<Vec<Vec<u8>>, _>>()?;
+                        Ok(Command::SDiff { keys })
+                    }
+
+                    "SINTERSTORE" => {
+                        if array.len() < 3 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "SINTERSTORE".to_string(),
+                                expected: 3,
+                                got: array.len(),
+                            });
+                        }
+                        let destination = extract_bytes(&array[1])?;
+                        let keys = array[2..]
+                            .iter()
+                            .map(extract_bytes)
+                            .collect::<Result<Vec<Vec<u8>>, _>>()?;
+                        Ok(Command::SInterStore { destination, keys })
+                    }
+
+                    "SUNIONSTORE" => {
+                        if array.len() < 3 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "SUNIONSTORE".to_string(),
+                                expected: 3,
+                                got: array.len(),
+                            });
+                        }
+                        let destination = extract_bytes(&array[1])?;
+                        let keys = array[2..]
+                            .iter()
+                            .map(extract_bytes)
+                            .collect::<Result<Vec<Vec<u8>>, _>>()?;
+                        Ok(Command::SUnionStore { destination, keys })
+                    }
+
+                    "SDIFFSTORE" => {
+                        if array.len() < 3 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "SDIFFSTORE".to_string(),
+                                expected: 3,
+                                got: array.len(),
+                            });
+                        }
+                        let destination = extract_bytes(&array[1])?;
+                        let keys = array[2..]
+                            .iter()
+                            .map(extract_bytes)
+                            .collect::<Result<Vec<Vec<u8>>, _>>()?;
+                        Ok(Command::SDiffStore { destination, keys })
+                    }
+
+                    "LPUSH" => {
+                        if array.len() < 3 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "LPUSH".to_string(),
+                                expected: 3,
+                                got: array.len(),
+                            });
+                        }
+
+                        let key = extract_bytes(&array[1])?;
+                        let values = array[2..]
+                            .iter()
+                            .map(extract_bytes)
+                            .collect::<Result<Vec<Vec<u8>>, _>>()?;
+                        Ok(Command::LPush { key, values })
+                    }
+
+                    "RPUSH" => {
+                        if array.len() < 3 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "RPUSH".to_string(),
+                                expected: 3,
+                                got: array.len(),
+                            });
+                        }
+
+                        let key = extract_bytes(&array[1])?;
+                        let values = array[2..]
+                            .iter()
+                            .map(extract_bytes)
+                            .collect::<Result<Vec<Vec<u8>>, _>>()?;
+                        Ok(Command::RPush { key, values })
+                    }
+
+                    "LRANGE" => {
+                        if array.len() != 4 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "LRANGE".to_string(),
+                                expected: 4,
+                                got: array.len(),
+                            });
+                        }
+
+                        let key = extract_bytes(&array[1])?;
+                        let start = extract_integer(&array[2])?;
+                        let stop = extract_integer(&array[3])?;
+                        Ok(Command::LRange { key, start, stop })
+                    }
+
+                    "LLEN" => {
+                        if array.len() != 2 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "LLEN".to_string(),
+                                expected: 2,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        Ok(Command::LLen { key })
+                    }
+
+                    "LSET" => {
+                        if array.len() != 4 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "LSET".to_string(),
+                                expected: 4,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        let index = extract_integer(&array[2])?;
+                        let value = extract_bytes(&array[3])?;
+                        Ok(Command::LSet { key, index, value })
+                    }
+
+                    "LINSERT" => {
+                        if array.len() != 5 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "LINSERT".to_string(),
+                                expected: 5,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        let before = match extract_string(&array[2])?.to_uppercase().as_str() {
+                            "BEFORE" => true,
+                            "AFTER" => false,
+                            _ => return Err(CommandError::ParseError("syntax error".to_string())),
+                        };
+                        let pivot = extract_bytes(&array[3])?;
+                        let value = extract_bytes(&array[4])?;
+                        Ok(Command::LInsert {
+                            key,
+                            before,
+                            pivot,
+                            value,
+                        })
+                    }
+
+                    "LREM" => {
+                        if array.len() != 4 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "LREM".to_string(),
+                                expected: 4,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        let count = extract_integer(&array[2])?;
+                        let value = extract_bytes(&array[3])?;
+                        Ok(Command::LRem { key, count, value })
+                    }
+
+                    "LTRIM" => {
+                        if array.len() != 4 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "LTRIM".to_string(),
+                                expected: 4,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        let start = extract_integer(&array[2])?;
+                        let stop = extract_integer(&array[3])?;
+                        Ok(Command::LTrim { key, start, stop })
+                    }
+
+                    "RPOPLPUSH" => {
+                        if array.len() != 3 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "RPOPLPUSH".to_string(),
+                                expected: 3,
+                                got: array.len(),
+                            });
+                        }
+                        let source = extract_bytes(&array[1])?;
+                        let destination = extract_bytes(&array[2])?;
+                        Ok(Command::RPopLPush {
+                            source,
+                            destination,
+                        })
+                    }
+
+                    "LMOVE" => {
+                        if array.len() != 5 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "LMOVE".to_string(),
+                                expected: 5,
+                                got: array.len(),
+                            });
+                        }
+                        let list_end = |value: &RespValue| match extract_string(value)?
+                            .to_uppercase()
+                            .as_str()
+                        {
+                            "LEFT" => Ok(ListEnd::Left),
+                            "RIGHT" => Ok(ListEnd::Right),
+                            _ => Err(CommandError::ParseError("syntax error".to_string())),
+                        };
+                        Ok(Command::LMove {
+                            source: extract_bytes(&array[1])?,
+                            destination: extract_bytes(&array[2])?,
+                            from: list_end(&array[3])?,
+                            to: list_end(&array[4])?,
+                        })
+                    }
+
+                    "SELECT" => {
+                        if array.len() != 2 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "SELECT".to_string(),
+                                expected: 2,
+                                got: array.len(),
+                            });
+                        }
+                        let index = extract_integer(&array[1])?;
+                        Ok(Command::Select { index })
+                    }
+
+                    "MOVE" => {
+                        if array.len() != 3 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "MOVE".to_string(),
+                                expected: 3,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        let db = extract_integer(&array[2])?;
+                        Ok(Command::Move { key, db })
+                    }
+
+                    "COPY" => {
+                        if array.len() < 3 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "COPY".to_string(),
+                                expected: 3,
+                                got: array.len(),
+                            });
+                        }
+                        let source = extract_bytes(&array[1])?;
+                        let destination = extract_bytes(&array[2])?;
+                        let mut db = None;
+                        let mut replace = false;
+                        let mut options = array[3..].iter();
+                        while let Some(option) = options.next() {
+                            match extract_string(option)?.to_uppercase().as_str() {
+                                "REPLACE" => replace = true,
+                                "DB" => match options.next() {
+                                    Some(value) => db = Some(extract_integer(value)?),
+                                    None => {
+                                        return Err(CommandError::ParseError(
+                                            "syntax error".to_string(),
+                                        ))
+                                    }
+                                },
+                                _ => {
+                                    return Err(CommandError::ParseError(
+                                        "syntax error".to_string(),
+                                    ))
+                                }
+                            }
+                        }
+                        Ok(Command::Copy {
+                            source,
+                            destination,
+                            db,
+                            replace,
+                        })
+                    }
+
+                    "BLPOP" | "BRPOP" => {
+                        if array.len() < 3 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: command_name,
+                                expected: 3,
+                                got: array.len(),
+                            });
+                        }
+
+                        let keys = array[1..array.len() - 1]
+                            .iter()
+                            .map(extract_bytes)
+                            .collect::<Result<Vec<Vec<u8>>, _>>()?;
+                        let timeout = extract_string(&array[array.len() - 1])?
+                            .parse::<f64>()
+                            .ok()
+                            .filter(|timeout| timeout.is_finite())
+                            .ok_or_else(|| {
+                                CommandError::ParseError(
+                                    "timeout is not a float or out of range".to_string(),
+                                )
+                            })?;
+                        if timeout < 0.0 {
+                            return Err(CommandError::ParseError(
+                                "timeout is negative".to_string(),
+                            ));
+                        }
+
+                        if command_name == "BLPOP" {
+                            Ok(Command::BLPop { keys, timeout })
+                        } else {
+                            Ok(Command::BRPop { keys, timeout })
+                        }
+                    }
+
+                    "GETEX" => {
+                        if array.len() < 2 || array.len() > 4 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "GETEX".to_string(),
+                                expected: 2,
+                                got: array.len(),
+                            });
+                        }
+
+                        let key = extract_bytes(&array[1])?;
+                        let expiry = match &array[2..] {
+                            [] => None,
+                            [option] => match extract_string(option)?.to_uppercase().as_str() {
+                                "PERSIST" => Some(Expiry::Persist),
+                                _ => {
+                                    return Err(CommandError::ParseError(
+                                        "syntax error".to_string(),
+                                    ))
+                                }
+                            },
+                            [option, amount] => {
+                                let amount = extract_integer(amount)?;
+                                if amount <= 0 {
+                                    return Err(CommandError::ParseError(
+                                        "invalid expire time in 'getex' command".to_string(),
+                                    ));
+                                }
+                                let amount = amount as u64;
+                                match extract_string(option)?.to_uppercase().as_str() {
+                                    "EX" => Some(Expiry::Ex(amount)),
+                                    "PX" => Some(Expiry::Px(amount)),
+                                    "EXAT" => Some(Expiry::ExAt(amount)),
+                                    "PXAT" => Some(Expiry::PxAt(amount)),
+                                    _ => {
+                                        return Err(CommandError::ParseError(
+                                            "syntax error".to_string(),
+                                        ))
+                                    }
+                                }
+                            }
+                            _ => unreachable!(),
+                        };
+                        Ok(Command::GetEx { key, expiry })
+                    }
+
+                    "SORT" => {
+                        if array.len() < 2 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "SORT".to_string(),
+                                expected: 2,
+                                got: array.len(),
+                            });
+                        }
+
+                        let key = extract_bytes(&array[1])?;
+                        let mut alpha = false;
+                        let mut descending = false;
+                        let mut limit = None;
+                        let mut i = 2;
+                        while i < array.len() {
+                            match extract_string(&array[i])?.to_uppercase().as_str() {
+                                "ALPHA" => alpha = true,
+                                "ASC" => descending = false,
+                                "DESC" => descending = true,
+                                "LIMIT" if i + 2 < array.len() => {
+                                    let offset = extract_integer(&array[i + 1])?;
+                                    let count = extract_integer(&array[i + 2])?;
+                                    limit = Some((offset, count));
+                                    i += 2;
+                                }
+                                _ => {
+                                    return Err(CommandError::ParseError(
+                                        "syntax error".to_string(),
+                                    ))
+                                }
+                            }
+                            i += 1;
+                        }
+                        Ok(Command::Sort {
+                            key,
+                            alpha,
+                            descending,
+                            limit,
+                        })
+                    }
+
+                    "DUMP" => {
+                        if array.len() != 2 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "DUMP".to_string(),
+                                expected: 2,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        Ok(Command::Dump { key })
+                    }
+
+                    "RESTORE" => {
+                        if array.len() != 4 && array.len() != 5 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "RESTORE".to_string(),
+                                expected: 4,
+                                got: array.len(),
+                            });
+                        }
+
+                        let key = extract_bytes(&array[1])?;
+                        let ttl = extract_integer(&array[2])?;
+                        let payload = extract_bytes(&array[3])?;
+                        let replace = match array.get(4) {
+                            Some(option)
+                                if extract_string(option)?.eq_ignore_ascii_case("REPLACE") =>
+                            {
+                                true
+                            }
+                            Some(_) => {
+                                return Err(CommandError::ParseError("syntax error".to_string()))
+                            }
+                            None => false,
+                        };
+                        Ok(Command::Restore {
+                            key,
+                            ttl,
+                            payload,
+                            replace,
+                        })
+                    }
+
+                    "EVAL" => {
+                        let (script, keys, args) = parse_script_call("EVAL", &array)?;
+                        Ok(Command::Eval { script, keys, args })
+                    }
+
+                    "EVALSHA" => {
+                        let (sha, keys, args) = parse_script_call("EVALSHA", &array)?;
+                        Ok(Command::EvalSha { sha, keys, args })
+                    }
+
+                    "SCRIPT" => {
+                        if array.len() < 2 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "SCRIPT".to_string(),
+                                expected: 2,
+                                got: array.len(),
+                            });
+                        }
+
+                        match extract_string(&array[1])?.to_uppercase().as_str() {
+                            "LOAD" => {
+                                if array.len() != 3 {
+                                    return Err(CommandError::WrongNumberOfArguments {
+                                        cmd: "SCRIPT LOAD".to_string(),
+                                        expected: 3,
+                                        got: array.len(),
+                                    });
+                                }
+                                let script = extract_string(&array[2])?;
+                                Ok(Command::ScriptLoad { script })
+                            }
+                            "HELP" if array.len() == 2 => Ok(Command::Help { command: "SCRIPT" }),
+                            subcommand => Err(CommandError::UnknownCommand(format!(
+                                "SCRIPT {}",
+                                subcommand
+                            ))),
+                        }
+                    }
+
+                    "APPEND" => {
+                        if array.len() != 3 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "APPEND".to_string(),
+                                expected: 3,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        let value = extract_bytes(&array[2])?;
+                        Ok(Command::Append { key, value })
+                    }
+
+                    "STRLEN" => {
+                        if array.len() != 2 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "STRLEN".to_string(),
+                                expected: 2,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        Ok(Command::StrLen { key })
+                    }
+
+                    "SETRANGE" => {
+                        if array.len() != 4 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "SETRANGE".to_string(),
+                                expected: 4,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        let offset = extract_integer(&array[2])?;
+                        let value = extract_bytes(&array[3])?;
+                        Ok(Command::SetRange { key, offset, value })
+                    }
+
+                    "GETRANGE" => {
+                        if array.len() != 4 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "GETRANGE".to_string(),
+                                expected: 4,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        let start = extract_integer(&array[2])?;
+                        let end = extract_integer(&array[3])?;
+                        Ok(Command::GetRange { key, start, end })
+                    }
+
+                    "SCAN" => {
+                        let (cursor, pattern, count, value_type) =
+                            parse_scan("SCAN", &array, 1, true)?;
+                        Ok(Command::Scan {
+                            cursor,
+                            pattern,
+                            count,
+                            value_type,
+                        })
+                    }
+
+                    "SSCAN" | "HSCAN" => {
+                        if array.len() < 3 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: command_name,
+                                expected: 3,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        let (cursor, pattern, count, _) =
+                            parse_scan(&command_name, &array, 2, false)?;
+                        if command_name == "SSCAN" {
+                            Ok(Command::SScan {
+                                key,
+                                cursor,
+                                pattern,
+                                count,
+                            })
+                        } else {
+                            Ok(Command::HScan {
+                                key,
+                                cursor,
+                                pattern,
+                                count,
+                            })
+                        }
+                    }
+
+                    "MSET" => {
+                        if array.len() < 3 || array.len() % 2 != 1 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "MSET".to_string(),
+                                expected: 3,
+                                got: array.len(),
+                            });
+                        }
+                        let pairs = array[1..]
+                            .chunks(2)
+                            .map(|pair| Ok((extract_bytes(&pair[0])?, extract_bytes(&pair[1])?)))
+                            .collect::<Result<_, CommandError>>()?;
+                        Ok(Command::MSet { pairs })
+                    }
+
+                    "HSET" => {
+                        if array.len() < 4 || array.len() % 2 != 0 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "HSET".to_string(),
+                                expected: 4,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        let pairs = array[2..]
+                            .chunks(2)
+                            .map(|pair| Ok((extract_bytes(&pair[0])?, extract_bytes(&pair[1])?)))
+                            .collect::<Result<_, CommandError>>()?;
+                        Ok(Command::HSet { key, pairs })
+                    }
+
+                    "ZADD" => {
+                        if array.len() < 4 || array.len() % 2 != 0 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "ZADD".to_string(),
+                                expected: 4,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        let pairs = array[2..]
+                            .chunks(2)
+                            .map(|pair| Ok((extract_score(&pair[0])?, extract_bytes(&pair[1])?)))
+                            .collect::<Result<_, CommandError>>()?;
+                        Ok(Command::ZAdd { key, pairs })
+                    }
+
+                    "ZSCORE" | "ZRANK" => {
+                        if array.len() != 3 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: command_name,
+                                expected: 3,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        let member = extract_bytes(&array[2])?;
+                        if command_name == "ZSCORE" {
+                            Ok(Command::ZScore { key, member })
+                        } else {
+                            Ok(Command::ZRank { key, member })
+                        }
+                    }
+
+                    "ZRANGE" | "ZREVRANGE" => {
+                        if array.len() != 4 && array.len() != 5 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: command_name,
+                                expected: 4,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        let start = extract_integer(&array[2])?;
+                        let stop = extract_integer(&array[3])?;
+                        let (withscores, limit) = parse_range_options(&array[4..])?;
+                        if limit.is_some() {
+                            return Err(CommandError::ParseError("syntax error".to_string()));
+                        }
+                        if command_name == "ZREVRANGE" {
+                            return Ok(Command::ZRevRange {
+                                key,
+                                start,
+                                stop,
+                                withscores,
+                            });
+                        }
+                        Ok(Command::ZRange {
+                            key,
+                            start,
+                            stop,
+                            withscores,
+                        })
+                    }
+
+                    "ZRANGEBYSCORE" | "ZREVRANGEBYSCORE" => {
+                        if array.len() < 4 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: command_name,
+                                expected: 4,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        let first = extract_score_bound(&array[2])?;
+                        let second = extract_score_bound(&array[3])?;
+                        let (withscores, limit) = parse_range_options(&array[4..])?;
+                        // The reverse variant takes its bounds as max then min
+                        if command_name == "ZREVRANGEBYSCORE" {
+                            return Ok(Command::ZRevRangeByScore {
+                                key,
+                                max: first,
+                                min: second,
+                                withscores,
+                                limit,
+                            });
+                        }
+                        let (min, max) = (first, second);
+                        Ok(Command::ZRangeByScore {
+                            key,
+                            min,
+                            max,
+                            withscores,
+                            limit,
+                        })
+                    }
+
+                    "ZCOUNT" => {
+                        if array.len() != 4 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "ZCOUNT".to_string(),
+                                expected: 4,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        let min = extract_score_bound(&array[2])?;
+                        let max = extract_score_bound(&array[3])?;
+                        Ok(Command::ZCount { key, min, max })
+                    }
+
+                    "ZRANGEBYLEX" => {
+                        if array.len() != 4 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "ZRANGEBYLEX".to_string(),
+                                expected: 4,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        let min = extract_lex_bound(&array[2])?;
+                        let max = extract_lex_bound(&array[3])?;
+                        Ok(Command::ZRangeByLex { key, min, max })
+                    }
+
+                    "ZINCRBY" => {
+                        if array.len() != 4 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "ZINCRBY".to_string(),
+                                expected: 4,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        let increment = extract_score(&array[2])?;
+                        let member = extract_bytes(&array[3])?;
+                        Ok(Command::ZIncrBy {
+                            key,
+                            increment,
+                            member,
+                        })
+                    }
+
+                    "ZCARD" => {
+                        if array.len() != 2 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "ZCARD".to_string(),
+                                expected: 2,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        Ok(Command::ZCard { key })
+                    }
+
+                    "ZREM" => {
+                        if array.len() < 3 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "ZREM".to_string(),
+                                expected: 3,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        let members = array[2..].iter().map(extract_bytes).collect::<Result<
+                            Vec<Vec<u8>>,
+                            _,
+                        >>(
+                        )?;
+                        Ok(Command::ZRem { key, members })
+                    }
+
+                    "ZPOPMIN" | "ZPOPMAX" => {
+                        if !(2..=3).contains(&array.len()) {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: command_name,
+                                expected: 2,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        let count = match array.get(2) {
+                            Some(count) => {
+                                Some(usize::try_from(extract_integer(count)?).map_err(|_| {
+                                    CommandError::ParseError(
+                                        "value is out of range, must be positive".to_string(),
+                                    )
+                                })?)
+                            }
+                            None => None,
+                        };
+                        Ok(if command_name == "ZPOPMIN" {
+                            Command::ZPopMin { key, count }
+                        } else {
+                            Command::ZPopMax { key, count }
+                        })
+                    }
+
+                    "HSETNX" => {
+                        if array.len() != 4 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "HSETNX".to_string(),
+                                expected: 4,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        let field = extract_bytes(&array[2])?;
+                        let value = extract_bytes(&array[3])?;
+                        Ok(Command::HSetNx { key, field, value })
+                    }
+
+                    "HMGET" => {
+                        if array.len() < 3 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "HMGET".to_string(),
+                                expected: 3,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        let fields = array[2..]
+                            .iter()
+                            .map(extract_bytes)
+                            .collect::<Result<Vec<Vec<u8>>, _>>()?;
+                        Ok(Command::HMGet { key, fields })
+                    }
+
+                    "HINCRBY" | "HINCRBYFLOAT" => {
+                        if array.len() != 4 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: command_name,
+                                expected: 4,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        let field = extract_bytes(&array[2])?;
+                        if command_name == "HINCRBY" {
+                            let increment = extract_integer(&array[3])?;
+                            return Ok(Command::HIncrBy {
+                                key,
+                                field,
+                                increment,
+                            });
+                        }
+                        let increment = extract_string(&array[3])?
+                            .parse::<f64>()
+                            .ok()
+                            .filter(|increment| increment.is_finite())
+                            .ok_or_else(|| {
+                                CommandError::ParseError("value is not a valid float".to_string())
+                            })?;
+                        Ok(Command::HIncrByFloat {
+                            key,
+                            field,
+                            increment,
+                        })
+                    }
+
+                    "HGET" => {
+                        if array.len() != 3 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "HGET".to_string(),
+                                expected: 3,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[1])?;
+                        let field = extract_bytes(&array[2])?;
+                        Ok(Command::HGet { key, field })
+                    }
+
+                    "OBJECT" => {
+                        if array.len() < 2 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "OBJECT".to_string(),
+                                expected: 2,
+                                got: array.len(),
+                            });
+                        }
+
+                        let subcommand = extract_string(&array[1])?.to_uppercase();
+                        if subcommand == "HELP" && array.len() == 2 {
+                            return Ok(Command::Help { command: "OBJECT" });
+                        }
+                        if array.len() != 3 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: format!("OBJECT {}", subcommand),
+                                expected: 3,
+                                got: array.len(),
+                            });
+                        }
+                        let key = extract_bytes(&array[2])?;
+                        match subcommand.as_str() {
+                            "REFCOUNT" => Ok(Command::ObjectRefCount { key }),
+                            "FREQ" => Ok(Command::ObjectFreq { key }),
+                            "ENCODING" => Ok(Command::ObjectEncoding { key }),
+                            _ => Err(CommandError::UnknownCommand(format!(
+                                "OBJECT {}",
+                                subcommand
+                            ))),
+                        }
+                    }
+
+                    "MONITOR" => {
+                        if array.len() != 1 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "MONITOR".to_string(),
+                                expected: 1,
+                                got: array.len(),
+                            });
+                        }
+                        Ok(Command::Monitor)
+                    }
+
+                    "MULTI" | "EXEC" | "DISCARD" => {
+                        if array.len() != 1 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: command_name,
+                                expected: 1,
+                                got: array.len(),
+                            });
+                        }
+                        Ok(match command_name.as_str() {
+                            "MULTI" => Command::Multi,
+                            "EXEC" => Command::Exec,
+                            _ => Command::Discard,
+                        })
+                    }
+
+                    "HELLO" => {
+                        let protover = array
+                            .get(1)
+                            .map(|protover| {
+                                extract_string(protover)?.parse::<i64>().map_err(|_| {
+                                    CommandError::ParseError(
+                                        "Protocol version is not an integer or out of range"
+                                            .to_string(),
+                                    )
+                                })
+                            })
+                            .transpose()?;
+
+                        let mut auth = None;
+                        let mut setname = None;
+                        let mut i = 2;
+                        while i < array.len() {
+                            match extract_string(&array[i])?.to_uppercase().as_str() {
+                                "AUTH" if i + 2 < array.len() => {
+                                    auth = Some((
+                                        extract_string(&array[i + 1])?,
+                                        extract_string(&array[i + 2])?,
+                                    ));
+                                    i += 3;
+                                }
+                                "SETNAME" if i + 1 < array.len() => {
+                                    setname = Some(extract_string(&array[i + 1])?);
+                                    i += 2;
+                                }
+                                _ => {
+                                    return Err(CommandError::ParseError(
+                                        "syntax error".to_string(),
+                                    ))
+                                }
+                            }
+                        }
+                        Ok(Command::Hello {
+                            protover,
+                            auth,
+                            setname,
+                        })
+                    }
+
+                    "SUBSCRIBE" | "PSUBSCRIBE" => {
+                        if array.len() < 2 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: command_name,
+                                expected: 2,
+                                got: array.len(),
+                            });
+                        }
+                        let names = array[1..]
+                            .iter()
+                            .map(extract_string)
+                            .collect::<Result<Vec<_>, _>>()?;
+                        Ok(if command_name == "SUBSCRIBE" {
+                            Command::Subscribe { channels: names }
+                        } else {
+                            Command::PSubscribe { patterns: names }
+                        })
+                    }
+
+                    "UNSUBSCRIBE" | "PUNSUBSCRIBE" => {
+                        let names = array[1..]
+                            .iter()
+                            .map(extract_string)
+                            .collect::<Result<Vec<_>, _>>()?;
+                        Ok(if command_name == "UNSUBSCRIBE" {
+                            Command::Unsubscribe { channels: names }
+                        } else {
+                            Command::PUnsubscribe { patterns: names }
+                        })
+                    }
+
+                    "PUBLISH" => {
+                        if array.len() != 3 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "PUBLISH".to_string(),
+                                expected: 3,
+                                got: array.len(),
+                            });
+                        }
+                        Ok(Command::Publish {
+                            channel: extract_string(&array[1])?,
+                            message: extract_string(&array[2])?,
+                        })
+                    }
+
+                    "PUBSUB" => {
+                        if array.len() < 2 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "PUBSUB".to_string(),
+                                expected: 2,
+                                got: array.len(),
+                            });
+                        }
+
+                        match extract_string(&array[1])?.to_uppercase().as_str() {
+                            "CHANNELS" if array.len() <= 3 => Ok(Command::PubSubChannels {
+                                pattern: array.get(2).map(extract_string).transpose()?,
+                            }),
+                            "NUMSUB" => Ok(Command::PubSubNumSub {
+                                channels: array[2..]
+                                    .iter()
+                                    .map(extract_string)
+                                    .collect::<Result<_, _>>()?,
+                            }),
+                            "NUMPAT" if array.len() == 2 => Ok(Command::PubSubNumPat),
+                            "HELP" if array.len() == 2 => Ok(Command::Help { command: "PUBSUB" }),
+                            subcommand @ ("CHANNELS" | "NUMPAT" | "HELP") => {
+                                Err(CommandError::WrongNumberOfArguments {
+                                    cmd: format!("PUBSUB {}", subcommand),
+                                    expected: if subcommand == "CHANNELS" { 3 } else { 2 },
+                                    got: array.len(),
+                                })
+                            }
+                            subcommand => Err(CommandError::UnknownCommand(format!(
+                                "PUBSUB {}",
+                                subcommand
+                            ))),
+                        }
+                    }
+
+                    "MEMORY" => {
+                        if array.len() < 2 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "MEMORY".to_string(),
+                                expected: 2,
+                                got: array.len(),
+                            });
+                        }
+
+                        match extract_string(&array[1])?.to_uppercase().as_str() {
+                            "USAGE" if array.len() >= 3 => {
+                                let key = extract_bytes(&array[2])?;
+                                let samples = match &array[3..] {
+                                    [] => None,
+                                    [option, count]
+                                        if extract_string(option)?
+                                            .eq_ignore_ascii_case("SAMPLES") =>
+                                    {
+                                        Some(usize::try_from(extract_integer(count)?).map_err(
+                                            |_| {
+                                                CommandError::ParseError(
+                                                    "SAMPLES can't be negative".to_string(),
+                                                )
+                                            },
+                                        )?)
+                                    }
+                                    _ => {
+                                        return Err(CommandError::ParseError(
+                                            "syntax error".to_string(),
+                                        ))
+                                    }
+                                };
+                                Ok(Command::MemoryUsage { key, samples })
+                            }
+                            "HELP" if array.len() == 2 => Ok(Command::Help { command: "MEMORY" }),
+                            subcommand @ ("USAGE" | "HELP") => {
+                                Err(CommandError::WrongNumberOfArguments {
+                                    cmd: format!("MEMORY {}", subcommand),
+                                    expected: if subcommand == "USAGE" { 3 } else { 2 },
+                                    got: array.len(),
+                                })
+                            }
+                            subcommand => Err(CommandError::UnknownCommand(format!(
+                                "MEMORY {}",
+                                subcommand
+                            ))),
+                        }
+                    }
+
+                    "AUTH" => match array.len() {
+                        2 => Ok(Command::Auth {
+                            username: None,
+                            password: extract_string(&array[1])?,
+                        }),
+                        3 => Ok(Command::Auth {
+                            username: Some(extract_string(&array[1])?),
+                            password: extract_string(&array[2])?,
+                        }),
+                        got => Err(CommandError::WrongNumberOfArguments {
+                            cmd: "AUTH".to_string(),
+                            expected: 2,
+                            got,
+                        }),
+                    },
+
+                    "CLIENT" => {
+                        let Some(subcommand) = array.get(1) else {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "CLIENT".to_string(),
+                                expected: 2,
+                                got: array.len(),
+                            });
+                        };
+                        let subcommand = extract_string(subcommand)?.to_uppercase();
+                        let expected = match subcommand.as_str() {
+                            "HELP" => 2,
+                            "SETINFO" => 4,
+                            "GETNAME" => 2,
+                            _ => 3,
+                        };
+                        if array.len() != expected {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: format!("CLIENT {}", subcommand),
+                                expected,
+                                got: array.len(),
+                            });
+                        }
+
+                        // Client libraries send these while connecting; they
+                        // are accepted so the handshake succeeds
+                        match subcommand.as_str() {
+                            "SETINFO" => {
+                                let attribute = extract_string(&array[2])?;
+                                if !["LIB-NAME", "LIB-VER"]
+                                    .contains(&attribute.to_uppercase().as_str())
+                                {
+                                    return Err(CommandError::ParseError(format!(
+                                        "unrecognized option '{}'",
+                                        attribute
+                                    )));
+                                }
+                                let value = extract_string(&array[3])?;
+                                Ok(Command::ClientSetInfo { attribute, value })
+                            }
+                            "SETNAME" => Ok(Command::ClientSetName {
+                                name: extract_string(&array[2])?,
+                            }),
+                            "GETNAME" => Ok(Command::ClientGetName),
+                            "HELP" => Ok(Command::Help { command: "CLIENT" }),
+                            "NO-EVICT" => Ok(Command::ClientNoEvict {
+                                enabled: extract_switch(&array[2])?,
+                            }),
+                            "NO-TOUCH" => Ok(Command::ClientNoTouch {
+                                enabled: extract_switch(&array[2])?,
+                            }),
+                            subcommand => Err(CommandError::UnknownCommand(format!(
+                                "CLIENT {}",
+                                subcommand
+                            ))),
+                        }
+                    }
+
+                    "SLOWLOG" => {
+                        if array.len() < 2 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "SLOWLOG".to_string(),
+                                expected: 2,
+                                got: array.len(),
+                            });
+                        }
+
+                        match extract_string(&array[1])?.to_uppercase().as_str() {
+                            "GET" if array.len() <= 3 => {
+                                let count = array.get(2).map(extract_integer).transpose()?;
+                                Ok(Command::SlowLogGet { count })
+                            }
+                            "LEN" if array.len() == 2 => Ok(Command::SlowLogLen),
+                            "RESET" if array.len() == 2 => Ok(Command::SlowLogReset),
+                            "GET" | "LEN" | "RESET" => Err(CommandError::WrongNumberOfArguments {
+                                cmd: "SLOWLOG".to_string(),
+                                expected: 2,
+                                got: array.len(),
+                            }),
+                            "HELP" if array.len() == 2 => Ok(Command::Help { command: "SLOWLOG" }),
+                            subcommand => Err(CommandError::UnknownCommand(format!(
+                                "SLOWLOG {}",
+                                subcommand
+                            ))),
+                        }
+                    }
+
+                    "CONFIG" => {
+                        if array.len() < 2 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "CONFIG".to_string(),
+                                expected: 2,
+                                got: array.len(),
+                            });
+                        }
+
+                        match extract_string(&array[1])?.to_uppercase().as_str() {
+                            "GET" if array.len() == 3 => Ok(Command::ConfigGet {
+                                pattern: extract_string(&array[2])?,
+                            }),
+                            "SET" if array.len() == 4 => Ok(Command::ConfigSet {
+                                parameter: extract_string(&array[2])?,
+                                value: extract_string(&array[3])?,
+                            }),
+                            "GET" | "SET" => Err(CommandError::WrongNumberOfArguments {
+                                cmd: "CONFIG".to_string(),
+                                expected: if array.len() < 3 { 3 } else { 4 },
+                                got: array.len(),
+                            }),
+                            "REWRITE" if array.len() == 2 => Ok(Command::ConfigRewrite),
+                            "REWRITE" => Err(CommandError::WrongNumberOfArguments {
+                                cmd: "CONFIG REWRITE".to_string(),
+                                expected: 2,
+                                got: array.len(),
+                            }),
+                            "HELP" if array.len() == 2 => Ok(Command::Help { command: "CONFIG" }),
+                            subcommand => Err(CommandError::UnknownCommand(format!(
+                                "CONFIG {}",
+                                subcommand
+                            ))),
+                        }
+                    }
+
+                    "DEBUG" => {
+                        if array.len() < 2 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "DEBUG".to_string(),
+                                expected: 2,
+                                got: array.len(),
+                            });
+                        }
+
+                        match extract_string(&array[1])?.to_uppercase().as_str() {
+                            "SLEEP" if array.len() == 3 => {
+                                let seconds = extract_string(&array[2])?
+                                    .parse::<f64>()
+                                    .ok()
+                                    .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+                                    .ok_or_else(|| {
+                                        CommandError::ParseError(
+                                            "value is not a valid float".to_string(),
+                                        )
+                                    })?;
+                                Ok(Command::DebugSleep { seconds })
+                            }
+                            "SLEEP" => Err(CommandError::WrongNumberOfArguments {
+                                cmd: "DEBUG SLEEP".to_string(),
+                                expected: 3,
+                                got: array.len(),
+                            }),
+                            "OBJECT" if array.len() == 3 => Ok(Command::DebugObject {
+                                key: extract_bytes(&array[2])?,
+                            }),
+                            "OBJECT" => Err(CommandError::WrongNumberOfArguments {
+                                cmd: "DEBUG OBJECT".to_string(),
+                                expected: 3,
+                                got: array.len(),
+                            }),
+                            "RELOAD" if array.len() == 2 => Ok(Command::DebugReload),
+                            "HELP" if array.len() == 2 => Ok(Command::Help { command: "DEBUG" }),
+                            subcommand => Err(CommandError::UnknownCommand(format!(
+                                "DEBUG {}",
+                                subcommand
+                            ))),
+                        }
+                    }
+
+                    "INFO" => {
+                        if array.len() > 2 {
+                            return Err(CommandError::WrongNumberOfArguments {
+                                cmd: "INFO".to_string(),
+                                expected: 2,
+                                got: array.len(),
+                            });
+                        }
+                        let section = array.get(1).map(extract_string).transpose()?;
+                        Ok(Command::Info { section })
+                    }
+
+                    _ => Err(CommandError::UnknownCommand(command_name)),
+                }
+            }
+            _ => Err(CommandError::ParseError("expected array".to_string())),
+        }
+    }
+}
+
+/// Parses a command sent under the `rename-command` map, which goes from
+/// lowercase original names to their aliases. Clients must use the alias,
+/// and a renamed original is unknown, as is anything renamed to "".
+pub fn parse_renamed(
+    value: RespValue,
+    renames: &HashMap<String, String>,
+) -> Result<Command, CommandError> {
+    let RespValue::Array(Some(mut array)) = value else {
+        return Command::try_from(value);
+    };
+    if let Some(RespValue::BulkString(Some(name))) = array.first_mut() {
+        let lowercase = String::from_utf8_lossy(name).to_lowercase();
+        if let Some((original, _)) = renames
+            .iter()
+            .find(|(_, alias)| !alias.is_empty() && **alias == lowercase)
+        {
+            *name = original.clone().into_bytes();
+        } else if renames.contains_key(&lowercase) {
+            return Err(CommandError::UnknownCommand(lowercase.to_uppercase()));
+        }
+    }
+    Command::try_from(RespValue::Array(Some(array)))
+}
+
+/// Keys, values and other binary-safe arguments, taken as the raw bytes
+/// sent.
+fn extract_bytes(value: &RespValue) -> Result<Vec<u8>, CommandError> {
+    match value {
+        RespValue::BulkString(Some(bytes)) => Ok(bytes.clone()),
+        RespValue::SimpleString(s) => Ok(s.clone().into_bytes()),
+        _ => Err(CommandError::ParseError("expected string".to_string())),
+    }
+}
+
+/// Arguments read as text, such as numbers, options and patterns. Invalid
+/// UTF-8 is replaced rather than rejected, so it fails whatever parse or
+/// match comes next with that command's own error.
+fn extract_string(value: &RespValue) -> Result<String, CommandError> {
+    extract_bytes(value).map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// A script body (or its SHA1) with its keys and remaining arguments.
+type ScriptCall = (String, Vec<Vec<u8>>, Vec<Vec<u8>>);
+
+/// Splits `<cmd> body numkeys key... arg...` into the script body (or its
+/// SHA1), the keys and the remaining arguments.
+fn parse_script_call(cmd: &str, array: &[RespValue]) -> Result<ScriptCall, CommandError> {
+    if array.len() < 3 {
+        return Err(CommandError::WrongNumberOfArguments {
+            cmd: cmd.to_string(),
+            expected: 3,
+            got: array.len(),
+        });
+    }
+
+    let body = extract_string(&array[1])?;
+    let numkeys = extract_integer(&array[2])?;
+    if numkeys < 0 {
+        return Err(CommandError::ParseError(
+            "Number of keys can't be negative".to_string(),
+        ));
+    }
+    if numkeys as usize > array.len() - 3 {
+        return Err(CommandError::ParseError(
+            "Number of keys can't be greater than number of args".to_string(),
+        ));
+    }
+
+    let split = 3 + numkeys as usize;
+    let keys = array[3..split]
+        .iter()
+        .map(extract_bytes)
+        .collect::<Result<_, _>>()?;
+    let args = array[split..]
+        .iter()
+        .map(extract_bytes)
+        .collect::<Result<_, _>>()?;
+    Ok((body, keys, args))
+}
+
+/// Parses the `ON`/`OFF` argument of a connection setting.
+fn extract_switch(value: &RespValue) -> Result<bool, CommandError> {
+    match extract_string(value)?.to_uppercase().as_str() {
+        "ON" => Ok(true),
+        "OFF" => Ok(false),
+        _ => Err(CommandError::ParseError("syntax error".to_string())),
+    }
+}
+
+fn extract_score(value: &RespValue) -> Result<f64, CommandError> {
+    extract_string(value)?
+        .parse::<f64>()
+        .ok()
+        .filter(|score| !score.is_nan())
+        .ok_or_else(|| CommandError::ParseError("value is not a valid float".to_string()))
+}
+
+fn extract_score_bound(value: &RespValue) -> Result<ScoreBound, CommandError> {
+    ScoreBound::parse(&extract_string(value)?)
+        .ok_or_else(|| CommandError::ParseError("min or max is not a float".to_string()))
+}
+
+fn extract_lex_bound(value: &RespValue) -> Result<LexBound, CommandError> {
+    LexBound::parse(&extract_string(value)?).ok_or_else(|| {
+        CommandError::ParseError("min or max not valid string range item".to_string())
+    })
+}
+
+/// Parses the `[WITHSCORES] [LIMIT offset count]` options of a sorted-set
+/// range query, in either order.
+fn parse_range_options(options: &[RespValue]) -> Result<(bool, Option<(i64, i64)>), CommandError> {
+    let mut withscores = false;
+    let mut limit = None;
+    let mut i = 0;
+    while i < options.len() {
+        match extract_string(&options[i])?.to_uppercase().as_str() {
+            "WITHSCORES" => {
+                withscores = true;
+                i += 1;
+            }
+            "LIMIT" if i + 2 < options.len() => {
+                limit = Some((
+                    extract_integer(&options[i + 1])?,
+                    extract_integer(&options[i + 2])?,
+                ));
+                i += 3;
+            }
+            _ => return Err(CommandError::ParseError("syntax error".to_string())),
+        }
+    }
+    Ok((withscores, limit))
+}
+
+/// Parses `cursor [MATCH pattern] [COUNT n] [TYPE type]` starting at
+/// `array[cursor_index]`. TYPE is only accepted when `allow_type` is set.
+fn parse_scan(
+    cmd: &str,
+    array: &[RespValue],
+    cursor_index: usize,
+    allow_type: bool,
+) -> Result<(u64, Option<String>, usize, Option<String>), CommandError> {
+    if array.len() <= cursor_index {
+        return Err(CommandError::WrongNumberOfArguments {
+            cmd: cmd.to_string(),
+            expected: cursor_index + 1,
+            got: array.len(),
+        });
+    }
+
+    let cursor = extract_string(&array[cursor_index])?
+        .parse::<u64>()
+        .map_err(|_| CommandError::ParseError("invalid cursor".to_string()))?;
+    let mut pattern = None;
+    let mut count = 10;
+    let mut value_type = None;
+    for option in array[cursor_index + 1..].chunks(2) {
+        let [name, value] = option else {
+            return Err(CommandError::ParseError("syntax error".to_string()));
+        };
+        match extract_string(name)?.to_uppercase().as_str() {
+            "MATCH" => pattern = Some(extract_string(value)?),
+            "COUNT" => {
+                count = extract_integer(value)?;
+                if count < 1 {
+                    return Err(CommandError::ParseError("syntax error".to_string()));
+                }
+            }
+            "TYPE" if allow_type => value_type = Some(extract_string(value)?.to_lowercase()),
+            _ => return Err(CommandError::ParseError("syntax error".to_string())),
+        }
+    }
+    Ok((cursor, pattern, count as usize, value_type))
+}
+
+fn extract_integer(value: &RespValue) -> Result<i64, CommandError> {
+    extract_string(value)?.parse::<i64>().map_err(|_| {
+        CommandError::ParseError("value is not an integer or out of range".to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_get() {
+        let input = RespValue::Array(Some(vec![
+            RespValue::BulkString(Some("GET".into())),
+            RespValue::BulkString(Some("mykey".into())),
+        ]));
+
+        assert_eq!(
+            Command::try_from(input).unwrap(),
+            Command::Get {
+                key: "mykey".into()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_set() {
+        let input = RespValue::Array(Some(vec![
+            RespValue::BulkString(Some("SET".into())),
+            RespValue::BulkString(Some("mykey".into())),
+            RespValue::BulkString(Some("myvalue".into())),
+        ]));
+
+        assert_eq!(
+            Command::try_from(input).unwrap(),
+            Command::Set {
+                key: "mykey".into(),
+                value: "myvalue".into(),
+                condition: None,
+                get: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_del() {
+        let input = RespValue::Array(Some(vec![
+            RespValue::BulkString(Some("DEL".into())),
+            RespValue::BulkString(Some("key1".into())),
+            RespValue::BulkString(Some("key2".into())),
+        ]));
+
+        assert_eq!(
+            Command::try_from(input).unwrap(),
+            Command::Del {
+                keys: vec!["key1".into(), "key2".into()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_ping() {
+        let input = RespValue::Array(Some(vec![RespValue::BulkString(Some("PING".into()))]));
+
+        assert_eq!(Command::try_from(input).unwrap(), Command::Ping);
+    }
+
+    #[test]
+    fn test_parse_renamed() {
+        let renames = HashMap::from([
+            ("flushall".to_string(), "secretflush".to_string()),
+            ("debug".to_string(), String::new()),
+        ]);
+        let parse = |name: &str| {
+            parse_renamed(
+                RespValue::Array(Some(vec![RespValue::BulkString(Some(
+                    name.as_bytes().to_vec(),
+                ))])),
+                &renames,
+            )
+        };
+
+        assert_eq!(
+            parse("SecretFlush").unwrap(),
+            Command::FlushAll {
+                asynchronous: false
+            }
+        );
+        assert!(matches!(
+            parse("FLUSHALL"),
+            Err(CommandError::UnknownCommand(cmd)) if cmd == "FLUSHALL"
+        ));
+        assert!(matches!(
+            parse("DEBUG"),
+            Err(CommandError::UnknownCommand(_))
+        ));
+        assert!(matches!(parse(""), Err(CommandError::UnknownCommand(_))));
+        assert_eq!(parse("PING").unwrap(), Command::Ping);
+    }
+
+    #[test]
+    fn test_parse_time() {
+        let input = RespValue::Array(Some(vec![RespValue::BulkString(Some("TIME".into()))]));
+
+        assert_eq!(Command::try_from(input).unwrap(), Command::Time);
+    }
+
+    #[test]
+    fn test_parse_bitcount() {
+        let input = RespValue::Array(Some(vec![
+            RespValue::BulkString(Some("BITCOUNT".into())),
+            RespValue::BulkString(Some("mykey".into())),
+            RespValue::BulkString(Some("1".into())),
+            RespValue::BulkString(Some("-1".into())),
+        ]));
+
+        assert_eq!(
+            Command::try_from(input).unwrap(),
+            Command::BitCount {
+                key: "mykey".into(),
+                range: Some((1, -1)),
+            }
+        );
+
+        let input = RespValue::Array(Some(vec![
+            RespValue::BulkString(Some("BITCOUNT".into())),
+            RespValue::BulkString(Some("mykey".into())),
+            RespValue::BulkString(Some("1".into())),
+        ]));
+        assert!(matches!(
+            Command::try_from(input),
+            Err(CommandError::WrongNumberOfArguments { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_sintercard() {
+        let parse = |args: &[&str]| {
+            Command::try_from(RespValue::Array(Some(
+                args.iter()
+                    .map(|arg| RespValue::BulkString(Some(arg.as_bytes().to_vec())))
+                    .collect(),
+            )))
+        };
+
+        assert_eq!(
+            parse(&["SINTERCARD", "2", "a", "b", "limit", "5"]).unwrap(),
+            Command::SInterCard {
+                keys: vec!["a".into(), "b".into()],
+                limit: 5,
+            }
+        );
+        assert_eq!(
+            parse(&["SINTERCARD", "1", "a"]).unwrap(),
+            Command::SInterCard {
+                keys: vec!["a".into()],
+                limit: 0,
+            }
+        );
+        for args in [
+            &["SINTERCARD", "0", "a"][..],
+            &["SINTERCARD", "3", "a", "b"],
+            &["SINTERCARD", "1", "a", "b"],
+            &["SINTERCARD", "1", "a", "LIMIT", "-1"],
+        ] {
+            assert!(
+                matches!(parse(args), Err(CommandError::ParseError(_))),
+                "{:?}",
+                args
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_memory_usage() {
+        let parse = |args: &[&str]| {
+            Command::try_from(RespValue::Array(Some(
+                args.iter()
+                    .map(|arg| RespValue::BulkString(Some(arg.as_bytes().to_vec())))
+                    .collect(),
+            )))
+        };
+
+        assert_eq!(
+            parse(&["MEMORY", "usage", "k", "SAMPLES", "0"]).unwrap(),
+            Command::MemoryUsage {
+                key: "k".into(),
+                samples: Some(0),
+            }
+        );
+        assert!(matches!(
+            parse(&["MEMORY", "USAGE"]),
+            Err(CommandError::WrongNumberOfArguments { .. })
+        ));
+        assert!(matches!(
+            parse(&["MEMORY", "USAGE", "k", "SAMPLES"]),
+            Err(CommandError::ParseError(_))
+        ));
+        assert!(matches!(
+            parse(&["MEMORY", "DOCTOR"]),
+            Err(CommandError::UnknownCommand(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_sinterstore() {
+        let input = RespValue::Array(Some(vec![
+            RespValue::BulkString(Some("SINTERSTORE".into())),
+            RespValue::BulkString(Some("dest".into())),
+            RespValue::BulkString(Some("a".into())),
+            RespValue::BulkString(Some("b".into())),
+        ]));
+
+        assert_eq!(
+            Command::try_from(input).unwrap(),
+            Command::SInterStore {
+                destination: "dest".into(),
+                keys: vec!["a".into(), "b".into()],
+            }
+        );
+
+        let input = RespValue::Array(Some(vec![
+            RespValue::BulkString(Some("SINTERSTORE".into())),
+            RespValue::BulkString(Some("dest".into())),
+        ]));
+        assert!(matches!(
+            Command::try_from(input),
+            Err(CommandError::WrongNumberOfArguments { .. })
+        ));
+    }
+
+    fn getex(args: &[&str]) -> Result<Command, CommandError> {
+        let mut array = vec![RespValue::BulkString(Some("GETEX".into()))];
+        array.extend(
+            args.iter()
+                .map(|arg| RespValue::BulkString(Some(arg.as_bytes().to_vec()))),
+        );
+        Command::try_from(RespValue::Array(Some(array)))
+    }
+
+    #[test]
+    fn test_parse_getex() {
+        let cases = [
+            (vec!["k"], None),
+            (vec!["k", "EX", "10"], Some(Expiry::Ex(10))),
+            (vec!["k", "px", "1500"], Some(Expiry::Px(1500))),
+            (
+                vec!["k", "EXAT", "1700000000"],
+                Some(Expiry::ExAt(1700000000)),
+            ),
+            (
+                vec!["k", "PXAT", "1700000000000"],
+                Some(Expiry::PxAt(1700000000000)),
+            ),
+            (vec!["k", "PERSIST"], Some(Expiry::Persist)),
+        ];
+
+        for (args, expiry) in cases {
+            assert_eq!(
+                getex(&args).unwrap(),
+                Command::GetEx {
+                    key: "k".into(),
+                    expiry,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_getex_rejects_bad_options() {
+        assert!(matches!(
+            getex(&["k", "EX", "0"]),
+            Err(CommandError::ParseError(_))
+        ));
+        assert!(matches!(
+            getex(&["k", "EX", "abc"]),
+            Err(CommandError::ParseError(_))
+        ));
+        assert!(matches!(
+            getex(&["k", "FOO", "10"]),
+            Err(CommandError::ParseError(_))
+        ));
+        assert!(matches!(
+            getex(&["k", "EX"]),
+            Err(CommandError::ParseError(_))
+        ));
+        assert!(matches!(
+            getex(&["k", "EX", "10", "PERSIST"]),
+            Err(CommandError::WrongNumberOfArguments { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_sort_options() {
+        let input = RespValue::Array(Some(
+            ["SORT", "mylist", "LIMIT", "1", "2", "alpha", "DESC"]
+                .iter()
+                .map(|arg| RespValue::BulkString(Some(arg.as_bytes().to_vec())))
+                .collect(),
+        ));
+
+        assert_eq!(
+            Command::try_from(input).unwrap(),
+            Command::Sort {
+                key: "mylist".into(),
+                alpha: true,
+                descending: true,
+                limit: Some((1, 2)),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_sort_incomplete_limit() {
+        let input = RespValue::Array(Some(
+            ["SORT", "mylist", "LIMIT", "1"]
+                .iter()
+                .map(|arg| RespValue::BulkString(Some(arg.as_bytes().to_vec())))
+                .collect(),
+        ));
+
+        assert!(matches!(
+            Command::try_from(input),
+            Err(CommandError::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_eval_splits_keys_and_args() {
+        let input = RespValue::Array(Some(
+            ["EVAL", "return 1", "2", "k1", "k2", "a1"]
+                .iter()
+                .map(|arg| RespValue::BulkString(Some(arg.as_bytes().to_vec())))
+                .collect(),
+        ));
+
+        assert_eq!(
+            Command::try_from(input).unwrap(),
+            Command::Eval {
+                script: "return 1".to_string(),
+                keys: vec!["k1".into(), "k2".into()],
+                args: vec!["a1".into()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_eval_too_many_keys() {
+        let input = RespValue::Array(Some(
+            ["EVAL", "return 1", "2", "k1"]
+                .iter()
+                .map(|arg| RespValue::BulkString(Some(arg.as_bytes().to_vec())))
+                .collect(),
+        ));
+
+        assert!(matches!(
+            Command::try_from(input),
+            Err(CommandError::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_scan_options() {
+        let input = RespValue::Array(Some(
+            ["SCAN", "17", "type", "List", "COUNT", "100", "MATCH", "a*"]
+                .iter()
+                .map(|arg| RespValue::BulkString(Some(arg.as_bytes().to_vec())))
+                .collect(),
+        ));
+
+        assert_eq!(
+            Command::try_from(input).unwrap(),
+            Command::Scan {
+                cursor: 17,
+                pattern: Some("a*".to_string()),
+                count: 100,
+                value_type: Some("list".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_flush_modifiers() {
+        let parse = |args: &[&str]| {
+            Command::try_from(RespValue::Array(Some(
+                args.iter()
+                    .map(|arg| RespValue::BulkString(Some(arg.as_bytes().to_vec())))
+                    .collect(),
+            )))
+        };
+
+        assert_eq!(
+            parse(&["FLUSHALL", "async"]).unwrap(),
+            Command::FlushAll { asynchronous: true }
+        );
+        assert_eq!(
+            parse(&["FLUSHDB"]).unwrap(),
+            Command::FlushDb {
+                asynchronous: false
+            }
+        );
+        assert!(matches!(
+            parse(&["FLUSHALL", "LATER"]),
+            Err(CommandError::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_persist_and_keys() {
+        let parse = |args: &[&str]| {
+            Command::try_from(RespValue::Array(Some(
+                args.iter()
+                    .map(|arg| RespValue::BulkString(Some(arg.as_bytes().to_vec())))
+                    .collect(),
+            )))
+        };
+
+        assert_eq!(
+            parse(&["PERSIST", "k"]).unwrap(),
+            Command::Persist { key: "k".into() }
+        );
+        assert_eq!(
+            parse(&["keys", "user:*"]).unwrap(),
+            Command::Keys {
+                pattern: "user:*".to_string()
+            }
+        );
+        for args in [&["KEYS"][..], &["KEYS", "a*", "b*"]] {
+            assert!(matches!(
+                parse(args),
+                Err(CommandError::WrongNumberOfArguments { expected: 2, .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_single_key_commands_reject_wrong_argument_counts() {
+        for name in ["INCR", "DECR", "GET", "TTL", "PERSIST"] {
+            for args in [vec![name], vec![name, "k", "extra"]] {
+                let input = RespValue::Array(Some(
+                    args.iter()
+                        .map(|arg| RespValue::BulkString(Some(arg.as_bytes().to_vec())))
+                        .collect(),
+                ));
+
+                match Command::try_from(input) {
+                    Err(CommandError::WrongNumberOfArguments { cmd, expected, got }) => {
+                        assert_eq!(cmd, name);
+                        assert_eq!(expected, 2);
+                        assert_eq!(got, args.len());
+                    }
+                    other => panic!("{:?} should be rejected, got {:?}", args, other),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_help_subcommands() {
+        for name in [
+            "OBJECT", "COMMAND", "CONFIG", "DEBUG", "SLOWLOG", "SCRIPT", "PUBSUB", "MEMORY",
+            "CLIENT",
+        ] {
+            let input = RespValue::Array(Some(vec![
+                RespValue::BulkString(Some(name.to_lowercase().into_bytes())),
+                RespValue::BulkString(Some("help".into())),
+            ]));
+
+            assert_eq!(
+                Command::try_from(input).unwrap(),
+                Command::Help { command: name }
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_zrevrangebyscore_takes_max_first() {
+        let input = RespValue::Array(Some(
+            ["ZREVRANGEBYSCORE", "z", "+inf", "(1", "WITHSCORES"]
+                .iter()
+                .map(|arg| RespValue::BulkString(Some(arg.as_bytes().to_vec())))
+                .collect(),
+        ));
+
+        assert_eq!(
+            Command::try_from(input).unwrap(),
+            Command::ZRevRangeByScore {
+                key: "z".into(),
+                max: ScoreBound::parse("+inf").unwrap(),
+                min: ScoreBound::parse("(1").unwrap(),
+                withscores: true,
+                limit: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_zrangebylex_rejects_malformed_bound() {
+        let input = RespValue::Array(Some(
+            ["ZRANGEBYLEX", "z", "a", "+"]
+                .iter()
+                .map(|arg| RespValue::BulkString(Some(arg.as_bytes().to_vec())))
+                .collect(),
+        ));
+
+        assert!(matches!(
+            Command::try_from(input),
+            Err(CommandError::ParseError(msg)) if msg == "min or max not valid string range item"
+        ));
+    }
+
+    #[test]
+    fn test_unknown_command() {
+        let input = RespValue::Array(Some(vec![RespValue::BulkString(Some("UNKNOWN".into()))]));
+
+        assert!(matches!(
+            Command::try_from(input),
+            Err(CommandError::UnknownCommand(_))
+        ));
+    }
+
+    #[test]
+    fn test_wrong_number_of_arguments() {
+        let input = RespValue::Array(Some(vec![RespValue::BulkString(Some("GET".into()))]));
+
+        assert!(matches!(
+            Command::try_from(input),
+            Err(CommandError::WrongNumberOfArguments { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_hello_options() {
+        let hello = |args: &[&str]| {
+            Command::try_from(RespValue::Array(Some(
+                args.iter()
+                    .map(|arg| RespValue::BulkString(Some(arg.as_bytes().to_vec())))
+                    .collect(),
+            )))
+        };
+
+        assert_eq!(
+            hello(&["HELLO", "3", "auth", "default", "secret", "SETNAME", "app"]).unwrap(),
+            Command::Hello {
+                protover: Some(3),
+                auth: Some(("default".to_string(), "secret".to_string())),
+                setname: Some("app".to_string()),
+            }
+        );
+        assert!(matches!(
+            hello(&["HELLO", "3", "AUTH", "default"]),
+            Err(CommandError::ParseError(_))
+        ));
+        assert!(matches!(
+            hello(&["HELLO", "3", "FOO"]),
+            Err(CommandError::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_bitpos() {
+        let bitpos = |args: &[&str]| {
+            Command::try_from(RespValue::Array(Some(
+                args.iter()
+                    .map(|arg| RespValue::BulkString(Some(arg.as_bytes().to_vec())))
+                    .collect(),
+            )))
+        };
+
+        assert_eq!(
+            bitpos(&["BITPOS", "k", "1"]).unwrap(),
+            Command::BitPos {
+                key: "k".into(),
+                bit: true,
+                range: None,
+                unit: RangeUnit::Byte,
+            }
+        );
+        assert_eq!(
+            bitpos(&["BITPOS", "k", "0", "2", "-1", "bit"]).unwrap(),
+            Command::BitPos {
+                key: "k".into(),
+                bit: false,
+                range: Some((2, Some(-1))),
+                unit: RangeUnit::Bit,
+            }
+        );
+        assert!(matches!(
+            bitpos(&["BITPOS", "k", "2"]),
+            Err(CommandError::ParseError(_))
+        ));
+        assert!(matches!(
+            bitpos(&["BITPOS", "k", "1", "0", "1", "WORD"]),
+            Err(CommandError::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_copy_options() {
+        let copy = |args: &[&str]| {
+            Command::try_from(RespValue::Array(Some(
+                args.iter()
+                    .map(|arg| RespValue::BulkString(Some(arg.as_bytes().to_vec())))
+                    .collect(),
+            )))
+        };
+
+        assert_eq!(
+            copy(&["COPY", "a", "b", "replace", "DB", "2"]).unwrap(),
+            Command::Copy {
+                source: "a".into(),
+                destination: "b".into(),
+                db: Some(2),
+                replace: true,
+            }
+        );
+        assert!(matches!(
+            copy(&["COPY", "a", "b", "DB"]),
+            Err(CommandError::ParseError(_))
         ));
     }
 }
@@ -1,126 +1,290 @@
-use std::sync::{Arc, Mutex};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use log::debug;
+use log::{debug, warn};
 
-use crate::{command::Command, resp::RespValue, storage::Storage};
+use crate::{
+    blocking::LIST_WAITERS,
+    command::{Command, CommandError, Expiry, ListEnd, RangeUnit, SetCondition},
+    command_table::{self, CommandSpec, COMMAND_TABLE},
+    config::Config,
+    connection::ConnectionContext,
+    persistence, pubsub,
+    resp::RespValue,
+    scripting,
+    slowlog::SLOWLOG,
+    sorted_set::format_score,
+    stats::{self, COMMAND_STATS, KEYSPACE_STATS, LOCK_STATS},
+    storage::{lock_storage, Databases, ExpireOutcome, SetOperation, Storage, StorageError, Value},
+    util::{normalize_range, parse_int},
+};
 
-pub fn handle_command(command: Command, storage: &Arc<Mutex<Storage>>) -> RespValue {
+pub fn handle_command(
+    command: Command,
+    databases: &Databases,
+    ctx: &mut ConnectionContext,
+) -> RespValue {
+    if is_disabled(ctx, &command) {
+        // Like any unknown command, this dooms an open transaction
+        if ctx.transaction.is_some() {
+            ctx.transaction_dirty = true;
+        }
+        let name = command.name().split('|').next().unwrap_or_default();
+        return RespValue::Error(CommandError::UnknownCommand(name.to_uppercase()).to_string());
+    }
+
+    // HELLO has its own NOAUTH reply, since it can carry credentials itself
+    if needs_auth(ctx) && !matches!(command, Command::Auth { .. } | Command::Hello { .. }) {
+        return RespValue::Error("NOAUTH Authentication required.".to_string());
+    }
+
+    // A subscribed connection only receives messages until it unsubscribes
+    if pubsub::is_subscribed(ctx)
+        && !matches!(
+            command,
+            Command::Subscribe { .. }
+                | Command::Unsubscribe { .. }
+                | Command::PSubscribe { .. }
+                | Command::PUnsubscribe { .. }
+                | Command::Ping
+        )
+    {
+        return RespValue::Error(format!(
+            "ERR Can't execute '{}': only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING are allowed in \
+             this context",
+            command.name()
+        ));
+    }
+
+    // Inside MULTI everything but the transaction commands is held for EXEC
+    if let Some(queued) = &mut ctx.transaction {
+        if !matches!(command, Command::Multi | Command::Exec | Command::Discard) {
+            queued.push(command);
+            return RespValue::SimpleString("QUEUED".to_string());
+        }
+    }
+
+    let name = command.name();
+    let start = Instant::now();
+    stats::take_lock_wait();
+    let reply = execute(command, databases, ctx);
+    COMMAND_STATS.record(name, start.elapsed());
+    let waited = stats::take_lock_wait();
+    if ctx.config.read().unwrap().is_slow_lock_wait(waited) {
+        LOCK_STATS.record_slow_wait();
+        warn!("{} waited {:?} for database locks", name, waited);
+    }
+    reply
+}
+
+pub fn is_disabled(ctx: &ConnectionContext, command: &Command) -> bool {
+    ctx.config.read().unwrap().is_disabled(command.name())
+}
+
+pub fn needs_auth(ctx: &ConnectionContext) -> bool {
+    !ctx.authenticated && !ctx.config.read().unwrap().requirepass.is_empty()
+}
+
+/// Checks credentials for AUTH and HELLO AUTH. The only user is `default`,
+/// whose password is `requirepass`; with no password set it accepts any.
+fn authenticate(
+    ctx: &mut ConnectionContext,
+    username: &str,
+    password: &str,
+) -> Result<(), RespValue> {
+    let requirepass = ctx.config.read().unwrap().requirepass.clone();
+    if username != "default" || !requirepass.is_empty() && password != requirepass {
+        return Err(RespValue::Error(
+            "WRONGPASS invalid username-password pair or user is disabled.".to_string(),
+        ));
+    }
+    ctx.authenticated = true;
+    Ok(())
+}
+
+/// Sets the connection name for CLIENT SETNAME and HELLO SETNAME; an empty
+/// name clears it.
+fn set_client_name(ctx: &mut ConnectionContext, name: String) -> Result<(), RespValue> {
+    if !name.bytes().all(|byte| (b'!'..=b'~').contains(&byte)) {
+        return Err(RespValue::Error(
+            "ERR Client names cannot contain spaces, newlines or special characters.".to_string(),
+        ));
+    }
+    ctx.name = (!name.is_empty()).then_some(name);
+    Ok(())
+}
+
+/// Runs a command through its table entry when it has a handler, checking
+/// its keys against the entry's type and running the handler under one
+/// lock, and through `dispatch` otherwise.
+fn execute(command: Command, databases: &Databases, ctx: &mut ConnectionContext) -> RespValue {
+    let Some(CommandSpec {
+        key_type: Some(key_type),
+        handler: Some(handler),
+        ..
+    }) = command_table::lookup(command.name())
+    else {
+        return dispatch(command, databases, ctx);
+    };
+
+    let mut storage = lock_storage(databases.get(ctx.db));
+    let wrong_type = command.keys().into_iter().any(|key| {
+        storage
+            .value_type(key)
+            .is_some_and(|held| held != *key_type)
+    });
+    if wrong_type {
+        return RespValue::Error(StorageError::WrongType.to_string());
+    }
+    handler(&mut storage, command)
+}
+
+fn dispatch(command: Command, databases: &Databases, ctx: &mut ConnectionContext) -> RespValue {
+    let storage = databases.get(ctx.db);
     match command {
+        // Subscribed connections can only receive arrays, so PING gets one
+        Command::Ping if pubsub::is_subscribed(ctx) => RespValue::Array(Some(vec![
+            RespValue::BulkString(Some(b"pong".to_vec())),
+            RespValue::BulkString(Some(Vec::new())),
+        ])),
         Command::Ping => RespValue::SimpleString("PONG".to_string()),
 
-        Command::Get { key } => {
-            let mut storage = storage.lock().unwrap();
-            match storage.get(key) {
-                Some(value) => RespValue::BulkString(Some(value.to_string().clone())),
-                None => RespValue::BulkString(None),
+        Command::Set {
+            key,
+            value,
+            condition,
+            get,
+        } => {
+            let mut storage = lock_storage(storage);
+            let old = match get.then(|| storage.get(key.clone())).transpose() {
+                Ok(old) => old.flatten(),
+                Err(e) => return RespValue::Error(e.to_string()),
+            };
+            let exists = storage.value(&key).is_some();
+            let apply = match condition {
+                Some(SetCondition::Nx) => !exists,
+                Some(SetCondition::Xx) => exists,
+                None => true,
+            };
+            if apply {
+                storage.set(key, value);
             }
-        }
 
-        Command::Set { key, value } => {
-            let mut storage = storage.lock().unwrap();
-            storage.set(key, value);
-            RespValue::SimpleString("OK".to_string())
+            // With GET the old value is returned even when NX/XX skipped the set
+            match (get, old) {
+                (true, Some(old)) => bulk_string(&old),
+                (true, None) => RespValue::BulkString(None),
+                (false, _) if apply => RespValue::SimpleString("OK".to_string()),
+                (false, _) => RespValue::BulkString(None),
+            }
         }
 
         Command::Del { keys } => {
             println!("Got DEL command for keys: {:?}", keys);
-            let mut storage = storage.lock().unwrap();
+            let mut storage = lock_storage(storage);
             for key in keys {
                 storage.del(key);
             }
             RespValue::SimpleString("OK".to_string())
         }
 
-        Command::CommandDocs => {
-            println!("Got COMMAND DOCS command");
-            RespValue::SimpleString("OK".to_string()) // Placeholder response
-        }
-
-        Command::IncrBy { key, value } => {
-            let mut storage = storage.lock().unwrap();
-            match handle_numeric_operation(&mut storage, key, value.parse::<i64>(), |n, incr| {
-                n + incr
-            }) {
-                Ok(new_value) => RespValue::Integer(new_value),
-                Err(err_msg) => RespValue::Error(err_msg),
-            }
-        }
-
-        Command::Incr { key } => {
-            let mut storage = storage.lock().unwrap();
-            match handle_numeric_operation(&mut storage, key, Ok(1), |n, _| n + 1) {
-                Ok(new_value) => RespValue::Integer(new_value),
-                Err(err_msg) => RespValue::Error(err_msg),
-            }
+        Command::CommandList => {
+            RespValue::Array(Some(COMMAND_TABLE.iter().map(CommandSpec::info).collect()))
         }
-
-        Command::DecrBy { key, value } => {
-            let mut storage = storage.lock().unwrap();
-            match handle_numeric_operation(&mut storage, key, value.parse::<i64>(), |n, decr| {
-                n - decr
-            }) {
-                Ok(new_value) => RespValue::Integer(new_value),
-                Err(err_msg) => RespValue::Error(err_msg),
-            }
+        Command::CommandGetKeys { args } => match command_table::get_keys(&args) {
+            Ok(keys) => RespValue::Array(Some(
+                keys.into_iter()
+                    .map(|key| RespValue::BulkString(Some(key.into_bytes())))
+                    .collect(),
+            )),
+            Err(e) => RespValue::Error(e),
+        },
+        Command::Help { command } => help_reply(command, subcommand_help(command)),
+        Command::CommandCount => RespValue::Integer(COMMAND_TABLE.len() as i64),
+        Command::CommandInfo { names } => RespValue::Array(Some(
+            names
+                .iter()
+                .map(|name| {
+                    command_table::lookup(name).map_or(RespValue::Array(None), CommandSpec::info)
+                })
+                .collect(),
+        )),
+        // No documentation is kept, so each command maps to an empty doc entry
+        Command::CommandDocs { names } => {
+            let specs: Vec<&CommandSpec> = if names.is_empty() {
+                COMMAND_TABLE.iter().collect()
+            } else {
+                names
+                    .iter()
+                    .filter_map(|name| command_table::lookup(name))
+                    .collect()
+            };
+            RespValue::Array(Some(
+                specs
+                    .into_iter()
+                    .flat_map(|spec| {
+                        [
+                            RespValue::BulkString(Some(spec.name.as_bytes().to_vec())),
+                            RespValue::Array(Some(Vec::new())),
+                        ]
+                    })
+                    .collect(),
+            ))
         }
 
-        Command::Decr { key } => {
-            let mut storage = storage.lock().unwrap();
-            match handle_numeric_operation(&mut storage, key, Ok(1), |n, _| n - 1) {
-                Ok(new_value) => RespValue::Integer(new_value),
-                Err(err_msg) => RespValue::Error(err_msg),
-            }
-        }
+        // One entry per key, with missing keys as nil bulk strings, so the
+        // array itself is never nil
         Command::MGet { keys } => {
-            let mut storage = storage.lock().unwrap();
-            let values: Vec<RespValue> = keys
-                .iter()
-                .map(|key| match storage.get(key.to_string()) {
-                    Some(value) => RespValue::BulkString(Some(value.clone())),
-                    None => RespValue::BulkString(None),
+            let mut storage = lock_storage(storage);
+            let values = keys
+                .into_iter()
+                .map(|key| {
+                    KEYSPACE_STATS.record(storage.value(&key).is_some());
+                    match storage.get_string(&key) {
+                        Some(value) => bulk_string(&value),
+                        None => RespValue::BulkString(None),
+                    }
                 })
                 .collect();
-            if values.len() == 1 {
-                values.into_iter().next().unwrap()
-            } else {
-                RespValue::Array(Some(values))
+            RespValue::Array(Some(values))
+        }
+        Command::FlushAll { asynchronous } => {
+            for storage in databases.iter() {
+                flush(storage, asynchronous);
             }
+            RespValue::SimpleString("OK".to_string())
         }
-        Command::FlushAll => {
-            let mut storage = storage.lock().unwrap();
-            storage.clear();
+        Command::FlushDb { asynchronous } => {
+            flush(storage, asynchronous);
             RespValue::SimpleString("OK".to_string())
         }
         Command::Exists { keys } => {
-            let storage = storage.lock().unwrap();
-            let count = keys
-                .iter()
-                .filter(|key| storage.has(key.to_string()))
-                .count();
+            let storage = lock_storage(storage);
+            let count = keys.iter().filter(|key| storage.has(key.to_vec())).count();
             RespValue::Integer(count as i64)
         }
         Command::Expire { key, expire } => {
-            let mut storage = storage.lock().unwrap();
+            let mut storage = lock_storage(storage);
             let Ok(ttl) = expire.parse::<i64>() else {
                 return RespValue::Error("value is not an integer or out of range".to_string());
             };
-            if !storage.has(key.clone()) {
-                return RespValue::SimpleString("0".to_string());
+            match storage.set_expire(key, ttl) {
+                ExpireOutcome::Missing => RespValue::Integer(0),
+                ExpireOutcome::Set | ExpireOutcome::Deleted => RespValue::Integer(1),
             }
-            storage.set_expire(key, ttl);
-            RespValue::SimpleString("1".to_string())
         }
         Command::Persist { key } => {
-            let mut storage = storage.lock().unwrap();
+            let mut storage = lock_storage(storage);
             let result = storage.remove_expire(key);
             match result {
-                Ok(_) => RespValue::SimpleString("1".to_string()),
-                Err(_) => RespValue::SimpleString("0".to_string()),
+                Ok(_) => RespValue::Integer(1),
+                Err(_) => RespValue::Integer(0),
             }
         }
         Command::Keys { pattern } => {
             debug!("Got KEYS command for pattern: {}", pattern);
-            let storage = storage.lock().unwrap();
+            let storage = lock_storage(storage);
             let keys = storage.keys(pattern);
             debug!("Found keys: {:?}", keys);
             RespValue::Array(Some(
@@ -129,30 +293,4809 @@ pub fn handle_command(command: Command, storage: &Arc<Mutex<Storage>>) -> RespVa
                     .collect(),
             ))
         }
-        Command::TTL { key } => {
-            let storage = storage.lock().unwrap();
+        Command::Ttl { key } => {
+            let storage = lock_storage(storage);
             let ttl = storage.get_ttl(key);
             RespValue::Integer(ttl)
         }
+        Command::ExpireTime { key } => {
+            let mut storage = lock_storage(storage);
+            match storage.expire_time(&key) {
+                Some(Some(expire_at)) => RespValue::Integer(expire_at as i64),
+                Some(None) => RespValue::Integer(-1),
+                None => RespValue::Integer(-2),
+            }
+        }
+        Command::PExpireTime { key } => {
+            let mut storage = lock_storage(storage);
+            match storage.expire_time(&key) {
+                Some(Some(expire_at)) => RespValue::Integer(expire_at as i64 * 1000),
+                Some(None) => RespValue::Integer(-1),
+                None => RespValue::Integer(-2),
+            }
+        }
+        Command::Time => match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(now) => RespValue::Array(Some(vec![
+                RespValue::BulkString(Some(now.as_secs().to_string().into_bytes())),
+                RespValue::BulkString(Some(now.subsec_micros().to_string().into_bytes())),
+            ])),
+            Err(_) => RespValue::Error("ERR system clock is set before the Unix epoch".to_string()),
+        },
+        Command::Save => match persistence::save(databases, persistence::snapshot_path()) {
+            Ok(_) => RespValue::SimpleString("OK".to_string()),
+            Err(e) => RespValue::Error(format!("ERR {}", e)),
+        },
+        Command::BgSave => {
+            if persistence::background_save(databases, persistence::snapshot_path()) {
+                RespValue::SimpleString("Background saving started".to_string())
+            } else {
+                RespValue::Error("ERR Background save already in progress".to_string())
+            }
+        }
+        Command::LastSave => RespValue::Integer(persistence::last_save() as i64),
+        // There's no replication, so the server is always a master with no
+        // replicas at offset 0
+        Command::Role => RespValue::Array(Some(vec![
+            RespValue::BulkString(Some(b"master".to_vec())),
+            RespValue::Integer(0),
+            RespValue::Array(Some(vec![])),
+        ])),
+        // Like Redis, collections are estimated from five elements by default
+        Command::MemoryUsage { key, samples } => {
+            let mut storage = lock_storage(storage);
+            match storage.memory_usage(&key, samples.unwrap_or(5)) {
+                Some(bytes) => RespValue::Integer(bytes as i64),
+                None => RespValue::BulkString(None),
+            }
+        }
+        Command::SInterStore { destination, keys } => {
+            set_operation_store(storage, SetOperation::Inter, destination, keys)
+        }
+        Command::SUnionStore { destination, keys } => {
+            set_operation_store(storage, SetOperation::Union, destination, keys)
+        }
+        Command::SDiffStore { destination, keys } => {
+            set_operation_store(storage, SetOperation::Diff, destination, keys)
+        }
+        Command::BLPop { keys, timeout } => blocking_pop(storage, ctx, keys, timeout, true),
+        Command::BRPop { keys, timeout } => blocking_pop(storage, ctx, keys, timeout, false),
+        Command::Dump { key } => {
+            let mut storage = lock_storage(storage);
+            match storage.value(&key) {
+                Some(value) => bulk_string(&persistence::dump_value(value)),
+                None => RespValue::BulkString(None),
+            }
+        }
+        Command::Restore {
+            key,
+            ttl,
+            payload,
+            replace,
+        } => {
+            if ttl < 0 {
+                return RespValue::Error("ERR Invalid TTL value, must be >= 0".to_string());
+            }
+            let value = match persistence::restore_value(payload.as_slice()) {
+                Ok(value) => value,
+                Err(_) => {
+                    return RespValue::Error(
+                        "ERR DUMP payload version or checksum are wrong".to_string(),
+                    )
+                }
+            };
+
+            let mut storage = lock_storage(storage);
+            if !replace && storage.value(&key).is_some() {
+                return RespValue::Error("BUSYKEY Target key name already exists.".to_string());
+            }
+            storage.set_value(key.clone(), value);
+            // TTL is in milliseconds, 0 meaning no expiry
+            if ttl > 0 {
+                let deadline = expiry_deadline(&storage, &Expiry::Px(ttl as u64));
+                storage.set_expire_at(key, deadline);
+            } else {
+                let _ = storage.remove_expire(key);
+            }
+            RespValue::SimpleString("OK".to_string())
+        }
+        Command::Scan {
+            cursor,
+            pattern,
+            count,
+            value_type,
+        } => {
+            let mut storage = lock_storage(storage);
+            let (next_cursor, keys) = storage.scan(cursor, count);
+            // Filters apply after the step so COUNT bounds the keys examined,
+            // not the keys returned
+            let matches = glob_filter(pattern);
+            let keys = keys
+                .into_iter()
+                .filter(|key| matches(key.as_slice()))
+                .filter(|key| {
+                    value_type
+                        .as_deref()
+                        .is_none_or(|wanted| storage.value_type(key) == Some(wanted))
+                })
+                .collect();
+            scan_reply(next_cursor, keys)
+        }
+        Command::MSet { pairs } => {
+            let mut storage = lock_storage(storage);
+            for (key, value) in pairs {
+                storage.set(key, value);
+            }
+            RespValue::SimpleString("OK".to_string())
+        }
+        // Values are never shared between keys, so every object has one reference
+        Command::ObjectRefCount { key } => {
+            let mut storage = lock_storage(storage);
+            match storage.value(&key) {
+                Some(_) => RespValue::Integer(1),
+                None => RespValue::Error("ERR no such key".to_string()),
+            }
+        }
+        Command::ObjectEncoding { key } => {
+            let limits = ctx.config.read().unwrap().encoding_limits;
+            let mut storage = lock_storage(storage);
+            match storage.value(&key) {
+                Some(value) => bulk_string(value.encoding(&limits).as_bytes()),
+                None => RespValue::Error("ERR no such key".to_string()),
+            }
+        }
+        // Access frequency is only tracked under an LFU maxmemory policy, and
+        // this server has no eviction policies
+        Command::ObjectFreq { key } => {
+            let mut storage = lock_storage(storage);
+            match storage.value(&key) {
+                Some(_) => RespValue::Error(
+                    "ERR An LFU maxmemory policy is not selected, access frequency not tracked"
+                        .to_string(),
+                ),
+                None => RespValue::Error("ERR no such key".to_string()),
+            }
+        }
+        // The connection loop intercepts MONITOR; it only gets here from
+        // contexts without a client socket, such as scripts
+        Command::Monitor => RespValue::Error("ERR MONITOR is not allowed here".to_string()),
+        // Like MONITOR, subscribing needs the client socket to deliver
+        // messages on, so the connection loop handles these
+        Command::Subscribe { .. }
+        | Command::Unsubscribe { .. }
+        | Command::PSubscribe { .. }
+        | Command::PUnsubscribe { .. } => RespValue::Error(format!(
+            "ERR {} is not allowed here",
+            command.name().to_uppercase()
+        )),
+        Command::Publish { channel, message } => {
+            let limit = ctx.config.read().unwrap().client_output_buffer_limit[2].hard;
+            RespValue::Integer(pubsub::publish(&channel, &message, limit) as i64)
+        }
+        Command::PubSubChannels { pattern } => RespValue::Array(Some(
+            pubsub::channels(pattern.as_deref())
+                .into_iter()
+                .map(|channel| RespValue::BulkString(Some(channel.into_bytes())))
+                .collect(),
+        )),
+        Command::PubSubNumSub { channels } => RespValue::Array(Some(
+            channels
+                .into_iter()
+                .flat_map(|channel| {
+                    let count = pubsub::subscriber_count(&channel);
+                    [
+                        RespValue::BulkString(Some(channel.into_bytes())),
+                        RespValue::Integer(count as i64),
+                    ]
+                })
+                .collect(),
+        )),
+        Command::PubSubNumPat => RespValue::Integer(pubsub::pattern_count() as i64),
+        Command::SlowLogGet { count } => {
+            // Redis returns ten entries by default and all of them for a negative count
+            let count = match count {
+                None => 10,
+                Some(count) if count < 0 => usize::MAX,
+                Some(count) => count as usize,
+            };
+            SLOWLOG.get(count)
+        }
+        Command::SlowLogLen => RespValue::Integer(SLOWLOG.len() as i64),
+        Command::SlowLogReset => {
+            SLOWLOG.reset();
+            RespValue::SimpleString("OK".to_string())
+        }
+        Command::Multi => {
+            if ctx.transaction.is_some() {
+                return RespValue::Error("ERR MULTI calls can not be nested".to_string());
+            }
+            ctx.transaction = Some(Vec::new());
+            RespValue::SimpleString("OK".to_string())
+        }
+        Command::Exec => {
+            let Some(queued) = ctx.transaction.take() else {
+                return RespValue::Error("ERR EXEC without MULTI".to_string());
+            };
+            // A transaction doomed while queuing is an error rather than the
+            // nil array Redis keeps for a failed WATCH, and an empty one
+            // replies with an empty array
+            if std::mem::take(&mut ctx.transaction_dirty) {
+                return RespValue::Error(
+                    "EXECABORT Transaction discarded because of previous errors.".to_string(),
+                );
+            }
+            // Like a script, the transaction runs against the data moved out
+            // from under every database's lock, which is held until the data
+            // is moved back, so no other client sees it half applied
+            let mut held: Vec<_> = databases
+                .iter()
+                .map(|storage| lock_storage(storage))
+                .collect();
+            let scoped: Databases = held
+                .iter_mut()
+                .map(|guard| std::mem::replace(&mut **guard, Storage::new()))
+                .collect();
+
+            ctx.in_exec = true;
+            let replies = panic::catch_unwind(AssertUnwindSafe(|| {
+                queued
+                    .into_iter()
+                    .map(|command| handle_command(command, &scoped, ctx))
+                    .collect()
+            }));
+            ctx.in_exec = false;
+
+            for (guard, storage) in held.iter_mut().zip(scoped.iter()) {
+                **guard = std::mem::replace(&mut *lock_storage(storage), Storage::new());
+            }
+            match replies {
+                Ok(replies) => RespValue::Array(Some(replies)),
+                Err(payload) => panic::resume_unwind(payload),
+            }
+        }
+        Command::Discard => {
+            if ctx.transaction.take().is_none() {
+                return RespValue::Error("ERR DISCARD without MULTI".to_string());
+            }
+            ctx.transaction_dirty = false;
+            RespValue::SimpleString("OK".to_string())
+        }
+        Command::Hello {
+            protover,
+            auth,
+            setname,
+        } => {
+            // Only RESP2 is spoken, so the reply is a flat list of pairs
+            if !matches!(protover, None | Some(2)) {
+                return RespValue::Error("NOPROTO unsupported protocol version".to_string());
+            }
+            if let Some((username, password)) = auth {
+                if let Err(e) = authenticate(ctx, &username, &password) {
+                    return e;
+                }
+            }
+            if needs_auth(ctx) {
+                return RespValue::Error(
+                    "NOAUTH HELLO must be called with the client already authenticated, \
+                     otherwise the HELLO <proto> AUTH <user> <pass> option can be used to \
+                     authenticate the client and select the RESP protocol version at the same \
+                     time"
+                        .to_string(),
+                );
+            }
+            if let Some(name) = setname {
+                if let Err(e) = set_client_name(ctx, name) {
+                    return e;
+                }
+            }
+            let field = |name: &str| RespValue::BulkString(Some(name.as_bytes().to_vec()));
+            RespValue::Array(Some(vec![
+                field("server"),
+                field("redis"),
+                field("version"),
+                field(env!("CARGO_PKG_VERSION")),
+                field("proto"),
+                RespValue::Integer(2),
+                field("id"),
+                RespValue::Integer(ctx.id as i64),
+                field("mode"),
+                field("standalone"),
+                field("role"),
+                field("master"),
+                field("modules"),
+                RespValue::Array(Some(Vec::new())),
+            ]))
+        }
+        Command::Auth { username, password } => {
+            if username.is_none() && ctx.config.read().unwrap().requirepass.is_empty() {
+                return RespValue::Error(
+                    "ERR AUTH <password> called without any password configured for the \
+                     default user. Are you sure your configuration is correct?"
+                        .to_string(),
+                );
+            }
+            match authenticate(ctx, username.as_deref().unwrap_or("default"), &password) {
+                Ok(()) => RespValue::SimpleString("OK".to_string()),
+                Err(e) => e,
+            }
+        }
+        Command::ClientSetName { name } => match set_client_name(ctx, name) {
+            Ok(()) => RespValue::SimpleString("OK".to_string()),
+            Err(e) => e,
+        },
+        Command::ClientGetName => RespValue::BulkString(ctx.name.clone().map(String::into_bytes)),
+        Command::ClientSetInfo { .. }
+        | Command::ClientNoEvict { .. }
+        | Command::ClientNoTouch { .. } => RespValue::SimpleString("OK".to_string()),
+        Command::Select { index } => {
+            if index < 0 || index as usize >= databases.len() {
+                return RespValue::Error("ERR DB index is out of range".to_string());
+            }
+            ctx.db = index as usize;
+            RespValue::SimpleString("OK".to_string())
+        }
+        Command::Move { key, db } => {
+            if db < 0 || db as usize >= databases.len() {
+                return RespValue::Error("ERR DB index is out of range".to_string());
+            }
+            let db = db as usize;
+            if db == ctx.db {
+                return RespValue::Error(
+                    "ERR source and destination objects are the same".to_string(),
+                );
+            }
+
+            let (mut source, mut destination) = lock_pair(databases, ctx.db, db);
+            if destination.value(&key).is_some() {
+                return RespValue::Integer(0);
+            }
+            let Some((value, expire_at)) = source.take(&key) else {
+                return RespValue::Integer(0);
+            };
+            destination.set_value(key.clone(), value);
+            if let Some(expire_at) = expire_at {
+                destination.set_expire_at(key, expire_at);
+            }
+            RespValue::Integer(1)
+        }
+        Command::Copy {
+            source,
+            destination,
+            db,
+            replace,
+        } => {
+            let db = match db {
+                None => ctx.db,
+                Some(db) if db < 0 || db as usize >= databases.len() => {
+                    return RespValue::Error("ERR DB index is out of range".to_string());
+                }
+                Some(db) => db as usize,
+            };
+            if db == ctx.db && source == destination {
+                return RespValue::Error(
+                    "ERR source and destination objects are the same".to_string(),
+                );
+            }
+
+            let copied = if db == ctx.db {
+                let mut storage = lock_storage(storage);
+                copy_key(&mut storage, None, &source, destination, replace)
+            } else {
+                let (mut from, mut to) = lock_pair(databases, ctx.db, db);
+                copy_key(&mut from, Some(&mut to), &source, destination, replace)
+            };
+            RespValue::Integer(copied as i64)
+        }
+        Command::ConfigGet { pattern } => {
+            let matches = glob_filter(Some(pattern.to_lowercase()));
+            let config = ctx.config.read().unwrap();
+            RespValue::Array(Some(
+                Config::parameters()
+                    .iter()
+                    .filter(|name| matches(name.as_bytes()))
+                    .flat_map(|name| {
+                        [
+                            RespValue::BulkString(Some(name.as_bytes().to_vec())),
+                            RespValue::BulkString(config.get(name).map(String::into_bytes)),
+                        ]
+                    })
+                    .collect(),
+            ))
+        }
+        Command::ConfigSet { parameter, value } => {
+            let parameter = parameter.to_lowercase();
+            match ctx.config.write().unwrap().set(&parameter, &value) {
+                Ok(()) => RespValue::SimpleString("OK".to_string()),
+                Err(e) => RespValue::Error(format!(
+                    "ERR CONFIG SET failed (possibly related to argument '{}') - {}",
+                    parameter, e
+                )),
+            }
+        }
+        Command::ConfigRewrite => match ctx.config.read().unwrap().rewrite() {
+            Ok(()) => RespValue::SimpleString("OK".to_string()),
+            Err(e) => RespValue::Error(format!("ERR {}", e)),
+        },
+        Command::DebugSleep { seconds } => {
+            let Ok(duration) = Duration::try_from_secs_f64(seconds) else {
+                return RespValue::Error("ERR timeout is out of range".to_string());
+            };
+            std::thread::sleep(duration);
+            RespValue::SimpleString("OK".to_string())
+        }
+        Command::DebugObject { key } => {
+            let limits = ctx.config.read().unwrap().encoding_limits;
+            let mut storage = lock_storage(storage);
+            match storage.value(&key) {
+                Some(value) => RespValue::BulkString(Some(
+                    format!(
+                        "Value at:{:p} refcount:1 encoding:{} serializedlength:{}",
+                        value,
+                        value.encoding(&limits),
+                        persistence::dump_value(value).len()
+                    )
+                    .into_bytes(),
+                )),
+                None => RespValue::Error("ERR no such key".to_string()),
+            }
+        }
+        Command::DebugReload => {
+            match persistence::reload(databases, persistence::snapshot_path()) {
+                Ok(()) => RespValue::SimpleString("OK".to_string()),
+                Err(e) => RespValue::Error(format!("ERR {}", e)),
+            }
+        }
+        Command::Info { section } => {
+            let info = match section.map(|s| s.to_lowercase()).as_deref() {
+                None | Some("all") | Some("everything") => format!(
+                    "{}\r\n{}\r\n{}\r\n{}",
+                    memory_info(databases, ctx),
+                    KEYSPACE_STATS.info() + &LOCK_STATS.info(),
+                    COMMAND_STATS.info(),
+                    keyspace_info(databases)
+                ),
+                Some("memory") => memory_info(databases, ctx),
+                Some("keyspace") => keyspace_info(databases),
+                Some("stats") => KEYSPACE_STATS.info() + &LOCK_STATS.info(),
+                Some("commandstats") => COMMAND_STATS.info(),
+                Some(_) => String::new(),
+            };
+            RespValue::BulkString(Some(info.into_bytes()))
+        }
+        Command::Eval { script, keys, args } => scripting::eval(&script, keys, args, storage),
+        Command::EvalSha { sha, keys, args } => scripting::eval_sha(&sha, keys, args, storage),
+        Command::ScriptLoad { script } => bulk_string(scripting::load(script).as_bytes()),
+        Command::Sort {
+            key,
+            alpha,
+            descending,
+            limit,
+        } => {
+            // A missing key sorts as an empty list, giving an empty array
+            // rather than nil
+            let mut storage = lock_storage(storage);
+            let elements = match storage.elements(&key) {
+                Ok(elements) => elements,
+                Err(e) => return RespValue::Error(e.to_string()),
+            };
+            match sort(elements, alpha, descending, limit) {
+                Ok(sorted) => bulk_string_array(sorted),
+                Err(e) => RespValue::Error(e),
+            }
+        }
+        // The rest run through the handlers in their table entries
+        _ => unreachable!("{} has a table handler", command.name()),
     }
 }
 
-fn handle_numeric_operation(
-    storage: &mut std::sync::MutexGuard<Storage>,
-    key: String,
-    value: Result<i64, std::num::ParseIntError>,
-    operation: impl FnOnce(i64, i64) -> i64,
-) -> Result<i64, String> {
-    let value = value.map_err(|_| "ERR value is not an integer or out of range".to_string())?;
+// Handlers for the commands whose table entries declare a key type. Each
+// runs with the selected database locked, after its keys have been checked
+// against that type under the same lock.
+
+pub fn get(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::Get { key } = command else {
+        unreachable!()
+    };
+    let value = storage.get(key);
+    KEYSPACE_STATS.record(!matches!(value, Ok(None)));
+    match value {
+        Ok(Some(value)) => bulk_string(&value),
+        Ok(None) => RespValue::BulkString(None),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
 
-    let default = "0".to_string();
-    let current_value = storage.get(key.clone()).unwrap_or(default);
+pub fn incrby(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::IncrBy { key, value } = command else {
+        unreachable!()
+    };
+    match handle_numeric_operation(storage, key, parse_int(value.as_bytes()), |n, incr| {
+        n.checked_add(incr)
+    }) {
+        Ok(new_value) => RespValue::Integer(new_value),
+        Err(err_msg) => RespValue::Error(err_msg),
+    }
+}
 
-    let current_num = current_value
-        .parse::<i64>()
-        .map_err(|_| "ERR value is not an integer or out of range".to_string())?;
-    let new_value = operation(current_num, value);
-    storage.set(key, new_value.to_string());
+pub fn incr(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::Incr { key } = command else {
+        unreachable!()
+    };
+    match handle_numeric_operation(storage, key, Some(1), i64::checked_add) {
+        Ok(new_value) => RespValue::Integer(new_value),
+        Err(err_msg) => RespValue::Error(err_msg),
+    }
+}
 
-    Ok(new_value)
+pub fn decrby(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::DecrBy { key, value } = command else {
+        unreachable!()
+    };
+    match handle_numeric_operation(storage, key, parse_int(value.as_bytes()), |n, decr| {
+        n.checked_sub(decr)
+    }) {
+        Ok(new_value) => RespValue::Integer(new_value),
+        Err(err_msg) => RespValue::Error(err_msg),
+    }
+}
+
+pub fn decr(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::Decr { key } = command else {
+        unreachable!()
+    };
+    match handle_numeric_operation(storage, key, Some(1), i64::checked_sub) {
+        Ok(new_value) => RespValue::Integer(new_value),
+        Err(err_msg) => RespValue::Error(err_msg),
+    }
+}
+
+pub fn setbit(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::SetBit { key, offset, value } = command else {
+        unreachable!()
+    };
+    let Some(offset) = parse_bit_offset(&offset) else {
+        return RespValue::Error("ERR bit offset is not an integer or out of range".to_string());
+    };
+    let bit = match value.as_str() {
+        "0" => false,
+        "1" => true,
+        _ => return RespValue::Error("ERR bit is not an integer or out of range".to_string()),
+    };
+    match storage.set_bit(key, offset, bit) {
+        Ok(previous) => RespValue::Integer(previous as i64),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn getbit(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::GetBit { key, offset } = command else {
+        unreachable!()
+    };
+    let Some(offset) = parse_bit_offset(&offset) else {
+        return RespValue::Error("ERR bit offset is not an integer or out of range".to_string());
+    };
+    match storage.get_bit(key, offset) {
+        Ok(bit) => RespValue::Integer(bit as i64),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn bitcount(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::BitCount { key, range } = command else {
+        unreachable!()
+    };
+    match storage.bit_count(key, range) {
+        Ok(count) => RespValue::Integer(count),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn bitpos(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::BitPos {
+        key,
+        bit,
+        range,
+        unit,
+    } = command
+    else {
+        unreachable!()
+    };
+    match storage.bit_pos(&key, bit, range, unit == RangeUnit::Bit) {
+        Ok(pos) => RespValue::Integer(pos),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn sadd(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::SAdd { key, members } = command else {
+        unreachable!()
+    };
+    match storage.sadd(key, members) {
+        Ok(added) => RespValue::Integer(added as i64),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn smove(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::SMove {
+        source,
+        destination,
+        member,
+    } = command
+    else {
+        unreachable!()
+    };
+    match storage.smove(&source, destination, &member) {
+        Ok(moved) => RespValue::Integer(moved as i64),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn smembers(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::SMembers { key } = command else {
+        unreachable!()
+    };
+    KEYSPACE_STATS.record(storage.value(&key).is_some());
+    match storage.smembers(key) {
+        Ok(members) => bulk_string_array(members),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn sinter(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::SInter { keys } = command else {
+        unreachable!()
+    };
+    set_operation(storage, SetOperation::Inter, keys)
+}
+
+pub fn sintercard(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::SInterCard { keys, limit } = command else {
+        unreachable!()
+    };
+    match storage.sintercard(&keys, limit) {
+        Ok(count) => RespValue::Integer(count as i64),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn sunion(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::SUnion { keys } = command else {
+        unreachable!()
+    };
+    set_operation(storage, SetOperation::Union, keys)
+}
+
+pub fn sdiff(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::SDiff { keys } = command else {
+        unreachable!()
+    };
+    set_operation(storage, SetOperation::Diff, keys)
+}
+
+pub fn getex(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::GetEx { key, expiry } = command else {
+        unreachable!()
+    };
+    let value = storage.get(key.clone());
+    KEYSPACE_STATS.record(!matches!(value, Ok(None)));
+    let value = match value {
+        Ok(Some(value)) => value,
+        Ok(None) => return RespValue::BulkString(None),
+        Err(e) => return RespValue::Error(e.to_string()),
+    };
+    match expiry {
+        Some(Expiry::Persist) => {
+            let _ = storage.remove_expire(key);
+        }
+        Some(expiry) => {
+            let deadline = expiry_deadline(storage, &expiry);
+            storage.set_expire_at(key, deadline);
+        }
+        None => {}
+    }
+    bulk_string(&value)
+}
+
+pub fn lpush(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::LPush { key, values } = command else {
+        unreachable!()
+    };
+    push(storage, key, values, true)
+}
+
+pub fn rpush(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::RPush { key, values } = command else {
+        unreachable!()
+    };
+    push(storage, key, values, false)
+}
+
+pub fn rpoplpush(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::RPopLPush {
+        source,
+        destination,
+    } = command
+    else {
+        unreachable!()
+    };
+    list_move(storage, &source, destination, ListEnd::Right, ListEnd::Left)
+}
+
+pub fn lmove(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::LMove {
+        source,
+        destination,
+        from,
+        to,
+    } = command
+    else {
+        unreachable!()
+    };
+    list_move(storage, &source, destination, from, to)
+}
+
+pub fn lset(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::LSet { key, index, value } = command else {
+        unreachable!()
+    };
+    match storage.lset(&key, index, value) {
+        Ok(()) => RespValue::SimpleString("OK".to_string()),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn linsert(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::LInsert {
+        key,
+        before,
+        pivot,
+        value,
+    } = command
+    else {
+        unreachable!()
+    };
+    match storage.linsert(&key, before, pivot.as_slice(), value) {
+        Ok(len) => RespValue::Integer(len),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn lrem(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::LRem { key, count, value } = command else {
+        unreachable!()
+    };
+    match storage.lrem(&key, count, value.as_slice()) {
+        Ok(removed) => RespValue::Integer(removed as i64),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn ltrim(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::LTrim { key, start, stop } = command else {
+        unreachable!()
+    };
+    match storage.ltrim(&key, start, stop) {
+        Ok(()) => RespValue::SimpleString("OK".to_string()),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn lrange(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::LRange { key, start, stop } = command else {
+        unreachable!()
+    };
+    KEYSPACE_STATS.record(storage.value(&key).is_some());
+    match storage.lrange(key, start, stop) {
+        Ok(items) => bulk_string_array(items),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn llen(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::LLen { key } = command else {
+        unreachable!()
+    };
+    KEYSPACE_STATS.record(storage.value(&key).is_some());
+    match storage.llen(key) {
+        Ok(len) => RespValue::Integer(len as i64),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn append(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::Append { key, value } = command else {
+        unreachable!()
+    };
+    match storage.append(key, value.as_slice()) {
+        Ok(len) => RespValue::Integer(len as i64),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn strlen(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::StrLen { key } = command else {
+        unreachable!()
+    };
+    KEYSPACE_STATS.record(storage.value(&key).is_some());
+    match storage.strlen(key) {
+        Ok(len) => RespValue::Integer(len as i64),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn setrange(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::SetRange { key, offset, value } = command else {
+        unreachable!()
+    };
+    // Same 512MB cap as SETBIT
+    if offset < 0 || offset as u64 + value.len() as u64 > 512 * 1024 * 1024 {
+        return RespValue::Error("ERR offset is out of range".to_string());
+    }
+    match storage.set_range(key, offset as usize, value.as_slice()) {
+        Ok(len) => RespValue::Integer(len as i64),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn getrange(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::GetRange { key, start, end } = command else {
+        unreachable!()
+    };
+    KEYSPACE_STATS.record(storage.value(&key).is_some());
+    match storage.get_range(&key, start, end) {
+        Ok(bytes) => bulk_string(&bytes),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn sscan(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::SScan {
+        key,
+        cursor,
+        pattern,
+        count,
+    } = command
+    else {
+        unreachable!()
+    };
+    match storage.sscan(&key, cursor, count) {
+        Ok((next_cursor, members)) => {
+            let matches = glob_filter(pattern);
+            let members = members.into_iter().filter(|m| matches(m)).collect();
+            scan_reply(next_cursor, members)
+        }
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn hscan(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::HScan {
+        key,
+        cursor,
+        pattern,
+        count,
+    } = command
+    else {
+        unreachable!()
+    };
+    match storage.hscan(&key, cursor, count) {
+        Ok((next_cursor, fields)) => {
+            let matches = glob_filter(pattern);
+            let items = fields
+                .into_iter()
+                .filter(|(field, _)| matches(field))
+                .flat_map(|(field, value)| [field, value])
+                .collect();
+            scan_reply(next_cursor, items)
+        }
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn hset(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::HSet { key, pairs } = command else {
+        unreachable!()
+    };
+    match storage.hset(key, pairs) {
+        Ok(added) => RespValue::Integer(added as i64),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn zadd(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::ZAdd { key, pairs } = command else {
+        unreachable!()
+    };
+    match storage.zadd(key, pairs) {
+        Ok(added) => RespValue::Integer(added as i64),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn zscore(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::ZScore { key, member } = command else {
+        unreachable!()
+    };
+    match storage.as_zset(&key) {
+        Ok(zset) => match zset.and_then(|zset| zset.score(member.as_slice())) {
+            Some(score) => RespValue::BulkString(Some(format_score(score).into_bytes())),
+            None => RespValue::BulkString(None),
+        },
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn zrange(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::ZRange {
+        key,
+        start,
+        stop,
+        withscores,
+    } = command
+    else {
+        unreachable!()
+    };
+    match storage.as_zset(&key) {
+        Ok(Some(zset)) => match normalize_range(start, stop, zset.len()) {
+            Some((start, stop)) => {
+                scored_reply(zset.iter().skip(start).take(stop - start + 1), withscores)
+            }
+            None => RespValue::Array(Some(Vec::new())),
+        },
+        Ok(None) => RespValue::Array(Some(Vec::new())),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn zrangebyscore(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::ZRangeByScore {
+        key,
+        min,
+        max,
+        withscores,
+        limit,
+    } = command
+    else {
+        unreachable!()
+    };
+    match storage.as_zset(&key) {
+        Ok(Some(zset)) => {
+            scored_reply(with_limit(zset.range_by_score(min, max), limit), withscores)
+        }
+        Ok(None) => RespValue::Array(Some(Vec::new())),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn zincrby(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::ZIncrBy {
+        key,
+        increment,
+        member,
+    } = command
+    else {
+        unreachable!()
+    };
+    match storage.zincr_by(key, increment, member) {
+        Ok(score) => RespValue::BulkString(Some(format_score(score).into_bytes())),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn zrank(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::ZRank { key, member } = command else {
+        unreachable!()
+    };
+    match storage.as_zset(&key) {
+        Ok(zset) => match zset.and_then(|zset| zset.rank(member.as_slice())) {
+            Some(rank) => RespValue::Integer(rank as i64),
+            None => RespValue::BulkString(None),
+        },
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn zcard(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::ZCard { key } = command else {
+        unreachable!()
+    };
+    match storage.as_zset(&key) {
+        Ok(zset) => RespValue::Integer(zset.map_or(0, |zset| zset.len()) as i64),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn zpopmin(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::ZPopMin { key, count } = command else {
+        unreachable!()
+    };
+    zpop(storage, &key, count, true)
+}
+
+pub fn zpopmax(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::ZPopMax { key, count } = command else {
+        unreachable!()
+    };
+    zpop(storage, &key, count, false)
+}
+
+pub fn zrem(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::ZRem { key, members } = command else {
+        unreachable!()
+    };
+    let members: Vec<&[u8]> = members.iter().map(|member| member.as_slice()).collect();
+    match storage.zrem(&key, &members) {
+        Ok(removed) => RespValue::Integer(removed as i64),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn zrevrange(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::ZRevRange {
+        key,
+        start,
+        stop,
+        withscores,
+    } = command
+    else {
+        unreachable!()
+    };
+    match storage.as_zset(&key) {
+        Ok(Some(zset)) => match normalize_range(start, stop, zset.len()) {
+            Some((start, stop)) => scored_reply(
+                zset.iter().rev().skip(start).take(stop - start + 1),
+                withscores,
+            ),
+            None => RespValue::Array(Some(Vec::new())),
+        },
+        Ok(None) => RespValue::Array(Some(Vec::new())),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn zcount(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::ZCount { key, min, max } = command else {
+        unreachable!()
+    };
+    match storage.as_zset(&key) {
+        Ok(zset) => {
+            RespValue::Integer(zset.map_or(0, |zset| zset.range_by_score(min, max).count()) as i64)
+        }
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn zrangebylex(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::ZRangeByLex { key, min, max } = command else {
+        unreachable!()
+    };
+    match storage.as_zset(&key) {
+        Ok(Some(zset)) => scored_reply(zset.range_by_lex(&min, &max), false),
+        Ok(None) => RespValue::Array(Some(Vec::new())),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn zrevrangebyscore(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::ZRevRangeByScore {
+        key,
+        max,
+        min,
+        withscores,
+        limit,
+    } = command
+    else {
+        unreachable!()
+    };
+    match storage.as_zset(&key) {
+        Ok(Some(zset)) => scored_reply(
+            with_limit(zset.range_by_score(min, max).rev(), limit),
+            withscores,
+        ),
+        Ok(None) => RespValue::Array(Some(Vec::new())),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn hsetnx(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::HSetNx { key, field, value } = command else {
+        unreachable!()
+    };
+    match storage.hsetnx(key, field, value) {
+        Ok(set) => RespValue::Integer(set as i64),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn hmget(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::HMGet { key, fields } = command else {
+        unreachable!()
+    };
+    KEYSPACE_STATS.record(storage.value(&key).is_some());
+    let fields: Vec<&[u8]> = fields.iter().map(|field| field.as_slice()).collect();
+    match storage.hmget(&key, &fields) {
+        Ok(values) => RespValue::Array(Some(
+            values
+                .into_iter()
+                .map(|value| match value {
+                    Some(value) => bulk_string(&value),
+                    None => RespValue::BulkString(None),
+                })
+                .collect(),
+        )),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn hincrby(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::HIncrBy {
+        key,
+        field,
+        increment,
+    } = command
+    else {
+        unreachable!()
+    };
+    match storage.hincr_by(key, field.as_slice(), increment) {
+        Ok(value) => RespValue::Integer(value),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn hincrbyfloat(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::HIncrByFloat {
+        key,
+        field,
+        increment,
+    } = command
+    else {
+        unreachable!()
+    };
+    match storage.hincr_by_float(key, field.as_slice(), increment) {
+        Ok(value) => bulk_string(&value),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+pub fn hget(storage: &mut Storage, command: Command) -> RespValue {
+    let Command::HGet { key, field } = command else {
+        unreachable!()
+    };
+    KEYSPACE_STATS.record(storage.value(&key).is_some());
+    match storage.hget(&key, field.as_slice()) {
+        Ok(Some(value)) => bulk_string(&value),
+        Ok(None) => RespValue::BulkString(None),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+/// Renders the `memory` INFO section, with usage summed over every database.
+fn memory_info(databases: &Databases, ctx: &ConnectionContext) -> String {
+    let used_memory: usize = databases
+        .iter()
+        .map(|storage| lock_storage(storage).used_memory())
+        .sum();
+    let config = ctx.config.read().unwrap();
+    format!(
+        "# Memory\r\nused_memory:{}\r\nmaxmemory:{}\r\nmaxmemory_policy:{}\r\n",
+        used_memory, config.maxmemory, config.maxmemory_policy
+    )
+}
+
+/// Renders the `keyspace` INFO section, one line per non-empty database.
+fn keyspace_info(databases: &Databases) -> String {
+    let mut info = "# Keyspace\r\n".to_string();
+    for (index, storage) in databases.iter().enumerate() {
+        let (keys, expires, avg_ttl) = lock_storage(storage).keyspace_stats();
+        if keys > 0 {
+            info.push_str(&format!(
+                "db{}:keys={},expires={},avg_ttl={}\r\n",
+                index, keys, expires, avg_ttl
+            ));
+        }
+    }
+    info
+}
+
+fn bulk_string(value: &[u8]) -> RespValue {
+    RespValue::BulkString(Some(value.to_vec()))
+}
+
+fn bulk_string_array(values: Vec<Vec<u8>>) -> RespValue {
+    RespValue::Array(Some(values.iter().map(|v| bulk_string(v)).collect()))
+}
+
+fn scan_reply(next_cursor: u64, items: Vec<Vec<u8>>) -> RespValue {
+    RespValue::Array(Some(vec![
+        RespValue::BulkString(Some(next_cursor.to_string().into_bytes())),
+        bulk_string_array(items),
+    ]))
+}
+
+/// Builds the MATCH predicate shared by the SCAN family. Without a pattern
+/// everything matches; an invalid pattern matches nothing.
+fn glob_filter(pattern: Option<String>) -> impl Fn(&[u8]) -> bool {
+    let pattern = pattern.map(|pattern| glob::Pattern::new(&pattern));
+    move |item| match &pattern {
+        None => true,
+        Some(Ok(pattern)) => pattern.matches(&String::from_utf8_lossy(item)),
+        Some(Err(_)) => false,
+    }
+}
+
+/// Locks the databases at `first` and `second`, which must differ, always
+/// taking the lower index first so that two clients working across the same
+/// pair cannot deadlock.
+fn lock_pair(
+    databases: &Databases,
+    first: usize,
+    second: usize,
+) -> (MutexGuard<'_, Storage>, MutexGuard<'_, Storage>) {
+    if first < second {
+        let first = lock_storage(databases.get(first));
+        (first, lock_storage(databases.get(second)))
+    } else {
+        let second = lock_storage(databases.get(second));
+        (lock_storage(databases.get(first)), second)
+    }
+}
+
+/// Copies the value at `source` and its expiry to `destination`, in `to` or
+/// back into `from` when that is `None`. An existing destination is only
+/// overwritten with `replace`. Returns whether the key was copied.
+fn copy_key(
+    from: &mut Storage,
+    to: Option<&mut Storage>,
+    source: &[u8],
+    destination: Vec<u8>,
+    replace: bool,
+) -> bool {
+    let Some(expire_at) = from.expire_time(source) else {
+        return false;
+    };
+    let value = from.value(source).cloned().unwrap();
+    let to = match to {
+        Some(to) => to,
+        None => from,
+    };
+    if to.value(&destination).is_some() && !replace {
+        return false;
+    }
+
+    to.take(&destination);
+    to.set_value(destination.clone(), value);
+    if let Some(expire_at) = expire_at {
+        to.set_expire_at(destination, expire_at);
+    }
+    true
+}
+
+fn set_operation(storage: &mut Storage, operation: SetOperation, keys: Vec<Vec<u8>>) -> RespValue {
+    match storage.set_operation(operation, &keys) {
+        Ok(members) => bulk_string_array(members.into_iter().collect()),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+// The result is computed and stored under a single lock so no client can
+// observe the destination half-written
+fn set_operation_store(
+    storage: &Arc<Mutex<Storage>>,
+    operation: SetOperation,
+    destination: Vec<u8>,
+    keys: Vec<Vec<u8>>,
+) -> RespValue {
+    let mut storage = lock_storage(storage);
+    match storage.set_operation(operation, &keys) {
+        Ok(members) => RespValue::Integer(storage.store_set(destination, members) as i64),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+/// Converts an expiry option to the absolute Unix second it ends at, rounding
+/// millisecond forms up so a key never expires early.
+fn expiry_deadline(storage: &Storage, expiry: &Expiry) -> u64 {
+    let now_ms = storage.now_millis();
+    match *expiry {
+        Expiry::Ex(secs) => now_ms.div_ceil(1000) + secs,
+        Expiry::Px(ms) => (now_ms + ms).div_ceil(1000),
+        Expiry::ExAt(timestamp) => timestamp,
+        Expiry::PxAt(timestamp) => timestamp.div_ceil(1000),
+        Expiry::Persist => 0,
+    }
+}
+
+/// Orders elements numerically, or byte-wise with `alpha`, then applies a
+/// `LIMIT offset count` window. A negative count means "to the end".
+fn sort(
+    mut elements: Vec<Vec<u8>>,
+    alpha: bool,
+    descending: bool,
+    limit: Option<(i64, i64)>,
+) -> Result<Vec<Vec<u8>>, String> {
+    if alpha {
+        elements.sort();
+    } else {
+        let mut scored = elements
+            .into_iter()
+            .map(|element| {
+                std::str::from_utf8(&element)
+                    .ok()
+                    .and_then(|s| s.trim().parse::<f64>().ok())
+                    .map(|score| (score, element))
+                    .ok_or_else(|| {
+                        "ERR One or more scores can't be converted into double".to_string()
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        scored.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        elements = scored.into_iter().map(|(_, element)| element).collect();
+    }
+    if descending {
+        elements.reverse();
+    }
+
+    if let Some((offset, count)) = limit {
+        let offset = offset.max(0) as usize;
+        let count = if count < 0 {
+            usize::MAX
+        } else {
+            count as usize
+        };
+        elements = elements.into_iter().skip(offset).take(count).collect();
+    }
+    Ok(elements)
+}
+
+// Without a count one member is popped, but the reply is the same flat
+// member/score array either way
+fn zpop(storage: &mut Storage, key: &[u8], count: Option<usize>, lowest: bool) -> RespValue {
+    match storage.zpop(key, count.unwrap_or(1), lowest) {
+        Ok(popped) => scored_reply(
+            popped
+                .iter()
+                .map(|(member, score)| (member.as_slice(), *score)),
+            true,
+        ),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+/// Replies with the members of a sorted-set range, each followed by its
+/// score when `withscores` is set.
+fn scored_reply<'a>(items: impl Iterator<Item = (&'a [u8], f64)>, withscores: bool) -> RespValue {
+    let mut reply = Vec::new();
+    for (member, score) in items {
+        reply.push(bulk_string(member));
+        if withscores {
+            reply.push(RespValue::BulkString(Some(
+                format_score(score).into_bytes(),
+            )));
+        }
+    }
+    RespValue::Array(Some(reply))
+}
+
+/// Applies a `LIMIT offset count` option. A negative count means no limit,
+/// and a negative offset selects nothing.
+fn with_limit<T>(
+    items: impl Iterator<Item = T>,
+    limit: Option<(i64, i64)>,
+) -> impl Iterator<Item = T> {
+    let (offset, count) = match limit {
+        None => (0, usize::MAX),
+        Some((offset, _)) if offset < 0 => (0, 0),
+        Some((offset, count)) => (
+            offset as usize,
+            usize::try_from(count).unwrap_or(usize::MAX),
+        ),
+    };
+    items.skip(offset).take(count)
+}
+
+/// Formats a HELP reply the way Redis does: a usage line, the given
+/// subcommand descriptions, then the entry for HELP itself.
+fn help_reply(command: &str, lines: &[&str]) -> RespValue {
+    let usage = format!(
+        "{} <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+        command
+    );
+    RespValue::Array(Some(
+        std::iter::once(usage)
+            .chain(lines.iter().map(|line| line.to_string()))
+            .chain(["HELP".to_string(), "    Print this help.".to_string()])
+            .map(RespValue::SimpleString)
+            .collect(),
+    ))
+}
+
+fn subcommand_help(command: &str) -> &'static [&'static str] {
+    match command {
+        "OBJECT" => &[
+            "ENCODING <key>",
+            "    Return the kind of internal representation used in order to store the value",
+            "    associated with a <key>.",
+            "FREQ <key>",
+            "    Return the access frequency index of the key <key>.",
+            "REFCOUNT <key>",
+            "    Return the number of references of the value associated with the specified",
+            "    <key>.",
+        ],
+        "COMMAND" => &[
+            "(no subcommand)",
+            "    Return details about all commands.",
+            "COUNT",
+            "    Return the total number of commands in this server.",
+            "DOCS [<command-name> ...]",
+            "    Return documentation details about multiple commands.",
+            "    If no command names are given, documentation details for all",
+            "    commands are returned.",
+            "GETKEYS <full-command>",
+            "    Return the keys from a full command.",
+            "INFO [<command-name> ...]",
+            "    Return details about multiple commands.",
+            "    If no command names are given, details for all commands are returned.",
+        ],
+        "CONFIG" => &[
+            "GET <pattern>",
+            "    Return parameters matching the glob-like <pattern> and their values.",
+            "SET <directive> <value>",
+            "    Set the configuration <directive> to <value>.",
+            "REWRITE",
+            "    Rewrite the configuration file.",
+        ],
+        "DEBUG" => &[
+            "OBJECT <key>",
+            "    Show low-level info about the <key> and associated value.",
+            "RELOAD",
+            "    Save the snapshot on disk and reload it back to memory.",
+            "SLEEP <seconds>",
+            "    Stop the server for <seconds>. Decimals allowed.",
+        ],
+        "PUBSUB" => &[
+            "CHANNELS [<pattern>]",
+            "    Return the currently active channels matching a <pattern> (default: '*').",
+            "NUMPAT",
+            "    Return number of subscriptions to patterns.",
+            "NUMSUB [<channel> ...]",
+            "    Return the number of subscribers for the specified channels, excluding",
+            "    pattern subscriptions(default: no channels).",
+        ],
+        "MEMORY" => &[
+            "USAGE <key> [SAMPLES <count>]",
+            "    Return memory in bytes used by <key> and its value. Nested values are",
+            "    sampled up to <count> times (default: 5, 0 means sample all).",
+        ],
+        "SLOWLOG" => &[
+            "GET [<count>]",
+            "    Return top <count> entries from the slowlog (default: 10, -1 mean all).",
+            "    Entries are made of:",
+            "    id, timestamp, time in microseconds, arguments array.",
+            "LEN",
+            "    Return the length of the slowlog.",
+            "RESET",
+            "    Reset the slowlog.",
+        ],
+        "SCRIPT" => &[
+            "LOAD <script>",
+            "    Load a script into the scripts cache without executing it.",
+        ],
+        "CLIENT" => &[
+            "GETNAME",
+            "    Return the name of the current connection.",
+            "NO-EVICT (ON|OFF)",
+            "    Protect current client connection from eviction.",
+            "NO-TOUCH (ON|OFF)",
+            "    Will not touch LRU/LFU stats when this mode is on.",
+            "SETINFO <option> <value>",
+            "    Set client meta attr. Options are:",
+            "    * LIB-NAME: the client lib name.",
+            "    * LIB-VER: the client lib version.",
+            "SETNAME <name>",
+            "    Assign the name <name> to the current connection.",
+        ],
+        _ => &[],
+    }
+}
+
+/// Empties a database. An asynchronous flush swaps the contents out under the
+/// lock and frees them on a background thread, so the reply does not wait on
+/// deallocating a large keyspace.
+fn flush(storage: &Arc<Mutex<Storage>>, asynchronous: bool) {
+    let mut storage = lock_storage(storage);
+    if asynchronous {
+        let old = storage.take_contents();
+        std::thread::spawn(move || drop(old));
+    } else {
+        storage.clear();
+    }
+}
+
+fn push(storage: &mut Storage, key: Vec<u8>, values: Vec<Vec<u8>>, front: bool) -> RespValue {
+    match storage.push(key.clone(), values, front) {
+        Ok(len) => {
+            LIST_WAITERS.notify(&key);
+            RespValue::Integer(len as i64)
+        }
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+/// Pops from the first non-empty list in `keys`, waiting up to `timeout`
+/// seconds (0 for no limit) for one to be pushed to. Replies with
+/// `[key, element]`, or a nil array on timeout.
+fn blocking_pop(
+    storage: &Arc<Mutex<Storage>>,
+    ctx: &ConnectionContext,
+    keys: Vec<Vec<u8>>,
+    timeout: f64,
+    front: bool,
+) -> RespValue {
+    let deadline = if timeout > 0.0 {
+        // A finite timeout can still be too long for a Duration or Instant
+        match Duration::try_from_secs_f64(timeout)
+            .ok()
+            .and_then(|timeout| Instant::now().checked_add(timeout))
+        {
+            Some(deadline) => Some(deadline),
+            None => return RespValue::Error("ERR timeout is out of range".to_string()),
+        }
+    } else {
+        None
+    };
+    let watch = LIST_WAITERS.watch(&keys);
+
+    loop {
+        let seen = {
+            let mut storage = lock_storage(storage);
+            for key in &keys {
+                match storage.pop(key, front) {
+                    Ok(Some(value)) => {
+                        return RespValue::Array(Some(vec![
+                            RespValue::BulkString(Some(key.clone())),
+                            bulk_string(&value),
+                        ]))
+                    }
+                    Ok(None) => {}
+                    Err(e) => return RespValue::Error(e.to_string()),
+                }
+            }
+            watch.pushes()
+        };
+
+        // Nothing else can push while a script or transaction holds the
+        // lock, so waiting there could never succeed. Timing out replies with
+        // a nil array, unlike the empty arrays of the non-blocking list reads.
+        if ctx.in_script || ctx.in_exec || !watch.wait(seen, deadline) {
+            return RespValue::Array(None);
+        }
+    }
+}
+
+fn list_move(
+    storage: &mut Storage,
+    source: &[u8],
+    destination: Vec<u8>,
+    from: ListEnd,
+    to: ListEnd,
+) -> RespValue {
+    match storage.lmove(
+        source,
+        destination.clone(),
+        from == ListEnd::Left,
+        to == ListEnd::Left,
+    ) {
+        Ok(Some(value)) => {
+            LIST_WAITERS.notify(&destination);
+            bulk_string(&value)
+        }
+        Ok(None) => RespValue::BulkString(None),
+        Err(e) => RespValue::Error(e.to_string()),
+    }
+}
+
+// Redis caps string values at 512MB, so bit offsets must fit in 2^32 bits
+fn parse_bit_offset(offset: &str) -> Option<usize> {
+    offset.parse::<u32>().ok().map(|offset| offset as usize)
+}
+
+fn handle_numeric_operation(
+    storage: &mut Storage,
+    key: Vec<u8>,
+    value: Option<i64>,
+    operation: impl FnOnce(i64, i64) -> Option<i64>,
+) -> Result<i64, String> {
+    let value = value.ok_or_else(|| "ERR value is not an integer or out of range".to_string())?;
+
+    // Only strings hold counters; anything else must be rejected before the
+    // result is written back over it
+    let current_num = match storage.value(&key) {
+        None => 0,
+        Some(Value::Int(n)) => *n,
+        Some(Value::String(bytes)) => parse_int(bytes)
+            .ok_or_else(|| "ERR value is not an integer or out of range".to_string())?,
+        Some(_) => return Err(StorageError::WrongType.to_string()),
+    };
+    let new_value =
+        operation(current_num, value).ok_or_else(|| StorageError::Overflow.to_string())?;
+    storage.set_int(key, new_value);
+
+    Ok(new_value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sorted_set::{LexBound, ScoreBound};
+
+    fn new_storage() -> Databases {
+        Databases::new(Storage::new(), 16)
+    }
+
+    fn run(command: Command, storage: &Databases) -> RespValue {
+        handle_command(command, storage, &mut ConnectionContext::default())
+    }
+
+    #[test]
+    fn test_setbit_and_getbit() {
+        let storage = new_storage();
+
+        let reply = run(
+            Command::SetBit {
+                key: "k".into(),
+                offset: "7".to_string(),
+                value: "1".to_string(),
+            },
+            &storage,
+        );
+        assert_eq!(reply, RespValue::Integer(0));
+        assert_eq!(
+            storage.get(0).lock().unwrap().get("k".into()),
+            Ok(Some(vec![0x01]))
+        );
+
+        let reply = run(
+            Command::GetBit {
+                key: "k".into(),
+                offset: "7".to_string(),
+            },
+            &storage,
+        );
+        assert_eq!(reply, RespValue::Integer(1));
+    }
+
+    #[test]
+    fn test_setbit_rejects_invalid_arguments() {
+        let storage = new_storage();
+
+        let reply = run(
+            Command::SetBit {
+                key: "k".into(),
+                offset: "-1".to_string(),
+                value: "1".to_string(),
+            },
+            &storage,
+        );
+        assert_eq!(
+            reply,
+            RespValue::Error("ERR bit offset is not an integer or out of range".to_string())
+        );
+
+        let reply = run(
+            Command::SetBit {
+                key: "k".into(),
+                offset: "0".to_string(),
+                value: "2".to_string(),
+            },
+            &storage,
+        );
+        assert_eq!(
+            reply,
+            RespValue::Error("ERR bit is not an integer or out of range".to_string())
+        );
+        assert_eq!(storage.get(0).lock().unwrap().get("k".into()), Ok(None));
+    }
+
+    fn sadd(storage: &Databases, key: &str, members: &[&str]) {
+        run(
+            Command::SAdd {
+                key: key.into(),
+                members: members.iter().map(|m| m.as_bytes().to_vec()).collect(),
+            },
+            storage,
+        );
+    }
+
+    fn smembers(storage: &Databases, key: &str) -> Vec<String> {
+        let RespValue::Array(Some(members)) = run(Command::SMembers { key: key.into() }, storage)
+        else {
+            panic!("SMEMBERS should reply with an array");
+        };
+        let mut members: Vec<String> = members
+            .into_iter()
+            .map(|m| match m {
+                RespValue::BulkString(Some(m)) => String::from_utf8(m).unwrap(),
+                other => panic!("unexpected member {:?}", other),
+            })
+            .collect();
+        members.sort();
+        members
+    }
+
+    #[test]
+    fn test_sintercard() {
+        let storage = new_storage();
+        sadd(&storage, "a", &["1", "2", "3"]);
+        sadd(&storage, "b", &["2", "3", "4"]);
+        set(&storage, "s", "v");
+        let sintercard = |keys: &[&str], limit| {
+            run(
+                Command::SInterCard {
+                    keys: keys.iter().map(|key| key.as_bytes().to_vec()).collect(),
+                    limit,
+                },
+                &storage,
+            )
+        };
+
+        assert_eq!(sintercard(&["a", "b"], 0), RespValue::Integer(2));
+        assert_eq!(sintercard(&["a", "b"], 1), RespValue::Integer(1));
+        assert_eq!(sintercard(&["a", "missing"], 0), RespValue::Integer(0));
+        assert!(matches!(
+            sintercard(&["a", "s"], 0),
+            RespValue::Error(e) if e.starts_with("WRONGTYPE")
+        ));
+    }
+
+    #[test]
+    fn test_sinterstore_creates_destination() {
+        let storage = new_storage();
+        sadd(&storage, "a", &["1", "2", "3"]);
+        sadd(&storage, "b", &["2", "3", "4"]);
+
+        let reply = run(
+            Command::SInterStore {
+                destination: "dest".into(),
+                keys: vec!["a".into(), "b".into()],
+            },
+            &storage,
+        );
+
+        assert_eq!(reply, RespValue::Integer(2));
+        assert_eq!(smembers(&storage, "dest"), vec!["2", "3"]);
+    }
+
+    #[test]
+    fn test_sinterstore_empty_result_deletes_destination() {
+        let storage = new_storage();
+        sadd(&storage, "a", &["1"]);
+        sadd(&storage, "b", &["2"]);
+        sadd(&storage, "dest", &["old"]);
+
+        let reply = run(
+            Command::SInterStore {
+                destination: "dest".into(),
+                keys: vec!["a".into(), "b".into()],
+            },
+            &storage,
+        );
+
+        assert_eq!(reply, RespValue::Integer(0));
+        assert!(!storage.get(0).lock().unwrap().has("dest".into()));
+    }
+
+    fn smove(storage: &Databases, source: &str, destination: &str, member: &str) -> RespValue {
+        run(
+            Command::SMove {
+                source: source.into(),
+                destination: destination.into(),
+                member: member.into(),
+            },
+            storage,
+        )
+    }
+
+    #[test]
+    fn test_smove_into_set_already_holding_member() {
+        let storage = new_storage();
+        sadd(&storage, "src", &["a", "b"]);
+        sadd(&storage, "dst", &["a"]);
+
+        assert_eq!(smove(&storage, "src", "dst", "a"), RespValue::Integer(1));
+        assert_eq!(smembers(&storage, "src"), vec!["b"]);
+        assert_eq!(smembers(&storage, "dst"), vec!["a"]);
+        assert_eq!(
+            smove(&storage, "src", "dst", "missing"),
+            RespValue::Integer(0)
+        );
+    }
+
+    #[test]
+    fn test_smove_last_member_removes_source() {
+        let storage = new_storage();
+        sadd(&storage, "src", &["a"]);
+        set(&storage, "string", "v");
+
+        assert!(matches!(
+            smove(&storage, "src", "string", "a"),
+            RespValue::Error(e) if e.starts_with("WRONGTYPE")
+        ));
+        assert_eq!(smove(&storage, "src", "dst", "a"), RespValue::Integer(1));
+        assert!(!lock_storage(storage.get(0)).has("src".into()));
+        assert_eq!(smembers(&storage, "dst"), vec!["a"]);
+    }
+
+    #[test]
+    fn test_sdiffstore_empty_result_deletes_destination_of_other_type() {
+        let storage = new_storage();
+        sadd(&storage, "a", &["1"]);
+        set(&storage, "dest", "old");
+
+        let reply = run(
+            Command::SDiffStore {
+                destination: "dest".into(),
+                keys: vec!["a".into(), "a".into()],
+            },
+            &storage,
+        );
+
+        assert_eq!(reply, RespValue::Integer(0));
+        assert!(!lock_storage(storage.get(0)).has("dest".into()));
+    }
+
+    #[test]
+    fn test_set_store_with_wrong_type_source_leaves_destination() {
+        let storage = new_storage();
+        sadd(&storage, "a", &["1"]);
+        set(&storage, "s", "v");
+        sadd(&storage, "dest", &["old"]);
+
+        for command in [
+            Command::SInterStore {
+                destination: "dest".into(),
+                keys: vec!["a".into(), "s".into()],
+            },
+            Command::SUnionStore {
+                destination: "dest".into(),
+                keys: vec!["s".into(), "a".into()],
+            },
+            Command::SDiffStore {
+                destination: "dest".into(),
+                keys: vec!["missing".into(), "s".into()],
+            },
+        ] {
+            assert!(matches!(
+                run(command, &storage),
+                RespValue::Error(e) if e.starts_with("WRONGTYPE")
+            ));
+            assert_eq!(smembers(&storage, "dest"), vec!["old"]);
+        }
+    }
+
+    #[test]
+    fn test_sunionstore_and_sdiffstore() {
+        let storage = new_storage();
+        sadd(&storage, "a", &["1", "2"]);
+        sadd(&storage, "b", &["2", "3"]);
+
+        let reply = run(
+            Command::SUnionStore {
+                destination: "union".into(),
+                keys: vec!["a".into(), "b".into()],
+            },
+            &storage,
+        );
+        assert_eq!(reply, RespValue::Integer(3));
+        assert_eq!(smembers(&storage, "union"), vec!["1", "2", "3"]);
+
+        let reply = run(
+            Command::SDiffStore {
+                destination: "diff".into(),
+                keys: vec!["a".into(), "b".into()],
+            },
+            &storage,
+        );
+        assert_eq!(reply, RespValue::Integer(1));
+        assert_eq!(smembers(&storage, "diff"), vec!["1"]);
+    }
+
+    #[test]
+    fn test_lpush_onto_string_returns_wrongtype() {
+        let storage = new_storage();
+        run(
+            Command::Set {
+                key: "k".into(),
+                value: "v".into(),
+                condition: None,
+                get: false,
+            },
+            &storage,
+        );
+
+        let reply = run(
+            Command::LPush {
+                key: "k".into(),
+                values: vec!["x".into()],
+            },
+            &storage,
+        );
+        assert_eq!(
+            reply,
+            RespValue::Error(
+                "WRONGTYPE Operation against a key holding the wrong kind of value".to_string()
+            )
+        );
+
+        let reply = run(Command::Get { key: "k".into() }, &storage);
+        assert_eq!(reply, RespValue::BulkString(Some("v".into())));
+    }
+
+    #[test]
+    fn test_rpoplpush_rotates_list() {
+        let storage = new_storage();
+        rpush(&storage, "l", &["a", "b", "c"]);
+
+        let reply = run(
+            Command::RPopLPush {
+                source: "l".into(),
+                destination: "l".into(),
+            },
+            &storage,
+        );
+
+        assert_eq!(reply, RespValue::BulkString(Some("c".into())));
+        assert_eq!(
+            run(
+                Command::LRange {
+                    key: "l".into(),
+                    start: 0,
+                    stop: -1,
+                },
+                &storage,
+            ),
+            bulk_strings(&["c", "a", "b"])
+        );
+    }
+
+    #[test]
+    fn test_lmove_between_lists() {
+        let storage = new_storage();
+        rpush(&storage, "src", &["a", "b"]);
+        rpush(&storage, "dst", &["x"]);
+        let lmove = || {
+            run(
+                Command::LMove {
+                    source: "src".into(),
+                    destination: "dst".into(),
+                    from: ListEnd::Left,
+                    to: ListEnd::Right,
+                },
+                &storage,
+            )
+        };
+
+        assert_eq!(lmove(), RespValue::BulkString(Some("a".into())));
+        assert_eq!(lmove(), RespValue::BulkString(Some("b".into())));
+        assert_eq!(lmove(), RespValue::BulkString(None));
+        assert_eq!(
+            run(
+                Command::LRange {
+                    key: "dst".into(),
+                    start: 0,
+                    stop: -1,
+                },
+                &storage,
+            ),
+            bulk_strings(&["x", "a", "b"])
+        );
+    }
+
+    #[test]
+    fn test_blpop_pops_first_non_empty_list() {
+        let storage = new_storage();
+        rpush(&storage, "second", &["a", "b"]);
+
+        let reply = run(
+            Command::BRPop {
+                keys: vec!["first".into(), "second".into()],
+                timeout: 0.0,
+            },
+            &storage,
+        );
+
+        assert_eq!(reply, bulk_strings(&["second", "b"]));
+    }
+
+    #[test]
+    fn test_blpop_times_out_with_nil() {
+        let storage = new_storage();
+        let start = Instant::now();
+
+        let reply = run(
+            Command::BLPop {
+                keys: vec!["blpop-timeout".into()],
+                timeout: 0.05,
+            },
+            &storage,
+        );
+
+        assert_eq!(reply, RespValue::Array(None));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_huge_timeouts_are_out_of_range() {
+        let storage = new_storage();
+        let out_of_range = RespValue::Error("ERR timeout is out of range".to_string());
+
+        assert_eq!(
+            run(
+                Command::BLPop {
+                    keys: vec!["k".into()],
+                    timeout: 1e300,
+                },
+                &storage,
+            ),
+            out_of_range
+        );
+        assert_eq!(
+            run(Command::DebugSleep { seconds: 1e300 }, &storage),
+            out_of_range
+        );
+    }
+
+    #[test]
+    fn test_lset_out_of_range() {
+        let storage = new_storage();
+        rpush(&storage, "l", &["a"]);
+
+        let reply = run(
+            Command::LSet {
+                key: "l".into(),
+                index: 5,
+                value: "x".into(),
+            },
+            &storage,
+        );
+
+        assert_eq!(
+            reply,
+            RespValue::Error("ERR index out of range".to_string())
+        );
+    }
+
+    #[test]
+    fn test_linsert_before_and_missing_pivot() {
+        let storage = new_storage();
+        rpush(&storage, "l", &["a", "c"]);
+        let linsert = |pivot: &str| {
+            run(
+                Command::LInsert {
+                    key: "l".into(),
+                    before: true,
+                    pivot: pivot.into(),
+                    value: "b".into(),
+                },
+                &storage,
+            )
+        };
+
+        assert_eq!(linsert("c"), RespValue::Integer(3));
+        assert_eq!(linsert("nope"), RespValue::Integer(-1));
+        assert_eq!(
+            run(
+                Command::LRange {
+                    key: "l".into(),
+                    start: 0,
+                    stop: -1,
+                },
+                &storage,
+            ),
+            bulk_strings(&["a", "b", "c"])
+        );
+    }
+
+    #[test]
+    fn test_lrem_removes_last_match() {
+        let storage = new_storage();
+        rpush(&storage, "l", &["x", "a", "x"]);
+
+        let reply = run(
+            Command::LRem {
+                key: "l".into(),
+                count: -1,
+                value: "x".into(),
+            },
+            &storage,
+        );
+
+        assert_eq!(reply, RespValue::Integer(1));
+        assert_eq!(
+            run(
+                Command::LRange {
+                    key: "l".into(),
+                    start: 0,
+                    stop: -1,
+                },
+                &storage,
+            ),
+            bulk_strings(&["x", "a"])
+        );
+    }
+
+    #[test]
+    fn test_ltrim_to_empty_range_deletes_key() {
+        let storage = new_storage();
+        rpush(&storage, "l", &["a", "b"]);
+
+        let reply = run(
+            Command::LTrim {
+                key: "l".into(),
+                start: 1,
+                stop: 0,
+            },
+            &storage,
+        );
+
+        assert_eq!(reply, RespValue::SimpleString("OK".to_string()));
+        assert!(!lock_storage(storage.get(0)).has("l".into()));
+    }
+
+    #[test]
+    fn test_rpush_then_lrange() {
+        let storage = new_storage();
+
+        let reply = run(
+            Command::RPush {
+                key: "l".into(),
+                values: vec!["a".into(), "b".into()],
+            },
+            &storage,
+        );
+        assert_eq!(reply, RespValue::Integer(2));
+
+        let reply = run(
+            Command::LRange {
+                key: "l".into(),
+                start: 0,
+                stop: -1,
+            },
+            &storage,
+        );
+        assert_eq!(
+            reply,
+            RespValue::Array(Some(vec![
+                RespValue::BulkString(Some("a".into())),
+                RespValue::BulkString(Some("b".into())),
+            ]))
+        );
+    }
+
+    fn set(storage: &Databases, key: &str, value: &str) {
+        run(
+            Command::Set {
+                key: key.into(),
+                value: value.into(),
+                condition: None,
+                get: false,
+            },
+            storage,
+        );
+    }
+
+    fn ttl(storage: &Databases, key: &str) -> i64 {
+        match run(Command::Ttl { key: key.into() }, storage) {
+            RespValue::Integer(ttl) => ttl,
+            other => panic!("unexpected TTL reply {:?}", other),
+        }
+    }
+
+    fn getex(storage: &Databases, key: &str, expiry: Option<Expiry>) -> RespValue {
+        run(
+            Command::GetEx {
+                key: key.into(),
+                expiry,
+            },
+            storage,
+        )
+    }
+
+    #[test]
+    fn test_getex_relative_expiry() {
+        let storage = new_storage();
+        set(&storage, "k", "v");
+
+        let reply = getex(&storage, "k", Some(Expiry::Ex(100)));
+        assert_eq!(reply, RespValue::BulkString(Some("v".into())));
+        assert!((99..=101).contains(&ttl(&storage, "k")));
+
+        getex(&storage, "k", Some(Expiry::Px(5_000)));
+        assert!((4..=6).contains(&ttl(&storage, "k")));
+    }
+
+    #[test]
+    fn test_getex_absolute_expiry() {
+        let storage = new_storage();
+        set(&storage, "k", "v");
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        getex(&storage, "k", Some(Expiry::ExAt(now + 200)));
+        assert!((199..=200).contains(&ttl(&storage, "k")));
+
+        getex(&storage, "k", Some(Expiry::PxAt((now + 300) * 1000)));
+        assert!((299..=300).contains(&ttl(&storage, "k")));
+    }
+
+    #[test]
+    fn test_getex_persist_and_no_option() {
+        let storage = new_storage();
+        set(&storage, "k", "v");
+        getex(&storage, "k", Some(Expiry::Ex(100)));
+
+        let reply = getex(&storage, "k", None);
+        assert_eq!(reply, RespValue::BulkString(Some("v".into())));
+        assert!(ttl(&storage, "k") > 0);
+
+        let reply = getex(&storage, "k", Some(Expiry::Persist));
+        assert_eq!(reply, RespValue::BulkString(Some("v".into())));
+        assert_eq!(ttl(&storage, "k"), -1);
+    }
+
+    #[test]
+    fn test_getex_reads_the_storage_clock() {
+        let clock = Arc::new(crate::clock::MockClock::new(1_000));
+        let storage = Databases::new(Storage::with_clock(clock.clone()), 16);
+        set(&storage, "k", "v");
+
+        getex(&storage, "k", Some(Expiry::Px(5_000)));
+        assert_eq!(ttl(&storage, "k"), 5);
+
+        clock.advance(6);
+        assert_eq!(ttl(&storage, "k"), -2);
+    }
+
+    #[test]
+    fn test_async_flush_keeps_the_storage_clock() {
+        let clock = Arc::new(crate::clock::MockClock::new(1_000));
+        let storage = Databases::new(Storage::with_clock(clock.clone()), 16);
+        set(&storage, "old", "v");
+
+        run(Command::FlushDb { asynchronous: true }, &storage);
+        assert_eq!(ttl(&storage, "old"), -2);
+        set(&storage, "k", "v");
+        getex(&storage, "k", Some(Expiry::Ex(10)));
+        clock.advance(11);
+
+        assert_eq!(ttl(&storage, "k"), -2);
+    }
+
+    #[test]
+    fn test_getex_missing_key() {
+        let storage = new_storage();
+
+        let reply = getex(&storage, "missing", Some(Expiry::Ex(100)));
+
+        assert_eq!(reply, RespValue::BulkString(None));
+        assert_eq!(ttl(&storage, "missing"), -2);
+    }
+
+    fn rpush(storage: &Databases, key: &str, values: &[&str]) {
+        run(
+            Command::RPush {
+                key: key.into(),
+                values: values.iter().map(|v| v.as_bytes().to_vec()).collect(),
+            },
+            storage,
+        );
+    }
+
+    fn sort(
+        storage: &Databases,
+        key: &str,
+        alpha: bool,
+        descending: bool,
+        limit: Option<(i64, i64)>,
+    ) -> RespValue {
+        run(
+            Command::Sort {
+                key: key.into(),
+                alpha,
+                descending,
+                limit,
+            },
+            storage,
+        )
+    }
+
+    fn bulk_strings(values: &[&str]) -> RespValue {
+        RespValue::Array(Some(
+            values
+                .iter()
+                .map(|v| RespValue::BulkString(Some(v.as_bytes().to_vec())))
+                .collect(),
+        ))
+    }
+
+    #[test]
+    fn test_sort_numeric_and_alpha() {
+        let storage = new_storage();
+        rpush(&storage, "nums", &["10", "2", "-1.5", "3"]);
+
+        assert_eq!(
+            sort(&storage, "nums", false, false, None),
+            bulk_strings(&["-1.5", "2", "3", "10"])
+        );
+        assert_eq!(
+            sort(&storage, "nums", true, false, None),
+            bulk_strings(&["-1.5", "10", "2", "3"])
+        );
+    }
+
+    #[test]
+    fn test_sort_set_descending_with_limit() {
+        let storage = new_storage();
+        sadd(&storage, "s", &["1", "5", "3", "4", "2"]);
+
+        assert_eq!(
+            sort(&storage, "s", false, true, None),
+            bulk_strings(&["5", "4", "3", "2", "1"])
+        );
+        assert_eq!(
+            sort(&storage, "s", false, true, Some((1, 2))),
+            bulk_strings(&["4", "3"])
+        );
+    }
+
+    #[test]
+    fn test_sort_non_numeric_without_alpha() {
+        let storage = new_storage();
+        rpush(&storage, "words", &["b", "a"]);
+
+        assert_eq!(
+            sort(&storage, "words", false, false, None),
+            RespValue::Error("ERR One or more scores can't be converted into double".to_string())
+        );
+        assert_eq!(
+            sort(&storage, "words", true, false, None),
+            bulk_strings(&["a", "b"])
+        );
+    }
+
+    fn dump(storage: &Databases, key: &str) -> Vec<u8> {
+        match run(Command::Dump { key: key.into() }, storage) {
+            RespValue::BulkString(Some(payload)) => payload,
+            other => panic!("unexpected DUMP reply {:?}", other),
+        }
+    }
+
+    fn restore(
+        storage: &Databases,
+        key: &str,
+        ttl: i64,
+        payload: &[u8],
+        replace: bool,
+    ) -> RespValue {
+        run(
+            Command::Restore {
+                key: key.into(),
+                ttl,
+                payload: payload.to_vec(),
+                replace,
+            },
+            storage,
+        )
+    }
+
+    #[test]
+    fn test_dump_and_restore_with_ttl() {
+        let storage = new_storage();
+        rpush(&storage, "src", &["a", "b", "c"]);
+
+        let payload = dump(&storage, "src");
+        let reply = restore(&storage, "dst", 10_000, &payload, false);
+
+        assert_eq!(reply, RespValue::SimpleString("OK".to_string()));
+        assert!((9..=11).contains(&ttl(&storage, "dst")));
+        assert_eq!(
+            run(
+                Command::LRange {
+                    key: "dst".into(),
+                    start: 0,
+                    stop: -1,
+                },
+                &storage,
+            ),
+            bulk_strings(&["a", "b", "c"])
+        );
+    }
+
+    #[test]
+    fn test_restore_existing_key_requires_replace() {
+        let storage = new_storage();
+        set(&storage, "k", "old");
+        set(&storage, "src", "new");
+        let payload = dump(&storage, "src");
+
+        assert_eq!(
+            restore(&storage, "k", 0, &payload, false),
+            RespValue::Error("BUSYKEY Target key name already exists.".to_string())
+        );
+        assert_eq!(
+            restore(&storage, "k", 0, &payload, true),
+            RespValue::SimpleString("OK".to_string())
+        );
+        assert_eq!(
+            storage.get(0).lock().unwrap().get("k".into()),
+            Ok(Some(b"new".to_vec()))
+        );
+    }
+
+    #[test]
+    fn test_dump_missing_key_and_bad_payload() {
+        let storage = new_storage();
+
+        assert_eq!(
+            run(
+                Command::Dump {
+                    key: "missing".into(),
+                },
+                &storage,
+            ),
+            RespValue::BulkString(None)
+        );
+        assert_eq!(
+            restore(&storage, "k", 0, b"garbage", false),
+            RespValue::Error("ERR DUMP payload version or checksum are wrong".to_string())
+        );
+    }
+
+    #[test]
+    fn test_info_stats_counts_keyspace_hits_and_misses() {
+        let storage = new_storage();
+        let stat = |name: &str| {
+            let RespValue::BulkString(Some(info)) = run(
+                Command::Info {
+                    section: Some("stats".to_string()),
+                },
+                &storage,
+            ) else {
+                panic!("INFO should return a bulk string");
+            };
+            String::from_utf8(info)
+                .unwrap()
+                .lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+                .map(|value| value.parse::<u64>().unwrap())
+                .unwrap()
+        };
+        let get = |key: &str| run(Command::Get { key: key.into() }, &storage);
+
+        // Other tests share the global counters, so only a lower bound is exact
+        let misses = stat("keyspace_misses");
+        get("missing");
+        assert!(stat("keyspace_misses") > misses);
+
+        set(&storage, "k", "v");
+        let hits = stat("keyspace_hits");
+        get("k");
+        assert!(stat("keyspace_hits") > hits);
+    }
+
+    #[test]
+    fn test_info_stats_reports_contended_lock_waits() {
+        let storage = new_storage();
+        let stat = |name: &str| {
+            let RespValue::BulkString(Some(info)) = run(
+                Command::Info {
+                    section: Some("stats".to_string()),
+                },
+                &storage,
+            ) else {
+                panic!("INFO should return a bulk string");
+            };
+            String::from_utf8(info)
+                .unwrap()
+                .lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+                .map(|value| value.parse::<u64>().unwrap())
+                .unwrap()
+        };
+        let contended = stat("storage_lock_contended");
+
+        let (locked, wait_locked) = std::sync::mpsc::channel();
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let _guard = lock_storage(storage.get(0));
+                locked.send(()).unwrap();
+                std::thread::sleep(Duration::from_millis(50));
+            });
+            wait_locked.recv().unwrap();
+            run(Command::Get { key: "k".into() }, &storage);
+        });
+
+        assert!(stat("storage_lock_contended") > contended);
+        assert!(stat("storage_lock_max_wait_usec") >= 25_000);
+        assert!(stat("storage_lock_wait_usec") >= 25_000);
+    }
+
+    #[test]
+    fn test_info_memory_tracks_large_values() {
+        let storage = new_storage();
+        let info = || {
+            let RespValue::BulkString(Some(info)) = run(
+                Command::Info {
+                    section: Some("memory".to_string()),
+                },
+                &storage,
+            ) else {
+                panic!("INFO should return a bulk string");
+            };
+            String::from_utf8(info).unwrap()
+        };
+        let used_memory = || {
+            info()
+                .lines()
+                .find_map(|line| line.strip_prefix("used_memory:"))
+                .map(|value| value.parse::<usize>().unwrap())
+                .unwrap()
+        };
+
+        let empty = used_memory();
+        set(&storage, "big", &"x".repeat(100_000));
+        assert!(used_memory() >= empty + 100_000);
+
+        run(
+            Command::Del {
+                keys: vec!["big".into()],
+            },
+            &storage,
+        );
+        assert_eq!(used_memory(), empty);
+        assert!(info().contains("maxmemory:0\r\nmaxmemory_policy:noeviction\r\n"));
+    }
+
+    #[test]
+    fn test_info_keyspace_lists_non_empty_databases() {
+        let storage = new_storage();
+        {
+            let mut db0 = lock_storage(storage.get(0));
+            db0.set("a".into(), b"1".to_vec());
+            db0.set("b".into(), b"2".to_vec());
+            let deadline = expiry_deadline(&db0, &Expiry::Ex(100));
+            db0.set_expire_at("b".into(), deadline);
+        }
+        lock_storage(storage.get(2)).set("c".into(), b"3".to_vec());
+
+        let RespValue::BulkString(Some(info)) = run(
+            Command::Info {
+                section: Some("keyspace".to_string()),
+            },
+            &storage,
+        ) else {
+            panic!("INFO should return a bulk string");
+        };
+
+        let info = String::from_utf8(info).unwrap();
+        let lines: Vec<&str> = info.lines().collect();
+        assert_eq!(lines[0], "# Keyspace");
+        assert!(lines[1].starts_with("db0:keys=2,expires=1,avg_ttl="));
+        let avg_ttl: u64 = lines[1].rsplit('=').next().unwrap().parse().unwrap();
+        assert!(avg_ttl > 90_000 && avg_ttl <= 101_000);
+        assert_eq!(lines[2], "db2:keys=1,expires=0,avg_ttl=0");
+        assert_eq!(lines.len(), 3);
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        let storage = new_storage();
+        set(&storage, "short", "v");
+        set(&storage, "long", &"v".repeat(1000));
+        let memory_usage = |key: &str, samples| {
+            run(
+                Command::MemoryUsage {
+                    key: key.into(),
+                    samples,
+                },
+                &storage,
+            )
+        };
+
+        let (RespValue::Integer(short), RespValue::Integer(long)) =
+            (memory_usage("short", None), memory_usage("long", None))
+        else {
+            panic!("MEMORY USAGE should return integers for existing keys");
+        };
+        assert!(long > short + 900);
+        assert_eq!(memory_usage("missing", None), RespValue::BulkString(None));
+
+        // Equal-sized elements estimate the same from a sample as in full
+        run(
+            Command::RPush {
+                key: "list".into(),
+                values: vec!["item".into(); 100],
+            },
+            &storage,
+        );
+        assert_eq!(memory_usage("list", Some(3)), memory_usage("list", Some(0)));
+    }
+
+    #[test]
+    fn test_info_commandstats_counts_gets() {
+        let storage = new_storage();
+        let get_calls = || {
+            let RespValue::BulkString(Some(info)) = run(
+                Command::Info {
+                    section: Some("commandstats".to_string()),
+                },
+                &storage,
+            ) else {
+                panic!("INFO should return a bulk string");
+            };
+            String::from_utf8(info)
+                .unwrap()
+                .lines()
+                .find_map(|line| line.strip_prefix("cmdstat_get:calls="))
+                .and_then(|rest| rest.split(',').next())
+                .map_or(0, |calls| calls.parse::<u64>().unwrap())
+        };
+
+        // Other tests share the global counters, so only a lower bound is exact
+        let before = get_calls();
+        for _ in 0..3 {
+            run(Command::Get { key: "k".into() }, &storage);
+        }
+        assert!(get_calls() >= before + 3);
+    }
+
+    #[test]
+    fn test_config_set_is_visible_to_config_get() {
+        let storage = new_storage();
+        let mut ctx = ConnectionContext::default();
+
+        assert_eq!(
+            handle_command(
+                Command::ConfigSet {
+                    parameter: "HZ".to_string(),
+                    value: "50".to_string(),
+                },
+                &storage,
+                &mut ctx,
+            ),
+            RespValue::SimpleString("OK".to_string())
+        );
+        assert!(matches!(
+            handle_command(
+                Command::ConfigSet {
+                    parameter: "active-expire-samples".to_string(),
+                    value: "lots".to_string(),
+                },
+                &storage,
+                &mut ctx,
+            ),
+            RespValue::Error(_)
+        ));
+
+        assert_eq!(
+            handle_command(
+                Command::ConfigGet {
+                    pattern: "active-expire-*".to_string(),
+                },
+                &storage,
+                &mut ctx,
+            ),
+            bulk_strings(&[
+                "active-expire-samples",
+                "20",
+                "active-expire-threshold",
+                "25"
+            ])
+        );
+        assert_eq!(ctx.config.read().unwrap().hz, 50);
+    }
+
+    #[test]
+    fn test_config_file_values_visible_to_config_get() {
+        let path = std::env::temp_dir().join(format!("rrrr-get-{}.conf", std::process::id()));
+        std::fs::write(&path, "port 7001\nappendonly yes\nmaxmemory 2mb\n").unwrap();
+        let mut config = Config::default();
+        config.load_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let storage = new_storage();
+        let mut ctx = ConnectionContext {
+            config: std::sync::Arc::new(std::sync::RwLock::new(config)),
+            ..ConnectionContext::default()
+        };
+        let config_get = |pattern: &str, ctx: &mut ConnectionContext| {
+            handle_command(
+                Command::ConfigGet {
+                    pattern: pattern.to_string(),
+                },
+                &storage,
+                ctx,
+            )
+        };
+
+        assert_eq!(
+            config_get("port", &mut ctx),
+            bulk_strings(&["port", "7001"])
+        );
+        assert_eq!(
+            config_get("appendonly", &mut ctx),
+            bulk_strings(&["appendonly", "yes"])
+        );
+        assert_eq!(
+            config_get("maxmemory", &mut ctx),
+            bulk_strings(&["maxmemory", "2097152"])
+        );
+    }
+
+    #[test]
+    fn test_hello_replies_with_resp2_server_info() {
+        let storage = new_storage();
+
+        let RespValue::Array(Some(fields)) = run(
+            Command::Hello {
+                protover: None,
+                auth: None,
+                setname: None,
+            },
+            &storage,
+        ) else {
+            panic!("HELLO should return an array");
+        };
+
+        let proto = fields
+            .chunks(2)
+            .find(|pair| pair[0] == RespValue::BulkString(Some("proto".into())))
+            .map(|pair| &pair[1]);
+        assert_eq!(proto, Some(&RespValue::Integer(2)));
+        assert_eq!(
+            run(
+                Command::Hello {
+                    protover: Some(2),
+                    auth: None,
+                    setname: None
+                },
+                &storage
+            ),
+            run(
+                Command::Hello {
+                    protover: None,
+                    auth: None,
+                    setname: None
+                },
+                &storage
+            )
+        );
+        assert_eq!(
+            run(
+                Command::Hello {
+                    protover: Some(3),
+                    auth: None,
+                    setname: None
+                },
+                &storage
+            ),
+            RespValue::Error("NOPROTO unsupported protocol version".to_string())
+        );
+    }
+
+    #[test]
+    fn test_select_within_default_database_count() {
+        let storage = Databases::new(Storage::new(), Config::default().databases);
+        let mut ctx = ConnectionContext::default();
+
+        assert_eq!(
+            handle_command(Command::Select { index: 15 }, &storage, &mut ctx),
+            RespValue::SimpleString("OK".to_string())
+        );
+        assert_eq!(ctx.db, 15);
+        assert_eq!(
+            handle_command(Command::Select { index: 16 }, &storage, &mut ctx),
+            RespValue::Error("ERR DB index is out of range".to_string())
+        );
+        assert_eq!(ctx.db, 15);
+    }
+
+    #[test]
+    fn test_move_keeps_ttl() {
+        let storage = new_storage();
+        set(&storage, "k", "v");
+        expire(&storage, "k", 100);
+
+        assert_eq!(
+            run(
+                Command::Move {
+                    key: "k".into(),
+                    db: 3,
+                },
+                &storage,
+            ),
+            RespValue::Integer(1)
+        );
+
+        assert!(!lock_storage(storage.get(0)).has("k".into()));
+        let mut target = lock_storage(storage.get(3));
+        assert_eq!(target.get("k".into()), Ok(Some(b"v".to_vec())));
+        assert_eq!(target.get_ttl("k".into()), 100);
+    }
+
+    #[test]
+    fn test_move_refuses_missing_or_existing_keys() {
+        let storage = new_storage();
+        let move_to_1 = |key: &str| {
+            run(
+                Command::Move {
+                    key: key.into(),
+                    db: 1,
+                },
+                &storage,
+            )
+        };
+        set(&storage, "k", "source");
+        lock_storage(storage.get(1)).set("k".into(), b"target".to_vec());
+
+        assert_eq!(move_to_1("missing"), RespValue::Integer(0));
+        assert_eq!(move_to_1("k"), RespValue::Integer(0));
+        assert_eq!(
+            lock_storage(storage.get(0)).get("k".into()),
+            Ok(Some(b"source".to_vec()))
+        );
+    }
+
+    #[test]
+    fn test_copy_into_another_database() {
+        let storage = new_storage();
+        set(&storage, "k", "v");
+        expire(&storage, "k", 100);
+        let copy = |db| {
+            run(
+                Command::Copy {
+                    source: "k".into(),
+                    destination: "k".into(),
+                    db,
+                    replace: false,
+                },
+                &storage,
+            )
+        };
+
+        assert_eq!(copy(Some(1)), RespValue::Integer(1));
+        assert_eq!(copy(Some(1)), RespValue::Integer(0));
+        assert_eq!(
+            copy(None),
+            RespValue::Error("ERR source and destination objects are the same".to_string())
+        );
+        assert_eq!(
+            copy(Some(0)),
+            RespValue::Error("ERR source and destination objects are the same".to_string())
+        );
+
+        lock_storage(storage.get(1)).set("k".into(), b"changed".to_vec());
+        assert_eq!(
+            lock_storage(storage.get(0)).get("k".into()),
+            Ok(Some(b"v".to_vec()))
+        );
+        let mut target = lock_storage(storage.get(1));
+        assert_eq!(target.get("k".into()), Ok(Some(b"changed".to_vec())));
+        drop(target);
+
+        assert_eq!(
+            run(
+                Command::Copy {
+                    source: "k".into(),
+                    destination: "k".into(),
+                    db: Some(1),
+                    replace: true,
+                },
+                &storage,
+            ),
+            RespValue::Integer(1)
+        );
+        target = lock_storage(storage.get(1));
+        assert_eq!(target.get("k".into()), Ok(Some(b"v".to_vec())));
+        assert_eq!(target.get_ttl("k".into()), 100);
+    }
+
+    #[test]
+    fn test_copy_within_database_is_independent() {
+        let storage = new_storage();
+        rpush(&storage, "src", &["a"]);
+
+        assert_eq!(
+            run(
+                Command::Copy {
+                    source: "src".into(),
+                    destination: "dst".into(),
+                    db: None,
+                    replace: false,
+                },
+                &storage,
+            ),
+            RespValue::Integer(1)
+        );
+        rpush(&storage, "dst", &["b"]);
+
+        assert_eq!(
+            run(Command::LLen { key: "src".into() }, &storage),
+            RespValue::Integer(1)
+        );
+        assert_eq!(
+            run(Command::LLen { key: "dst".into() }, &storage),
+            RespValue::Integer(2)
+        );
+    }
+
+    #[test]
+    fn test_flushall_async_empties_every_database() {
+        let storage = new_storage();
+        set(&storage, "k", "v");
+        lock_storage(storage.get(2)).set("k".into(), b"v".to_vec());
+
+        assert_eq!(
+            run(Command::FlushAll { asynchronous: true }, &storage),
+            RespValue::SimpleString("OK".to_string())
+        );
+
+        for db in 0..storage.len() {
+            assert_eq!(
+                lock_storage(storage.get(db)).keys("*".to_string()),
+                Vec::<Vec<u8>>::new()
+            );
+        }
+    }
+
+    #[test]
+    fn test_flushdb_only_clears_selected_database() {
+        let storage = new_storage();
+        set(&storage, "k", "v");
+        lock_storage(storage.get(1)).set("k".into(), b"v".to_vec());
+        let mut ctx = ConnectionContext {
+            db: 1,
+            ..ConnectionContext::default()
+        };
+
+        handle_command(
+            Command::FlushDb {
+                asynchronous: false,
+            },
+            &storage,
+            &mut ctx,
+        );
+
+        assert!(lock_storage(storage.get(0)).has("k".into()));
+        assert!(!lock_storage(storage.get(1)).has("k".into()));
+    }
+
+    #[test]
+    fn test_bare_command_lists_every_command() {
+        let storage = new_storage();
+
+        let RespValue::Array(Some(entries)) = run(Command::CommandList, &storage) else {
+            panic!("COMMAND should return an array");
+        };
+
+        assert_eq!(entries.len(), COMMAND_TABLE.len());
+        assert_eq!(
+            run(Command::CommandCount, &storage),
+            RespValue::Integer(COMMAND_TABLE.len() as i64)
+        );
+    }
+
+    #[test]
+    fn test_command_info_get_metadata() {
+        let storage = new_storage();
+
+        let reply = run(
+            Command::CommandInfo {
+                names: vec!["get".to_string()],
+            },
+            &storage,
+        );
+
+        let RespValue::Array(Some(entries)) = reply else {
+            panic!("COMMAND INFO should return an array");
+        };
+        let RespValue::Array(Some(info)) = &entries[0] else {
+            panic!("GET should have an info entry");
+        };
+        assert_eq!(info[0], RespValue::BulkString(Some("get".into())));
+        assert_eq!(info[1], RespValue::Integer(2));
+        let RespValue::Array(Some(flags)) = &info[2] else {
+            panic!("flags should be an array");
+        };
+        assert!(flags.contains(&RespValue::SimpleString("readonly".to_string())));
+        assert_eq!(
+            info[3..],
+            [
+                RespValue::Integer(1),
+                RespValue::Integer(1),
+                RespValue::Integer(1),
+                RespValue::Array(Some(vec![RespValue::SimpleString("@string".to_string())])),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_command_info_reports_unknown_names_as_nil() {
+        let storage = new_storage();
+
+        let RespValue::Array(Some(entries)) = run(
+            Command::CommandInfo {
+                names: vec!["GET".to_string(), "nosuchcommand".to_string()],
+            },
+            &storage,
+        ) else {
+            panic!("COMMAND INFO should return an array");
+        };
+
+        assert_eq!(entries[0], command_table::lookup("get").unwrap().info());
+        assert_eq!(entries[1], RespValue::Array(None));
+    }
+
+    #[test]
+    fn test_object_help_describes_subcommands() {
+        let storage = new_storage();
+
+        let RespValue::Array(Some(lines)) = run(Command::Help { command: "OBJECT" }, &storage)
+        else {
+            panic!("OBJECT HELP should return an array");
+        };
+
+        assert!(matches!(&lines[0], RespValue::SimpleString(line) if line.contains("OBJECT")));
+        assert!(lines.contains(&RespValue::SimpleString("REFCOUNT <key>".to_string())));
+        assert_eq!(
+            lines.last(),
+            Some(&RespValue::SimpleString("    Print this help.".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_client_help_describes_subcommands() {
+        let storage = new_storage();
+
+        let RespValue::Array(Some(lines)) = run(Command::Help { command: "CLIENT" }, &storage)
+        else {
+            panic!("CLIENT HELP should return an array");
+        };
+
+        assert!(matches!(&lines[0], RespValue::SimpleString(line) if line.contains("CLIENT")));
+        for subcommand in ["GETNAME", "SETNAME <name>", "SETINFO <option> <value>"] {
+            assert!(lines.contains(&RespValue::SimpleString(subcommand.to_string())));
+        }
+    }
+
+    fn set_with(
+        storage: &Databases,
+        key: &str,
+        value: &str,
+        condition: Option<SetCondition>,
+        get: bool,
+    ) -> RespValue {
+        run(
+            Command::Set {
+                key: key.into(),
+                value: value.into(),
+                condition,
+                get,
+            },
+            storage,
+        )
+    }
+
+    #[test]
+    fn test_set_get_returns_old_value() {
+        let storage = new_storage();
+
+        assert_eq!(
+            set_with(&storage, "k", "v1", None, true),
+            RespValue::BulkString(None)
+        );
+        assert_eq!(
+            set_with(&storage, "k", "v2", None, true),
+            RespValue::BulkString(Some("v1".into()))
+        );
+        assert_eq!(
+            run(Command::Get { key: "k".into() }, &storage),
+            RespValue::BulkString(Some("v2".into()))
+        );
+    }
+
+    #[test]
+    fn test_set_nx_get_skips_set_but_returns_old_value() {
+        let storage = new_storage();
+        set(&storage, "k", "v1");
+
+        assert_eq!(
+            set_with(&storage, "k", "v2", Some(SetCondition::Nx), true),
+            RespValue::BulkString(Some("v1".into()))
+        );
+        assert_eq!(
+            set_with(&storage, "k", "v3", Some(SetCondition::Nx), false),
+            RespValue::BulkString(None)
+        );
+        assert_eq!(
+            set_with(&storage, "missing", "v", Some(SetCondition::Xx), false),
+            RespValue::BulkString(None)
+        );
+        assert_eq!(
+            lock_storage(storage.get(0)).get("k".into()),
+            Ok(Some(b"v1".to_vec()))
+        );
+    }
+
+    #[test]
+    fn test_set_get_on_wrong_type_does_not_set() {
+        let storage = new_storage();
+        sadd(&storage, "s", &["a"]);
+
+        assert!(matches!(
+            set_with(&storage, "s", "v", None, true),
+            RespValue::Error(e) if e.starts_with("WRONGTYPE")
+        ));
+        assert_eq!(lock_storage(storage.get(0)).value_type(b"s"), Some("set"));
+    }
+
+    #[test]
+    fn test_context_db_selects_storage() {
+        let storage = new_storage();
+        let mut ctx = ConnectionContext {
+            db: 1,
+            ..ConnectionContext::default()
+        };
+
+        handle_command(
+            Command::Set {
+                key: "k".into(),
+                value: "v".into(),
+                condition: None,
+                get: false,
+            },
+            &storage,
+            &mut ctx,
+        );
+
+        assert_eq!(
+            storage.get(1).lock().unwrap().get("k".into()),
+            Ok(Some(b"v".to_vec()))
+        );
+        assert_eq!(storage.get(0).lock().unwrap().get("k".into()), Ok(None));
+        ctx.db = 0;
+        assert_eq!(
+            handle_command(Command::Get { key: "k".into() }, &storage, &mut ctx,),
+            RespValue::BulkString(None)
+        );
+    }
+
+    fn expire(storage: &Databases, key: &str, ttl: i64) -> RespValue {
+        run(
+            Command::Expire {
+                key: key.into(),
+                expire: ttl.to_string(),
+            },
+            storage,
+        )
+    }
+
+    #[test]
+    fn test_expire_positive_ttl() {
+        let storage = new_storage();
+        set(&storage, "k", "v");
+
+        assert_eq!(expire(&storage, "k", 100), RespValue::Integer(1));
+        assert_eq!(ttl(&storage, "k"), 100);
+        assert_eq!(expire(&storage, "missing", 100), RespValue::Integer(0));
+    }
+
+    #[test]
+    fn test_expire_zero_ttl_deletes_key_immediately() {
+        let storage = new_storage();
+        set(&storage, "k", "v");
+
+        assert_eq!(expire(&storage, "k", 0), RespValue::Integer(1));
+        assert_eq!(ttl(&storage, "k"), -2);
+        assert_eq!(
+            run(Command::Get { key: "k".into() }, &storage,),
+            RespValue::BulkString(None)
+        );
+        assert_eq!(expire(&storage, "missing", 0), RespValue::Integer(0));
+    }
+
+    #[test]
+    fn test_expire_negative_ttl_deletes_key() {
+        let storage = new_storage();
+        set(&storage, "k", "v");
+
+        assert_eq!(expire(&storage, "k", -1), RespValue::Integer(1));
+        assert_eq!(ttl(&storage, "k"), -2);
+        assert_eq!(expire(&storage, "k", -1), RespValue::Integer(0));
+    }
+
+    #[test]
+    fn test_expiretime_reports_absolute_expiry() {
+        let storage = new_storage();
+        set(&storage, "volatile", "v");
+        set(&storage, "persistent", "v");
+        expire(&storage, "volatile", 100);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let expiretime = |key: &str| run(Command::ExpireTime { key: key.into() }, &storage);
+        let pexpiretime = |key: &str| run(Command::PExpireTime { key: key.into() }, &storage);
+
+        let RespValue::Integer(at) = expiretime("volatile") else {
+            panic!("EXPIRETIME should return an integer");
+        };
+        assert!((now + 99..=now + 101).contains(&at));
+        assert_eq!(pexpiretime("volatile"), RespValue::Integer(at * 1000));
+        assert_eq!(expiretime("persistent"), RespValue::Integer(-1));
+        assert_eq!(pexpiretime("persistent"), RespValue::Integer(-1));
+        assert_eq!(expiretime("missing"), RespValue::Integer(-2));
+        assert_eq!(pexpiretime("missing"), RespValue::Integer(-2));
+    }
+
+    #[test]
+    fn test_string_commands_see_integer_as_decimal() {
+        let storage = new_storage();
+        let key = || "counter".to_string();
+        let get = || {
+            run(
+                Command::Get {
+                    key: key().into_bytes(),
+                },
+                &storage,
+            )
+        };
+        let incr = || {
+            run(
+                Command::Incr {
+                    key: key().into_bytes(),
+                },
+                &storage,
+            )
+        };
+        let strlen = || {
+            run(
+                Command::StrLen {
+                    key: key().into_bytes(),
+                },
+                &storage,
+            )
+        };
+
+        assert_eq!(incr(), RespValue::Integer(1));
+        assert_eq!(get(), RespValue::BulkString(Some("1".into())));
+        assert_eq!(strlen(), RespValue::Integer(1));
+
+        let reply = run(
+            Command::Append {
+                key: key().into_bytes(),
+                value: "0".into(),
+            },
+            &storage,
+        );
+        assert_eq!(reply, RespValue::Integer(2));
+        assert_eq!(get(), RespValue::BulkString(Some("10".into())));
+        assert_eq!(incr(), RespValue::Integer(11));
+        assert_eq!(strlen(), RespValue::Integer(2));
+
+        let reply = run(
+            Command::SetRange {
+                key: key().into_bytes(),
+                offset: 0,
+                value: "9".into(),
+            },
+            &storage,
+        );
+        assert_eq!(reply, RespValue::Integer(2));
+        assert_eq!(get(), RespValue::BulkString(Some("91".into())));
+        assert_eq!(incr(), RespValue::Integer(92));
+        assert_eq!(get(), RespValue::BulkString(Some("92".into())));
+    }
+
+    #[test]
+    fn test_setrange_pads_and_skips_empty_writes() {
+        let storage = new_storage();
+
+        let reply = run(
+            Command::SetRange {
+                key: "k".into(),
+                offset: 2,
+                value: "ab".into(),
+            },
+            &storage,
+        );
+        assert_eq!(reply, RespValue::Integer(4));
+        assert_eq!(
+            storage.get(0).lock().unwrap().get("k".into()),
+            Ok(Some(b"\0\0ab".to_vec()))
+        );
+
+        let reply = run(
+            Command::SetRange {
+                key: "missing".into(),
+                offset: 5,
+                value: String::new().into_bytes(),
+            },
+            &storage,
+        );
+        assert_eq!(reply, RespValue::Integer(0));
+        assert_eq!(ttl(&storage, "missing"), -2);
+    }
+
+    #[test]
+    fn test_incr_on_list_returns_wrongtype() {
+        let storage = new_storage();
+        run(
+            Command::LPush {
+                key: "k".into(),
+                values: vec!["a".into()],
+            },
+            &storage,
+        );
+
+        let wrongtype = RespValue::Error(StorageError::WrongType.to_string());
+        assert_eq!(run(Command::Incr { key: "k".into() }, &storage,), wrongtype);
+        assert_eq!(
+            run(
+                Command::DecrBy {
+                    key: "k".into(),
+                    value: "5".to_string(),
+                },
+                &storage,
+            ),
+            wrongtype
+        );
+        assert_eq!(
+            run(
+                Command::LRange {
+                    key: "k".into(),
+                    start: 0,
+                    stop: -1,
+                },
+                &storage,
+            ),
+            bulk_strings(&["a"])
+        );
+    }
+
+    #[test]
+    fn test_scan_type_filter() {
+        let storage = new_storage();
+        for i in 0..5 {
+            set(&storage, &format!("string:{}", i), "v");
+            rpush(&storage, &format!("list:{}", i), &["a"]);
+        }
+
+        let reply = run(
+            Command::Scan {
+                cursor: 0,
+                pattern: None,
+                count: 100,
+                value_type: Some("list".to_string()),
+            },
+            &storage,
+        );
+
+        let RespValue::Array(Some(parts)) = reply else {
+            panic!("SCAN should return an array");
+        };
+        assert_eq!(parts[0], RespValue::BulkString(Some("0".into())));
+        let RespValue::Array(Some(keys)) = &parts[1] else {
+            panic!("SCAN keys should be an array");
+        };
+        let mut keys: Vec<_> = keys
+            .iter()
+            .map(|key| match key {
+                RespValue::BulkString(Some(key)) => String::from_utf8(key.clone()).unwrap(),
+                other => panic!("unexpected key {:?}", other),
+            })
+            .collect();
+        keys.sort();
+        assert_eq!(keys, ["list:0", "list:1", "list:2", "list:3", "list:4"]);
+    }
+
+    #[test]
+    fn test_hscan_covers_every_field_once() {
+        let storage = new_storage();
+        let pairs = (0..100)
+            .map(|i| {
+                (
+                    format!("field:{}", i).into_bytes(),
+                    format!("value:{}", i).into_bytes(),
+                )
+            })
+            .collect();
+        run(
+            Command::HSet {
+                key: "h".into(),
+                pairs,
+            },
+            &storage,
+        );
+
+        let mut seen = std::collections::HashMap::new();
+        let mut cursor = 0;
+        let mut calls = 0;
+        loop {
+            let RespValue::Array(Some(parts)) = run(
+                Command::HScan {
+                    key: "h".into(),
+                    cursor,
+                    pattern: None,
+                    count: 10,
+                },
+                &storage,
+            ) else {
+                panic!("HSCAN should return an array");
+            };
+            let [RespValue::BulkString(Some(next)), RespValue::Array(Some(items))] =
+                parts.as_slice()
+            else {
+                panic!("unexpected HSCAN reply {:?}", parts);
+            };
+            for pair in items.chunks(2) {
+                let [RespValue::BulkString(Some(field)), RespValue::BulkString(Some(value))] = pair
+                else {
+                    panic!("unexpected HSCAN pair {:?}", pair);
+                };
+                assert!(seen.insert(field.clone(), value.clone()).is_none());
+            }
+            calls += 1;
+            cursor = std::str::from_utf8(next).unwrap().parse().unwrap();
+            if cursor == 0 {
+                break;
+            }
+        }
+
+        assert!(calls > 1);
+        assert_eq!(seen.len(), 100);
+        assert_eq!(seen[&b"field:42"[..]], b"value:42");
+    }
+
+    fn hset(storage: &Databases, key: &str, pairs: &[(&str, &str)]) {
+        run(
+            Command::HSet {
+                key: key.into(),
+                pairs: pairs
+                    .iter()
+                    .map(|(field, value)| (field.as_bytes().to_vec(), value.as_bytes().to_vec()))
+                    .collect(),
+            },
+            storage,
+        );
+    }
+
+    #[test]
+    fn test_hsetnx_skips_existing_field() {
+        let storage = new_storage();
+        let hsetnx = |value: &str| {
+            run(
+                Command::HSetNx {
+                    key: "h".into(),
+                    field: "f".into(),
+                    value: value.into(),
+                },
+                &storage,
+            )
+        };
+
+        assert_eq!(hsetnx("first"), RespValue::Integer(1));
+        assert_eq!(hsetnx("second"), RespValue::Integer(0));
+        assert_eq!(
+            run(
+                Command::HGet {
+                    key: "h".into(),
+                    field: "f".into(),
+                },
+                &storage,
+            ),
+            RespValue::BulkString(Some("first".into()))
+        );
+    }
+
+    #[test]
+    fn test_hmget_mixes_values_and_nils() {
+        let storage = new_storage();
+        hset(&storage, "h", &[("a", "1"), ("c", "3")]);
+        let hmget = |key: &str| {
+            run(
+                Command::HMGet {
+                    key: key.into(),
+                    fields: vec!["a".into(), "b".into(), "c".into()],
+                },
+                &storage,
+            )
+        };
+        let nil = RespValue::BulkString(None);
+
+        assert_eq!(
+            hmget("h"),
+            RespValue::Array(Some(vec![
+                RespValue::BulkString(Some("1".into())),
+                nil.clone(),
+                RespValue::BulkString(Some("3".into())),
+            ]))
+        );
+        assert_eq!(
+            hmget("missing"),
+            RespValue::Array(Some(vec![nil.clone(), nil.clone(), nil]))
+        );
+    }
+
+    #[test]
+    fn test_hincrby_creates_and_increments_field() {
+        let storage = new_storage();
+        let hincrby = |increment: i64| {
+            run(
+                Command::HIncrBy {
+                    key: "h".into(),
+                    field: "n".into(),
+                    increment,
+                },
+                &storage,
+            )
+        };
+
+        assert_eq!(hincrby(5), RespValue::Integer(5));
+        assert_eq!(hincrby(-7), RespValue::Integer(-2));
+        assert_eq!(hincrby(i64::MAX), RespValue::Integer(i64::MAX - 2));
+        assert_eq!(
+            hincrby(i64::MAX),
+            RespValue::Error("ERR increment or decrement would overflow".to_string())
+        );
+    }
+
+    #[test]
+    fn test_hincrbyfloat_returns_bulk_string() {
+        let storage = new_storage();
+        hset(&storage, "h", &[("f", "10.5")]);
+
+        let reply = run(
+            Command::HIncrByFloat {
+                key: "h".into(),
+                field: "f".into(),
+                increment: 0.1,
+            },
+            &storage,
+        );
+
+        assert_eq!(reply, RespValue::BulkString(Some("10.6".into())));
+    }
+
+    #[test]
+    fn test_hincrby_on_non_numeric_field() {
+        let storage = new_storage();
+        hset(&storage, "h", &[("f", "abc")]);
+
+        let reply = run(
+            Command::HIncrBy {
+                key: "h".into(),
+                field: "f".into(),
+                increment: 1,
+            },
+            &storage,
+        );
+
+        assert_eq!(
+            reply,
+            RespValue::Error("ERR hash value is not an integer".to_string())
+        );
+    }
+
+    #[test]
+    fn test_sscan_with_match() {
+        let storage = new_storage();
+        sadd(&storage, "s", &["apple", "avocado", "banana"]);
+
+        let reply = run(
+            Command::SScan {
+                key: "s".into(),
+                cursor: 0,
+                pattern: Some("a*".to_string()),
+                count: 10,
+            },
+            &storage,
+        );
+
+        let RespValue::Array(Some(parts)) = reply else {
+            panic!("SSCAN should return an array");
+        };
+        assert_eq!(parts[0], RespValue::BulkString(Some("0".into())));
+        let RespValue::Array(Some(members)) = &parts[1] else {
+            panic!("SSCAN members should be an array");
+        };
+        let mut members: Vec<_> = members.iter().collect();
+        members.sort_by_key(|member| format!("{:?}", member));
+        assert_eq!(
+            members,
+            [
+                &RespValue::BulkString(Some("apple".into())),
+                &RespValue::BulkString(Some("avocado".into())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_commands_work_after_storage_is_poisoned() {
+        let storage = new_storage();
+        set(&storage, "k", "v");
+
+        std::thread::scope(|scope| {
+            let poisoner = scope.spawn(|| {
+                let _guard = storage.get(0).lock().unwrap();
+                panic!("poison the storage mutex");
+            });
+            assert!(poisoner.join().is_err());
+        });
+        assert!(storage.get(0).is_poisoned());
+
+        assert_eq!(
+            run(Command::Get { key: "k".into() }, &storage,),
+            RespValue::BulkString(Some("v".into()))
+        );
+        set(&storage, "k", "w");
+        assert_eq!(ttl(&storage, "k"), -1);
+        assert_eq!(
+            run(Command::Get { key: "k".into() }, &storage,),
+            RespValue::BulkString(Some("w".into()))
+        );
+    }
+
+    #[test]
+    fn test_object_refcount() {
+        let storage = new_storage();
+        set(&storage, "k", "v");
+        let refcount = |key: &str| run(Command::ObjectRefCount { key: key.into() }, &storage);
+
+        assert_eq!(refcount("k"), RespValue::Integer(1));
+        assert_eq!(
+            refcount("missing"),
+            RespValue::Error("ERR no such key".to_string())
+        );
+    }
+
+    #[test]
+    fn test_object_encoding_promotes_collections() {
+        let storage = new_storage();
+        let mut ctx = ConnectionContext::default();
+        let mut encoding = |key: &str| {
+            handle_command(
+                Command::ObjectEncoding { key: key.into() },
+                &storage,
+                &mut ctx,
+            )
+        };
+        sadd(&storage, "set", &["1", "2", "3"]);
+        hset(&storage, "hash", &[("f", "v")]);
+        rpush(&storage, "list", &["a"]);
+
+        assert_eq!(
+            encoding("set"),
+            RespValue::BulkString(Some(b"intset".to_vec()))
+        );
+        assert_eq!(
+            encoding("hash"),
+            RespValue::BulkString(Some(b"listpack".to_vec()))
+        );
+        assert_eq!(
+            encoding("list"),
+            RespValue::BulkString(Some(b"listpack".to_vec()))
+        );
+        assert_eq!(
+            encoding("missing"),
+            RespValue::Error("ERR no such key".to_string())
+        );
+
+        sadd(&storage, "set", &["a"]);
+        assert_eq!(
+            encoding("set"),
+            RespValue::BulkString(Some(b"hashtable".to_vec()))
+        );
+    }
+
+    #[test]
+    fn test_object_encoding_follows_configured_limits() {
+        let storage = new_storage();
+        let mut ctx = ConnectionContext::default();
+        sadd(&storage, "set", &["1", "2", "3"]);
+        zadd(&storage, "zset", &[(1.0, "a"), (2.0, "b")]);
+        for (parameter, value) in [
+            ("set-max-intset-entries", "2"),
+            ("zset-max-listpack-entries", "1"),
+        ] {
+            handle_command(
+                Command::ConfigSet {
+                    parameter: parameter.to_string(),
+                    value: value.to_string(),
+                },
+                &storage,
+                &mut ctx,
+            );
+        }
+
+        for (key, expected) in [("set", "hashtable"), ("zset", "skiplist")] {
+            assert_eq!(
+                handle_command(
+                    Command::ObjectEncoding { key: key.into() },
+                    &storage,
+                    &mut ctx
+                ),
+                RespValue::BulkString(Some(expected.as_bytes().to_vec()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_object_freq_without_lfu_policy() {
+        let storage = new_storage();
+        set(&storage, "k", "v");
+        let freq = |key: &str| run(Command::ObjectFreq { key: key.into() }, &storage);
+
+        assert_eq!(
+            freq("missing"),
+            RespValue::Error("ERR no such key".to_string())
+        );
+        assert!(matches!(freq("k"), RespValue::Error(e) if e.contains("LFU maxmemory policy")));
+    }
+
+    #[test]
+    fn test_mget_returns_nil_for_missing_and_wrong_type_keys() {
+        let storage = new_storage();
+        set(&storage, "string", "value");
+        rpush(&storage, "list", &["a"]);
+
+        let reply = run(
+            Command::MGet {
+                keys: vec!["string".into(), "missing".into(), "list".into()],
+            },
+            &storage,
+        );
+
+        assert_eq!(
+            reply,
+            RespValue::Array(Some(vec![
+                RespValue::BulkString(Some("value".into())),
+                RespValue::BulkString(None),
+                RespValue::BulkString(None),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_mget_single_key_is_still_an_array() {
+        let storage = new_storage();
+        set(&storage, "k", "v");
+
+        let reply = run(
+            Command::MGet {
+                keys: vec!["k".into()],
+            },
+            &storage,
+        );
+
+        assert_eq!(reply, bulk_strings(&["v"]));
+    }
+
+    #[test]
+    fn test_time_returns_seconds_and_micros() {
+        let storage = new_storage();
+
+        let RespValue::Array(Some(parts)) = run(Command::Time, &storage) else {
+            panic!("TIME should reply with an array");
+        };
+        assert_eq!(parts.len(), 2);
+
+        let RespValue::BulkString(Some(secs)) = &parts[0] else {
+            panic!("seconds should be a bulk string");
+        };
+        let secs = std::str::from_utf8(secs).unwrap().parse::<u64>().unwrap();
+        // 2023-11-14, comfortably before any clock this test will run on
+        assert!(secs > 1_700_000_000);
+
+        let RespValue::BulkString(Some(micros)) = &parts[1] else {
+            panic!("microseconds should be a bulk string");
+        };
+        assert!(std::str::from_utf8(micros).unwrap().parse::<u32>().unwrap() < 1_000_000);
+    }
+
+    #[test]
+    fn test_role_reports_standalone_master() {
+        let storage = new_storage();
+
+        assert_eq!(
+            run(Command::Role, &storage),
+            RespValue::Array(Some(vec![
+                RespValue::BulkString(Some("master".into())),
+                RespValue::Integer(0),
+                RespValue::Array(Some(vec![])),
+            ]))
+        );
+    }
+
+    fn zadd(storage: &Databases, key: &str, pairs: &[(f64, &str)]) -> RespValue {
+        run(
+            Command::ZAdd {
+                key: key.into(),
+                pairs: pairs
+                    .iter()
+                    .map(|(score, member)| (*score, member.as_bytes().to_vec()))
+                    .collect(),
+            },
+            storage,
+        )
+    }
+
+    #[test]
+    fn test_zincrby_moves_member_rank() {
+        let storage = new_storage();
+        assert_eq!(
+            zadd(&storage, "z", &[(1.0, "a"), (2.0, "b"), (3.0, "c")]),
+            RespValue::Integer(3)
+        );
+        let zrank = |member: &str| {
+            run(
+                Command::ZRank {
+                    key: "z".into(),
+                    member: member.into(),
+                },
+                &storage,
+            )
+        };
+
+        assert_eq!(zrank("a"), RespValue::Integer(0));
+        let reply = run(
+            Command::ZIncrBy {
+                key: "z".into(),
+                increment: 2.5,
+                member: "a".into(),
+            },
+            &storage,
+        );
+
+        assert_eq!(reply, RespValue::BulkString(Some("3.5".into())));
+        assert_eq!(zrank("a"), RespValue::Integer(2));
+        assert_eq!(zrank("b"), RespValue::Integer(0));
+        assert_eq!(zrank("missing"), RespValue::BulkString(None));
+    }
+
+    #[test]
+    fn test_zrange_withscores() {
+        let storage = new_storage();
+        zadd(&storage, "z", &[(2.0, "b"), (1.0, "a"), (1.5, "c")]);
+
+        let reply = run(
+            Command::ZRange {
+                key: "z".into(),
+                start: 0,
+                stop: -2,
+                withscores: true,
+            },
+            &storage,
+        );
+
+        assert_eq!(reply, bulk_strings(&["a", "1", "c", "1.5"]));
+    }
+
+    #[test]
+    fn test_zrangebyscore_with_limit() {
+        let storage = new_storage();
+        zadd(
+            &storage,
+            "z",
+            &[(1.0, "a"), (2.0, "b"), (3.0, "c"), (4.0, "d")],
+        );
+        let zrangebyscore = |min: &str, max: &str, limit: Option<(i64, i64)>| {
+            run(
+                Command::ZRangeByScore {
+                    key: "z".into(),
+                    min: ScoreBound::parse(min).unwrap(),
+                    max: ScoreBound::parse(max).unwrap(),
+                    withscores: false,
+                    limit,
+                },
+                &storage,
+            )
+        };
+
+        assert_eq!(zrangebyscore("(1", "3", None), bulk_strings(&["b", "c"]));
+        assert_eq!(
+            zrangebyscore("-inf", "+inf", Some((1, 2))),
+            bulk_strings(&["b", "c"])
+        );
+        assert_eq!(
+            zrangebyscore("-inf", "+inf", Some((2, -1))),
+            bulk_strings(&["c", "d"])
+        );
+        assert_eq!(
+            zrangebyscore("-inf", "+inf", Some((-1, 1))),
+            bulk_strings(&[])
+        );
+    }
+
+    #[test]
+    fn test_zpopmin_single_member() {
+        let storage = new_storage();
+        zadd(&storage, "z", &[(2.0, "b"), (1.0, "a"), (3.0, "c")]);
+
+        assert_eq!(
+            run(
+                Command::ZPopMin {
+                    key: "z".into(),
+                    count: None,
+                },
+                &storage,
+            ),
+            bulk_strings(&["a", "1"])
+        );
+        assert_eq!(
+            run(
+                Command::ZPopMax {
+                    key: "z".into(),
+                    count: Some(1),
+                },
+                &storage,
+            ),
+            bulk_strings(&["c", "3"])
+        );
+        assert_eq!(
+            run(Command::ZCard { key: "z".into() }, &storage),
+            RespValue::Integer(1)
+        );
+    }
+
+    #[test]
+    fn test_zpopmax_more_than_exist_removes_key() {
+        let storage = new_storage();
+        zadd(&storage, "z", &[(1.0, "a"), (2.5, "b")]);
+        let zpopmax = |count| {
+            run(
+                Command::ZPopMax {
+                    key: "z".into(),
+                    count,
+                },
+                &storage,
+            )
+        };
+
+        assert_eq!(zpopmax(Some(0)), bulk_strings(&[]));
+        assert_eq!(zpopmax(Some(5)), bulk_strings(&["b", "2.5", "a", "1"]));
+        assert!(!lock_storage(storage.get(0)).has("z".into()));
+        assert_eq!(zpopmax(None), bulk_strings(&[]));
+    }
+
+    #[test]
+    fn test_zrem_last_member_removes_key() {
+        let storage = new_storage();
+        zadd(&storage, "z", &[(1.0, "a"), (2.0, "b")]);
+        let zrem = |members: &[&str]| {
+            run(
+                Command::ZRem {
+                    key: "z".into(),
+                    members: members
+                        .iter()
+                        .map(|member| member.as_bytes().to_vec())
+                        .collect(),
+                },
+                &storage,
+            )
+        };
+
+        assert_eq!(zrem(&["a", "missing"]), RespValue::Integer(1));
+        assert_eq!(
+            run(Command::ZCard { key: "z".into() }, &storage),
+            RespValue::Integer(1)
+        );
+        assert_eq!(zrem(&["b"]), RespValue::Integer(1));
+        assert_eq!(
+            run(
+                Command::Exists {
+                    keys: vec!["z".into()]
+                },
+                &storage
+            ),
+            RespValue::Integer(0)
+        );
+    }
+
+    #[test]
+    fn test_zrevrange_descending_withscores() {
+        let storage = new_storage();
+        zadd(&storage, "z", &[(1.0, "a"), (2.5, "b"), (3.0, "c")]);
+
+        let reply = run(
+            Command::ZRevRange {
+                key: "z".into(),
+                start: 0,
+                stop: 1,
+                withscores: true,
+            },
+            &storage,
+        );
+
+        assert_eq!(reply, bulk_strings(&["c", "3", "b", "2.5"]));
+    }
+
+    #[test]
+    fn test_zrevrangebyscore_descending_with_limit() {
+        let storage = new_storage();
+        zadd(
+            &storage,
+            "z",
+            &[(1.0, "a"), (2.0, "b"), (3.0, "c"), (4.0, "d")],
+        );
+        let zrevrangebyscore = |max: &str, min: &str, withscores: bool, limit| {
+            run(
+                Command::ZRevRangeByScore {
+                    key: "z".into(),
+                    max: ScoreBound::parse(max).unwrap(),
+                    min: ScoreBound::parse(min).unwrap(),
+                    withscores,
+                    limit,
+                },
+                &storage,
+            )
+        };
+
+        assert_eq!(
+            zrevrangebyscore("(4", "2", true, None),
+            bulk_strings(&["c", "3", "b", "2"])
+        );
+        assert_eq!(
+            zrevrangebyscore("+inf", "-inf", false, Some((1, 2))),
+            bulk_strings(&["c", "b"])
+        );
+    }
+
+    #[test]
+    fn test_zcount_inclusive_and_exclusive() {
+        let storage = new_storage();
+        zadd(&storage, "z", &[(1.0, "a"), (2.0, "b"), (3.0, "c")]);
+        let zcount = |min: &str, max: &str| {
+            run(
+                Command::ZCount {
+                    key: "z".into(),
+                    min: ScoreBound::parse(min).unwrap(),
+                    max: ScoreBound::parse(max).unwrap(),
+                },
+                &storage,
+            )
+        };
+
+        assert_eq!(zcount("1", "3"), RespValue::Integer(3));
+        assert_eq!(zcount("(1", "(3"), RespValue::Integer(1));
+        assert_eq!(zcount("-inf", "(2"), RespValue::Integer(1));
+    }
+
+    #[test]
+    fn test_zrangebylex_mixed_bounds() {
+        let storage = new_storage();
+        zadd(
+            &storage,
+            "z",
+            &[(0.0, "a"), (0.0, "b"), (0.0, "c"), (0.0, "d")],
+        );
+
+        let reply = run(
+            Command::ZRangeByLex {
+                key: "z".into(),
+                min: LexBound::parse("[a").unwrap(),
+                max: LexBound::parse("(c").unwrap(),
+            },
+            &storage,
+        );
+
+        assert_eq!(reply, bulk_strings(&["a", "b"]));
+    }
+
+    #[test]
+    fn test_exec_runs_queued_commands_in_order() {
+        let storage = new_storage();
+        let mut ctx = ConnectionContext::default();
+
+        handle_command(Command::Multi, &storage, &mut ctx);
+        let queued = handle_command(
+            Command::Set {
+                key: "k".into(),
+                value: "1".into(),
+                condition: None,
+                get: false,
+            },
+            &storage,
+            &mut ctx,
+        );
+        handle_command(Command::Incr { key: "k".into() }, &storage, &mut ctx);
+
+        assert_eq!(queued, RespValue::SimpleString("QUEUED".to_string()));
+        assert_eq!(
+            run(Command::Get { key: "k".into() }, &storage),
+            RespValue::BulkString(None)
+        );
+        assert_eq!(
+            handle_command(Command::Exec, &storage, &mut ctx),
+            RespValue::Array(Some(vec![
+                RespValue::SimpleString("OK".to_string()),
+                RespValue::Integer(2),
+            ]))
+        );
+        assert!(ctx.transaction.is_none());
+    }
+
+    #[test]
+    fn test_exec_holds_database_locks_until_done() {
+        let storage = Arc::new(new_storage());
+        let mut ctx = ConnectionContext::default();
+        handle_command(Command::Multi, &storage, &mut ctx);
+        set(&storage, "k", "1");
+        for command in [
+            Command::DebugSleep { seconds: 0.2 },
+            Command::Get { key: "k".into() },
+        ] {
+            handle_command(command, &storage, &mut ctx);
+        }
+
+        let exec = {
+            let storage = storage.clone();
+            std::thread::spawn(move || handle_command(Command::Exec, &storage, &mut ctx))
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        set(&storage, "k", "2");
+
+        // The write waited for EXEC, so the queued GET still saw the old value
+        assert_eq!(
+            exec.join().unwrap(),
+            RespValue::Array(Some(vec![
+                RespValue::SimpleString("OK".to_string()),
+                RespValue::BulkString(Some(b"1".to_vec())),
+            ]))
+        );
+        assert_eq!(
+            run(Command::Get { key: "k".into() }, &storage),
+            RespValue::BulkString(Some(b"2".to_vec()))
+        );
+    }
+
+    #[test]
+    fn test_blocking_pop_in_exec_does_not_wait() {
+        let storage = new_storage();
+        let mut ctx = ConnectionContext::default();
+        handle_command(Command::Multi, &storage, &mut ctx);
+        handle_command(
+            Command::BRPop {
+                keys: vec!["empty".into()],
+                timeout: 0.0,
+            },
+            &storage,
+            &mut ctx,
+        );
+
+        assert_eq!(
+            handle_command(Command::Exec, &storage, &mut ctx),
+            RespValue::Array(Some(vec![RespValue::Array(None)]))
+        );
+        assert!(!ctx.in_exec);
+    }
+
+    #[test]
+    fn test_exec_after_queuing_error_aborts() {
+        let storage = new_storage();
+        let mut ctx = ConnectionContext::default();
+
+        handle_command(Command::Multi, &storage, &mut ctx);
+        handle_command(Command::Incr { key: "k".into() }, &storage, &mut ctx);
+        ctx.transaction_dirty = true;
+
+        assert_eq!(
+            handle_command(Command::Exec, &storage, &mut ctx),
+            RespValue::Error(
+                "EXECABORT Transaction discarded because of previous errors.".to_string()
+            )
+        );
+        assert!(ctx.transaction.is_none());
+        assert!(!ctx.transaction_dirty);
+        assert_eq!(
+            run(Command::Get { key: "k".into() }, &storage),
+            RespValue::BulkString(None)
+        );
+    }
+
+    #[test]
+    fn test_transaction_commands_out_of_place() {
+        let storage = new_storage();
+        let mut ctx = ConnectionContext::default();
+
+        assert_eq!(
+            handle_command(Command::Exec, &storage, &mut ctx),
+            RespValue::Error("ERR EXEC without MULTI".to_string())
+        );
+        assert_eq!(
+            handle_command(Command::Discard, &storage, &mut ctx),
+            RespValue::Error("ERR DISCARD without MULTI".to_string())
+        );
+
+        handle_command(Command::Multi, &storage, &mut ctx);
+        assert_eq!(
+            handle_command(Command::Multi, &storage, &mut ctx),
+            RespValue::Error("ERR MULTI calls can not be nested".to_string())
+        );
+        // The nested MULTI leaves the open transaction in place
+        assert_eq!(ctx.transaction, Some(Vec::new()));
+    }
+
+    #[test]
+    fn test_string_commands_on_list_are_wrongtype() {
+        let storage = new_storage();
+        rpush(&storage, "list", &["a"]);
+        let key = || "list".to_string();
+        let commands = [
+            Command::Append {
+                key: key().into_bytes(),
+                value: "x".into(),
+            },
+            Command::SetRange {
+                key: key().into_bytes(),
+                offset: 0,
+                value: "x".into(),
+            },
+            Command::SetRange {
+                key: key().into_bytes(),
+                offset: 0,
+                value: String::new().into_bytes(),
+            },
+            Command::SetBit {
+                key: key().into_bytes(),
+                offset: "0".to_string(),
+                value: "1".to_string(),
+            },
+            Command::GetRange {
+                key: key().into_bytes(),
+                start: 0,
+                end: -1,
+            },
+            Command::StrLen {
+                key: key().into_bytes(),
+            },
+            Command::GetBit {
+                key: key().into_bytes(),
+                offset: "0".to_string(),
+            },
+            Command::BitCount {
+                key: key().into_bytes(),
+                range: None,
+            },
+        ];
+
+        for command in commands {
+            let name = command.name();
+            assert_eq!(
+                run(command, &storage),
+                RespValue::Error(StorageError::WrongType.to_string()),
+                "{}",
+                name
+            );
+        }
+        assert_eq!(
+            storage.get(0).lock().unwrap().value(b"list"),
+            Some(&Value::List(std::collections::VecDeque::from([
+                b"a".to_vec()
+            ])))
+        );
+    }
+
+    #[test]
+    fn test_getrange() {
+        let storage = new_storage();
+        set(&storage, "k", "Hello World");
+        let getrange = |key: &str, start: i64, end: i64| {
+            run(
+                Command::GetRange {
+                    key: key.into(),
+                    start,
+                    end,
+                },
+                &storage,
+            )
+        };
+
+        assert_eq!(
+            getrange("k", 0, 4),
+            RespValue::BulkString(Some("Hello".into()))
+        );
+        assert_eq!(
+            getrange("k", -5, -1),
+            RespValue::BulkString(Some("World".into()))
+        );
+        assert_eq!(
+            getrange("k", 5, 2),
+            RespValue::BulkString(Some(String::new().into_bytes()))
+        );
+        assert_eq!(
+            getrange("missing", 0, -1),
+            RespValue::BulkString(Some(String::new().into_bytes()))
+        );
+    }
+
+    /// Pins the replies of INCR, DECR, INCRBY and DECRBY, including their
+    /// error strings, at the boundaries clients have tripped over.
+    mod counters {
+        use super::*;
+
+        const NOT_AN_INTEGER: &str = "ERR value is not an integer or out of range";
+        const OVERFLOW: &str = "ERR increment or decrement would overflow";
+
+        fn incr(storage: &Databases, key: &str) -> RespValue {
+            run(Command::Incr { key: key.into() }, storage)
+        }
+
+        fn decr(storage: &Databases, key: &str) -> RespValue {
+            run(Command::Decr { key: key.into() }, storage)
+        }
+
+        fn incrby(storage: &Databases, key: &str, value: &str) -> RespValue {
+            run(
+                Command::IncrBy {
+                    key: key.into(),
+                    value: value.to_string(),
+                },
+                storage,
+            )
+        }
+
+        fn decrby(storage: &Databases, key: &str, value: &str) -> RespValue {
+            run(
+                Command::DecrBy {
+                    key: key.into(),
+                    value: value.to_string(),
+                },
+                storage,
+            )
+        }
+
+        fn error(message: &str) -> RespValue {
+            RespValue::Error(message.to_string())
+        }
+
+        fn get(storage: &Databases, key: &str) -> RespValue {
+            run(Command::Get { key: key.into() }, storage)
+        }
+
+        #[test]
+        fn test_fresh_keys_start_at_zero() {
+            let storage = new_storage();
+
+            assert_eq!(incr(&storage, "a"), RespValue::Integer(1));
+            assert_eq!(decr(&storage, "b"), RespValue::Integer(-1));
+            assert_eq!(incrby(&storage, "c", "5"), RespValue::Integer(5));
+            assert_eq!(decrby(&storage, "d", "5"), RespValue::Integer(-5));
+        }
+
+        #[test]
+        fn test_results_go_negative() {
+            let storage = new_storage();
+            set(&storage, "k", "3");
+
+            assert_eq!(decrby(&storage, "k", "10"), RespValue::Integer(-7));
+            assert_eq!(incrby(&storage, "k", "-3"), RespValue::Integer(-10));
+            assert_eq!(
+                get(&storage, "k"),
+                RespValue::BulkString(Some("-10".into()))
+            );
+        }
+
+        #[test]
+        fn test_overflow_at_max() {
+            let storage = new_storage();
+            set(&storage, "k", &i64::MAX.to_string());
+
+            assert_eq!(incr(&storage, "k"), error(OVERFLOW));
+            assert_eq!(incrby(&storage, "k", "1"), error(OVERFLOW));
+            assert_eq!(decrby(&storage, "k", "-1"), error(OVERFLOW));
+            assert_eq!(
+                get(&storage, "k"),
+                RespValue::BulkString(Some(i64::MAX.to_string().into_bytes()))
+            );
+        }
+
+        #[test]
+        fn test_underflow_at_min() {
+            let storage = new_storage();
+            set(&storage, "k", &i64::MIN.to_string());
+
+            assert_eq!(decr(&storage, "k"), error(OVERFLOW));
+            assert_eq!(incrby(&storage, "k", "-1"), error(OVERFLOW));
+            assert_eq!(
+                incrby(&storage, "k", &i64::MAX.to_string()),
+                RespValue::Integer(-1)
+            );
+            assert_eq!(
+                decrby(&storage, "zero", &i64::MIN.to_string()),
+                error(OVERFLOW)
+            );
+        }
+
+        #[test]
+        fn test_non_canonical_stored_values() {
+            let storage = new_storage();
+            for value in [" 10", "10 ", "+10", "010", "1.5", "abc", ""] {
+                set(&storage, "k", value);
+
+                assert_eq!(incr(&storage, "k"), error(NOT_AN_INTEGER), "{:?}", value);
+                assert_eq!(
+                    get(&storage, "k"),
+                    RespValue::BulkString(Some(value.into()))
+                );
+            }
+        }
+
+        #[test]
+        fn test_non_integer_amounts() {
+            let storage = new_storage();
+            set(&storage, "k", "1");
+
+            for amount in ["1.5", "abc", " 1", "+1", "", "9223372036854775808"] {
+                assert_eq!(
+                    incrby(&storage, "k", amount),
+                    error(NOT_AN_INTEGER),
+                    "{:?}",
+                    amount
+                );
+                assert_eq!(
+                    decrby(&storage, "k", amount),
+                    error(NOT_AN_INTEGER),
+                    "{:?}",
+                    amount
+                );
+            }
+            assert_eq!(get(&storage, "k"), RespValue::BulkString(Some("1".into())));
+        }
+    }
+
+    #[test]
+    fn test_debug_object() {
+        let storage = new_storage();
+        set(&storage, "counter", "42");
+        set(&storage, "greeting", "hello");
+        let debug_object = |key: &str| run(Command::DebugObject { key: key.into() }, &storage);
+
+        assert!(matches!(
+            debug_object("counter"),
+            RespValue::BulkString(Some(info)) if String::from_utf8_lossy(&info).contains(" encoding:int ")
+        ));
+        assert!(matches!(
+            debug_object("greeting"),
+            RespValue::BulkString(Some(info))
+                if info.starts_with(b"Value at:0x")
+                    && String::from_utf8_lossy(&info).contains(" encoding:embstr ")
+        ));
+        assert_eq!(
+            debug_object("missing"),
+            RespValue::Error("ERR no such key".to_string())
+        );
+    }
+
+    #[test]
+    fn test_hello_auth_against_password_protected_server() {
+        let storage = new_storage();
+        let mut ctx = ConnectionContext {
+            config: std::sync::Arc::new(std::sync::RwLock::new(Config {
+                requirepass: "secret".to_string(),
+                ..Config::default()
+            })),
+            ..ConnectionContext::default()
+        };
+        let hello = |protover, password: &str, ctx: &mut ConnectionContext| {
+            handle_command(
+                Command::Hello {
+                    protover: Some(protover),
+                    auth: Some(("default".to_string(), password.to_string())),
+                    setname: Some("worker".to_string()),
+                },
+                &storage,
+                ctx,
+            )
+        };
+        let get = |ctx: &mut ConnectionContext| {
+            handle_command(Command::Get { key: "k".into() }, &storage, ctx)
+        };
+
+        assert_eq!(
+            get(&mut ctx),
+            RespValue::Error("NOAUTH Authentication required.".to_string())
+        );
+        assert!(matches!(
+            handle_command(
+                Command::Hello {
+                    protover: None,
+                    auth: None,
+                    setname: None
+                },
+                &storage,
+                &mut ctx
+            ),
+            RespValue::Error(e) if e.starts_with("NOAUTH HELLO must be called")
+        ));
+        // The protocol version is checked before the credentials
+        assert_eq!(
+            hello(3, "secret", &mut ctx),
+            RespValue::Error("NOPROTO unsupported protocol version".to_string())
+        );
+        assert!(!ctx.authenticated);
+        assert_eq!(
+            hello(2, "wrong", &mut ctx),
+            RespValue::Error(
+                "WRONGPASS invalid username-password pair or user is disabled.".to_string()
+            )
+        );
+
+        assert!(matches!(
+            hello(2, "secret", &mut ctx),
+            RespValue::Array(Some(_))
+        ));
+        assert_eq!(get(&mut ctx), RespValue::BulkString(None));
+        assert_eq!(
+            handle_command(Command::ClientGetName, &storage, &mut ctx),
+            RespValue::BulkString(Some("worker".into()))
+        );
+    }
+
+    #[test]
+    fn test_auth() {
+        let storage = new_storage();
+        let auth = |username: Option<&str>, password: &str, ctx: &mut ConnectionContext| {
+            handle_command(
+                Command::Auth {
+                    username: username.map(str::to_string),
+                    password: password.to_string(),
+                },
+                &storage,
+                ctx,
+            )
+        };
+
+        let mut ctx = ConnectionContext::default();
+        assert!(matches!(
+            auth(None, "secret", &mut ctx),
+            RespValue::Error(e) if e.starts_with("ERR AUTH <password> called without")
+        ));
+        assert_eq!(
+            auth(Some("default"), "anything", &mut ctx),
+            RespValue::SimpleString("OK".to_string())
+        );
+
+        let mut ctx = ConnectionContext {
+            config: std::sync::Arc::new(std::sync::RwLock::new(Config {
+                requirepass: "secret".to_string(),
+                ..Config::default()
+            })),
+            ..ConnectionContext::default()
+        };
+        assert!(matches!(
+            auth(Some("admin"), "secret", &mut ctx),
+            RespValue::Error(e) if e.starts_with("WRONGPASS")
+        ));
+        assert_eq!(
+            auth(None, "secret", &mut ctx),
+            RespValue::SimpleString("OK".to_string())
+        );
+        assert!(ctx.authenticated);
+    }
+
+    #[test]
+    fn test_pubsub_introspection() {
+        let storage = new_storage();
+        let mut subscribers = Vec::new();
+        for channels in [&["numsub:a", "numsub:b"][..], &["numsub:a"]] {
+            let mut ctx = ConnectionContext {
+                id: crate::connection::next_client_id(),
+                ..ConnectionContext::default()
+            };
+            crate::pubsub::subscribe(
+                &mut ctx,
+                crate::pubsub::Kind::Channel,
+                channels.iter().map(|c| c.to_string()).collect(),
+                || Ok(Box::new(std::io::sink())),
+            )
+            .unwrap();
+            subscribers.push(crate::pubsub::DisconnectGuard(ctx.id));
+        }
+
+        assert_eq!(
+            run(
+                Command::PubSubChannels {
+                    pattern: Some("numsub:*".to_string())
+                },
+                &storage
+            ),
+            bulk_strings(&["numsub:a", "numsub:b"])
+        );
+        assert_eq!(
+            run(
+                Command::PubSubNumSub {
+                    channels: vec!["numsub:a".to_string(), "numsub:c".to_string()]
+                },
+                &storage
+            ),
+            RespValue::Array(Some(vec![
+                RespValue::BulkString(Some("numsub:a".into())),
+                RespValue::Integer(2),
+                RespValue::BulkString(Some("numsub:c".into())),
+                RespValue::Integer(0),
+            ]))
+        );
+        assert!(matches!(
+            run(Command::PubSubNumPat, &storage),
+            RespValue::Integer(n) if n >= 0
+        ));
+    }
+
+    #[test]
+    fn test_disabled_command_is_unknown() {
+        let storage = new_storage();
+        set(&storage, "k", "v");
+        let mut ctx = ConnectionContext {
+            config: std::sync::Arc::new(std::sync::RwLock::new(Config {
+                disabled_commands: vec!["flushall".to_string()],
+                ..Config::default()
+            })),
+            ..ConnectionContext::default()
+        };
+
+        assert_eq!(
+            handle_command(
+                Command::FlushAll {
+                    asynchronous: false
+                },
+                &storage,
+                &mut ctx
+            ),
+            RespValue::Error("unknown command 'FLUSHALL'".to_string())
+        );
+        assert_eq!(
+            handle_command(Command::Get { key: "k".into() }, &storage, &mut ctx),
+            RespValue::BulkString(Some("v".into()))
+        );
+    }
+
+    #[test]
+    fn test_empty_and_nil_aggregate_replies_on_the_wire() {
+        let storage = new_storage();
+        let wire = |reply: RespValue| {
+            let mut writer = std::io::BufWriter::new(Vec::new());
+            crate::resp::write_resp(&reply, &mut writer).unwrap();
+            String::from_utf8(writer.into_inner().unwrap()).unwrap()
+        };
+        let missing = || "no-such-key".to_string();
+
+        let mut ctx = ConnectionContext::default();
+        handle_command(Command::Multi, &storage, &mut ctx);
+        assert_eq!(
+            wire(handle_command(Command::Exec, &storage, &mut ctx)),
+            "*0\r\n"
+        );
+        let blpop = Command::BLPop {
+            keys: vec![missing().into_bytes()],
+            timeout: 0.01,
+        };
+        assert_eq!(wire(run(blpop, &storage)), "*-1\r\n");
+        let sort = Command::Sort {
+            key: missing().into_bytes(),
+            alpha: false,
+            descending: false,
+            limit: None,
+        };
+        assert_eq!(wire(run(sort, &storage)), "*0\r\n");
+        let mget = Command::MGet {
+            keys: vec![missing().into_bytes()],
+        };
+        assert_eq!(wire(run(mget, &storage)), "*1\r\n$-1\r\n");
+        for command in [
+            Command::Keys {
+                pattern: "no-such-*".to_string(),
+            },
+            Command::SMembers {
+                key: missing().into_bytes(),
+            },
+            Command::LRange {
+                key: missing().into_bytes(),
+                start: 0,
+                stop: -1,
+            },
+        ] {
+            assert_eq!(wire(run(command, &storage)), "*0\r\n");
+        }
+    }
+
+    #[test]
+    fn test_key_type_check_matches_handlers() {
+        let seeded = || {
+            let storage = new_storage();
+            set(&storage, "string", "1");
+            let mut db = lock_storage(storage.get(0));
+            db.sadd("set".into(), vec![b"a".to_vec()]).unwrap();
+            db.as_list_mut("list".into())
+                .unwrap()
+                .push_back(b"a".to_vec());
+            db.as_hash_mut("hash".into())
+                .unwrap()
+                .insert(b"f".to_vec(), b"v".to_vec());
+            db.as_zset_mut("zset".into())
+                .unwrap()
+                .insert(b"a".to_vec(), 1.0);
+            drop(db);
+            storage
+        };
+        let key = |key: &str| key.to_string();
+        let cases: Vec<Box<dyn Fn() -> Command>> = vec![
+            Box::new(|| Command::Get {
+                key: key("list").into_bytes(),
+            }),
+            Box::new(|| Command::Get {
+                key: key("string").into_bytes(),
+            }),
+            Box::new(|| Command::Incr {
+                key: key("hash").into_bytes(),
+            }),
+            Box::new(|| Command::Append {
+                key: key("zset").into_bytes(),
+                value: key("x").into_bytes(),
+            }),
+            Box::new(|| Command::LPush {
+                key: key("string").into_bytes(),
+                values: vec![key("x").into_bytes()],
+            }),
+            Box::new(|| Command::LRange {
+                key: key("list").into_bytes(),
+                start: 0,
+                stop: -1,
+            }),
+            Box::new(|| Command::SAdd {
+                key: key("list").into_bytes(),
+                members: vec![key("x").into_bytes()],
+            }),
+            Box::new(|| Command::SInter {
+                keys: vec![key("set").into_bytes(), key("string").into_bytes()],
+            }),
+            Box::new(|| Command::HGet {
+                key: key("zset").into_bytes(),
+                field: key("f").into_bytes(),
+            }),
+            Box::new(|| Command::HGet {
+                key: key("hash").into_bytes(),
+                field: key("f").into_bytes(),
+            }),
+            Box::new(|| Command::ZScore {
+                key: key("set").into_bytes(),
+                member: key("a").into_bytes(),
+            }),
+            Box::new(|| Command::LMove {
+                source: key("list").into_bytes(),
+                destination: key("string").into_bytes(),
+                from: ListEnd::Left,
+                to: ListEnd::Right,
+            }),
+            Box::new(|| Command::BLPop {
+                keys: vec![key("missing").into_bytes(), key("hash").into_bytes()],
+                timeout: 0.01,
+            }),
+        ];
+
+        for command in cases {
+            let name = command().name();
+            let checked = execute(command(), &seeded(), &mut ConnectionContext::default());
+            // The handler alone, relying on the typed storage accessors
+            let storage = seeded();
+            let unchecked = match command_table::lookup(name).and_then(|spec| spec.handler) {
+                Some(handler) => handler(&mut lock_storage(storage.get(0)), command()),
+                None => dispatch(command(), &storage, &mut ConnectionContext::default()),
+            };
+            assert_eq!(checked, unchecked, "{}", name);
+            if let RespValue::Error(e) = &checked {
+                assert!(e.starts_with("WRONGTYPE"), "{}: {}", name, e);
+            }
+        }
+    }
 }
@@ -0,0 +1,377 @@
+use crate::command::Command;
+use crate::command_handler as handler;
+use crate::resp::RespValue;
+use crate::storage::Storage;
+
+/// Runs a command against the selected database, which the caller holds
+/// locked.
+pub type Handler = fn(&mut Storage, Command) -> RespValue;
+
+/// Static metadata for one command, in the shape COMMAND INFO reports it.
+pub struct CommandSpec {
+    pub name: &'static str,
+    /// Argument count including the command name; negative means at least
+    /// that many.
+    pub arity: i64,
+    pub flags: &'static [&'static str],
+    /// Position of the first key argument, or 0 for commands without keys.
+    pub first_key: i64,
+    /// Position of the last key argument; negative counts back from the end.
+    pub last_key: i64,
+    /// Distance between consecutive key arguments.
+    pub step: i64,
+    /// The type, as TYPE names it, that existing keys must hold for the
+    /// command to run; anything else fails with WRONGTYPE up front.
+    pub key_type: Option<&'static str>,
+    /// Runs the command once its keys have passed the type check, under the
+    /// same lock. Commands without one go through the general dispatch.
+    pub handler: Option<Handler>,
+}
+
+const fn spec(
+    name: &'static str,
+    arity: i64,
+    flags: &'static [&'static str],
+    first_key: i64,
+    last_key: i64,
+    step: i64,
+) -> CommandSpec {
+    CommandSpec {
+        name,
+        arity,
+        flags,
+        first_key,
+        last_key,
+        step,
+        key_type: None,
+        handler: None,
+    }
+}
+
+#[rustfmt::skip]
+pub static COMMAND_TABLE: &[CommandSpec] = &[
+    spec("get", 2, &["readonly", "fast"], 1, 1, 1).holding("string", handler::get),
+    spec("mget", -2, &["readonly", "fast"], 1, -1, 1),
+    spec("set", -3, &["write", "denyoom"], 1, 1, 1),
+    spec("incrby", 3, &["write", "denyoom", "fast"], 1, 1, 1).holding("string", handler::incrby),
+    spec("incr", 2, &["write", "denyoom", "fast"], 1, 1, 1).holding("string", handler::incr),
+    spec("decrby", 3, &["write", "denyoom", "fast"], 1, 1, 1).holding("string", handler::decrby),
+    spec("decr", 2, &["write", "denyoom", "fast"], 1, 1, 1).holding("string", handler::decr),
+    spec("del", -2, &["write"], 1, -1, 1),
+    spec("ping", -1, &["fast"], 0, 0, 0),
+    spec("command", -1, &["loading", "stale"], 0, 0, 0),
+    spec("exists", -2, &["readonly", "fast"], 1, -1, 1),
+    spec("expire", -3, &["write", "fast"], 1, 1, 1),
+    spec("persist", 2, &["write", "fast"], 1, 1, 1),
+    spec("ttl", 2, &["readonly", "fast"], 1, 1, 1),
+    spec("expiretime", 2, &["readonly", "fast"], 1, 1, 1),
+    spec("pexpiretime", 2, &["readonly", "fast"], 1, 1, 1),
+    spec("flushall", -1, &["write"], 0, 0, 0),
+    spec("flushdb", -1, &["write"], 0, 0, 0),
+    spec("keys", 2, &["readonly"], 0, 0, 0),
+    spec("time", 1, &["loading", "stale", "fast"], 0, 0, 0),
+    spec("save", 1, &["admin", "noscript"], 0, 0, 0),
+    spec("bgsave", -1, &["admin", "noscript"], 0, 0, 0),
+    spec("lastsave", 1, &["loading", "stale", "fast"], 0, 0, 0),
+    spec("role", 1, &["noscript", "loading", "stale", "fast"], 0, 0, 0),
+    spec("setbit", 4, &["write", "denyoom"], 1, 1, 1).holding("string", handler::setbit),
+    spec("getbit", 3, &["readonly", "fast"], 1, 1, 1).holding("string", handler::getbit),
+    spec("bitcount", -2, &["readonly"], 1, 1, 1).holding("string", handler::bitcount),
+    spec("bitpos", -3, &["readonly"], 1, 1, 1).holding("string", handler::bitpos),
+    spec("sadd", -3, &["write", "denyoom", "fast"], 1, 1, 1).holding("set", handler::sadd),
+    spec("smembers", 2, &["readonly"], 1, 1, 1).holding("set", handler::smembers),
+    spec("smove", 4, &["write", "fast"], 1, 2, 1).holding("set", handler::smove),
+    spec("sinter", -2, &["readonly"], 1, -1, 1).holding("set", handler::sinter),
+    spec("sintercard", -3, &["readonly", "movablekeys"], 0, 0, 0).holding("set", handler::sintercard),
+    spec("sunion", -2, &["readonly"], 1, -1, 1).holding("set", handler::sunion),
+    spec("sdiff", -2, &["readonly"], 1, -1, 1).holding("set", handler::sdiff),
+    spec("sinterstore", -3, &["write", "denyoom"], 1, -1, 1),
+    spec("sunionstore", -3, &["write", "denyoom"], 1, -1, 1),
+    spec("sdiffstore", -3, &["write", "denyoom"], 1, -1, 1),
+    spec("lpush", -3, &["write", "denyoom", "fast"], 1, 1, 1).holding("list", handler::lpush),
+    spec("rpush", -3, &["write", "denyoom", "fast"], 1, 1, 1).holding("list", handler::rpush),
+    spec("lrange", 4, &["readonly"], 1, 1, 1).holding("list", handler::lrange),
+    spec("llen", 2, &["readonly", "fast"], 1, 1, 1).holding("list", handler::llen),
+    spec("lset", 4, &["write", "denyoom"], 1, 1, 1).holding("list", handler::lset),
+    spec("linsert", 5, &["write", "denyoom"], 1, 1, 1).holding("list", handler::linsert),
+    spec("lrem", 4, &["write"], 1, 1, 1).holding("list", handler::lrem),
+    spec("ltrim", 4, &["write"], 1, 1, 1).holding("list", handler::ltrim),
+    spec("rpoplpush", 3, &["write", "denyoom"], 1, 2, 1).holding("list", handler::rpoplpush),
+    spec("lmove", 5, &["write", "denyoom"], 1, 2, 1).holding("list", handler::lmove),
+    spec("select", 2, &["loading", "stale", "fast"], 0, 0, 0),
+    spec("move", 3, &["write", "fast"], 1, 1, 1),
+    spec("copy", -3, &["write", "denyoom"], 1, 2, 1),
+    spec("blpop", -3, &["write", "noscript"], 1, -2, 1).blocking_on("list"),
+    spec("brpop", -3, &["write", "noscript"], 1, -2, 1).blocking_on("list"),
+    spec("getex", -2, &["write", "fast"], 1, 1, 1).holding("string", handler::getex),
+    spec("sort", -2, &["write", "denyoom"], 1, 1, 1),
+    spec("dump", 2, &["readonly"], 1, 1, 1),
+    spec("restore", -4, &["write", "denyoom"], 1, 1, 1),
+    spec("eval", -3, &["noscript", "movablekeys"], 0, 0, 0),
+    spec("evalsha", -3, &["noscript", "movablekeys"], 0, 0, 0),
+    spec("script", -2, &["noscript"], 0, 0, 0),
+    spec("append", 3, &["write", "denyoom", "fast"], 1, 1, 1).holding("string", handler::append),
+    spec("strlen", 2, &["readonly", "fast"], 1, 1, 1).holding("string", handler::strlen),
+    spec("setrange", 4, &["write", "denyoom"], 1, 1, 1).holding("string", handler::setrange),
+    spec("getrange", 4, &["readonly"], 1, 1, 1).holding("string", handler::getrange),
+    spec("scan", -2, &["readonly"], 0, 0, 0),
+    spec("sscan", -3, &["readonly"], 1, 1, 1).holding("set", handler::sscan),
+    spec("hscan", -3, &["readonly"], 1, 1, 1).holding("hash", handler::hscan),
+    spec("mset", -3, &["write", "denyoom"], 1, -1, 2),
+    spec("hset", -4, &["write", "denyoom", "fast"], 1, 1, 1).holding("hash", handler::hset),
+    spec("zadd", -4, &["write", "denyoom", "fast"], 1, 1, 1).holding("zset", handler::zadd),
+    spec("zscore", 3, &["readonly", "fast"], 1, 1, 1).holding("zset", handler::zscore),
+    spec("zrange", -4, &["readonly"], 1, 1, 1).holding("zset", handler::zrange),
+    spec("zrangebyscore", -4, &["readonly"], 1, 1, 1).holding("zset", handler::zrangebyscore),
+    spec("zincrby", 4, &["write", "denyoom", "fast"], 1, 1, 1).holding("zset", handler::zincrby),
+    spec("zrank", 3, &["readonly", "fast"], 1, 1, 1).holding("zset", handler::zrank),
+    spec("zcard", 2, &["readonly", "fast"], 1, 1, 1).holding("zset", handler::zcard),
+    spec("zrem", -3, &["write", "fast"], 1, 1, 1).holding("zset", handler::zrem),
+    spec("zpopmin", -2, &["write", "fast"], 1, 1, 1).holding("zset", handler::zpopmin),
+    spec("zpopmax", -2, &["write", "fast"], 1, 1, 1).holding("zset", handler::zpopmax),
+    spec("zrevrange", -4, &["readonly"], 1, 1, 1).holding("zset", handler::zrevrange),
+    spec("zrevrangebyscore", -4, &["readonly"], 1, 1, 1).holding("zset", handler::zrevrangebyscore),
+    spec("zcount", 4, &["readonly", "fast"], 1, 1, 1).holding("zset", handler::zcount),
+    spec("zrangebylex", 4, &["readonly"], 1, 1, 1).holding("zset", handler::zrangebylex),
+    spec("hsetnx", 4, &["write", "denyoom", "fast"], 1, 1, 1).holding("hash", handler::hsetnx),
+    spec("hmget", -3, &["readonly", "fast"], 1, 1, 1).holding("hash", handler::hmget),
+    spec("hincrby", 4, &["write", "denyoom", "fast"], 1, 1, 1).holding("hash", handler::hincrby),
+    spec("hincrbyfloat", 4, &["write", "denyoom", "fast"], 1, 1, 1).holding("hash", handler::hincrbyfloat),
+    spec("hget", 3, &["readonly", "fast"], 1, 1, 1).holding("hash", handler::hget),
+    spec("multi", 1, &["noscript", "loading", "stale", "fast"], 0, 0, 0),
+    spec("exec", 1, &["noscript", "loading", "stale", "skip_slowlog"], 0, 0, 0),
+    spec("discard", 1, &["noscript", "loading", "stale", "fast"], 0, 0, 0),
+    spec("hello", -1, &["noscript", "loading", "stale", "fast"], 0, 0, 0),
+    spec("client", -2, &["admin", "noscript", "loading", "stale"], 0, 0, 0),
+    spec("auth", -2, &["noscript", "loading", "stale", "fast", "no-auth"], 0, 0, 0),
+    spec("subscribe", -2, &["pubsub", "noscript", "loading", "stale"], 0, 0, 0),
+    spec("unsubscribe", -1, &["pubsub", "noscript", "loading", "stale"], 0, 0, 0),
+    spec("psubscribe", -2, &["pubsub", "noscript", "loading", "stale"], 0, 0, 0),
+    spec("punsubscribe", -1, &["pubsub", "noscript", "loading", "stale"], 0, 0, 0),
+    spec("publish", 3, &["pubsub", "loading", "stale", "fast"], 0, 0, 0),
+    spec("pubsub", -2, &["pubsub", "random", "loading", "stale"], 0, 0, 0),
+    spec("object", -2, &["readonly"], 2, 2, 1),
+    spec("memory", -2, &["readonly"], 2, 2, 1),
+    spec("monitor", 1, &["admin", "noscript"], 0, 0, 0),
+    spec("slowlog", -2, &["admin"], 0, 0, 0),
+    spec("config", -2, &["admin", "noscript"], 0, 0, 0),
+    spec("debug", -2, &["admin", "noscript"], 0, 0, 0),
+    spec("info", -1, &["loading", "stale"], 0, 0, 0),
+];
+
+/// Finds a command's metadata by name, ignoring case.
+pub fn lookup(name: &str) -> Option<&'static CommandSpec> {
+    COMMAND_TABLE
+        .iter()
+        .find(|spec| spec.name.eq_ignore_ascii_case(name))
+}
+
+/// Returns the key arguments of the full command line `args` (name first),
+/// using the key positions in the table.
+pub fn get_keys(args: &[String]) -> Result<Vec<String>, String> {
+    let spec = lookup(&args[0]).ok_or("ERR Invalid command specified")?;
+    let len = args.len() as i64;
+    if (spec.arity > 0 && len != spec.arity) || len < spec.arity.abs() {
+        return Err("ERR Invalid number of arguments specified for command".to_string());
+    }
+
+    // These say how many keys follow in their numkeys argument, which EVAL
+    // and EVALSHA put after the script and SINTERCARD first
+    if spec.flags.contains(&"movablekeys") {
+        let position = if spec.name.starts_with("eval") { 2 } else { 1 };
+        return args
+            .get(position)
+            .and_then(|numkeys| numkeys.parse::<usize>().ok())
+            .and_then(|numkeys| numkeys.checked_add(position + 1))
+            .and_then(|end| args.get(position + 1..end))
+            .map(|keys| keys.to_vec())
+            .ok_or_else(|| "ERR Invalid arguments specified for command".to_string());
+    }
+    if spec.first_key == 0 {
+        return Err("ERR The command has no key arguments".to_string());
+    }
+
+    let last_key = if spec.last_key < 0 {
+        len + spec.last_key
+    } else {
+        spec.last_key
+    };
+    // Subcommand forms such as OBJECT HELP can be shorter than the table's
+    // key positions allow for
+    (spec.first_key..=last_key)
+        .step_by(spec.step as usize)
+        .map(|position| args.get(position as usize).cloned())
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| "ERR Invalid arguments specified for command".to_string())
+}
+
+impl CommandSpec {
+    const fn holding(self, key_type: &'static str, handler: Handler) -> Self {
+        CommandSpec {
+            key_type: Some(key_type),
+            handler: Some(handler),
+            ..self
+        }
+    }
+
+    // The blocking pops wait without the lock held, so rather than through a
+    // handler they are dispatched generally and check types as they pop
+    const fn blocking_on(self, key_type: &'static str) -> Self {
+        CommandSpec {
+            key_type: Some(key_type),
+            ..self
+        }
+    }
+
+    /// The ACL category of the data type the command works on, if any.
+    pub fn category(&self) -> Option<&'static str> {
+        self.key_type.map(|key_type| match key_type {
+            "string" => "@string",
+            "list" => "@list",
+            "set" => "@set",
+            "hash" => "@hash",
+            "zset" => "@sortedset",
+            _ => unreachable!("unknown key type {}", key_type),
+        })
+    }
+
+    /// The COMMAND INFO entry:
+    /// `[name, arity, [flags...], first, last, step, [categories...]]`.
+    pub fn info(&self) -> RespValue {
+        RespValue::Array(Some(vec![
+            RespValue::BulkString(Some(self.name.as_bytes().to_vec())),
+            RespValue::Integer(self.arity),
+            RespValue::Array(Some(
+                self.flags
+                    .iter()
+                    .map(|flag| RespValue::SimpleString(flag.to_string()))
+                    .collect(),
+            )),
+            RespValue::Integer(self.first_key),
+            RespValue::Integer(self.last_key),
+            RespValue::Integer(self.step),
+            RespValue::Array(Some(
+                self.category()
+                    .map(|category| RespValue::SimpleString(category.to_string()))
+                    .into_iter()
+                    .collect(),
+            )),
+        ]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{Command, CommandError};
+
+    #[test]
+    fn test_every_listed_command_is_parsed() {
+        for spec in COMMAND_TABLE {
+            let name = RespValue::BulkString(Some(spec.name.to_uppercase().into_bytes()));
+            let parsed = Command::try_from(RespValue::Array(Some(vec![name])));
+
+            assert!(
+                !matches!(parsed, Err(CommandError::UnknownCommand(cmd)) if cmd == spec.name.to_uppercase()),
+                "{} is listed but not parsed",
+                spec.name
+            );
+        }
+    }
+
+    #[test]
+    fn test_key_types_have_categories() {
+        for spec in COMMAND_TABLE.iter().filter(|spec| spec.key_type.is_some()) {
+            assert!(spec.category().is_some(), "{}", spec.name);
+        }
+        assert_eq!(lookup("zadd").unwrap().category(), Some("@sortedset"));
+        assert_eq!(lookup("del").unwrap().category(), None);
+    }
+
+    fn get_keys_of(args: &[&str]) -> Result<Vec<String>, String> {
+        get_keys(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_get_keys_single_key() {
+        assert_eq!(get_keys_of(&["GET", "k"]), Ok(vec!["k".to_string()]));
+    }
+
+    #[test]
+    fn test_get_keys_steps_over_values() {
+        assert_eq!(
+            get_keys_of(&["MSET", "a", "1", "b", "2"]),
+            Ok(vec!["a".to_string(), "b".to_string()])
+        );
+        assert_eq!(
+            get_keys_of(&["EVAL", "return 1", "2", "x", "y", "arg"]),
+            Ok(vec!["x".to_string(), "y".to_string()])
+        );
+        assert_eq!(
+            get_keys_of(&["SINTERCARD", "2", "x", "y", "LIMIT", "1"]),
+            Ok(vec!["x".to_string(), "y".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_command_keys_match_key_positions() {
+        let lines: &[&[&str]] = &[
+            &["GET", "k"],
+            &["MSET", "a", "1", "b", "2"],
+            &["SMOVE", "s", "d", "m"],
+            &["SINTERSTORE", "d", "a", "b"],
+            &["BLPOP", "a", "b", "0"],
+            &["ZADD", "z", "1", "m"],
+            &["SINTERCARD", "2", "x", "y"],
+        ];
+        for args in lines {
+            let command = Command::try_from(RespValue::Array(Some(
+                args.iter()
+                    .map(|arg| RespValue::BulkString(Some(arg.as_bytes().to_vec())))
+                    .collect(),
+            )))
+            .unwrap();
+            let keys = command
+                .keys()
+                .into_iter()
+                .map(|key| String::from_utf8(key.to_vec()).unwrap())
+                .collect();
+
+            assert_eq!(get_keys_of(args), Ok(keys), "{}", args[0]);
+        }
+    }
+
+    #[test]
+    fn test_get_keys_errors() {
+        assert_eq!(
+            get_keys_of(&["PING"]),
+            Err("ERR The command has no key arguments".to_string())
+        );
+        assert_eq!(
+            get_keys_of(&["NOSUCHCOMMAND", "k"]),
+            Err("ERR Invalid command specified".to_string())
+        );
+        assert_eq!(
+            get_keys_of(&["GET"]),
+            Err("ERR Invalid number of arguments specified for command".to_string())
+        );
+    }
+
+    #[test]
+    fn test_get_keys_rejects_missing_key_positions() {
+        let invalid = Err("ERR Invalid arguments specified for command".to_string());
+        assert_eq!(get_keys_of(&["OBJECT", "HELP"]), invalid);
+        assert_eq!(get_keys_of(&["MEMORY", "DOCTOR"]), invalid);
+        assert_eq!(
+            get_keys_of(&["SINTERCARD", "18446744073709551615", "a"]),
+            invalid
+        );
+        assert_eq!(get_keys_of(&["EVAL", "return 1", "2", "x"]), invalid);
+    }
+
+    #[test]
+    fn test_lookup_ignores_case() {
+        assert_eq!(lookup("GeT").map(|spec| spec.arity), Some(2));
+        assert!(lookup("nosuchcommand").is_none());
+    }
+}
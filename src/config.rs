@@ -0,0 +1,756 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::storage::EncodingLimits;
+
+/// The server's configuration, shared so CONFIG SET changes are seen by
+/// every connection and background task.
+pub type SharedConfig = Arc<RwLock<Config>>;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Seconds of idle time before TCP keepalive probes start; 0 disables them.
+    pub tcp_keepalive: u64,
+    /// Upper bound on the bulk bytes a single request may declare.
+    pub max_request_size: usize,
+    /// Commands taking at least this many microseconds are recorded in the
+    /// slowlog; a negative value disables it.
+    pub slowlog_log_slower_than: i64,
+    /// A command that waits at least this many microseconds in total for
+    /// database locks is logged as a warning; a negative value disables it.
+    pub lock_wait_log_slower_than: i64,
+    /// Number of slowlog entries kept before the oldest are dropped.
+    pub slowlog_max_len: usize,
+    /// Number of databases allocated at startup; fixed for the server's
+    /// lifetime.
+    pub databases: usize,
+    /// Active expiry cycles per second.
+    pub hz: u64,
+    /// Keys with a TTL sampled per database in each active expiry pass.
+    pub active_expire_samples: usize,
+    /// Percentage of expired keys in a sample above which the sweeper runs
+    /// another pass straight away instead of waiting for the next cycle.
+    pub active_expire_threshold: u64,
+    /// TCP port to listen on; fixed for the server's lifetime.
+    pub port: u16,
+    /// Addresses to listen on, space separated; only the first is used.
+    pub bind: String,
+    /// Path of a Unix domain socket to listen on as well as TCP; empty to
+    /// listen on TCP only. Fixed for the server's lifetime.
+    pub unixsocket: String,
+    /// Port to accept TLS connections on alongside the plaintext port; 0
+    /// disables TLS. Fixed for the server's lifetime, as are the
+    /// certificate and key.
+    pub tls_port: u16,
+    /// PEM file with the certificate chain presented to TLS clients.
+    pub tls_cert_file: String,
+    /// PEM file with the private key for `tls_cert_file`.
+    pub tls_key_file: String,
+    /// Expect each connection to open with a PROXY protocol v1 header, as
+    /// sent by a load balancer, and treat its source address as the client's.
+    pub proxy_protocol: bool,
+    /// Refuse TCP connections from non-loopback peers while the server is
+    /// bound to a non-loopback address without a `requirepass`.
+    pub protected_mode: bool,
+    /// Output buffer limits for normal, replica and pub/sub clients, in that
+    /// order. Only the pub/sub hard limit is enforced.
+    pub client_output_buffer_limit: [OutputBufferLimit; 3],
+    /// Sizes past which collections stop reporting their compact encodings.
+    pub encoding_limits: EncodingLimits,
+    /// Lowercase names of commands that reply as if they didn't exist. A
+    /// name like `debug` covers every subcommand, while `config|set` covers
+    /// just the one. Fixed for the server's lifetime.
+    pub disabled_commands: Vec<String>,
+    /// `rename-command` entries from lowercase original names to the
+    /// aliases clients must use instead; an empty alias disables the
+    /// command. Only settable from a config file, one directive per command.
+    pub rename_commands: HashMap<String, String>,
+    // These are accepted so that Redis config files load and round-trip
+    // through CONFIG, but the server does not act on them yet
+    pub requirepass: String,
+    pub appendonly: bool,
+    pub maxmemory: u64,
+    pub maxmemory_policy: String,
+    /// Snapshot points as `seconds changes` pairs.
+    pub save: String,
+    /// The file the config was loaded from, which CONFIG REWRITE updates.
+    pub config_file: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            tcp_keepalive: 300,
+            max_request_size: 512 * 1024 * 1024,
+            slowlog_log_slower_than: 10_000,
+            lock_wait_log_slower_than: 100_000,
+            slowlog_max_len: 128,
+            databases: 16,
+            hz: 10,
+            active_expire_samples: 20,
+            active_expire_threshold: 25,
+            port: 6379,
+            bind: "127.0.0.1".to_string(),
+            unixsocket: String::new(),
+            tls_port: 0,
+            tls_cert_file: String::new(),
+            tls_key_file: String::new(),
+            proxy_protocol: false,
+            protected_mode: true,
+            client_output_buffer_limit: [
+                OutputBufferLimit::default(),
+                OutputBufferLimit {
+                    hard: 256 * 1024 * 1024,
+                    soft: 64 * 1024 * 1024,
+                    soft_seconds: 60,
+                },
+                OutputBufferLimit {
+                    hard: 32 * 1024 * 1024,
+                    soft: 8 * 1024 * 1024,
+                    soft_seconds: 60,
+                },
+            ],
+            encoding_limits: EncodingLimits::default(),
+            disabled_commands: Vec::new(),
+            rename_commands: HashMap::new(),
+            requirepass: String::new(),
+            appendonly: false,
+            maxmemory: 0,
+            maxmemory_policy: "noeviction".to_string(),
+            save: "3600 1 300 100 60 10000".to_string(),
+            config_file: None,
+        }
+    }
+}
+
+/// One client class's `client-output-buffer-limit`: the connection is
+/// closed once its pending output passes `hard` bytes, or stays above `soft`
+/// bytes for `soft_seconds`. Zero disables a limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct OutputBufferLimit {
+    pub hard: u64,
+    pub soft: u64,
+    pub soft_seconds: u64,
+}
+
+// Client classes of client-output-buffer-limit, in the order Redis reports them
+const CLIENT_CLASSES: [&str; 3] = ["normal", "slave", "pubsub"];
+
+const MAXMEMORY_POLICIES: &[&str] = &[
+    "volatile-lru",
+    "volatile-lfu",
+    "volatile-random",
+    "volatile-ttl",
+    "allkeys-lru",
+    "allkeys-lfu",
+    "allkeys-random",
+    "noeviction",
+];
+
+/// Parameters that can only be set at startup, from a config file.
+const IMMUTABLE: &[&str] = &[
+    "databases",
+    "port",
+    "bind",
+    "unixsocket",
+    "tls-port",
+    "tls-cert-file",
+    "tls-key-file",
+    "disabled-commands",
+    "rename-command",
+];
+
+impl Config {
+    pub fn from_env() -> Self {
+        let defaults = Config::default();
+        Self {
+            tcp_keepalive: env_or("TCP_KEEPALIVE", defaults.tcp_keepalive),
+            max_request_size: env_or("MAX_REQUEST_SIZE", defaults.max_request_size),
+            slowlog_log_slower_than: env_or(
+                "SLOWLOG_LOG_SLOWER_THAN",
+                defaults.slowlog_log_slower_than,
+            ),
+            slowlog_max_len: env_or("SLOWLOG_MAX_LEN", defaults.slowlog_max_len),
+            databases: env_or("DATABASES", defaults.databases),
+            hz: env_or("HZ", defaults.hz),
+            active_expire_samples: env_or("ACTIVE_EXPIRE_SAMPLES", defaults.active_expire_samples),
+            active_expire_threshold: env_or(
+                "ACTIVE_EXPIRE_THRESHOLD",
+                defaults.active_expire_threshold,
+            ),
+            ..defaults
+        }
+    }
+
+    /// Applies the directives of a Redis-style config file on top of this
+    /// config. Blank lines and `#` comments are skipped, and repeated `save`
+    /// lines add to each other as in Redis.
+    pub fn load_file(&mut self, path: &Path) -> Result<(), String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("can't open config file '{}': {}", path.display(), e))?;
+
+        let mut seen_save = false;
+        for (number, line) in contents.lines().enumerate() {
+            let Some((name, mut value)) = parse_directive(line) else {
+                continue;
+            };
+            if name == "save" && seen_save && !value.is_empty() {
+                value = format!("{} {}", self.save, value);
+            }
+            seen_save |= name == "save";
+            self.apply(&name, &value)
+                .map_err(|e| format!("line {}: '{}': {}", number + 1, line.trim(), e))?;
+        }
+        self.config_file = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    /// Writes the current parameters back to the config file. Lines for
+    /// known parameters are updated in place and everything else in the file
+    /// is kept; parameters the file doesn't mention are appended if they
+    /// differ from their defaults.
+    pub fn rewrite(&self) -> Result<(), String> {
+        let path = self
+            .config_file
+            .as_ref()
+            .ok_or("The server is running without a config file")?;
+        // Like Redis, recreate the file if it has gone missing
+        let contents = fs::read_to_string(path).unwrap_or_default();
+
+        let mut written = HashSet::new();
+        let mut lines = Vec::new();
+        for line in contents.lines() {
+            match parse_directive(line) {
+                Some((name, _)) if self.get(&name).is_some() => {
+                    if written.insert(name.clone()) {
+                        lines.push(self.directive(&name));
+                    }
+                }
+                _ => lines.push(line.to_string()),
+            }
+        }
+        let defaults = Config::default();
+        for name in Self::parameters() {
+            if !written.contains(*name) && self.get(name) != defaults.get(name) {
+                lines.push(self.directive(name));
+            }
+        }
+
+        fs::write(path, lines.join("\n") + "\n")
+            .map_err(|e| format!("Rewriting config file: {}", e))
+    }
+
+    fn directive(&self, name: &str) -> String {
+        match self.get(name).unwrap_or_default() {
+            value if value.is_empty() => format!("{} \"\"", name),
+            value => format!("{} {}", name, value),
+        }
+    }
+
+    /// Returns the value of a CONFIG parameter by its Redis-style name.
+    pub fn get(&self, name: &str) -> Option<String> {
+        let value = match name {
+            "tcp-keepalive" => self.tcp_keepalive.to_string(),
+            "proto-max-bulk-len" => self.max_request_size.to_string(),
+            "slowlog-log-slower-than" => self.slowlog_log_slower_than.to_string(),
+            "lock-wait-log-slower-than" => self.lock_wait_log_slower_than.to_string(),
+            "slowlog-max-len" => self.slowlog_max_len.to_string(),
+            "databases" => self.databases.to_string(),
+            "hz" => self.hz.to_string(),
+            "active-expire-samples" => self.active_expire_samples.to_string(),
+            "active-expire-threshold" => self.active_expire_threshold.to_string(),
+            "port" => self.port.to_string(),
+            "bind" => self.bind.clone(),
+            "unixsocket" => self.unixsocket.clone(),
+            "tls-port" => self.tls_port.to_string(),
+            "tls-cert-file" => self.tls_cert_file.clone(),
+            "tls-key-file" => self.tls_key_file.clone(),
+            "proxy-protocol" => if self.proxy_protocol { "yes" } else { "no" }.to_string(),
+            "protected-mode" => if self.protected_mode { "yes" } else { "no" }.to_string(),
+            "client-output-buffer-limit" => CLIENT_CLASSES
+                .iter()
+                .zip(&self.client_output_buffer_limit)
+                .map(|(class, limit)| {
+                    format!(
+                        "{} {} {} {}",
+                        class, limit.hard, limit.soft, limit.soft_seconds
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
+            "hash-max-listpack-entries" => {
+                self.encoding_limits.hash_max_listpack_entries.to_string()
+            }
+            "hash-max-listpack-value" => self.encoding_limits.hash_max_listpack_value.to_string(),
+            "set-max-intset-entries" => self.encoding_limits.set_max_intset_entries.to_string(),
+            "zset-max-listpack-entries" => {
+                self.encoding_limits.zset_max_listpack_entries.to_string()
+            }
+            "zset-max-listpack-value" => self.encoding_limits.zset_max_listpack_value.to_string(),
+            "list-max-listpack-size" => self.encoding_limits.list_max_listpack_size.to_string(),
+            "disabled-commands" => self.disabled_commands.join(" "),
+            "requirepass" => self.requirepass.clone(),
+            "appendonly" => if self.appendonly { "yes" } else { "no" }.to_string(),
+            "maxmemory" => self.maxmemory.to_string(),
+            "maxmemory-policy" => self.maxmemory_policy.clone(),
+            "save" => self.save.clone(),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Updates a CONFIG parameter, leaving the config untouched if the name
+    /// is unknown or the value doesn't parse.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        if IMMUTABLE.contains(&name) {
+            return Err("can't set immutable config".to_string());
+        }
+        self.apply(name, value)
+    }
+
+    /// Like `set`, but also accepts the parameters fixed once the server is
+    /// running.
+    fn apply(&mut self, name: &str, value: &str) -> Result<(), String> {
+        fn parse<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+            value
+                .parse()
+                .map_err(|_| "argument couldn't be parsed".to_string())
+        }
+
+        match name {
+            "tcp-keepalive" => self.tcp_keepalive = parse(value)?,
+            "proto-max-bulk-len" => self.max_request_size = parse(value)?,
+            "slowlog-log-slower-than" => self.slowlog_log_slower_than = parse(value)?,
+            "lock-wait-log-slower-than" => self.lock_wait_log_slower_than = parse(value)?,
+            "slowlog-max-len" => self.slowlog_max_len = parse(value)?,
+            "databases" => match parse(value)? {
+                0 => return Err("argument must be positive".to_string()),
+                databases => self.databases = databases,
+            },
+            // Redis clamps hz to 1..=500 rather than rejecting it
+            "hz" => self.hz = parse::<u64>(value)?.clamp(1, 500),
+            "active-expire-samples" => match parse(value)? {
+                0 => return Err("argument must be positive".to_string()),
+                samples => self.active_expire_samples = samples,
+            },
+            "active-expire-threshold" => match parse(value)? {
+                threshold @ 0..=100 => self.active_expire_threshold = threshold,
+                _ => return Err("argument must be between 0 and 100".to_string()),
+            },
+            "port" => self.port = parse(value)?,
+            "bind" => self.bind = value.to_string(),
+            "unixsocket" => self.unixsocket = value.to_string(),
+            "tls-port" => self.tls_port = parse(value)?,
+            "tls-cert-file" => self.tls_cert_file = value.to_string(),
+            "tls-key-file" => self.tls_key_file = value.to_string(),
+            "proxy-protocol" => match value.to_lowercase().as_str() {
+                "yes" => self.proxy_protocol = true,
+                "no" => self.proxy_protocol = false,
+                _ => return Err("argument must be 'yes' or 'no'".to_string()),
+            },
+            "protected-mode" => match value.to_lowercase().as_str() {
+                "yes" => self.protected_mode = true,
+                "no" => self.protected_mode = false,
+                _ => return Err("argument must be 'yes' or 'no'".to_string()),
+            },
+            "client-output-buffer-limit" => {
+                self.client_output_buffer_limit =
+                    parse_buffer_limits(value, self.client_output_buffer_limit)?
+            }
+            "hash-max-listpack-entries" => {
+                self.encoding_limits.hash_max_listpack_entries = parse(value)?
+            }
+            "hash-max-listpack-value" => {
+                self.encoding_limits.hash_max_listpack_value = parse(value)?
+            }
+            "set-max-intset-entries" => self.encoding_limits.set_max_intset_entries = parse(value)?,
+            "zset-max-listpack-entries" => {
+                self.encoding_limits.zset_max_listpack_entries = parse(value)?
+            }
+            "zset-max-listpack-value" => {
+                self.encoding_limits.zset_max_listpack_value = parse(value)?
+            }
+            "list-max-listpack-size" => self.encoding_limits.list_max_listpack_size = parse(value)?,
+            "disabled-commands" => {
+                self.disabled_commands = value.split_whitespace().map(str::to_lowercase).collect()
+            }
+            "rename-command" => {
+                let mut fields = value.split_whitespace();
+                let (Some(original), Some(alias), None) =
+                    (fields.next(), fields.next(), fields.next())
+                else {
+                    return Err("wrong number of arguments".to_string());
+                };
+                // The value only loses its quotes when it's quoted whole
+                let alias = alias.trim_matches('"');
+                self.rename_commands
+                    .insert(original.to_lowercase(), alias.to_lowercase());
+            }
+            "requirepass" => self.requirepass = value.to_string(),
+            "appendonly" => match value.to_lowercase().as_str() {
+                "yes" => self.appendonly = true,
+                "no" => self.appendonly = false,
+                _ => return Err("argument must be 'yes' or 'no'".to_string()),
+            },
+            "maxmemory" => {
+                self.maxmemory = parse_memory(value).ok_or("argument must be a memory value")?
+            }
+            "maxmemory-policy" => {
+                let policy = value.to_lowercase();
+                if !MAXMEMORY_POLICIES.contains(&policy.as_str()) {
+                    return Err("argument(s) must be one of the following: ".to_string()
+                        + &MAXMEMORY_POLICIES.join(", "));
+                }
+                self.maxmemory_policy = policy;
+            }
+            "save" => self.save = value.to_string(),
+            _ => return Err("Unknown option or number of arguments".to_string()),
+        }
+        Ok(())
+    }
+
+    /// Names of every parameter CONFIG GET can report.
+    pub fn parameters() -> &'static [&'static str] {
+        &[
+            "tcp-keepalive",
+            "proto-max-bulk-len",
+            "slowlog-log-slower-than",
+            "lock-wait-log-slower-than",
+            "slowlog-max-len",
+            "databases",
+            "hz",
+            "active-expire-samples",
+            "active-expire-threshold",
+            "port",
+            "bind",
+            "unixsocket",
+            "tls-port",
+            "tls-cert-file",
+            "tls-key-file",
+            "proxy-protocol",
+            "protected-mode",
+            "client-output-buffer-limit",
+            "hash-max-listpack-entries",
+            "hash-max-listpack-value",
+            "set-max-intset-entries",
+            "zset-max-listpack-entries",
+            "zset-max-listpack-value",
+            "list-max-listpack-size",
+            "disabled-commands",
+            "requirepass",
+            "appendonly",
+            "maxmemory",
+            "maxmemory-policy",
+            "save",
+        ]
+    }
+
+    /// The address to listen on, from the first `bind` entry and `port`.
+    pub fn listen_addr(&self) -> String {
+        self.addr_for(self.port)
+    }
+
+    /// The address the TLS port is served on.
+    pub fn tls_listen_addr(&self) -> String {
+        self.addr_for(self.tls_port)
+    }
+
+    fn addr_for(&self, port: u16) -> String {
+        let host = self.bind.split_whitespace().next().unwrap_or("127.0.0.1");
+        format!("{}:{}", host, port)
+    }
+
+    pub fn expire_cycle_interval(&self) -> Duration {
+        Duration::from_millis(1000 / self.hz.clamp(1, 500))
+    }
+
+    pub fn is_slow(&self, elapsed: Duration) -> bool {
+        self.slowlog_log_slower_than >= 0
+            && elapsed.as_micros() >= self.slowlog_log_slower_than as u128
+    }
+
+    pub fn is_slow_lock_wait(&self, waited: Duration) -> bool {
+        self.lock_wait_log_slower_than >= 0
+            && waited.as_micros() >= self.lock_wait_log_slower_than as u128
+    }
+
+    /// Whether a command, by its `Command::name`, has been disabled either
+    /// on its own or through its parent command.
+    pub fn is_disabled(&self, name: &str) -> bool {
+        let parent = name.split('|').next().unwrap_or(name);
+        self.disabled_commands
+            .iter()
+            .any(|disabled| disabled == name || disabled == parent)
+    }
+
+    /// Whether a TCP client connecting from `peer` must be turned away by
+    /// protected mode. Loopback clients are always let in.
+    pub fn refuses_peer(&self, peer: IpAddr) -> bool {
+        self.protected_mode
+            && self.requirepass.is_empty()
+            && !peer.to_canonical().is_loopback()
+            && self.bind.split_whitespace().any(|addr| {
+                // A leading '-' only marks the address as optional to bind
+                match addr.trim_start_matches('-') {
+                    "localhost" => false,
+                    addr => !addr.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback()),
+                }
+            })
+    }
+
+    pub fn keepalive_duration(&self) -> Option<Duration> {
+        (self.tcp_keepalive > 0).then(|| Duration::from_secs(self.tcp_keepalive))
+    }
+}
+
+/// Splits a config file line into its lowercased directive and its
+/// arguments, unquoting a value wrapped in double quotes.
+fn parse_directive(line: &str) -> Option<(String, String)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (name, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let value = value.trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value);
+    Some((name.to_lowercase(), value.to_string()))
+}
+
+/// Applies `class hard soft seconds` groups on top of `limits`; classes not
+/// mentioned keep their current limits.
+fn parse_buffer_limits(
+    value: &str,
+    mut limits: [OutputBufferLimit; 3],
+) -> Result<[OutputBufferLimit; 3], String> {
+    let fields: Vec<&str> = value.split_whitespace().collect();
+    if fields.is_empty() || !fields.len().is_multiple_of(4) {
+        return Err("Wrong number of arguments in buffer limit configuration.".to_string());
+    }
+    for group in fields.chunks(4) {
+        let class = match group[0].to_lowercase().as_str() {
+            "normal" => 0,
+            "slave" | "replica" => 1,
+            "pubsub" => 2,
+            _ => {
+                return Err(
+                    "Invalid client class specified in buffer limit configuration.".to_string(),
+                )
+            }
+        };
+        let (Some(hard), Some(soft), Ok(soft_seconds)) = (
+            parse_memory(group[1]),
+            parse_memory(group[2]),
+            group[3].parse(),
+        ) else {
+            return Err(
+                "Error in hard, soft or soft_seconds setting in buffer limit configuration."
+                    .to_string(),
+            );
+        };
+        limits[class] = OutputBufferLimit {
+            hard,
+            soft,
+            soft_seconds,
+        };
+    }
+    Ok(limits)
+}
+
+/// Parses a byte count with an optional unit, as in `maxmemory 100mb`. The
+/// `k`/`m`/`g` units are powers of ten and `kb`/`mb`/`gb` powers of two.
+fn parse_memory(value: &str) -> Option<u64> {
+    let value = value.to_lowercase();
+    let digits = value.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let multiplier = match &value[digits.len()..] {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    digits.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    match std::env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            eprintln!("Ignoring invalid value for {}: {}", name, value);
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_parameter_round_trips() {
+        let mut config = Config::default();
+
+        for name in Config::parameters()
+            .iter()
+            .filter(|name| !IMMUTABLE.contains(name))
+        {
+            let value = config.get(name).unwrap();
+            assert_eq!(config.set(name, &value), Ok(()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_client_output_buffer_limit() {
+        let mut config = Config::default();
+
+        config
+            .set("client-output-buffer-limit", "pubsub 1mb 0 0")
+            .unwrap();
+
+        assert_eq!(
+            config.get("client-output-buffer-limit").unwrap(),
+            "normal 0 0 0 slave 268435456 67108864 60 pubsub 1048576 0 0"
+        );
+        assert!(config
+            .set("client-output-buffer-limit", "pubsub 1mb 0")
+            .is_err());
+        assert!(config
+            .set("client-output-buffer-limit", "monitor 1mb 0 0")
+            .is_err());
+        assert_eq!(config.client_output_buffer_limit[2].hard, 1024 * 1024);
+    }
+
+    #[test]
+    fn test_disabled_commands() {
+        let mut config = Config::default();
+
+        config
+            .apply("disabled-commands", "FLUSHALL debug config|set")
+            .unwrap();
+
+        assert!(config.is_disabled("flushall"));
+        assert!(config.is_disabled("debug|object"));
+        assert!(config.is_disabled("config|set"));
+        assert!(!config.is_disabled("config|get"));
+        assert!(config.set("disabled-commands", "").is_err());
+    }
+
+    #[test]
+    fn test_protected_mode_refuses_remote_peers_when_exposed() {
+        let remote: IpAddr = "10.0.0.5".parse().unwrap();
+        let loopback: IpAddr = "127.0.0.1".parse().unwrap();
+        let mut config = Config::default();
+        assert!(!config.refuses_peer(remote));
+
+        config.bind = "127.0.0.1 -::1 0.0.0.0".to_string();
+        assert!(config.refuses_peer(remote));
+        assert!(!config.refuses_peer(loopback));
+        assert!(!config.refuses_peer("::ffff:127.0.0.1".parse().unwrap()));
+
+        config.requirepass = "secret".to_string();
+        assert!(!config.refuses_peer(remote));
+        config.requirepass.clear();
+        config.set("protected-mode", "no").unwrap();
+        assert!(!config.refuses_peer(remote));
+    }
+
+    #[test]
+    fn test_set_rejects_bad_values() {
+        let mut config = Config::default();
+
+        assert!(config.set("hz", "fast").is_err());
+        assert!(config.set("active-expire-threshold", "101").is_err());
+        assert!(config.set("no-such-option", "1").is_err());
+        assert_eq!(config.hz, 10);
+
+        assert!(config.set("databases", "32").is_err());
+        assert_eq!(config.databases, 16);
+        assert!(config.set("port", "7000").is_err());
+        assert!(config.set("appendonly", "maybe").is_err());
+        assert!(config.set("maxmemory", "10tb").is_err());
+        assert!(config.set("maxmemory-policy", "allkeys-fifo").is_err());
+
+        config.set("hz", "100000").unwrap();
+        assert_eq!(config.hz, 500);
+    }
+
+    fn write_config_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("rrrr-{}-{}.conf", name, std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_load_file() {
+        let path = write_config_file(
+            "load",
+            "# comment\n\nport 7000\nbind 0.0.0.0 ::1\nrequirepass \"\"\nappendonly yes\n\
+             maxmemory 100mb\nsave 900 1\nsave 300 10\ndatabases 4\nunixsocket /tmp/rrrr.sock\n\
+             rename-command FLUSHALL SECRETFLUSH\nrename-command DEBUG \"\"\n",
+        );
+        let mut config = Config::default();
+
+        config.load_file(&path).unwrap();
+
+        assert_eq!(config.listen_addr(), "0.0.0.0:7000");
+        assert_eq!(config.requirepass, "");
+        assert!(config.appendonly);
+        assert_eq!(config.maxmemory, 100 * 1024 * 1024);
+        assert_eq!(config.save, "900 1 300 10");
+        assert_eq!(config.databases, 4);
+        assert_eq!(config.unixsocket, "/tmp/rrrr.sock");
+        assert_eq!(
+            config.rename_commands,
+            HashMap::from([
+                ("flushall".to_string(), "secretflush".to_string()),
+                ("debug".to_string(), String::new()),
+            ])
+        );
+        assert_eq!(config.config_file, Some(path.clone()));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_load_file_reports_bad_line() {
+        let path = write_config_file("bad", "port 7000\nhz often\n");
+
+        let err = Config::default().load_file(&path).unwrap_err();
+
+        assert_eq!(err, "line 2: 'hz often': argument couldn't be parsed");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_rewrite_updates_file_in_place() {
+        let path = write_config_file("rewrite", "# keep me\nhz 20\nport 7000\nhz 30\n");
+        let mut config = Config::default();
+        config.load_file(&path).unwrap();
+
+        config.set("hz", "50").unwrap();
+        config.set("maxmemory", "1kb").unwrap();
+        config.rewrite().unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# keep me\nhz 50\nport 7000\nmaxmemory 1024\n"
+        );
+        let mut reloaded = Config::default();
+        reloaded.load_file(&path).unwrap();
+        assert_eq!(reloaded.hz, 50);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_rewrite_without_file() {
+        assert!(Config::default().rewrite().is_err());
+    }
+}
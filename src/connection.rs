@@ -0,0 +1,99 @@
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::command::Command;
+use crate::config::SharedConfig;
+
+/// Per-connection state that commands may read or change, such as the
+/// selected database.
+#[derive(Debug, Default)]
+pub struct ConnectionContext {
+    /// Unique id of the client, as HELLO reports it.
+    pub id: u64,
+    /// Index into the server's databases that commands run against.
+    pub db: usize,
+    /// Set once AUTH or HELLO has accepted the client's credentials; until
+    /// then a server with `requirepass` refuses other commands.
+    pub authenticated: bool,
+    /// Name set by CLIENT SETNAME or HELLO SETNAME.
+    pub name: Option<String>,
+    /// Commands queued since MULTI, or None outside a transaction.
+    pub transaction: Option<Vec<Command>>,
+    /// Set when a command sent since MULTI failed to parse, so that EXEC
+    /// discards the transaction instead of running it.
+    pub transaction_dirty: bool,
+    /// Channels the connection is subscribed to.
+    pub subscriptions: HashSet<String>,
+    /// Glob patterns the connection is subscribed to.
+    pub patterns: HashSet<String>,
+    /// Set for commands a script issues, where blocking commands must reply
+    /// immediately instead of waiting.
+    pub in_script: bool,
+    /// Set while EXEC runs the queued commands, which like a script's
+    /// must not block.
+    pub in_exec: bool,
+    /// The server configuration, which CONFIG GET and CONFIG SET act on.
+    pub config: SharedConfig,
+}
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Hands out client ids, counting up from 1 like Redis.
+pub fn next_client_id() -> u64 {
+    NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// A socket a client is connected over, with the few operations the
+/// connection loop needs beyond reading and writing.
+pub trait ClientStream: Read + Write + Send + Sized + 'static {
+    /// A second handle to the same socket, which MONITOR keeps to stream
+    /// commands to the client.
+    fn try_clone(&self) -> io::Result<Self>;
+
+    /// The client's address as MONITOR and the logs show it.
+    fn peer_addr(&self) -> String;
+
+    /// Closes the socket in both directions, ignoring errors.
+    fn shutdown(&self);
+}
+
+impl ClientStream for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn peer_addr(&self) -> String {
+        TcpStream::peer_addr(self)
+            .map(|addr| addr.to_string())
+            .unwrap_or_default()
+    }
+
+    fn shutdown(&self) {
+        let _ = TcpStream::shutdown(self, Shutdown::Both);
+    }
+}
+
+#[cfg(unix)]
+impl ClientStream for UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+
+    // Unix socket clients are unnamed, so like Redis report the socket path
+    // the server is listening on, with port 0
+    fn peer_addr(&self) -> String {
+        self.local_addr()
+            .ok()
+            .and_then(|addr| addr.as_pathname().map(|path| path.display().to_string()))
+            .map(|path| format!("{}:0", path))
+            .unwrap_or_default()
+    }
+
+    fn shutdown(&self) {
+        let _ = UnixStream::shutdown(self, Shutdown::Both);
+    }
+}
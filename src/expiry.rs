@@ -0,0 +1,122 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::config::SharedConfig;
+use crate::storage::{lock_storage, Databases, Storage};
+
+/// Starts the background thread that reclaims expired keys nobody reads.
+/// Keys are otherwise only removed lazily when a command touches them.
+pub fn spawn_sweeper(databases: Arc<Databases>, config: SharedConfig) {
+    thread::spawn(move || loop {
+        // Read the parameters every cycle so CONFIG SET takes effect without
+        // restarting the sweeper
+        let (interval, samples, threshold) = {
+            let config = config.read().unwrap();
+            (
+                config.expire_cycle_interval(),
+                config.active_expire_samples,
+                config.active_expire_threshold as usize,
+            )
+        };
+        thread::sleep(interval);
+
+        for storage in databases.iter() {
+            sweep(storage, samples, threshold);
+        }
+    });
+}
+
+/// Sampling rounds a database gets per cycle. The lock is taken per round,
+/// so clients wait behind at most one sample, and a mass expiry is worked
+/// through over several cycles rather than in one stall.
+const MAX_ROUNDS: usize = 16;
+
+// Keeps sampling while a large share of the sample turns out to be expired,
+// as that suggests many more expired keys are still waiting
+fn sweep(storage: &Mutex<Storage>, samples: usize, threshold: usize) {
+    for _ in 0..MAX_ROUNDS {
+        let (sampled, expired) = lock_storage(storage).expire_sample(samples);
+        if sampled == 0 || expired * 100 <= sampled * threshold {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use std::sync::RwLock;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    fn databases_with_expired_key() -> Arc<Databases> {
+        let mut storage = Storage::new();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        storage.set("gone".into(), b"v".to_vec());
+        storage.set_expire_at("gone".into(), now - 10);
+        Arc::new(Databases::new(storage, 1))
+    }
+
+    fn start(hz: u64) -> Arc<Databases> {
+        let databases = databases_with_expired_key();
+        let config = Config {
+            hz,
+            ..Config::default()
+        };
+        spawn_sweeper(databases.clone(), Arc::new(RwLock::new(config)));
+        databases
+    }
+
+    #[test]
+    fn test_higher_hz_reclaims_expired_keys_sooner() {
+        let slow = start(1);
+        let fast = start(50);
+
+        thread::sleep(Duration::from_millis(300));
+
+        assert!(lock_storage(slow.get(0)).has("gone".into()));
+        assert!(!lock_storage(fast.get(0)).has("gone".into()));
+    }
+
+    fn expired_keys(count: usize) -> Mutex<Storage> {
+        let mut storage = Storage::new();
+        for i in 0..count {
+            storage.set(format!("k{}", i).into_bytes(), b"v".to_vec());
+            storage.set_expire_at(format!("k{}", i).into_bytes(), 1);
+        }
+        Mutex::new(storage)
+    }
+
+    // Expired keys aren't visible to reads, but hold memory until reclaimed
+    fn held_memory(storage: &Mutex<Storage>) -> usize {
+        lock_storage(storage).used_memory()
+    }
+
+    #[test]
+    fn test_sweep_repeats_while_most_keys_are_expired() {
+        let storage = expired_keys(100);
+
+        sweep(&storage, 10, 25);
+
+        assert_eq!(held_memory(&storage), 0);
+    }
+
+    #[test]
+    fn test_mass_expiry_is_cleared_over_several_cycles() {
+        let storage = expired_keys(1_000);
+
+        // One cycle only gets through part of them
+        sweep(&storage, 10, 25);
+        assert!(held_memory(&storage) > 0);
+
+        let mut cycles = 1;
+        while held_memory(&storage) > 0 {
+            sweep(&storage, 10, 25);
+            cycles += 1;
+            assert!(cycles < 1_000, "expired keys were never reclaimed");
+        }
+    }
+}
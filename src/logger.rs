@@ -3,8 +3,14 @@ use std::io::Write;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::thread;
 
+enum Message {
+    Line(String),
+    /// Acknowledged once every line sent before it is written.
+    Flush(Sender<()>),
+}
+
 pub struct Logger {
-    sender: Sender<String>,
+    sender: Sender<Message>,
 }
 
 impl Logger {
@@ -19,13 +25,22 @@ impl Logger {
     }
 
     pub fn log(&self, command: String) {
-        if let Err(e) = self.sender.send(command) {
+        if let Err(e) = self.sender.send(Message::Line(command)) {
             eprintln!("Failed to send log message: {}", e);
         }
     }
+
+    /// Blocks until every command logged so far has reached the log file.
+    pub fn flush(&self) {
+        let (done, flushed) = channel();
+        if self.sender.send(Message::Flush(done)).is_ok() {
+            // Fails only if the worker is gone, when there's nothing to wait for
+            let _ = flushed.recv();
+        }
+    }
 }
 
-fn log_worker(receiver: Receiver<String>, log_file: String) {
+fn log_worker(receiver: Receiver<Message>, log_file: String) {
     let mut file = match OpenOptions::new().create(true).append(true).open(&log_file) {
         Ok(f) => f,
         Err(e) => {
@@ -34,10 +49,20 @@ fn log_worker(receiver: Receiver<String>, log_file: String) {
         }
     };
 
-    while let Ok(command) = receiver.recv() {
-        let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f");
-        if let Err(e) = writeln!(file, "[{}] {}", timestamp, command) {
-            eprintln!("Failed to write to log file: {}", e);
+    while let Ok(message) = receiver.recv() {
+        match message {
+            Message::Line(command) => {
+                let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f");
+                if let Err(e) = writeln!(file, "[{}] {}", timestamp, command) {
+                    eprintln!("Failed to write to log file: {}", e);
+                }
+            }
+            Message::Flush(done) => {
+                if let Err(e) = file.flush() {
+                    eprintln!("Failed to flush log file: {}", e);
+                }
+                let _ = done.send(());
+            }
         }
     }
 }
@@ -1,28 +1,53 @@
-use std::convert::TryInto;
-use std::fs::File;
-use std::io::{self, BufWriter};
-use std::sync::{Arc, Mutex};
+use std::io::{self, BufWriter, Read, Write};
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Instant;
 use std::{
     io::BufReader,
-    net::{TcpListener, TcpStream},
+    net::{IpAddr, TcpListener, TcpStream},
 };
 
-use command::Command;
-use command_handler::handle_command;
+use command::{Command, CommandError};
+use command_handler::{handle_command, is_disabled, needs_auth};
+use config::{Config, SharedConfig};
+use connection::{ClientStream, ConnectionContext};
 use dotenvy::dotenv;
 use errors::ErrNum;
-use log::debug;
-use resp::{read_resp_from_stream, write_resp, RespError, RespValue};
-use storage::Storage;
+use resp::{read_resp_from_stream, write_resp, RespError, RespParser, RespValue};
+use shutdown::SHUTDOWN;
+use slowlog::SLOWLOG;
+use storage::{Databases, Storage};
 
+mod bench;
+mod blocking;
+mod client;
+mod clock;
 mod command;
+mod command_table;
+mod config;
+mod connection;
 mod errors;
+mod expiry;
+mod persistence;
+mod proxy;
+mod pubsub;
 mod resp;
+mod scripting;
+mod shutdown;
+mod slowlog;
+mod sorted_set;
+mod stats;
 mod storage;
+mod tls;
 mod util;
 
 mod command_handler;
 mod logger;
+mod monitor;
 use logger::Logger;
 
 fn initialize_support_systems() {
@@ -36,8 +61,8 @@ fn initialize_support_systems() {
     env_logger::init();
 }
 
-fn initialize_server() -> TcpListener {
-    let listener = match TcpListener::bind("127.0.0.1:6379") {
+fn initialize_server(config: &Config) -> TcpListener {
+    let listener = match TcpListener::bind(config.listen_addr()) {
         Ok(l) => l,
         Err(e) => {
             eprintln!("Failed to initialize TcpListener: {:?}", e);
@@ -48,90 +73,1346 @@ fn initialize_server() -> TcpListener {
     listener
 }
 
-fn handle_file(mut file: File, storage: Arc<Mutex<Storage>>) {
-    let mut reader = BufReader::new(file);
-    loop {
-        let resp_value = read_resp_from_stream(&mut reader).unwrap();
+/// Serves TLS connections accepted on `listener` on a background thread,
+/// wrapping each in a TLS session before handing it to `handle_stream`.
+fn spawn_tls_listener(
+    listener: TcpListener,
+    tls: Arc<rustls::ServerConfig>,
+    databases: Arc<Databases>,
+    logger: Arc<Logger>,
+    config: SharedConfig,
+) {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("Failed to accept TLS connection: {}", e);
+                    continue;
+                }
+            };
+            if refuse_protected(&mut stream, &config.read().unwrap()) {
+                continue;
+            }
+            if let Err(e) = configure_stream(&stream, &config.read().unwrap()) {
+                eprintln!("Failed to configure connection: {}", e);
+            }
+            let stream = match tls::TlsStream::new(tls.clone(), stream) {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("Failed to start TLS session: {}", e);
+                    continue;
+                }
+            };
+            let (databases, logger, config) = (databases.clone(), logger.clone(), config.clone());
+            thread::spawn(move || handle_stream(stream, databases, logger, config));
+        }
+    });
+}
 
-        if let RespValue::Array(Some(command_array)) = &resp_value {
-            let _ = match resp_value.try_into() {
-                Ok(command) => handle_command(command, &storage),
-                Err(e) => RespValue::Error(e.to_string()),
+/// Binds the Unix domain socket at `path`, replacing a stale socket file
+/// left behind by a previous run, and serves its connections on a
+/// background thread.
+#[cfg(unix)]
+fn spawn_unix_listener(
+    path: &str,
+    databases: Arc<Databases>,
+    logger: Arc<Logger>,
+    config: SharedConfig,
+) -> io::Result<()> {
+    let _ = std::fs::remove_file(path);
+    let listener = UnixListener::bind(path)?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("Failed to accept unix socket connection: {}", e);
+                    continue;
+                }
             };
+            let (databases, logger, config) = (databases.clone(), logger.clone(), config.clone());
+            thread::spawn(move || handle_stream(stream, databases, logger, config));
         }
-    }
+    });
+    Ok(())
 }
 
-fn handle_stream(mut stream: TcpStream, storage: Arc<Mutex<Storage>>, logger: Arc<Logger>) {
-    stream.set_nonblocking(false).unwrap();
-    let mut reader = BufReader::new(stream.try_clone().unwrap());
+/// Replays a stream of RESP-encoded commands against `storage` until EOF,
+/// returning how many commands were applied.
+#[allow(dead_code)]
+fn handle_file<R: Read>(file: R, databases: &Databases) -> Result<usize, RespError> {
+    let mut reader = BufReader::new(file);
+    let mut ctx = ConnectionContext::default();
+    let mut applied = 0;
 
     loop {
         let resp_value = match read_resp_from_stream(&mut reader) {
             Ok(value) => value,
+            Err(RespError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        };
+
+        if let RespValue::Array(Some(_)) = &resp_value {
+            match Command::try_from(resp_value) {
+                Ok(command) => {
+                    if let RespValue::Error(e) = handle_command(command, databases, &mut ctx) {
+                        eprintln!("Error replaying command: {}", e);
+                    }
+                    applied += 1;
+                }
+                Err(e) => eprintln!("Skipping unparseable command: {}", e),
+            }
+        }
+    }
+
+    Ok(applied)
+}
+
+fn load_databases(count: usize) -> Databases {
+    let empty = || Databases::new(Storage::new(), count);
+    if std::env::var("PERSISTENCE").unwrap_or_default() != "snapshot" {
+        return empty();
+    }
+
+    let path = persistence::snapshot_path();
+    match persistence::load(&path, count) {
+        Ok(storages) => storages.into_iter().collect(),
+        Err(RespError::IoError(e)) if e.kind() == io::ErrorKind::NotFound => empty(),
+        Err(e) => {
+            eprintln!("Failed to load snapshot {}: {}", path, e);
+            std::process::exit(ErrNum::Configuration as i32);
+        }
+    }
+}
+
+const PROTECTED_MODE_DENIED: &str = "-DENIED Running in protected mode because protected \
+mode is enabled and no password is set. In this mode connections are only accepted from the \
+loopback interface. To accept external connections, set a password with requirepass or \
+disable protected mode by sending 'CONFIG SET protected-mode no' from the loopback interface \
+or by setting 'protected-mode no' in the config file.\r\n";
+
+/// Tells a client connecting from `peer` that protected mode won't admit it.
+/// Returns whether the connection should be dropped.
+fn deny_peer<W: Write>(peer: IpAddr, config: &Config, stream: &mut W) -> bool {
+    if !config.refuses_peer(peer) {
+        return false;
+    }
+    eprintln!("Refusing connection from {} in protected mode", peer);
+    let _ = stream.write_all(PROTECTED_MODE_DENIED.as_bytes());
+    true
+}
+
+/// `deny_peer` for a freshly accepted TCP connection.
+fn refuse_protected(stream: &mut TcpStream, config: &Config) -> bool {
+    match stream.peer_addr() {
+        Ok(peer) => deny_peer(peer.ip(), config, stream),
+        Err(_) => false,
+    }
+}
+
+// Replies are flushed per command, so Nagle's algorithm would only add latency
+fn configure_stream(stream: &TcpStream, config: &Config) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    if let Some(idle) = config.keepalive_duration() {
+        let keepalive = socket2::TcpKeepalive::new().with_time(idle);
+        socket2::SockRef::from(stream).set_tcp_keepalive(&keepalive)?;
+    }
+    Ok(())
+}
+
+/// Runs one command's handler, turning a panic into an error reply so a
+/// single bad command cannot take its connection thread down.
+fn isolate(handler: impl FnOnce() -> RespValue) -> RespValue {
+    panic::catch_unwind(AssertUnwindSafe(handler)).unwrap_or_else(|_| {
+        RespValue::Error("ERR internal error while executing command".to_string())
+    })
+}
+
+/// Writes one reply. If the write fails part of the reply may already be on
+/// the wire, leaving the client unable to tell where the next one starts, so
+/// the connection is shut down in both directions.
+fn send_reply<S: ClientStream>(stream: &mut S, response: &RespValue) -> io::Result<()> {
+    let result = write_resp(response, &mut BufWriter::new(&mut *stream));
+    if let Err(e) = &result {
+        eprintln!("Error writing response: {}", e);
+        stream.shutdown();
+    }
+    result
+}
+
+/// Queues a command's reply in `pending`, or sends it through the
+/// connection's pub/sub outbox once it has one so the reply can't overtake
+/// messages queued ahead of it.
+fn respond(ctx: &ConnectionContext, pending: &mut Vec<u8>, response: &RespValue) -> io::Result<()> {
+    match pubsub::reply(ctx, response) {
+        Some(true) => Ok(()),
+        Some(false) => Err(io::ErrorKind::BrokenPipe.into()),
+        None => write_resp(response, &mut BufWriter::new(pending)),
+    }
+}
+
+/// Writes the queued replies in one go. Like `send_reply`, a failed write
+/// shuts the connection down.
+fn flush_replies<S: ClientStream>(stream: &mut S, pending: &mut Vec<u8>) -> io::Result<()> {
+    if pending.is_empty() {
+        return Ok(());
+    }
+    let result = stream.write_all(pending);
+    pending.clear();
+    if let Err(e) = &result {
+        eprintln!("Error writing response: {}", e);
+        stream.shutdown();
+    }
+    result
+}
+
+fn handle_stream<S: ClientStream>(
+    mut stream: S,
+    databases: Arc<Databases>,
+    logger: Arc<Logger>,
+    config: SharedConfig,
+) {
+    let mut addr = stream.peer_addr();
+    if config.read().unwrap().proxy_protocol {
+        match proxy::read_header(&mut stream) {
+            Ok(Some(source)) => addr = source,
+            Ok(None) => {}
             Err(e) => {
-                if let RespError::IoError(io_err) = &e {
-                    if io_err.kind() == io::ErrorKind::UnexpectedEof
-                        || io_err.kind() == io::ErrorKind::ConnectionReset
-                    {
+                eprintln!("Closing connection from {}: {}", addr, e);
+                stream.shutdown();
+                return;
+            }
+        }
+    }
+    let mut parser = RespParser::with_max_request_size(config.read().unwrap().max_request_size);
+    let mut ctx = ConnectionContext {
+        id: connection::next_client_id(),
+        config: config.clone(),
+        ..ConnectionContext::default()
+    };
+    let _pubsub = pubsub::DisconnectGuard(ctx.id);
+    let mut chunk = [0u8; 4096];
+    // Replies to the commands already read, sent once the parser runs dry so
+    // that a pipelined batch is answered with a single write
+    let mut pending = Vec::new();
+
+    loop {
+        let resp_value = match parser.next_value() {
+            Ok(Some(value)) => value,
+            Ok(None) => {
+                if flush_replies(&mut stream, &mut pending).is_err() {
+                    return;
+                }
+                match stream.read(&mut chunk) {
+                    Ok(0) => return,
+                    Ok(n) => parser.feed(&chunk[..n]),
+                    Err(e) => {
+                        if e.kind() != io::ErrorKind::ConnectionReset {
+                            eprintln!("Error reading from stream: {}", e);
+                        }
                         return;
                     }
                 }
-                eprintln!("Error reading from stream: {}", e);
                 continue;
             }
+            Err(e) => {
+                // RESP has no safe resync point once the parser is positioned
+                // mid-value, so reply with the error and drop the connection
+                if flush_replies(&mut stream, &mut pending).is_ok() {
+                    let _ = send_reply(&mut stream, &e.to_client_reply());
+                }
+                return;
+            }
+        };
+
+        // Once a shutdown starts, connections close rather than run more
+        // commands
+        let Some(_in_flight) = SHUTDOWN.begin() else {
+            let _ = flush_replies(&mut stream, &mut pending);
+            stream.shutdown();
+            return;
         };
 
         if let RespValue::Array(Some(command_array)) = &resp_value {
+            let args = command_array
+                .iter()
+                .map(|v| match v {
+                    RespValue::BulkString(Some(bytes)) => {
+                        String::from_utf8_lossy(bytes).into_owned()
+                    }
+                    RespValue::SimpleString(s) => s.to_string(),
+                    _ => String::new(),
+                })
+                .collect::<Vec<_>>();
+
             if let Some(RespValue::BulkString(Some(cmd_name))) = command_array.first() {
-                let command_str = command_array
-                    .iter()
-                    .skip(1)
-                    .map(|v| match v {
-                        RespValue::BulkString(Some(s)) => s.to_string(),
-                        RespValue::SimpleString(s) => s.to_string(),
-                        _ => String::new(),
-                    })
-                    .collect::<Vec<_>>()
-                    .join(" ");
-
-                logger.log(format!("{} {}", cmd_name.to_uppercase(), command_str));
+                let command_str = args[1..].join(" ");
+                logger.log(format!(
+                    "{} {}",
+                    String::from_utf8_lossy(cmd_name).to_uppercase(),
+                    command_str
+                ));
             }
 
-            let response = match resp_value.try_into() {
-                Ok(command) => handle_command(command, &storage),
-                Err(e) => RespValue::Error(e.to_string()),
+            let parsed =
+                command::parse_renamed(resp_value, &config.read().unwrap().rename_commands);
+            // A disabled command gets the same reply as one that doesn't
+            // exist, whatever its arguments
+            let unknown = || Err(CommandError::UnknownCommand(args[0].to_uppercase()));
+            let parsed = match parsed {
+                Ok(command) if is_disabled(&ctx, &command) => unknown(),
+                Err(_) if config.read().unwrap().is_disabled(&args[0].to_lowercase()) => unknown(),
+                parsed => parsed,
             };
-            let mut writer = BufWriter::new(&mut stream);
-            if let Err(e) = write_resp(&response, &mut writer) {
-                eprintln!("Error writing response: {}", e);
-                break;
+            // Earlier replies go out before a command that may block, or that
+            // hands the connection to writers on other threads
+            let flush_first = matches!(
+                parsed,
+                Ok(Command::Subscribe { .. }
+                    | Command::Unsubscribe { .. }
+                    | Command::PSubscribe { .. }
+                    | Command::PUnsubscribe { .. }
+                    | Command::Monitor
+                    | Command::BLPop { .. }
+                    | Command::BRPop { .. })
+            );
+            if flush_first && flush_replies(&mut stream, &mut pending).is_err() {
+                return;
+            }
+            let response = match parsed {
+                Ok(
+                    command @ (Command::Subscribe { .. }
+                    | Command::Unsubscribe { .. }
+                    | Command::PSubscribe { .. }
+                    | Command::PUnsubscribe { .. }),
+                ) if ctx.transaction.is_none() && !needs_auth(&ctx) => {
+                    monitor::feed(&addr, ctx.db, &args);
+                    // Confirmations go out through the connection's outbox, in
+                    // order with the messages delivered to it
+                    let writer = || Ok(Box::new(stream.try_clone()?) as Box<dyn pubsub::Outlet>);
+                    let result = match command {
+                        Command::Subscribe { channels } => {
+                            pubsub::subscribe(&mut ctx, pubsub::Kind::Channel, channels, writer)
+                        }
+                        Command::PSubscribe { patterns } => {
+                            pubsub::subscribe(&mut ctx, pubsub::Kind::Pattern, patterns, writer)
+                        }
+                        Command::Unsubscribe { channels } => {
+                            pubsub::unsubscribe(&mut ctx, pubsub::Kind::Channel, channels, writer)
+                        }
+                        Command::PUnsubscribe { patterns } => {
+                            pubsub::unsubscribe(&mut ctx, pubsub::Kind::Pattern, patterns, writer)
+                        }
+                        _ => unreachable!(),
+                    };
+                    match result {
+                        Ok(()) => continue,
+                        Err(e) => RespValue::Error(format!("ERR {}", e)),
+                    }
+                }
+                // Inside MULTI these are left to handle_command to queue
+                Ok(Command::Monitor) if ctx.transaction.is_none() && !needs_auth(&ctx) => {
+                    monitor::feed(&addr, ctx.db, &args);
+                    let outlet = || Ok(Box::new(stream.try_clone()?) as Box<dyn pubsub::Outlet>);
+                    match monitor::register(outlet, ctx.config.clone()) {
+                        Ok(()) => RespValue::SimpleString("OK".to_string()),
+                        Err(e) => RespValue::Error(format!("ERR {}", e)),
+                    }
+                }
+                Ok(command) => {
+                    monitor::feed(&addr, ctx.db, &args);
+                    let start = Instant::now();
+                    let response = isolate(|| handle_command(command, &databases, &mut ctx));
+                    let elapsed = start.elapsed();
+                    let (is_slow, slowlog_max_len) = {
+                        let config = config.read().unwrap();
+                        (config.is_slow(elapsed), config.slowlog_max_len)
+                    };
+                    if is_slow {
+                        SLOWLOG.record(args, elapsed, slowlog_max_len);
+                    }
+                    response
+                }
+                Err(e) => {
+                    // A command that cannot be queued dooms the whole transaction
+                    if ctx.transaction.is_some() {
+                        ctx.transaction_dirty = true;
+                    }
+                    RespValue::Error(e.to_string())
+                }
+            };
+            if respond(&ctx, &mut pending, &response).is_err()
+                || flush_first && flush_replies(&mut stream, &mut pending).is_err()
+            {
+                return;
             }
         } else {
             let response = RespValue::Error("Invalid command".to_string());
-            let mut writer = BufWriter::new(&mut stream);
-            if let Err(e) = write_resp(&response, &mut writer) {
-                eprintln!("Error writing response: {}", e);
-                break;
+            if respond(&ctx, &mut pending, &response).is_err() {
+                return;
             }
         }
     }
 }
 
 fn main() {
+    let mut args = std::env::args().skip(1).peekable();
+    if args.next_if_eq("bench").is_some() {
+        if let Err(e) = bench::main(args) {
+            eprintln!("{}", e);
+            std::process::exit(ErrNum::Configuration as i32);
+        }
+        return;
+    }
+
     initialize_support_systems();
 
-    let storage = Arc::new(Mutex::new(Storage::new()));
+    // Like redis-server, the only positional argument is a config file
+    let mut config = Config::from_env();
+    if let Some(path) = args.next() {
+        if let Err(e) = config.load_file(Path::new(&path)) {
+            eprintln!("Failed to load config: {}", e);
+            std::process::exit(ErrNum::Configuration as i32);
+        }
+    }
+
+    persistence::mark_server_start();
     let log_file = std::env::var("COMMAND_LOG").unwrap_or_else(|_| "commands.log".to_string());
     let logger = Arc::new(Logger::new(log_file));
 
-    let server = initialize_server();
+    let databases = Arc::new(load_databases(config.databases));
+    let config = Arc::new(RwLock::new(config));
+    expiry::spawn_sweeper(databases.clone(), config.clone());
+
+    #[cfg(unix)]
+    {
+        let (databases, logger) = (databases.clone(), logger.clone());
+        let snapshot = (std::env::var("PERSISTENCE").unwrap_or_default() == "snapshot")
+            .then(persistence::snapshot_path);
+        let result = shutdown::install_handlers(move || {
+            let code = shutdown::shut_down(
+                &SHUTDOWN,
+                &databases,
+                &logger,
+                snapshot.as_deref(),
+                shutdown::DRAIN_TIMEOUT,
+            );
+            std::process::exit(code);
+        });
+        if let Err(e) = result {
+            eprintln!("Failed to install signal handlers: {}", e);
+        }
+    }
+
+    let unixsocket = config.read().unwrap().unixsocket.clone();
+    if !unixsocket.is_empty() {
+        #[cfg(unix)]
+        if let Err(e) = spawn_unix_listener(
+            &unixsocket,
+            databases.clone(),
+            logger.clone(),
+            config.clone(),
+        ) {
+            eprintln!("Failed to open unix socket {}: {}", unixsocket, e);
+            std::process::exit(e.raw_os_error().unwrap_or(ErrNum::Connection as i32));
+        }
+        #[cfg(not(unix))]
+        eprintln!(
+            "Ignoring unixsocket {}: not supported on this platform",
+            unixsocket
+        );
+    }
+
+    let tls_port = config.read().unwrap().tls_port;
+    if tls_port != 0 {
+        let (addr, tls) = {
+            let config = config.read().unwrap();
+            let tls = tls::server_config(&config.tls_cert_file, &config.tls_key_file);
+            (config.tls_listen_addr(), tls)
+        };
+        let tls = tls.unwrap_or_else(|e| {
+            eprintln!("Failed to configure TLS: {}", e);
+            std::process::exit(ErrNum::Configuration as i32);
+        });
+        let listener = TcpListener::bind(&addr).unwrap_or_else(|e| {
+            eprintln!("Failed to listen for TLS on {}: {:?}", addr, e);
+            std::process::exit(e.raw_os_error().unwrap_or(ErrNum::Connection as i32));
+        });
+        spawn_tls_listener(
+            listener,
+            tls,
+            databases.clone(),
+            logger.clone(),
+            config.clone(),
+        );
+    }
+
+    let server = initialize_server(&config.read().unwrap());
 
     for stream in server.incoming() {
-        let storage = storage.clone();
+        // Turn new connections away while the signal handler shuts down
+        if SHUTDOWN.is_requested() {
+            continue;
+        }
+        let databases = databases.clone();
         let logger = logger.clone();
-        let file = File::open("commands.log").unwrap();
-        //handle_file(file, storage.clone());
-        handle_stream(stream.unwrap(), storage.clone(), logger);
+        let mut stream = stream.unwrap();
+        if refuse_protected(&mut stream, &config.read().unwrap()) {
+            continue;
+        }
+        if let Err(e) = configure_stream(&stream, &config.read().unwrap()) {
+            eprintln!("Failed to configure connection: {}", e);
+        }
+        //handle_file(File::open("commands.log").unwrap(), &databases);
+        let config = config.clone();
+        thread::spawn(move || handle_stream(stream, databases, logger, config));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, Cursor, Write};
+    use std::net::Shutdown;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn spawn_connection() -> TcpStream {
+        spawn_connection_with_config(Config::default())
+    }
+
+    fn spawn_connection_with_config(config: Config) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let storage = Arc::new(Databases::new(Storage::new(), Config::default().databases));
+        let log_file = std::env::temp_dir().join(format!("rrrr-test-{}.log", std::process::id()));
+        let logger = Arc::new(Logger::new(log_file.to_string_lossy().into_owned()));
+
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handle_stream(stream, storage, logger, Arc::new(RwLock::new(config)));
+        });
+        TcpStream::connect(addr).unwrap()
+    }
+
+    fn spawn_server() -> std::net::SocketAddr {
+        spawn_server_with(Arc::new(Databases::new(
+            Storage::new(),
+            Config::default().databases,
+        )))
+    }
+
+    fn spawn_server_with(storage: Arc<Databases>) -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let log_file = std::env::temp_dir().join(format!("rrrr-test-{}.log", std::process::id()));
+        let logger = Arc::new(Logger::new(log_file.to_string_lossy().into_owned()));
+        let config = Arc::new(RwLock::new(Config::default()));
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let (storage, logger, config) = (storage.clone(), logger.clone(), config.clone());
+                thread::spawn(move || handle_stream(stream.unwrap(), storage, logger, config));
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_configure_stream_enables_nodelay_and_keepalive() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (accepted, _) = listener.accept().unwrap();
+
+        configure_stream(&accepted, &Config::default()).unwrap();
+
+        assert!(accepted.nodelay().unwrap());
+        assert!(socket2::SockRef::from(&accepted).keepalive().unwrap());
+    }
+
+    #[test]
+    fn test_configure_stream_keepalive_disabled() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (accepted, _) = listener.accept().unwrap();
+        let config = Config {
+            tcp_keepalive: 0,
+            ..Config::default()
+        };
+
+        configure_stream(&accepted, &config).unwrap();
+
+        assert!(accepted.nodelay().unwrap());
+        assert!(!socket2::SockRef::from(&accepted).keepalive().unwrap());
+    }
+
+    #[test]
+    fn test_protocol_error_replies_and_closes_connection() {
+        let mut client = spawn_connection();
+
+        client
+            .write_all(b"!garbage\r\n*1\r\n$4\r\nPING\r\n")
+            .unwrap();
+
+        let mut reply = String::new();
+        client.read_to_string(&mut reply).unwrap();
+        assert_eq!(reply, "-ERR Protocol error: expected '$', got '!'\r\n");
+    }
+
+    #[test]
+    fn test_oversized_request_closes_connection() {
+        let mut client = spawn_connection_with_config(Config {
+            max_request_size: 8,
+            ..Config::default()
+        });
+
+        client
+            .write_all(b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n")
+            .unwrap();
+
+        let mut reply = String::new();
+        client.read_to_string(&mut reply).unwrap();
+        assert_eq!(reply, "-ERR Protocol error: invalid multibulk length\r\n");
+    }
+
+    #[test]
+    fn test_write_error_closes_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (accepted, _) = listener.accept().unwrap();
+        accepted.shutdown(Shutdown::Write).unwrap();
+        let log_file = std::env::temp_dir().join(format!("rrrr-test-{}.log", std::process::id()));
+        let logger = Arc::new(Logger::new(log_file.to_string_lossy().into_owned()));
+        let databases = Arc::new(Databases::new(Storage::new(), 1));
+
+        let (done, finished) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            handle_stream(accepted, databases, logger, SharedConfig::default());
+            done.send(()).unwrap();
+        });
+        client
+            .write_all(b"*1\r\n$4\r\nPING\r\n*1\r\n$4\r\nPING\r\n")
+            .unwrap();
+
+        finished
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .expect("the connection loop should stop after a failed write");
+        // The client sees the close as EOF, or as a reset if data was unread
+        let mut rest = Vec::new();
+        let read = client.read_to_end(&mut rest);
+        assert!(!matches!(read, Ok(n) if n > 0));
+    }
+
+    #[test]
+    fn test_valid_command_after_connect() {
+        let mut client = spawn_connection();
+
+        client.write_all(b"*1\r\n$4\r\nPING\r\n").unwrap();
+
+        let mut reply = [0u8; 7];
+        client.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"+PONG\r\n");
+    }
+
+    #[test]
+    fn test_debug_sleep_above_threshold_is_slowlogged() {
+        let mut client = spawn_connection_with_config(Config {
+            slowlog_log_slower_than: 20_000,
+            ..Config::default()
+        });
+
+        client
+            .write_all(b"*3\r\n$5\r\nDEBUG\r\n$5\r\nSLEEP\r\n$4\r\n0.05\r\n")
+            .unwrap();
+        let mut reply = [0u8; 5];
+        client.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"+OK\r\n");
+
+        // Other tests share the global slowlog, so look for this command's
+        // arguments rather than a specific position
+        let RespValue::Array(Some(entries)) = SLOWLOG.get(usize::MAX) else {
+            panic!("SLOWLOG GET should return an array");
+        };
+        let expected = RespValue::Array(Some(
+            ["DEBUG", "SLEEP", "0.05"]
+                .iter()
+                .map(|arg| RespValue::BulkString(Some(arg.as_bytes().to_vec())))
+                .collect(),
+        ));
+        let entry = entries
+            .iter()
+            .find_map(|entry| match entry {
+                RespValue::Array(Some(fields)) if fields[3] == expected => Some(fields),
+                _ => None,
+            })
+            .expect("DEBUG SLEEP should be in the slowlog");
+        assert!(matches!(entry[2], RespValue::Integer(us) if us >= 50_000));
+    }
+
+    #[test]
+    fn test_monitor_observes_other_clients() {
+        let addr = spawn_server();
+        let mut monitor = TcpStream::connect(addr).unwrap();
+        monitor.write_all(b"*1\r\n$7\r\nMONITOR\r\n").unwrap();
+        let mut lines = io::BufReader::new(monitor.try_clone().unwrap()).lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "+OK");
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .write_all(b"*3\r\n$3\r\nSET\r\n$13\r\nmonitored-key\r\n$1\r\nv\r\n")
+            .unwrap();
+
+        // Monitors see every connection in the process, including other tests
+        let line = lines
+            .map(|line| line.unwrap())
+            .find(|line| line.contains("monitored-key"))
+            .unwrap();
+        let client_addr = client.local_addr().unwrap();
+        assert!(line.starts_with('+'));
+        assert!(line.ends_with(&format!(
+            "[0 {}] \"SET\" \"monitored-key\" \"v\"",
+            client_addr
+        )));
+    }
+
+    #[test]
+    fn test_monitor_shows_the_selected_database() {
+        let addr = spawn_server();
+        let mut monitor = TcpStream::connect(addr).unwrap();
+        monitor.write_all(b"*1\r\n$7\r\nMONITOR\r\n").unwrap();
+        let mut lines = io::BufReader::new(monitor).lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "+OK");
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .write_all(b"*2\r\n$6\r\nSELECT\r\n$1\r\n3\r\n")
+            .unwrap();
+        client
+            .write_all(b"*2\r\n$3\r\nGET\r\n$12\r\nselected-key\r\n")
+            .unwrap();
+
+        let line = lines
+            .map(|line| line.unwrap())
+            .find(|line| line.contains("selected-key"))
+            .unwrap();
+        let client_addr = client.local_addr().unwrap();
+        assert!(line.ends_with(&format!("[3 {}] \"GET\" \"selected-key\"", client_addr)));
+    }
+
+    #[test]
+    fn test_monitor_that_stops_reading_is_disconnected() {
+        let mut monitor = spawn_connection_with_config(Config {
+            client_output_buffer_limit: [
+                config::OutputBufferLimit {
+                    hard: 64 * 1024,
+                    soft: 0,
+                    soft_seconds: 0,
+                },
+                Default::default(),
+                Default::default(),
+            ],
+            ..Config::default()
+        });
+        monitor.write_all(b"*1\r\n$7\r\nMONITOR\r\n").unwrap();
+        let mut monitor = BufReader::new(monitor);
+        let mut reply = String::new();
+        monitor.read_line(&mut reply).unwrap();
+        assert_eq!(reply, "+OK\r\n");
+
+        // Well past what the socket buffers hold, so the monitor's outbox
+        // fills while this client keeps getting its replies
+        let mut client = TcpStream::connect(spawn_server()).unwrap();
+        let mut replies = BufReader::new(client.try_clone().unwrap());
+        let message = "x".repeat(16 * 1024);
+        let set = format!(
+            "*3\r\n$3\r\nSET\r\n$12\r\nmonitor-load\r\n${}\r\n{}\r\n",
+            message.len(),
+            message
+        );
+        for _ in 0..2_000 {
+            client.write_all(set.as_bytes()).unwrap();
+            assert!(matches!(
+                read_resp_from_stream(&mut replies),
+                Ok(RespValue::SimpleString(_))
+            ));
+        }
+
+        monitor
+            .get_ref()
+            .set_read_timeout(Some(std::time::Duration::from_secs(10)))
+            .unwrap();
+        let mut received = Vec::new();
+        monitor.read_to_end(&mut received).unwrap();
+        assert!(!received.is_empty());
+    }
+
+    #[test]
+    fn test_blpop_wakes_when_another_client_pushes() {
+        let addr = spawn_server();
+        let mut blocked = TcpStream::connect(addr).unwrap();
+        blocked
+            .write_all(b"*3\r\n$5\r\nBLPOP\r\n$12\r\nblpop-wakeup\r\n$1\r\n5\r\n")
+            .unwrap();
+
+        // Give BLPOP time to find the list empty and start waiting
+        thread::sleep(std::time::Duration::from_millis(100));
+        let mut pusher = TcpStream::connect(addr).unwrap();
+        pusher
+            .write_all(b"*3\r\n$5\r\nRPUSH\r\n$12\r\nblpop-wakeup\r\n$1\r\nv\r\n")
+            .unwrap();
+        let mut reply = [0u8; 4];
+        pusher.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b":1\r\n");
+
+        let expected = b"*2\r\n$12\r\nblpop-wakeup\r\n$1\r\nv\r\n";
+        let mut reply = vec![0u8; expected.len()];
+        blocked.read_exact(&mut reply).unwrap();
+        assert_eq!(reply, expected);
+    }
+
+    #[test]
+    fn test_unparseable_command_in_multi_aborts_exec() {
+        let mut client = spawn_connection();
+
+        client
+            .write_all(b"*1\r\n$5\r\nMULTI\r\n*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n")
+            .unwrap();
+        client
+            .write_all(b"*1\r\n$6\r\nNOSUCH\r\n*1\r\n$4\r\nEXEC\r\n")
+            .unwrap();
+        client.write_all(b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n").unwrap();
+
+        let mut lines = io::BufReader::new(client).lines().map(|line| line.unwrap());
+        assert_eq!(lines.next().unwrap(), "+OK");
+        assert_eq!(lines.next().unwrap(), "+QUEUED");
+        assert_eq!(lines.next().unwrap(), "-unknown command 'NOSUCH'");
+        assert_eq!(
+            lines.next().unwrap(),
+            "-EXECABORT Transaction discarded because of previous errors."
+        );
+        assert_eq!(lines.next().unwrap(), "$-1");
+    }
+
+    #[test]
+    fn test_disabled_command_replies_like_an_unknown_one() {
+        let client = spawn_connection_with_config(Config {
+            disabled_commands: vec!["flushall".to_string()],
+            ..Config::default()
+        });
+        (&client)
+            .write_all(b"*1\r\n$8\r\nFlushAll\r\n*1\r\n$8\r\nFlushAlx\r\n")
+            .unwrap();
+        (&client)
+            .write_all(b"*2\r\n$8\r\nFlushAll\r\n$5\r\nbogus\r\n")
+            .unwrap();
+
+        let mut lines = io::BufReader::new(client).lines().map(|line| line.unwrap());
+        let disabled = lines.next().unwrap();
+        let unknown = lines.next().unwrap();
+        assert_eq!(disabled, unknown.replace("FLUSHALX", "FLUSHALL"));
+        assert_eq!(disabled, "-unknown command 'FLUSHALL'");
+        assert_eq!(lines.next().unwrap(), disabled);
+    }
+
+    #[test]
+    fn test_subscribe_inside_multi_is_queued() {
+        let mut client = spawn_connection();
+
+        client
+            .write_all(b"*1\r\n$5\r\nMULTI\r\n*2\r\n$9\r\nSUBSCRIBE\r\n$2\r\nch\r\n")
+            .unwrap();
+        client
+            .write_all(b"*1\r\n$4\r\nEXEC\r\n*1\r\n$4\r\nPING\r\n")
+            .unwrap();
+
+        let mut lines = io::BufReader::new(client).lines().map(|line| line.unwrap());
+        assert_eq!(lines.next().unwrap(), "+OK");
+        assert_eq!(lines.next().unwrap(), "+QUEUED");
+        assert_eq!(lines.next().unwrap(), "*1");
+        assert_eq!(lines.next().unwrap(), "-ERR SUBSCRIBE is not allowed here");
+        // The connection never entered subscribed mode
+        assert_eq!(lines.next().unwrap(), "+PONG");
+    }
+
+    #[test]
+    fn test_client_library_handshake() {
+        let mut client = spawn_connection();
+        let handshake: &[&[&str]] = &[
+            &["CLIENT", "SETINFO", "LIB-NAME", "redis-py"],
+            &["CLIENT", "SETINFO", "LIB-VER", "5.0.1"],
+            &["SELECT", "0"],
+            &["CLIENT", "NO-EVICT", "on"],
+            &["CLIENT", "NO-TOUCH", "off"],
+            &["PING"],
+        ];
+        for args in handshake {
+            let command = RespValue::Array(Some(
+                args.iter()
+                    .map(|arg| RespValue::BulkString(Some(arg.as_bytes().to_vec())))
+                    .collect(),
+            ));
+            write_resp(&command, &mut BufWriter::new(&mut client)).unwrap();
+        }
+
+        let mut lines = io::BufReader::new(client).lines().map(|line| line.unwrap());
+        for expected in ["+OK", "+OK", "+OK", "+OK", "+OK", "+PONG"] {
+            assert_eq!(lines.next().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_panicking_command_does_not_break_other_connections() {
+        let databases = Arc::new(Databases::new(Storage::new(), Config::default().databases));
+
+        // A handler that panics while holding the lock also poisons it
+        let reply = isolate(|| {
+            let _storage = databases.get(0).lock().unwrap();
+            panic!("handler bug");
+        });
+        assert_eq!(
+            reply,
+            RespValue::Error("ERR internal error while executing command".to_string())
+        );
+        assert!(databases.get(0).is_poisoned());
+
+        let mut client = TcpStream::connect(spawn_server_with(databases)).unwrap();
+        client
+            .write_all(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n*2\r\n$3\r\nGET\r\n$1\r\nk\r\n")
+            .unwrap();
+        let mut reply = [0u8; 12];
+        client.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"+OK\r\n$1\r\nv\r\n");
+    }
+
+    #[test]
+    fn test_handle_file_replays_until_eof() {
+        let input = concat!(
+            "*3\r\n$3\r\nSET\r\n$2\r\nk1\r\n$2\r\nv1\r\n",
+            "*3\r\n$3\r\nSET\r\n$2\r\nk2\r\n$1\r\n5\r\n",
+            "*2\r\n$4\r\nINCR\r\n$2\r\nk2\r\n",
+            "*2\r\n$3\r\nDEL\r\n$2\r\nk1\r\n",
+        );
+        let storage = Arc::new(Databases::new(Storage::new(), Config::default().databases));
+
+        let applied = handle_file(Cursor::new(input), &storage).unwrap();
+
+        assert_eq!(applied, 4);
+        let mut storage = storage.get(0).lock().unwrap();
+        assert_eq!(storage.get("k1".into()), Ok(None));
+        assert_eq!(storage.get("k2".into()), Ok(Some(b"6".to_vec())));
+    }
+
+    #[test]
+    fn test_handle_file_empty_input() {
+        let storage = Arc::new(Databases::new(Storage::new(), Config::default().databases));
+
+        assert_eq!(handle_file(Cursor::new(""), &storage).unwrap(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_ping_over_unix_socket() {
+        use std::os::unix::net::UnixStream;
+
+        let path = std::env::temp_dir().join(format!("rrrr-test-{}.sock", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        let storage = Arc::new(Databases::new(Storage::new(), Config::default().databases));
+        let log_file = std::env::temp_dir().join(format!("rrrr-test-{}.log", std::process::id()));
+        let logger = Arc::new(Logger::new(log_file.to_string_lossy().into_owned()));
+        let config = Arc::new(RwLock::new(Config::default()));
+        spawn_unix_listener(&path, storage, logger, config).unwrap();
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"*1\r\n$4\r\nPING\r\n").unwrap();
+
+        let mut reply = String::new();
+        BufReader::new(&mut client).read_line(&mut reply).unwrap();
+        assert_eq!(reply, "+PONG\r\n");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// A connection whose input is fixed up front and whose replies are
+    /// collected in memory, for driving `handle_stream` without a socket.
+    struct MemoryStream {
+        input: Cursor<Vec<u8>>,
+        output: Arc<std::sync::Mutex<Vec<u8>>>,
+        /// Reads and writes made, standing in for syscalls on a socket.
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl MemoryStream {
+        fn new(input: &[u8]) -> Self {
+            Self {
+                input: Cursor::new(input.to_vec()),
+                output: Arc::default(),
+                calls: Arc::default(),
+            }
+        }
+
+        /// Runs a connection over this stream to completion, returning the
+        /// replies written.
+        fn serve(self) -> Vec<u8> {
+            let output = self.output.clone();
+            let storage = Arc::new(Databases::new(Storage::new(), Config::default().databases));
+            let log_file =
+                std::env::temp_dir().join(format!("rrrr-test-{}.log", std::process::id()));
+            let logger = Arc::new(Logger::new(log_file.to_string_lossy().into_owned()));
+
+            handle_stream(
+                self,
+                storage,
+                logger,
+                Arc::new(RwLock::new(Config::default())),
+            );
+
+            let output = output.lock().unwrap();
+            output.clone()
+        }
+    }
+
+    impl Read for MemoryStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MemoryStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            self.output.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl ClientStream for MemoryStream {
+        fn try_clone(&self) -> io::Result<Self> {
+            Err(io::ErrorKind::Unsupported.into())
+        }
+
+        fn peer_addr(&self) -> String {
+            "memory:0".to_string()
+        }
+
+        fn shutdown(&self) {}
+    }
+
+    #[test]
+    fn test_protected_mode_denies_remote_peer() {
+        let config = Config {
+            bind: "0.0.0.0".to_string(),
+            ..Config::default()
+        };
+        let mut output = Vec::new();
+
+        assert!(!deny_peer(
+            "127.0.0.1".parse().unwrap(),
+            &config,
+            &mut output
+        ));
+        assert!(output.is_empty());
+        assert!(deny_peer(
+            "192.168.1.20".parse().unwrap(),
+            &config,
+            &mut output
+        ));
+        let reply = String::from_utf8(output).unwrap();
+        assert!(reply.starts_with("-DENIED "));
+        assert!(reply.contains("CONFIG SET protected-mode no"));
+        assert!(reply.ends_with("\r\n"));
+    }
+
+    #[test]
+    fn test_handle_stream_over_memory_stream() {
+        let stream = MemoryStream::new(
+            b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n*2\r\n$3\r\nGET\r\n$1\r\nk\r\n",
+        );
+
+        assert_eq!(stream.serve(), b"+OK\r\n$1\r\nv\r\n");
+    }
+
+    #[test]
+    fn test_binary_key_and_value_round_trip() {
+        let stream = MemoryStream::new(
+            b"*3\r\n$3\r\nSET\r\n$3\r\n\xff\x00k\r\n$2\r\n\x00\xfe\r\n*2\r\n$3\r\nGET\r\n$3\r\n\xff\x00k\r\n",
+        );
+
+        assert_eq!(stream.serve(), b"+OK\r\n$2\r\n\x00\xfe\r\n");
+    }
+
+    #[test]
+    fn test_boolean_replies_are_integers() {
+        let commands: &[(&[&str], &str)] = &[
+            (&["SET", "k", "v"], "+OK"),
+            (&["EXISTS", "k"], ":1"),
+            (&["HSETNX", "h", "f", "v"], ":1"),
+            (&["HSETNX", "h", "f", "v"], ":0"),
+            (&["EXPIRE", "k", "100"], ":1"),
+            (&["EXPIRE", "missing", "100"], ":0"),
+            (&["PERSIST", "k"], ":1"),
+            (&["PERSIST", "k"], ":0"),
+            (&["COPY", "k", "copy"], ":1"),
+            (&["COPY", "missing", "copy"], ":0"),
+            (&["MOVE", "k", "1"], ":1"),
+            (&["MOVE", "k", "1"], ":0"),
+            (&["SADD", "s", "m"], ":1"),
+            (&["SMOVE", "s", "t", "m"], ":1"),
+            (&["SMOVE", "s", "t", "m"], ":0"),
+        ];
+        let mut input = Vec::new();
+        let mut expected = Vec::new();
+        for (args, reply) in commands {
+            input.extend(format!("*{}\r\n", args.len()).into_bytes());
+            for arg in *args {
+                input.extend(format!("${}\r\n{}\r\n", arg.len(), arg).into_bytes());
+            }
+            expected.extend(format!("{}\r\n", reply).into_bytes());
+        }
+
+        let output = MemoryStream::new(&input).serve();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            String::from_utf8(expected).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_pipelined_commands_share_reads_and_writes() {
+        let stream = MemoryStream::new(&b"*1\r\n$4\r\nPING\r\n".repeat(100));
+        let calls = stream.calls.clone();
+
+        let output = stream.serve();
+
+        assert_eq!(output, b"+PONG\r\n".repeat(100));
+        // One read for the batch, one write for its replies and one read
+        // finding the end of input
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+    }
+
+    type TlsClient = rustls::StreamOwned<rustls::ClientConnection, TcpStream>;
+
+    /// Starts a TLS listener with a fresh self-signed certificate, returning
+    /// a function that opens client connections to it.
+    fn spawn_tls_server(name: &str) -> impl Fn() -> TlsClient {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let dir = std::env::temp_dir();
+        let cert_file = dir.join(format!("rrrr-{}-{}.crt", name, std::process::id()));
+        let key_file = dir.join(format!("rrrr-{}-{}.key", name, std::process::id()));
+        std::fs::write(&cert_file, cert.cert.pem()).unwrap();
+        std::fs::write(&key_file, cert.key_pair.serialize_pem()).unwrap();
+        let tls =
+            tls::server_config(&cert_file.to_string_lossy(), &key_file.to_string_lossy()).unwrap();
+        std::fs::remove_file(cert_file).unwrap();
+        std::fs::remove_file(key_file).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let storage = Arc::new(Databases::new(Storage::new(), Config::default().databases));
+        let log_file = dir.join(format!("rrrr-{}-{}.log", name, std::process::id()));
+        let logger = Arc::new(Logger::new(log_file.to_string_lossy().into_owned()));
+        let config = Arc::new(RwLock::new(Config::default()));
+        spawn_tls_listener(listener, tls, storage, logger, config);
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(cert.cert.der().clone()).unwrap();
+        let client_config = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        let client_config = Arc::new(client_config);
+        move || {
+            let connection = rustls::ClientConnection::new(
+                client_config.clone(),
+                "localhost".try_into().unwrap(),
+            )
+            .unwrap();
+            rustls::StreamOwned::new(connection, TcpStream::connect(addr).unwrap())
+        }
+    }
+
+    #[test]
+    fn test_ping_over_tls() {
+        let mut client = spawn_tls_server("ping")();
+        client.write_all(b"*1\r\n$4\r\nPING\r\n").unwrap();
+
+        let mut reply = String::new();
+        BufReader::new(&mut client).read_line(&mut reply).unwrap();
+        assert_eq!(reply, "+PONG\r\n");
+    }
+
+    #[test]
+    fn test_subscribe_over_tls() {
+        let connect = spawn_tls_server("subscribe");
+        let mut subscriber = connect();
+        subscriber
+            .write_all(b"*2\r\n$9\r\nSUBSCRIBE\r\n$9\r\ntls-feeds\r\n")
+            .unwrap();
+        let mut subscriber = BufReader::new(subscriber);
+        let mut read_lines = |count: usize| {
+            (0..count)
+                .map(|_| {
+                    let mut line = String::new();
+                    subscriber.read_line(&mut line).unwrap();
+                    line
+                })
+                .collect::<String>()
+        };
+        assert_eq!(
+            read_lines(6),
+            "*3\r\n$9\r\nsubscribe\r\n$9\r\ntls-feeds\r\n:1\r\n"
+        );
+
+        let mut publisher = connect();
+        publisher
+            .write_all(b"*3\r\n$7\r\nPUBLISH\r\n$9\r\ntls-feeds\r\n$5\r\nhello\r\n")
+            .unwrap();
+        let mut reply = String::new();
+        BufReader::new(&mut publisher)
+            .read_line(&mut reply)
+            .unwrap();
+
+        assert_eq!(reply, ":1\r\n");
+        assert_eq!(
+            read_lines(7),
+            "*3\r\n$7\r\nmessage\r\n$9\r\ntls-feeds\r\n$5\r\nhello\r\n"
+        );
+    }
+
+    #[test]
+    fn test_proxy_header_sets_client_address() {
+        let addr = spawn_server();
+        let mut monitor = TcpStream::connect(addr).unwrap();
+        monitor.write_all(b"*1\r\n$7\r\nMONITOR\r\n").unwrap();
+        let mut lines = io::BufReader::new(monitor.try_clone().unwrap()).lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "+OK");
+
+        let mut client = spawn_connection_with_config(Config {
+            proxy_protocol: true,
+            ..Config::default()
+        });
+        client
+            .write_all(b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 6379\r\n*2\r\n$3\r\nGET\r\n$7\r\nproxied\r\n")
+            .unwrap();
+
+        let mut reply = String::new();
+        BufReader::new(&mut client).read_line(&mut reply).unwrap();
+        assert_eq!(reply, "$-1\r\n");
+        let line = lines
+            .map(|line| line.unwrap())
+            .find(|line| line.contains("proxied"))
+            .unwrap();
+        assert!(line.ends_with("[0 203.0.113.7:51234] \"GET\" \"proxied\""));
+    }
+
+    #[test]
+    fn test_malformed_proxy_header_closes_connection() {
+        let mut client = spawn_connection_with_config(Config {
+            proxy_protocol: true,
+            ..Config::default()
+        });
+
+        client.write_all(b"*1\r\n$4\r\nPING\r\n").unwrap();
+
+        let mut reply = String::new();
+        client.read_to_string(&mut reply).unwrap();
+        assert_eq!(reply, "");
+    }
+
+    #[test]
+    fn test_subscriber_receives_published_messages() {
+        let addr = spawn_server();
+        let mut subscriber = TcpStream::connect(addr).unwrap();
+        subscriber
+            .write_all(b"*3\r\n$9\r\nSUBSCRIBE\r\n$5\r\nsub:a\r\n$5\r\nsub:b\r\n")
+            .unwrap();
+        let mut replies = BufReader::new(subscriber.try_clone().unwrap());
+        let mut read_reply = || read_resp_from_stream(&mut replies).unwrap();
+        let confirmation = |channel: &str, count| {
+            RespValue::Array(Some(vec![
+                RespValue::BulkString(Some("subscribe".into())),
+                RespValue::BulkString(Some(channel.as_bytes().to_vec())),
+                RespValue::Integer(count),
+            ]))
+        };
+        assert_eq!(read_reply(), confirmation("sub:a", 1));
+        assert_eq!(read_reply(), confirmation("sub:b", 2));
+
+        subscriber.write_all(b"*1\r\n$3\r\nGET\r\n").unwrap();
+        assert!(matches!(read_reply(), RespValue::Error(_)));
+        let mut publisher = TcpStream::connect(addr).unwrap();
+        publisher
+            .write_all(b"*3\r\n$7\r\nPUBLISH\r\n$5\r\nsub:b\r\n$2\r\nhi\r\n")
+            .unwrap();
+
+        let mut published = String::new();
+        BufReader::new(&mut publisher)
+            .read_line(&mut published)
+            .unwrap();
+        assert_eq!(published, ":1\r\n");
+        assert_eq!(
+            read_reply(),
+            RespValue::Array(Some(vec![
+                RespValue::BulkString(Some("message".into())),
+                RespValue::BulkString(Some("sub:b".into())),
+                RespValue::BulkString(Some("hi".into())),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_subscriber_that_stops_reading_is_disconnected() {
+        let addr = spawn_server();
+        let subscriber = TcpStream::connect(addr).unwrap();
+        (&subscriber)
+            .write_all(b"*2\r\n$9\r\nSUBSCRIBE\r\n$7\r\nflooded\r\n")
+            .unwrap();
+        let mut subscriber = BufReader::new(subscriber);
+        assert!(matches!(
+            read_resp_from_stream(&mut subscriber),
+            Ok(RespValue::Array(_))
+        ));
+        let mut publisher = spawn_connection_with_config(Config {
+            client_output_buffer_limit: [
+                Default::default(),
+                Default::default(),
+                config::OutputBufferLimit {
+                    hard: 64 * 1024,
+                    soft: 0,
+                    soft_seconds: 0,
+                },
+            ],
+            ..Config::default()
+        });
+        let mut replies = BufReader::new(publisher.try_clone().unwrap());
+        let message = "x".repeat(16 * 1024);
+        let publish = format!(
+            "*3\r\n$7\r\nPUBLISH\r\n$7\r\nflooded\r\n${}\r\n{}\r\n",
+            message.len(),
+            message
+        );
+
+        // The publisher keeps getting replies while the subscriber's socket
+        // buffers fill, until the subscriber is dropped and no one receives
+        let mut reply = String::new();
+        for _ in 0..10_000 {
+            publisher.write_all(publish.as_bytes()).unwrap();
+            reply.clear();
+            replies.read_line(&mut reply).unwrap();
+            if reply == ":0\r\n" {
+                break;
+            }
+        }
+        assert_eq!(reply, ":0\r\n");
+
+        let mut received = Vec::new();
+        subscriber.read_to_end(&mut received).unwrap();
+        assert!(!received.is_empty());
     }
 }
@@ -0,0 +1,120 @@
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::SharedConfig;
+use crate::pubsub::{Outbox, Outlet};
+use crate::resp::RespValue;
+
+// Connections that issued MONITOR, each with its connection's config for the
+// output limit; a monitor is dropped once its outbox closes it
+static MONITORS: LazyLock<Mutex<Vec<(Outbox, SharedConfig)>>> =
+    LazyLock::new(|| Mutex::new(Vec::new()));
+// Mirrors MONITORS.len() so the hot path can skip formatting without locking
+static MONITOR_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+// Commands whose arguments are credentials and must not be echoed
+const REDACTED_COMMANDS: &[&str] = &["AUTH", "HELLO", "MIGRATE"];
+
+/// Registers a monitoring connection, with `outlet` opening handles on it
+/// for its outbox.
+pub fn register(
+    outlet: impl Fn() -> io::Result<Box<dyn Outlet>>,
+    config: SharedConfig,
+) -> io::Result<()> {
+    let outbox = Outbox::open(&outlet)?;
+    let mut monitors = MONITORS.lock().unwrap();
+    monitors.push((outbox, config));
+    MONITOR_COUNT.store(monitors.len(), Ordering::SeqCst);
+    Ok(())
+}
+
+/// Sends a line describing the command `args` issued by `addr` against
+/// database `db` to every registered monitor.
+pub fn feed(addr: &str, db: usize, args: &[String]) {
+    if MONITOR_COUNT.load(Ordering::SeqCst) == 0 {
+        return;
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0);
+    let line = RespValue::SimpleString(format_line(timestamp, db, addr, args));
+
+    // Monitors are held to the normal client limit, as in Redis
+    let mut monitors = MONITORS.lock().unwrap();
+    monitors.retain(|(outbox, config)| {
+        let limit = config.read().unwrap().client_output_buffer_limit[0].hard;
+        outbox.send(&line, limit)
+    });
+    MONITOR_COUNT.store(monitors.len(), Ordering::SeqCst);
+}
+
+fn format_line(timestamp: f64, db: usize, addr: &str, args: &[String]) -> String {
+    let redact = args
+        .first()
+        .is_some_and(|name| REDACTED_COMMANDS.contains(&name.to_uppercase().as_str()));
+
+    let mut line = format!("{:.6} [{} {}]", timestamp, db, addr);
+    for (i, arg) in args.iter().enumerate() {
+        line.push(' ');
+        if redact && i > 0 {
+            line.push_str("\"(redacted)\"");
+        } else {
+            line.push_str(&quote(arg));
+        }
+    }
+    line
+}
+
+// Matches Redis' sdscatrepr so the line stays on one row and is unambiguous
+fn quote(arg: &str) -> String {
+    let mut quoted = String::with_capacity(arg.len() + 2);
+    quoted.push('"');
+    for byte in arg.bytes() {
+        match byte {
+            b'\\' => quoted.push_str("\\\\"),
+            b'"' => quoted.push_str("\\\""),
+            b'\n' => quoted.push_str("\\n"),
+            b'\r' => quoted.push_str("\\r"),
+            b'\t' => quoted.push_str("\\t"),
+            0x20..=0x7e => quoted.push(byte as char),
+            _ => quoted.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn test_format_line_quotes_arguments() {
+        let line = format_line(
+            1339518083.107412,
+            0,
+            "127.0.0.1:60866",
+            &strings(&["SET", "k", "a \"b\"\n"]),
+        );
+
+        assert_eq!(
+            line,
+            r#"1339518083.107412 [0 127.0.0.1:60866] "SET" "k" "a \"b\"\n""#
+        );
+    }
+
+    #[test]
+    fn test_format_line_redacts_auth() {
+        let line = format_line(1.0, 0, "127.0.0.1:1", &strings(&["auth", "secret"]));
+
+        assert_eq!(line, r#"1.000000 [0 127.0.0.1:1] "auth" "(redacted)""#);
+    }
+}
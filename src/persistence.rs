@@ -0,0 +1,448 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::debug;
+
+use crate::resp::{parse_resp, read_resp, write_resp, RespError, RespValue};
+use crate::sorted_set::{format_score, SortedSet};
+use crate::storage::{lock_storage, Databases, Storage, Value};
+
+// Unix timestamp of the last successful snapshot, or of server start until one happens
+static LAST_SAVE: AtomicU64 = AtomicU64::new(0);
+static BGSAVE_IN_PROGRESS: AtomicBool = AtomicBool::new(false);
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn snapshot_path() -> String {
+    std::env::var("SNAPSHOT_FILE").unwrap_or_else(|_| "dump.resp".to_string())
+}
+
+pub fn mark_server_start() {
+    LAST_SAVE.store(now_secs(), Ordering::SeqCst);
+}
+
+pub fn last_save() -> u64 {
+    LAST_SAVE.load(Ordering::SeqCst)
+}
+
+/// Saves every database, holding all of their locks so the snapshot is of
+/// a single point in time.
+pub fn save(databases: &Databases, path: impl AsRef<Path>) -> io::Result<()> {
+    let held: Vec<_> = databases
+        .iter()
+        .map(|storage| lock_storage(storage))
+        .collect();
+    write_snapshot(held.iter().map(|storage| &**storage), path)
+}
+
+/// Writes each non-empty database as a RESP integer holding its index,
+/// followed by its keys as RESP arrays of `[key, type, payload, expire_at]`,
+/// where `expire_at` is the absolute expiry in Unix seconds or -1 for no
+/// expiry.
+fn write_snapshot<'a>(
+    databases: impl Iterator<Item = &'a Storage>,
+    path: impl AsRef<Path>,
+) -> io::Result<()> {
+    let path = path.as_ref();
+    let tmp_path = path.with_extension("tmp");
+
+    {
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        for (index, storage) in databases.enumerate() {
+            if storage.entries().next().is_none() {
+                continue;
+            }
+            write_resp(&RespValue::Integer(index as i64), &mut writer)?;
+            for (key, value, expire_at) in storage.entries() {
+                let (value_type, payload) = encode_value(value);
+                let entry = RespValue::Array(Some(vec![
+                    RespValue::BulkString(Some(key.clone())),
+                    RespValue::BulkString(Some(value_type.as_bytes().to_vec())),
+                    payload,
+                    RespValue::Integer(expire_at.map(|at| at as i64).unwrap_or(-1)),
+                ]));
+                write_resp(&entry, &mut writer)?;
+            }
+        }
+    }
+    fs::rename(&tmp_path, path)?;
+
+    LAST_SAVE.store(now_secs(), Ordering::SeqCst);
+    debug!("Saved snapshot to {}", path.display());
+    Ok(())
+}
+
+/// Starts a snapshot of a point-in-time copy of every database on a
+/// background thread. Returns false if another background save is still
+/// running.
+pub fn background_save(databases: &Databases, path: String) -> bool {
+    if BGSAVE_IN_PROGRESS.swap(true, Ordering::SeqCst) {
+        return false;
+    }
+
+    let held: Vec<_> = databases
+        .iter()
+        .map(|storage| lock_storage(storage))
+        .collect();
+    let copies: Vec<Storage> = held.iter().map(|storage| (**storage).clone()).collect();
+    drop(held);
+    thread::spawn(move || {
+        if let Err(e) = write_snapshot(copies.iter(), &path) {
+            eprintln!("Background save to {} failed: {}", path, e);
+        }
+        BGSAVE_IN_PROGRESS.store(false, Ordering::SeqCst);
+    });
+    true
+}
+
+/// Loads a snapshot into `count` databases. Entries before any database
+/// index belong to the first, as in snapshots written before they had one.
+pub fn load(path: impl AsRef<Path>, count: usize) -> Result<Vec<Storage>, RespError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut databases: Vec<Storage> = (0..count).map(|_| Storage::new()).collect();
+    let mut index = 0;
+
+    loop {
+        let entry = match read_resp(&mut reader) {
+            Ok(entry) => entry,
+            Err(RespError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        };
+
+        let fields = match entry {
+            RespValue::Integer(next) => {
+                index = usize::try_from(next).map_err(|_| malformed_entry())?;
+                continue;
+            }
+            RespValue::Array(Some(fields)) => fields,
+            _ => return Err(malformed_entry()),
+        };
+        let [RespValue::BulkString(Some(key)), RespValue::BulkString(Some(value_type)), payload, RespValue::Integer(expire_at)] =
+            fields.as_slice()
+        else {
+            return Err(malformed_entry());
+        };
+
+        let storage = databases.get_mut(index).ok_or_else(|| {
+            RespError::ParseError(format!(
+                "snapshot has database {}, but only {} are configured",
+                index, count
+            ))
+        })?;
+        storage.set_value(key.clone(), decode_value(value_type, payload)?);
+        if *expire_at >= 0 {
+            storage.set_expire_at(key.clone(), *expire_at as u64);
+        }
+    }
+
+    Ok(databases)
+}
+
+/// Saves every database to `path` and replaces their contents with what
+/// loads back, so anything the snapshot format drops goes missing.
+pub fn reload(databases: &Databases, path: impl AsRef<Path>) -> Result<(), RespError> {
+    let path = path.as_ref();
+    let mut held: Vec<_> = databases
+        .iter()
+        .map(|storage| lock_storage(storage))
+        .collect();
+    write_snapshot(held.iter().map(|storage| &**storage), path)?;
+    for (storage, loaded) in held.iter_mut().zip(load(path, databases.len())?) {
+        storage.replace_contents(loaded);
+    }
+    Ok(())
+}
+
+/// Serializes a single value for DUMP as a RESP array of `[type, payload]`,
+/// the same encoding snapshot entries use for their value.
+pub fn dump_value(value: &Value) -> Vec<u8> {
+    let (value_type, payload) = encode_value(value);
+    let dump = RespValue::Array(Some(vec![
+        RespValue::BulkString(Some(value_type.as_bytes().to_vec())),
+        payload,
+    ]));
+
+    let mut bytes = Vec::new();
+    write_resp(&dump, &mut BufWriter::new(&mut bytes)).expect("writing to a Vec cannot fail");
+    bytes
+}
+
+/// Parses a payload produced by `dump_value`, rejecting trailing bytes.
+pub fn restore_value(bytes: &[u8]) -> Result<Value, RespError> {
+    let Some((RespValue::Array(Some(fields)), consumed)) = parse_resp(bytes)? else {
+        return Err(malformed_entry());
+    };
+    let [RespValue::BulkString(Some(value_type)), payload] = fields.as_slice() else {
+        return Err(malformed_entry());
+    };
+    if consumed != bytes.len() {
+        return Err(malformed_entry());
+    }
+    decode_value(value_type, payload)
+}
+
+fn malformed_entry() -> RespError {
+    RespError::ParseError("Malformed snapshot entry".to_string())
+}
+
+fn bulk(bytes: &[u8]) -> RespValue {
+    RespValue::BulkString(Some(bytes.to_vec()))
+}
+
+fn encode_value(value: &Value) -> (&'static str, RespValue) {
+    match value {
+        Value::String(bytes) => ("string", bulk(bytes)),
+        Value::Int(n) => ("string", bulk(n.to_string().as_bytes())),
+        Value::Set(members) => (
+            "set",
+            RespValue::Array(Some(members.iter().map(|m| bulk(m)).collect())),
+        ),
+        Value::List(items) => (
+            "list",
+            RespValue::Array(Some(items.iter().map(|i| bulk(i)).collect())),
+        ),
+        Value::Hash(fields) => (
+            "hash",
+            RespValue::Array(Some(
+                fields
+                    .iter()
+                    .flat_map(|(field, value)| [bulk(field), bulk(value)])
+                    .collect(),
+            )),
+        ),
+        Value::SortedSet(zset) => (
+            "zset",
+            RespValue::Array(Some(
+                zset.iter()
+                    .flat_map(|(member, score)| {
+                        [bulk(member), bulk(format_score(score).as_bytes())]
+                    })
+                    .collect(),
+            )),
+        ),
+    }
+}
+
+fn decode_value(value_type: &[u8], payload: &RespValue) -> Result<Value, RespError> {
+    match (value_type, payload) {
+        (b"string", RespValue::BulkString(Some(value))) => Ok(Value::from_bytes(value.clone())),
+        (b"set", RespValue::Array(Some(members))) => members
+            .iter()
+            .map(|member| match member {
+                RespValue::BulkString(Some(member)) => Ok(member.clone()),
+                _ => Err(malformed_entry()),
+            })
+            .collect::<Result<_, _>>()
+            .map(Value::Set),
+        (b"list", RespValue::Array(Some(items))) => items
+            .iter()
+            .map(|item| match item {
+                RespValue::BulkString(Some(item)) => Ok(item.clone()),
+                _ => Err(malformed_entry()),
+            })
+            .collect::<Result<_, _>>()
+            .map(Value::List),
+        (b"hash", RespValue::Array(Some(fields))) if fields.len() % 2 == 0 => fields
+            .chunks(2)
+            .map(|pair| match pair {
+                [RespValue::BulkString(Some(field)), RespValue::BulkString(Some(value))] => {
+                    Ok((field.clone(), value.clone()))
+                }
+                _ => Err(malformed_entry()),
+            })
+            .collect::<Result<_, _>>()
+            .map(Value::Hash),
+        (b"zset", RespValue::Array(Some(entries))) if entries.len() % 2 == 0 => {
+            let mut zset = SortedSet::default();
+            for pair in entries.chunks(2) {
+                let [RespValue::BulkString(Some(member)), RespValue::BulkString(Some(score))] =
+                    pair
+                else {
+                    return Err(malformed_entry());
+                };
+                let score = std::str::from_utf8(score)
+                    .ok()
+                    .and_then(|score| score.parse::<f64>().ok())
+                    .filter(|score| !score.is_nan())
+                    .ok_or_else(malformed_entry)?;
+                zset.insert(member.clone(), score);
+            }
+            Ok(Value::SortedSet(zset))
+        }
+        _ => Err(malformed_entry()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("rrrr-{}-{}.resp", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_dump_value_round_trip() {
+        let values = [
+            Value::String(b"hello".to_vec()),
+            Value::Int(-42),
+            Value::Set([b"a".to_vec(), b"b".to_vec()].into_iter().collect()),
+            Value::List([b"x".to_vec(), b"y".to_vec()].into_iter().collect()),
+            Value::Hash([(b"f".to_vec(), b"v".to_vec())].into_iter().collect()),
+            Value::SortedSet({
+                let mut zset = SortedSet::default();
+                zset.insert(b"m".to_vec(), 1.5);
+                zset.insert(b"n".to_vec(), f64::NEG_INFINITY);
+                zset
+            }),
+        ];
+
+        for value in values {
+            assert_eq!(restore_value(&dump_value(&value)).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_restore_value_rejects_garbage() {
+        assert!(restore_value(b"not a dump").is_err());
+        assert!(restore_value(b"*2\r\n$4\r\nblob\r\n$1\r\nx\r\n").is_err());
+    }
+
+    #[test]
+    fn test_save_and_load_round_trip() {
+        let path = temp_path("round-trip");
+        let mut storage = Storage::new();
+        storage.set("k1".into(), b"v1".to_vec());
+        storage.set("k2".into(), b"v2".to_vec());
+        storage.set_expire_at("k2".into(), now_secs() + 100);
+        storage
+            .sadd("s".into(), vec![b"a".to_vec(), b"b".to_vec()])
+            .unwrap();
+        storage
+            .push("l".into(), vec![b"x".to_vec(), b"y".to_vec()], false)
+            .unwrap();
+
+        save(&Databases::new(storage, 1), &path).unwrap();
+        let mut loaded = load(&path, 1).unwrap().remove(0);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.get("k1".into()), Ok(Some(b"v1".to_vec())));
+        assert_eq!(loaded.get("k2".into()), Ok(Some(b"v2".to_vec())));
+        let mut members = loaded.smembers("s".into()).unwrap();
+        members.sort();
+        assert_eq!(members, vec![b"a".to_vec(), b"b".to_vec()]);
+        assert_eq!(
+            loaded.lrange("l".into(), 0, -1),
+            Ok(vec![b"x".to_vec(), b"y".to_vec()])
+        );
+        assert_eq!(loaded.get_ttl("k1".into()), -1);
+        assert!(loaded.get_ttl("k2".into()) > 0);
+    }
+
+    #[test]
+    fn test_save_and_load_every_database() {
+        let path = temp_path("databases");
+        let databases = Databases::new(Storage::new(), 16);
+        lock_storage(databases.get(0)).set("k".into(), b"first".to_vec());
+        lock_storage(databases.get(1)).set("k".into(), b"second".to_vec());
+        lock_storage(databases.get(1)).set_expire_at("k".into(), now_secs() + 100);
+
+        save(&databases, &path).unwrap();
+        let mut loaded = load(&path, 16).unwrap();
+        let too_few = load(&path, 1);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.len(), 16);
+        assert_eq!(loaded[0].get("k".into()), Ok(Some(b"first".to_vec())));
+        assert_eq!(loaded[1].get("k".into()), Ok(Some(b"second".to_vec())));
+        assert!(loaded[1].get_ttl("k".into()) > 0);
+        assert!(loaded[2..].iter().all(|db| db.entries().next().is_none()));
+        assert!(matches!(too_few, Err(RespError::ParseError(_))));
+    }
+
+    #[test]
+    fn test_load_puts_entries_without_an_index_in_the_first_database() {
+        let path = temp_path("no-index");
+        fs::write(&path, "*4\r\n$1\r\nk\r\n$6\r\nstring\r\n$1\r\nv\r\n:-1\r\n").unwrap();
+
+        let mut loaded = load(&path, 2).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded[0].get("k".into()), Ok(Some(b"v".to_vec())));
+    }
+
+    #[test]
+    fn test_reload_preserves_every_type_and_ttl() {
+        let path = temp_path("reload");
+        let mut storage = Storage::new();
+        storage.set_value("str".into(), Value::String(b"hello".to_vec()));
+        storage.set_value("int".into(), Value::Int(-42));
+        storage.set_value(
+            "set".into(),
+            Value::Set([b"a".to_vec(), b"b".to_vec()].into_iter().collect()),
+        );
+        storage.set_value(
+            "list".into(),
+            Value::List([b"x".to_vec(), b"y".to_vec()].into_iter().collect()),
+        );
+        storage.set_value(
+            "hash".into(),
+            Value::Hash([(b"f".to_vec(), b"v".to_vec())].into_iter().collect()),
+        );
+        storage
+            .zadd(
+                "zset".into(),
+                vec![(1.5, b"m".to_vec()), (-2.0, b"n".to_vec())],
+            )
+            .unwrap();
+        storage.set_expire_at("str".into(), now_secs() + 100);
+        storage.set_expire_at("zset".into(), now_secs() + 200);
+        let snapshot = |storage: &Storage| {
+            let mut entries: Vec<_> = storage
+                .entries()
+                .map(|(key, value, expire_at)| (key.clone(), value.clone(), expire_at))
+                .collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            entries
+        };
+        let before = snapshot(&storage);
+        // The data lives outside the first database, next to a key in it
+        let databases = Databases::new(Storage::new(), 2);
+        lock_storage(databases.get(0)).set("first".into(), b"v".to_vec());
+        *lock_storage(databases.get(1)) = storage;
+
+        reload(&databases, &path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let storage = lock_storage(databases.get(1));
+        assert_eq!(snapshot(&storage), before);
+        assert_eq!(storage.entries().count(), 6);
+        assert_eq!(
+            lock_storage(databases.get(0)).get("first".into()),
+            Ok(Some(b"v".to_vec()))
+        );
+    }
+
+    #[test]
+    fn test_last_save_advances_after_save() {
+        let path = temp_path("last-save");
+        let before = last_save();
+        let started = now_secs();
+
+        save(&Databases::new(Storage::new(), 1), &path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let after = last_save();
+        assert!(after >= started);
+        assert!(after >= before);
+    }
+}
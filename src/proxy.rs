@@ -0,0 +1,108 @@
+use std::io::{self, Read};
+use std::net::IpAddr;
+
+// The longest v1 header the spec allows, CRLF included
+const MAX_HEADER_LEN: usize = 107;
+
+/// Reads a PROXY protocol v1 header from the start of a connection and
+/// returns the client address it carries, or `None` for `PROXY UNKNOWN`,
+/// where the connection's own peer address applies. Reads byte by byte so
+/// nothing past the header is consumed.
+pub fn read_header<R: Read>(stream: &mut R) -> io::Result<Option<String>> {
+    let mut header = Vec::with_capacity(MAX_HEADER_LEN);
+    let mut byte = [0u8; 1];
+    while !header.ends_with(b"\r\n") {
+        if header.len() == MAX_HEADER_LEN {
+            return Err(malformed("header too long"));
+        }
+        stream.read_exact(&mut byte)?;
+        header.push(byte[0]);
+    }
+
+    let header = std::str::from_utf8(&header[..header.len() - 2])
+        .map_err(|_| malformed("header is not ASCII"))?;
+    parse_header(header)
+}
+
+fn parse_header(header: &str) -> io::Result<Option<String>> {
+    let fields: Vec<&str> = header.split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", family @ ("TCP4" | "TCP6"), source, destination, source_port, destination_port] =>
+        {
+            let source: IpAddr = source.parse().map_err(|_| malformed("bad address"))?;
+            let destination: IpAddr = destination.parse().map_err(|_| malformed("bad address"))?;
+            let expect_v4 = *family == "TCP4";
+            if source.is_ipv4() != expect_v4 || destination.is_ipv4() != expect_v4 {
+                return Err(malformed("address doesn't match protocol family"));
+            }
+            let source_port = parse_port(source_port)?;
+            parse_port(destination_port)?;
+
+            Ok(Some(match source {
+                IpAddr::V4(ip) => format!("{}:{}", ip, source_port),
+                IpAddr::V6(ip) => format!("[{}]:{}", ip, source_port),
+            }))
+        }
+        _ => Err(malformed("not a PROXY v1 header")),
+    }
+}
+
+// Ports are plain decimal with no sign or leading zeros
+fn parse_port(port: &str) -> io::Result<u16> {
+    if port.starts_with('0') && port != "0" || port.starts_with('+') {
+        return Err(malformed("bad port"));
+    }
+    port.parse().map_err(|_| malformed("bad port"))
+}
+
+fn malformed(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid PROXY header: {}", reason),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_read_header_stops_after_crlf() {
+        let mut stream =
+            Cursor::new(b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 6379\r\n*1\r\n".to_vec());
+
+        let addr = read_header(&mut stream).unwrap();
+
+        assert_eq!(addr.as_deref(), Some("203.0.113.7:51234"));
+        assert_eq!(stream.position(), 44);
+    }
+
+    #[test]
+    fn test_parse_header() {
+        assert_eq!(
+            parse_header("PROXY TCP6 2001:db8::1 2001:db8::2 4000 6379").unwrap(),
+            Some("[2001:db8::1]:4000".to_string())
+        );
+        assert_eq!(parse_header("PROXY UNKNOWN").unwrap(), None);
+
+        for header in [
+            "PROXY TCP4 203.0.113.7 10.0.0.1 51234",
+            "PROXY TCP4 2001:db8::1 10.0.0.1 51234 6379",
+            "PROXY TCP4 203.0.113.7 10.0.0.1 051234 6379",
+            "PROXY TCP4 203.0.113.7 10.0.0.1 70000 6379",
+            "PROXY UDP4 203.0.113.7 10.0.0.1 51234 6379",
+            "*1",
+        ] {
+            assert!(parse_header(header).is_err(), "{}", header);
+        }
+    }
+
+    #[test]
+    fn test_read_header_rejects_overlong_line() {
+        let mut stream = Cursor::new(vec![b'A'; 200]);
+
+        assert!(read_header(&mut stream).is_err());
+    }
+}
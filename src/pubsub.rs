@@ -0,0 +1,638 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufWriter, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, LazyLock, Mutex};
+use std::thread;
+
+use crate::connection::{ClientStream, ConnectionContext};
+use crate::resp::{write_resp, RespValue};
+
+// Every subscription in the process, along with the outbox of each
+// subscribed connection that publishers deliver messages through
+static REGISTRY: LazyLock<Mutex<Registry>> = LazyLock::new(|| Mutex::new(Registry::default()));
+
+/// Whether a subscription names a channel exactly or matches channels by
+/// glob pattern.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Channel,
+    Pattern,
+}
+
+impl Kind {
+    fn subscribe_reply(self) -> &'static str {
+        match self {
+            Kind::Channel => "subscribe",
+            Kind::Pattern => "psubscribe",
+        }
+    }
+
+    fn unsubscribe_reply(self) -> &'static str {
+        match self {
+            Kind::Channel => "unsubscribe",
+            Kind::Pattern => "punsubscribe",
+        }
+    }
+}
+
+/// The write side of a subscribed connection, and a way to drop the
+/// connection when it falls too far behind.
+pub trait Outlet: Write + Send + 'static {
+    fn close(&self);
+}
+
+impl<S: ClientStream> Outlet for S {
+    fn close(&self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+impl Outlet for io::Sink {
+    fn close(&self) {}
+}
+
+/// Output waiting to be written to a subscribed or monitoring connection.
+/// A thread per connection does the writing, so a client that stops reading
+/// holds up only its own queue, never the publishers.
+pub struct Outbox {
+    queue: mpsc::Sender<Vec<u8>>,
+    /// Bytes queued but not yet written to the socket.
+    pending: Arc<AtomicUsize>,
+    /// A second handle on the connection, used to close it.
+    outlet: Box<dyn Outlet>,
+}
+
+impl Outbox {
+    pub fn open(outlet: &impl Fn() -> io::Result<Box<dyn Outlet>>) -> io::Result<Self> {
+        let mut writer = outlet()?;
+        let (queue, received) = mpsc::channel::<Vec<u8>>();
+        let pending = Arc::new(AtomicUsize::new(0));
+        let written = pending.clone();
+        thread::spawn(move || {
+            for bytes in received {
+                if writer
+                    .write_all(&bytes)
+                    .and_then(|()| writer.flush())
+                    .is_err()
+                {
+                    break;
+                }
+                written.fetch_sub(bytes.len(), Ordering::SeqCst);
+            }
+        });
+        Ok(Self {
+            queue,
+            pending,
+            outlet: outlet()?,
+        })
+    }
+
+    /// Queues `value` for the connection. When the queue would grow past
+    /// `limit` bytes (0 for no limit), or the writer has failed, the
+    /// connection is closed instead and false is returned.
+    pub fn send(&self, value: &RespValue, limit: u64) -> bool {
+        let mut bytes = Vec::new();
+        write_resp(value, &mut BufWriter::new(&mut bytes)).expect("writing to a Vec cannot fail");
+
+        let pending = self.pending.fetch_add(bytes.len(), Ordering::SeqCst) + bytes.len();
+        if limit > 0 && pending as u64 > limit || self.queue.send(bytes).is_err() {
+            self.outlet.close();
+            return false;
+        }
+        true
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    outboxes: HashMap<u64, Outbox>,
+    channels: HashMap<String, HashSet<u64>>,
+    patterns: HashMap<String, HashSet<u64>>,
+}
+
+impl Registry {
+    fn subscribers(&mut self, kind: Kind) -> &mut HashMap<String, HashSet<u64>> {
+        match kind {
+            Kind::Channel => &mut self.channels,
+            Kind::Pattern => &mut self.patterns,
+        }
+    }
+
+    fn add(&mut self, kind: Kind, name: &str, id: u64) {
+        self.subscribers(kind)
+            .entry(name.to_string())
+            .or_default()
+            .insert(id);
+    }
+
+    fn remove(&mut self, kind: Kind, name: &str, id: u64) {
+        let subscribers = self.subscribers(kind);
+        if let Some(ids) = subscribers.get_mut(name) {
+            ids.remove(&id);
+            if ids.is_empty() {
+                subscribers.remove(name);
+            }
+        }
+    }
+
+    /// Forgets a connection and everything it is subscribed to.
+    fn drop_client(&mut self, id: u64) {
+        self.outboxes.remove(&id);
+        for subscribers in [&mut self.channels, &mut self.patterns] {
+            subscribers.retain(|_, ids| {
+                ids.remove(&id);
+                !ids.is_empty()
+            });
+        }
+    }
+
+    /// Queues `value` for a subscribed connection, forgetting the
+    /// connection if its outbox closes it.
+    fn send(&mut self, id: u64, value: &RespValue, limit: u64) -> bool {
+        let Some(outbox) = self.outboxes.get(&id) else {
+            return false;
+        };
+        if !outbox.send(value, limit) {
+            self.drop_client(id);
+            return false;
+        }
+        true
+    }
+
+    fn open_outbox(
+        &mut self,
+        id: u64,
+        outlet: impl Fn() -> io::Result<Box<dyn Outlet>>,
+    ) -> io::Result<()> {
+        if let Entry::Vacant(entry) = self.outboxes.entry(id) {
+            entry.insert(Outbox::open(&outlet)?);
+        }
+        Ok(())
+    }
+}
+
+/// The pub/sub hard output limit from the connection's config.
+fn output_limit(ctx: &ConnectionContext) -> u64 {
+    ctx.config.read().unwrap().client_output_buffer_limit[2].hard
+}
+
+fn names(ctx: &mut ConnectionContext, kind: Kind) -> &mut HashSet<String> {
+    match kind {
+        Kind::Channel => &mut ctx.subscriptions,
+        Kind::Pattern => &mut ctx.patterns,
+    }
+}
+
+fn subscription_count(ctx: &ConnectionContext) -> i64 {
+    (ctx.subscriptions.len() + ctx.patterns.len()) as i64
+}
+
+/// Whether the connection is in subscribed mode, where it only receives
+/// messages and manages its subscriptions.
+pub fn is_subscribed(ctx: &ConnectionContext) -> bool {
+    subscription_count(ctx) > 0
+}
+
+fn confirmation(action: &str, name: Option<String>, count: i64) -> RespValue {
+    RespValue::Array(Some(vec![
+        RespValue::BulkString(Some(action.as_bytes().to_vec())),
+        RespValue::BulkString(name.map(String::into_bytes)),
+        RespValue::Integer(count),
+    ]))
+}
+
+/// Subscribes the connection to each of `subscribe_to`, queueing one
+/// `[subscribe, name, count]` confirmation per name in the outbox messages
+/// are delivered through. `outlet` opens handles on the connection for its
+/// outbox, the first time it subscribes.
+pub fn subscribe(
+    ctx: &mut ConnectionContext,
+    kind: Kind,
+    subscribe_to: Vec<String>,
+    outlet: impl Fn() -> io::Result<Box<dyn Outlet>>,
+) -> io::Result<()> {
+    let limit = output_limit(ctx);
+    let mut registry = REGISTRY.lock().unwrap();
+    registry.open_outbox(ctx.id, outlet)?;
+
+    for name in subscribe_to {
+        registry.add(kind, &name, ctx.id);
+        names(ctx, kind).insert(name.clone());
+        let count = subscription_count(ctx);
+        registry.send(
+            ctx.id,
+            &confirmation(kind.subscribe_reply(), Some(name), count),
+            limit,
+        );
+    }
+    Ok(())
+}
+
+/// Unsubscribes the connection from each of `unsubscribe_from`, or from
+/// everything of that kind when it is empty, confirming each one. With nothing to
+/// unsubscribe from a single confirmation with a nil name is written, as
+/// Redis does.
+pub fn unsubscribe(
+    ctx: &mut ConnectionContext,
+    kind: Kind,
+    unsubscribe_from: Vec<String>,
+    outlet: impl Fn() -> io::Result<Box<dyn Outlet>>,
+) -> io::Result<()> {
+    let limit = output_limit(ctx);
+    let mut registry = REGISTRY.lock().unwrap();
+    registry.open_outbox(ctx.id, outlet)?;
+
+    let mut unsubscribe_from = unsubscribe_from;
+    if unsubscribe_from.is_empty() {
+        unsubscribe_from = names(ctx, kind).iter().cloned().collect();
+        unsubscribe_from.sort();
+    }
+    if unsubscribe_from.is_empty() {
+        let count = subscription_count(ctx);
+        registry.send(
+            ctx.id,
+            &confirmation(kind.unsubscribe_reply(), None, count),
+            limit,
+        );
+    }
+    for name in unsubscribe_from {
+        registry.remove(kind, &name, ctx.id);
+        names(ctx, kind).remove(&name);
+        let count = subscription_count(ctx);
+        registry.send(
+            ctx.id,
+            &confirmation(kind.unsubscribe_reply(), Some(name), count),
+            limit,
+        );
+    }
+
+    Ok(())
+}
+
+/// Queues a command reply for a connection that has an outbox, so it stays
+/// in order behind the messages already queued for it. Returns `None` for
+/// a connection that has never subscribed, and `Some(false)` if the
+/// connection was closed for falling behind.
+pub fn reply(ctx: &ConnectionContext, value: &RespValue) -> Option<bool> {
+    let limit = output_limit(ctx);
+    let mut registry = REGISTRY.lock().unwrap();
+    if !registry.outboxes.contains_key(&ctx.id) {
+        return None;
+    }
+    Some(registry.send(ctx.id, value, limit))
+}
+
+/// Delivers `message` to every connection subscribed to `channel` directly
+/// or through a matching pattern, returning how many received it.
+/// Subscribers with more than `limit` bytes of output pending are
+/// disconnected rather than waited on.
+pub fn publish(channel: &str, message: &str, limit: u64) -> usize {
+    let mut registry = REGISTRY.lock().unwrap();
+    let mut deliveries = Vec::new();
+    for id in registry.channels.get(channel).into_iter().flatten() {
+        deliveries.push((
+            *id,
+            RespValue::Array(Some(vec![
+                RespValue::BulkString(Some("message".into())),
+                RespValue::BulkString(Some(channel.as_bytes().to_vec())),
+                RespValue::BulkString(Some(message.as_bytes().to_vec())),
+            ])),
+        ));
+    }
+    for (pattern, ids) in &registry.patterns {
+        if !glob::Pattern::new(pattern).is_ok_and(|glob| glob.matches(channel)) {
+            continue;
+        }
+        for id in ids {
+            deliveries.push((
+                *id,
+                RespValue::Array(Some(vec![
+                    RespValue::BulkString(Some("pmessage".into())),
+                    RespValue::BulkString(Some(pattern.as_bytes().to_vec())),
+                    RespValue::BulkString(Some(channel.as_bytes().to_vec())),
+                    RespValue::BulkString(Some(message.as_bytes().to_vec())),
+                ])),
+            ));
+        }
+    }
+
+    deliveries
+        .into_iter()
+        .filter(|(id, message)| registry.send(*id, message, limit))
+        .count()
+}
+
+/// Channels with at least one subscriber, optionally only those matching
+/// the glob `pattern`, for PUBSUB CHANNELS.
+pub fn channels(pattern: Option<&str>) -> Vec<String> {
+    let pattern = pattern.map(glob::Pattern::new);
+    let mut channels: Vec<String> = REGISTRY
+        .lock()
+        .unwrap()
+        .channels
+        .keys()
+        .filter(|channel| match &pattern {
+            None => true,
+            Some(Ok(pattern)) => pattern.matches(channel),
+            Some(Err(_)) => false,
+        })
+        .cloned()
+        .collect();
+    channels.sort();
+    channels
+}
+
+/// The number of connections subscribed to `channel` itself, not counting
+/// pattern subscribers.
+pub fn subscriber_count(channel: &str) -> usize {
+    REGISTRY
+        .lock()
+        .unwrap()
+        .channels
+        .get(channel)
+        .map_or(0, HashSet::len)
+}
+
+/// The number of pattern subscriptions across all connections.
+pub fn pattern_count() -> usize {
+    REGISTRY
+        .lock()
+        .unwrap()
+        .patterns
+        .values()
+        .map(HashSet::len)
+        .sum()
+}
+
+/// Removes a connection's subscriptions when it goes away, however the
+/// connection loop exits.
+pub struct DisconnectGuard(pub u64);
+
+impl Drop for DisconnectGuard {
+    fn drop(&mut self) {
+        REGISTRY.lock().unwrap().drop_client(self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::read_resp_from_stream;
+    use std::sync::Arc;
+
+    /// A connection whose output the test can read back.
+    #[derive(Clone)]
+    struct SharedBuffer {
+        id: u64,
+        bytes: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.bytes.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Outlet for SharedBuffer {
+        fn close(&self) {}
+    }
+
+    impl SharedBuffer {
+        fn outlet(&self) -> impl Fn() -> io::Result<Box<dyn Outlet>> {
+            let buffer = self.clone();
+            move || Ok(Box::new(buffer.clone()))
+        }
+
+        /// Everything written so far, once the outbox has drained.
+        fn replies(&self) -> Vec<RespValue> {
+            while REGISTRY
+                .lock()
+                .unwrap()
+                .outboxes
+                .get(&self.id)
+                .is_some_and(|outbox| outbox.pending.load(Ordering::SeqCst) > 0)
+            {
+                thread::yield_now();
+            }
+            let bytes = std::mem::take(&mut *self.bytes.lock().unwrap());
+            let mut reader = io::BufReader::new(bytes.as_slice());
+            std::iter::from_fn(|| read_resp_from_stream(&mut reader).ok()).collect()
+        }
+    }
+
+    fn subscriber() -> (ConnectionContext, SharedBuffer) {
+        let ctx = ConnectionContext {
+            id: crate::connection::next_client_id(),
+            ..ConnectionContext::default()
+        };
+        let output = SharedBuffer {
+            id: ctx.id,
+            bytes: Arc::default(),
+        };
+        (ctx, output)
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn test_subscribe_counts_each_channel() {
+        let (mut ctx, output) = subscriber();
+
+        subscribe(
+            &mut ctx,
+            Kind::Channel,
+            strings(&["count:a", "count:b"]),
+            output.outlet(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            output.replies(),
+            [
+                confirmation("subscribe", Some("count:a".to_string()), 1),
+                confirmation("subscribe", Some("count:b".to_string()), 2),
+            ]
+        );
+        drop(DisconnectGuard(ctx.id));
+    }
+
+    #[test]
+    fn test_unsubscribe_from_everything() {
+        let (mut ctx, output) = subscriber();
+        subscribe(
+            &mut ctx,
+            Kind::Pattern,
+            strings(&["all:*"]),
+            output.outlet(),
+        )
+        .unwrap();
+        subscribe(
+            &mut ctx,
+            Kind::Channel,
+            strings(&["all:b", "all:a"]),
+            output.outlet(),
+        )
+        .unwrap();
+        output.replies();
+
+        unsubscribe(&mut ctx, Kind::Channel, Vec::new(), output.outlet()).unwrap();
+        assert_eq!(
+            output.replies(),
+            [
+                confirmation("unsubscribe", Some("all:a".to_string()), 2),
+                confirmation("unsubscribe", Some("all:b".to_string()), 1),
+            ]
+        );
+
+        unsubscribe(&mut ctx, Kind::Pattern, Vec::new(), output.outlet()).unwrap();
+        unsubscribe(&mut ctx, Kind::Pattern, Vec::new(), output.outlet()).unwrap();
+        assert_eq!(
+            output.replies(),
+            [
+                confirmation("punsubscribe", Some("all:*".to_string()), 0),
+                confirmation("punsubscribe", None, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_publish_reaches_channel_and_pattern_subscribers() {
+        let (mut direct, direct_output) = subscriber();
+        let (mut by_pattern, pattern_output) = subscriber();
+        subscribe(
+            &mut direct,
+            Kind::Channel,
+            strings(&["news:tech"]),
+            direct_output.outlet(),
+        )
+        .unwrap();
+        subscribe(
+            &mut by_pattern,
+            Kind::Pattern,
+            strings(&["news:*"]),
+            pattern_output.outlet(),
+        )
+        .unwrap();
+        direct_output.replies();
+        pattern_output.replies();
+
+        assert_eq!(publish("news:tech", "hello", 0), 2);
+
+        assert_eq!(
+            direct_output.replies(),
+            [RespValue::Array(Some(vec![
+                RespValue::BulkString(Some("message".into())),
+                RespValue::BulkString(Some("news:tech".into())),
+                RespValue::BulkString(Some("hello".into())),
+            ]))]
+        );
+        assert_eq!(
+            pattern_output.replies(),
+            [RespValue::Array(Some(vec![
+                RespValue::BulkString(Some("pmessage".into())),
+                RespValue::BulkString(Some("news:*".into())),
+                RespValue::BulkString(Some("news:tech".into())),
+                RespValue::BulkString(Some("hello".into())),
+            ]))]
+        );
+
+        drop(DisconnectGuard(direct.id));
+        drop(DisconnectGuard(by_pattern.id));
+        assert_eq!(publish("news:tech", "again", 0), 0);
+    }
+
+    #[test]
+    fn test_introspection() {
+        let (mut first, first_output) = subscriber();
+        let (mut second, second_output) = subscriber();
+        subscribe(
+            &mut first,
+            Kind::Channel,
+            strings(&["intro:a", "intro:b"]),
+            first_output.outlet(),
+        )
+        .unwrap();
+        subscribe(
+            &mut second,
+            Kind::Channel,
+            strings(&["intro:a"]),
+            second_output.outlet(),
+        )
+        .unwrap();
+        subscribe(
+            &mut second,
+            Kind::Pattern,
+            strings(&["intro:*"]),
+            second_output.outlet(),
+        )
+        .unwrap();
+
+        assert_eq!(channels(Some("intro:*")), strings(&["intro:a", "intro:b"]));
+        assert_eq!(channels(Some("intro:[b]")), strings(&["intro:b"]));
+        assert_eq!(subscriber_count("intro:a"), 2);
+        assert_eq!(subscriber_count("intro:b"), 1);
+        assert_eq!(subscriber_count("intro:none"), 0);
+        // Other tests subscribe to patterns concurrently, so only a lower
+        // bound holds
+        assert!(pattern_count() >= 1);
+
+        drop(DisconnectGuard(first.id));
+        drop(DisconnectGuard(second.id));
+        assert_eq!(channels(Some("intro:*")), Vec::<String>::new());
+    }
+
+    /// A connection whose client never reads: writes block until it is
+    /// closed.
+    #[derive(Clone, Default)]
+    struct StalledClient(Arc<(Mutex<bool>, std::sync::Condvar)>);
+
+    impl Write for StalledClient {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            let (closed, changed) = &*self.0;
+            let _closed = changed
+                .wait_while(closed.lock().unwrap(), |closed| !*closed)
+                .unwrap();
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Outlet for StalledClient {
+        fn close(&self) {
+            let (closed, changed) = &*self.0;
+            *closed.lock().unwrap() = true;
+            changed.notify_all();
+        }
+    }
+
+    #[test]
+    fn test_stalled_subscriber_is_disconnected() {
+        let (mut ctx, _) = subscriber();
+        let client = StalledClient::default();
+        let outlet = {
+            let client = client.clone();
+            move || Ok(Box::new(client.clone()) as Box<dyn Outlet>)
+        };
+        subscribe(&mut ctx, Kind::Channel, strings(&["stalled"]), outlet).unwrap();
+
+        let message = "x".repeat(1024);
+        let delivered = (0..100)
+            .take_while(|_| publish("stalled", &message, 16 * 1024) == 1)
+            .count();
+
+        assert!(delivered < 16);
+        assert!(*client.0 .0.lock().unwrap());
+        assert_eq!(subscriber_count("stalled"), 0);
+    }
+}
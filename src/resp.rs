@@ -1,5 +1,4 @@
 use std::io::{self, BufRead, BufWriter, Read, Write};
-use std::net::TcpStream;
 
 use log::debug;
 
@@ -8,7 +7,7 @@ pub enum RespValue {
     SimpleString(String),
     Error(String),
     Integer(i64),
-    BulkString(Option<String>),    // None represents Null bulk string
+    BulkString(Option<Vec<u8>>),   // None represents Null bulk string
     Array(Option<Vec<RespValue>>), // None represents Null array
 }
 
@@ -39,101 +38,298 @@ impl std::fmt::Display for RespError {
 
 impl std::error::Error for RespError {}
 
-pub fn read_resp<R: BufRead>(reader: &mut R) -> Result<RespValue, RespError> {
-    let mut first_byte = [0u8; 1];
-    reader.read_exact(&mut first_byte)?;
-
-    debug!(
-        "First byte: {:?} ({})",
-        first_byte[0], first_byte[0] as char
-    );
-
-    match first_byte[0] as char {
-        '+' => read_simple_string(reader),
-        '-' => read_error(reader),
-        ':' => read_integer(reader),
-        '$' => read_bulk_string(reader),
-        '*' => read_array(reader),
-        _ => Err(RespError::ParseError(format!(
-            "Invalid RESP type byte: {}",
-            first_byte[0] as char
-        ))),
+impl RespError {
+    /// The error reply sent to a client whose request failed to parse,
+    /// worded like Redis's own protocol errors.
+    pub fn to_client_reply(&self) -> RespValue {
+        let reason = match self {
+            RespError::IoError(e) => e.to_string(),
+            RespError::ParseError(s) => s.clone(),
+            RespError::InvalidLength => "invalid bulk length".to_string(),
+            RespError::InvalidUtf8 => "invalid bulk string encoding".to_string(),
+        };
+        RespValue::Error(format!("ERR Protocol error: {}", reason))
     }
 }
 
-fn read_line<R: BufRead>(reader: &mut R) -> Result<String, RespError> {
-    let mut line = String::new();
-    reader.read_line(&mut line)?;
-    Ok(line.trim_end_matches("\r\n").to_string())
+/// Accumulates a connection's incoming bytes and yields each value once it
+/// has fully arrived, so a partial request never blocks the parser. Progress
+/// through a partial value is kept between feeds, so each byte is parsed
+/// once however many pieces the value arrives in.
+pub struct RespParser {
+    buffer: Vec<u8>,
+    /// Start of the bytes not parsed yet.
+    pos: usize,
+    /// Arrays still being filled, innermost last, each with the elements
+    /// parsed so far and the number still to come.
+    open: Vec<(Vec<RespValue>, usize)>,
+    /// Length of a bulk string whose header has been parsed but whose
+    /// payload hasn't fully arrived.
+    pending_bulk: Option<usize>,
+    /// Bulk bytes the request being parsed may still declare.
+    budget: usize,
+    max_request_size: usize,
+    /// Array elements the request being parsed may still declare.
+    element_budget: usize,
+    max_elements: usize,
+    max_depth: usize,
 }
 
-fn read_simple_string<R: BufRead>(reader: &mut R) -> Result<RespValue, RespError> {
-    Ok(RespValue::SimpleString(read_line(reader)?))
+// Snapshots are read with no limits, since they hold whole collections
+impl Default for RespParser {
+    fn default() -> Self {
+        Self::unlimited()
+    }
 }
 
-fn read_error<R: BufRead>(reader: &mut R) -> Result<RespValue, RespError> {
-    Ok(RespValue::Error(read_line(reader)?))
-}
+impl RespParser {
+    /// Rejects any single request whose bulk strings declare more than
+    /// `max_request_size` bytes in total, or whose arrays declare more than
+    /// `MAX_MULTIBULK_LEN` elements or nest deeper than `MAX_NESTING`.
+    pub fn with_max_request_size(max_request_size: usize) -> Self {
+        Self {
+            budget: max_request_size,
+            max_request_size,
+            element_budget: MAX_MULTIBULK_LEN,
+            max_elements: MAX_MULTIBULK_LEN,
+            max_depth: MAX_NESTING,
+            ..Self::unlimited()
+        }
+    }
 
-fn read_integer<R: BufRead>(reader: &mut R) -> Result<RespValue, RespError> {
-    let line = read_line(reader)?;
-    let num = line
-        .parse::<i64>()
-        .map_err(|_| RespError::ParseError("Invalid integer".to_string()))?;
-    Ok(RespValue::Integer(num))
-}
+    fn unlimited() -> Self {
+        Self {
+            buffer: Vec::new(),
+            pos: 0,
+            open: Vec::new(),
+            pending_bulk: None,
+            budget: usize::MAX,
+            max_request_size: usize::MAX,
+            element_budget: usize::MAX,
+            max_elements: usize::MAX,
+            max_depth: usize::MAX,
+        }
+    }
 
-fn read_bulk_string<R: BufRead>(reader: &mut R) -> Result<RespValue, RespError> {
-    let length_str = read_line(reader)?;
-    let length = length_str
-        .parse::<i64>()
-        .map_err(|_| RespError::ParseError("Invalid bulk string length".to_string()))?;
+    pub fn feed(&mut self, bytes: &[u8]) {
+        // Parsed bytes are dropped once they are at least half the buffer,
+        // so the copying stays linear in the bytes fed
+        if self.pos > 0 && self.pos >= self.buffer.len() / 2 {
+            self.buffer.drain(..self.pos);
+            self.pos = 0;
+        }
+        self.buffer.extend_from_slice(bytes);
+    }
 
-    if length == -1 {
-        return Ok(RespValue::BulkString(None));
+    /// Returns `Ok(None)` until a complete value is buffered.
+    pub fn next_value(&mut self) -> Result<Option<RespValue>, RespError> {
+        while let Some(mut value) = self.next_element()? {
+            // The element may complete the arrays around it, innermost first
+            loop {
+                let Some((elements, remaining)) = self.open.last_mut() else {
+                    self.budget = self.max_request_size;
+                    self.element_budget = self.max_elements;
+                    return Ok(Some(value));
+                };
+                elements.push(value);
+                *remaining -= 1;
+                if *remaining > 0 {
+                    break;
+                }
+                let (elements, _) = self.open.pop().unwrap();
+                value = RespValue::Array(Some(elements));
+            }
+        }
+        Ok(None)
     }
 
-    if length < 0 {
-        return Err(RespError::ParseError(
-            "Invalid bulk string length".to_string(),
-        ));
+    /// Bytes fed but not yet parsed.
+    fn unparsed(&self) -> usize {
+        self.buffer.len() - self.pos
+    }
+
+    /// Parses up to the next complete value that isn't a non-empty array,
+    /// opening any array headers on the way.
+    fn next_element(&mut self) -> Result<Option<RespValue>, RespError> {
+        loop {
+            if let Some(length) = self.pending_bulk {
+                return self.bulk_payload(length);
+            }
+
+            let Some(&type_byte) = self.buffer.get(self.pos) else {
+                return Ok(None);
+            };
+            debug!("First byte: {:?} ({})", type_byte, type_byte as char);
+            if !matches!(type_byte, b'+' | b'-' | b':' | b'$' | b'*') {
+                return Err(RespError::ParseError(format!(
+                    "expected '$', got '{}'",
+                    type_byte as char
+                )));
+            }
+            let Some((line, end)) = parse_line(&self.buffer, self.pos + 1)? else {
+                return Ok(None);
+            };
+            self.pos = end;
+
+            match type_byte {
+                b'+' => return Ok(Some(RespValue::SimpleString(line))),
+                b'-' => return Ok(Some(RespValue::Error(line))),
+                b':' => {
+                    let num = line
+                        .parse::<i64>()
+                        .map_err(|_| RespError::ParseError("Invalid integer".to_string()))?;
+                    return Ok(Some(RespValue::Integer(num)));
+                }
+                b'$' => match self.bulk_header(&line)? {
+                    Some(length) => self.pending_bulk = Some(length),
+                    None => return Ok(Some(RespValue::BulkString(None))),
+                },
+                _ => match array_header(&line)? {
+                    Some(0) => return Ok(Some(RespValue::Array(Some(Vec::new())))),
+                    Some(length) => self.open_array(length)?,
+                    None => return Ok(Some(RespValue::Array(None))),
+                },
+            }
+        }
     }
 
-    let length = length as usize;
-    let mut buf = vec![0u8; length + 2]; // +2 for CRLF
-    reader.read_exact(&mut buf)?;
+    /// Parses a bulk string's length, charging it to the request's budget so
+    /// an oversized request is rejected before its payload is buffered.
+    /// `None` is the null bulk string.
+    fn bulk_header(&mut self, line: &str) -> Result<Option<usize>, RespError> {
+        let length = line.parse::<i64>().map_err(|_| RespError::InvalidLength)?;
+        if length == -1 {
+            return Ok(None);
+        }
+
+        // No allocation can exceed isize::MAX bytes, and the payload is followed
+        // by a CRLF, so anything longer could never be buffered
+        if length < 0 || length > isize::MAX as i64 - 2 {
+            return Err(RespError::InvalidLength);
+        }
 
-    if buf[length..] != b"\r\n"[..] {
-        return Err(RespError::ParseError("Missing CRLF".to_string()));
+        let length = length as usize;
+        self.budget = self
+            .budget
+            .checked_sub(length)
+            .ok_or_else(|| RespError::ParseError("invalid multibulk length".to_string()))?;
+        Ok(Some(length))
     }
 
-    let s = String::from_utf8(buf[..length].to_vec()).map_err(|_| RespError::InvalidUtf8)?;
+    /// Starts filling an array of `length` elements, charging them to the
+    /// request's element budget so its `Vec`s can't grow without bound.
+    fn open_array(&mut self, length: usize) -> Result<(), RespError> {
+        let invalid = || RespError::ParseError("invalid multibulk length".to_string());
+        if self.open.len() >= self.max_depth {
+            return Err(invalid());
+        }
+        self.element_budget = self
+            .element_budget
+            .checked_sub(length)
+            .ok_or_else(invalid)?;
+        self.open
+            .push((Vec::with_capacity(length.min(1024)), length));
+        Ok(())
+    }
 
-    Ok(RespValue::BulkString(Some(s)))
-}
+    fn bulk_payload(&mut self, length: usize) -> Result<Option<RespValue>, RespError> {
+        let end = self
+            .pos
+            .checked_add(length + 2) // +2 for CRLF
+            .ok_or(RespError::InvalidLength)?;
+        if self.buffer.len() < end {
+            return Ok(None);
+        }
 
-fn read_array<R: BufRead>(reader: &mut R) -> Result<RespValue, RespError> {
-    let length_str = read_line(reader)?;
-    let length = length_str
-        .parse::<i64>()
-        .map_err(|_| RespError::ParseError("Invalid array length".to_string()))?;
+        if self.buffer[self.pos + length..end] != b"\r\n"[..] {
+            return Err(RespError::ParseError(
+                "expected CRLF after bulk string".to_string(),
+            ));
+        }
 
-    if length == -1 {
-        return Ok(RespValue::Array(None));
+        let bytes = self.buffer[self.pos..self.pos + length].to_vec();
+        self.pos = end;
+        self.pending_bulk = None;
+        Ok(Some(RespValue::BulkString(Some(bytes))))
     }
+}
 
-    if length < 0 {
-        return Err(RespError::ParseError("Invalid array length".to_string()));
+/// Parses an array's length, with `None` for the null array.
+fn array_header(line: &str) -> Result<Option<usize>, RespError> {
+    let length = line
+        .parse::<i64>()
+        .map_err(|_| RespError::ParseError("invalid multibulk length".to_string()))?;
+    match length {
+        -1 => Ok(None),
+        length if length < 0 => Err(RespError::ParseError(
+            "invalid multibulk length".to_string(),
+        )),
+        length => Ok(Some(length as usize)),
     }
+}
+
+/// Parses one value from the start of `buf`, returning it along with the
+/// number of bytes it occupied, or `Ok(None)` if `buf` ends before it does.
+pub fn parse_resp(buf: &[u8]) -> Result<Option<(RespValue, usize)>, RespError> {
+    let mut parser = RespParser::default();
+    parser.feed(buf);
+    Ok(parser.next_value()?.map(|value| (value, parser.pos)))
+}
+
+/// Blocking wrapper over `RespParser` that reads until a whole value is
+/// available, consuming exactly the bytes that value occupied.
+pub fn read_resp<R: BufRead>(reader: &mut R) -> Result<RespValue, RespError> {
+    let mut parser = RespParser::default();
+
+    loop {
+        let available = reader.fill_buf()?;
+        if available.is_empty() {
+            return Err(RespError::IoError(io::ErrorKind::UnexpectedEof.into()));
+        }
 
-    let length = length as usize;
-    let mut values = Vec::with_capacity(length);
+        let chunk_len = available.len();
+        parser.feed(available);
 
-    for _ in 0..length {
-        values.push(read_resp(reader)?);
+        match parser.next_value()? {
+            // Whatever follows the value stays in the reader for next time
+            Some(value) => {
+                reader.consume(chunk_len - parser.unparsed());
+                return Ok(value);
+            }
+            None => reader.consume(chunk_len),
+        }
     }
+}
+
+/// Most array elements a client request may declare, as in Redis.
+const MAX_MULTIBULK_LEN: usize = 1024 * 1024;
+
+/// Deepest a client request's arrays may nest. Commands are flat arrays, so
+/// anything deeper is only a way to pile up open arrays.
+const MAX_NESTING: usize = 8;
 
-    Ok(RespValue::Array(Some(values)))
+/// Longest line accepted for a simple string, error, integer or length
+/// header, matching Redis's limit on inline requests. Without it a peer that
+/// never sends a newline would have its bytes buffered without bound.
+const MAX_INLINE_LEN: usize = 64 * 1024;
+
+fn parse_line(buf: &[u8], pos: usize) -> Result<Option<(String, usize)>, RespError> {
+    let window = &buf[pos..buf.len().min(pos + MAX_INLINE_LEN)];
+    let Some(newline) = window.iter().position(|&b| b == b'\n') else {
+        if window.len() == MAX_INLINE_LEN {
+            return Err(RespError::ParseError("too big inline request".to_string()));
+        }
+        return Ok(None);
+    };
+    // A bare LF is a protocol error rather than a terminator; accepting it
+    // would leave this side and the peer disagreeing on where values end
+    if newline == 0 || window[newline - 1] != b'\r' {
+        return Err(RespError::ParseError(
+            "expected CRLF at end of line".to_string(),
+        ));
+    }
+    let line = std::str::from_utf8(&window[..newline - 1]).map_err(|_| RespError::InvalidUtf8)?;
+    Ok(Some((line.to_string(), pos + newline + 1)))
 }
 
 pub fn read_resp_from_stream<T: Read>(
@@ -141,6 +337,7 @@ pub fn read_resp_from_stream<T: Read>(
 ) -> Result<RespValue, RespError> {
     read_resp(stream)
 }
+
 pub fn write_resp<T: Write>(value: &RespValue, stream: &mut BufWriter<T>) -> Result<(), io::Error> {
     match value {
         RespValue::Array(Some(array)) => {
@@ -149,8 +346,10 @@ pub fn write_resp<T: Write>(value: &RespValue, stream: &mut BufWriter<T>) -> Res
                 write_resp(item, stream)?;
             }
         }
-        RespValue::BulkString(Some(s)) => {
-            write!(stream, "${}\r\n{}\r\n", s.len(), s)?;
+        RespValue::BulkString(Some(bytes)) => {
+            write!(stream, "${}\r\n", bytes.len())?;
+            stream.write_all(bytes)?;
+            stream.write_all(b"\r\n")?;
         }
         RespValue::BulkString(None) => {
             write!(stream, "$-1\r\n")?;
@@ -187,6 +386,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_bare_lf_rejected() {
+        for input in ["+OK\n", ":12\n", "$3\nfoo\r\n", "*1\n$3\r\nGET\r\n"] {
+            let mut reader = io::BufReader::new(Cursor::new(input));
+            let err = read_resp(&mut reader).unwrap_err();
+            assert_eq!(
+                err.to_client_reply(),
+                RespValue::Error("ERR Protocol error: expected CRLF at end of line".to_string()),
+                "{:?}",
+                input
+            );
+        }
+    }
+
     #[test]
     fn test_error() {
         let input = "-Error message\r\n";
@@ -210,7 +423,7 @@ mod tests {
         let mut reader = io::BufReader::new(Cursor::new(input));
         assert_eq!(
             read_resp(&mut reader).unwrap(),
-            RespValue::BulkString(Some("foobar".to_string()))
+            RespValue::BulkString(Some("foobar".into()))
         );
     }
 
@@ -228,8 +441,8 @@ mod tests {
         assert_eq!(
             read_resp(&mut reader).unwrap(),
             RespValue::Array(Some(vec![
-                RespValue::BulkString(Some("GET".to_string())),
-                RespValue::BulkString(Some("keys".to_string())),
+                RespValue::BulkString(Some("GET".into())),
+                RespValue::BulkString(Some("keys".into())),
             ]))
         );
     }
@@ -241,6 +454,223 @@ mod tests {
         assert_eq!(read_resp(&mut reader).unwrap(), RespValue::Array(None));
     }
 
+    #[test]
+    fn test_invalid_bulk_length_reply() {
+        for input in ["$abc\r\n", "$-5\r\n"] {
+            let mut reader = io::BufReader::new(Cursor::new(input));
+            let err = read_resp(&mut reader).unwrap_err();
+            assert_eq!(
+                err.to_client_reply(),
+                RespValue::Error("ERR Protocol error: invalid bulk length".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn test_huge_bulk_length_rejected() {
+        let input = format!("${}\r\n", i64::MAX);
+
+        assert!(matches!(
+            parse_resp(input.as_bytes()),
+            Err(RespError::InvalidLength)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_multibulk_length_reply() {
+        let input = "*x\r\n";
+        let mut reader = io::BufReader::new(Cursor::new(input));
+        let err = read_resp(&mut reader).unwrap_err();
+        assert_eq!(
+            err.to_client_reply(),
+            RespValue::Error("ERR Protocol error: invalid multibulk length".to_string())
+        );
+    }
+
+    #[test]
+    fn test_unexpected_type_byte_reply() {
+        let input = "*1\r\n GET\r\n";
+        let mut reader = io::BufReader::new(Cursor::new(input));
+        let err = read_resp(&mut reader).unwrap_err();
+        assert_eq!(
+            err.to_client_reply(),
+            RespValue::Error("ERR Protocol error: expected '$', got ' '".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parser_byte_at_a_time() {
+        let input = b"*3\r\n$3\r\nSET\r\n$5\r\nmykey\r\n:42\r\n";
+        let mut parser = RespParser::default();
+
+        for byte in &input[..input.len() - 1] {
+            parser.feed(&[*byte]);
+            assert_eq!(parser.next_value().unwrap(), None);
+        }
+        parser.feed(&input[input.len() - 1..]);
+
+        assert_eq!(
+            parser.next_value().unwrap(),
+            Some(RespValue::Array(Some(vec![
+                RespValue::BulkString(Some("SET".into())),
+                RespValue::BulkString(Some("mykey".into())),
+                RespValue::Integer(42),
+            ])))
+        );
+        assert_eq!(parser.next_value().unwrap(), None);
+    }
+
+    #[test]
+    fn test_parser_yields_pipelined_values_in_order() {
+        let mut parser = RespParser::default();
+        parser.feed(b"+OK\r\n:1\r\n$3\r\nfo");
+
+        assert_eq!(
+            parser.next_value().unwrap(),
+            Some(RespValue::SimpleString("OK".to_string()))
+        );
+        assert_eq!(parser.next_value().unwrap(), Some(RespValue::Integer(1)));
+        assert_eq!(parser.next_value().unwrap(), None);
+
+        parser.feed(b"o\r\n");
+        assert_eq!(
+            parser.next_value().unwrap(),
+            Some(RespValue::BulkString(Some("foo".into())))
+        );
+    }
+
+    #[test]
+    fn test_parser_large_array_in_small_chunks() {
+        let pairs = 80_000;
+        let mut input = format!("*{}\r\n$4\r\nMSET\r\n", 1 + 2 * pairs).into_bytes();
+        for i in 0..pairs {
+            let (key, value) = (format!("key:{}", i), format!("{}", i));
+            input.extend(format!("${}\r\n{}\r\n", key.len(), key).bytes());
+            input.extend(format!("${}\r\n{}\r\n", value.len(), value).bytes());
+        }
+        let mut parser = RespParser::default();
+
+        let (first, rest) = input.split_at(input.len() / 2);
+        for chunk in first.chunks(16) {
+            parser.feed(chunk);
+            assert_eq!(parser.next_value().unwrap(), None);
+        }
+        // The elements that have arrived are parsed already, not re-parsed
+        // once the rest comes in
+        assert!(parser.open[0].0.len() > pairs);
+
+        let mut value = None;
+        for chunk in rest.chunks(16) {
+            parser.feed(chunk);
+            if let Some(parsed) = parser.next_value().unwrap() {
+                value = Some(parsed);
+            }
+        }
+
+        let Some(RespValue::Array(Some(elements))) = value else {
+            panic!("expected an array, got {:?}", value);
+        };
+        assert_eq!(elements.len(), 1 + 2 * pairs);
+        assert_eq!(
+            elements.last(),
+            Some(&RespValue::BulkString(Some(
+                (pairs - 1).to_string().into_bytes()
+            )))
+        );
+        assert_eq!(parser.unparsed(), 0);
+    }
+
+    #[test]
+    fn test_parser_rejects_oversized_request() {
+        let mut parser = RespParser::with_max_request_size(10);
+        // Each bulk string fits on its own, but together they declare 11 bytes
+        parser.feed(b"*2\r\n$5\r\nhello\r\n$6\r\n");
+
+        let err = parser.next_value().unwrap_err();
+        assert_eq!(
+            err.to_client_reply(),
+            RespValue::Error("ERR Protocol error: invalid multibulk length".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parser_caps_declared_elements() {
+        let invalid = RespValue::Error("ERR Protocol error: invalid multibulk length".to_string());
+
+        let mut parser = RespParser::with_max_request_size(1024);
+        parser.feed(format!("*{}\r\n", MAX_MULTIBULK_LEN + 1).as_bytes());
+        assert_eq!(parser.next_value().unwrap_err().to_client_reply(), invalid);
+
+        // Nested arrays share the request's budget
+        let mut parser = RespParser::with_max_request_size(1024);
+        parser.feed(format!("*2\r\n*{}\r\n", MAX_MULTIBULK_LEN).as_bytes());
+        assert_eq!(parser.next_value().unwrap_err().to_client_reply(), invalid);
+
+        let mut parser = RespParser::with_max_request_size(1024);
+        parser.feed(&b"*1\r\n".repeat(MAX_NESTING + 1));
+        assert_eq!(parser.next_value().unwrap_err().to_client_reply(), invalid);
+    }
+
+    #[test]
+    fn test_parser_element_budget_applies_per_request() {
+        let mut parser = RespParser::with_max_request_size(1024);
+        let request = format!(
+            "*{}\r\n{}",
+            MAX_MULTIBULK_LEN,
+            "$0\r\n\r\n".repeat(MAX_MULTIBULK_LEN)
+        );
+
+        parser.feed(request.as_bytes());
+        parser.feed(request.as_bytes());
+        assert!(parser.next_value().unwrap().is_some());
+        assert!(parser.next_value().unwrap().is_some());
+    }
+
+    #[test]
+    fn test_parser_rejects_overlong_line() {
+        let mut parser = RespParser::default();
+        parser.feed(b"+");
+        parser.feed(&vec![b'a'; MAX_INLINE_LEN - 1]);
+        assert_eq!(parser.next_value().unwrap(), None);
+
+        // The limit trips as soon as it is reached, without waiting for a newline
+        parser.feed(b"a");
+        let err = parser.next_value().unwrap_err();
+        assert_eq!(
+            err.to_client_reply(),
+            RespValue::Error("ERR Protocol error: too big inline request".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parser_limit_applies_per_request() {
+        let mut parser = RespParser::with_max_request_size(10);
+        parser.feed(b"*1\r\n$5\r\nhello\r\n*1\r\n$5\r\nworld\r\n");
+
+        assert!(parser.next_value().unwrap().is_some());
+        assert!(parser.next_value().unwrap().is_some());
+    }
+
+    #[test]
+    fn test_read_resp_leaves_following_bytes_unread() {
+        let input = "+first\r\n+second\r\n";
+        // A tiny buffer forces values to straddle fill_buf calls
+        let mut reader = io::BufReader::with_capacity(3, Cursor::new(input));
+
+        assert_eq!(
+            read_resp(&mut reader).unwrap(),
+            RespValue::SimpleString("first".to_string())
+        );
+        assert_eq!(
+            read_resp(&mut reader).unwrap(),
+            RespValue::SimpleString("second".to_string())
+        );
+        assert!(matches!(
+            read_resp(&mut reader),
+            Err(RespError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof
+        ));
+    }
+
     #[test]
     fn test_nested_array() {
         let input = "*2\r\n*2\r\n+OK\r\n:1234\r\n$6\r\nfoobar\r\n";
@@ -252,8 +682,39 @@ mod tests {
                     RespValue::SimpleString("OK".to_string()),
                     RespValue::Integer(1234),
                 ])),
-                RespValue::BulkString(Some("foobar".to_string())),
+                RespValue::BulkString(Some("foobar".into())),
             ]))
         );
     }
+
+    fn encode(value: &RespValue) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_resp(value, &mut BufWriter::new(&mut out)).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_write_array_wire_format() {
+        let value = RespValue::Array(Some(vec![
+            RespValue::BulkString(Some("OK".into())),
+            RespValue::BulkString(Some("héllo".into())),
+            RespValue::BulkString(None),
+            RespValue::Array(Some(vec![RespValue::Integer(-3)])),
+            RespValue::Array(None),
+        ]));
+
+        let encoded = encode(&value);
+
+        // Lengths count bytes, and nothing follows the final element
+        assert_eq!(
+            encoded,
+            b"*5\r\n$2\r\nOK\r\n$6\r\nh\xc3\xa9llo\r\n$-1\r\n*1\r\n:-3\r\n*-1\r\n"
+        );
+        assert_eq!(parse_resp(&encoded).unwrap(), Some((value, encoded.len())));
+    }
+
+    #[test]
+    fn test_write_empty_array() {
+        assert_eq!(encode(&RespValue::Array(Some(Vec::new()))), b"*0\r\n");
+    }
 }
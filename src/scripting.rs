@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+
+use mlua::{Lua, Value as LuaValue, Variadic};
+use sha1::{Digest, Sha1};
+
+use crate::command::Command;
+use crate::command_handler::handle_command;
+use crate::connection::ConnectionContext;
+use crate::resp::RespValue;
+use crate::storage::{lock_storage, Databases, Storage};
+
+// Scripts cached by SCRIPT LOAD, keyed by the lowercase hex SHA1 of the body
+static SCRIPTS: LazyLock<Mutex<HashMap<String, String>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub fn sha1_hex(script: &str) -> String {
+    format!("{:x}", Sha1::digest(script.as_bytes()))
+}
+
+/// Caches `script` for EVALSHA and returns its SHA1.
+pub fn load(script: String) -> String {
+    let sha = sha1_hex(&script);
+    SCRIPTS.lock().unwrap().insert(sha.clone(), script);
+    sha
+}
+
+pub fn eval_sha(
+    sha: &str,
+    keys: Vec<Vec<u8>>,
+    args: Vec<Vec<u8>>,
+    storage: &Arc<Mutex<Storage>>,
+) -> RespValue {
+    let script = SCRIPTS.lock().unwrap().get(&sha.to_lowercase()).cloned();
+    match script {
+        Some(script) => eval(&script, keys, args, storage),
+        None => RespValue::Error("NOSCRIPT No matching script.".to_string()),
+    }
+}
+
+/// Runs `script` with `KEYS` and `ARGV` populated. The storage lock is held
+/// for the whole script, so no other client observes its writes half done.
+pub fn eval(
+    script: &str,
+    keys: Vec<Vec<u8>>,
+    args: Vec<Vec<u8>>,
+    storage: &Arc<Mutex<Storage>>,
+) -> RespValue {
+    let mut guard = lock_storage(storage);
+    // redis.call dispatches through handle_command, which takes its own lock,
+    // so the script runs against the data moved into a private mutex
+    let scoped = Arc::new(Databases::new(
+        std::mem::replace(&mut *guard, Storage::new()),
+        1,
+    ));
+
+    let result = run(script, keys, args, &scoped);
+
+    let mut scoped_storage = lock_storage(scoped.get(0));
+    *guard = std::mem::replace(&mut *scoped_storage, Storage::new());
+    match result {
+        Ok(value) => value,
+        Err(e) => RespValue::Error(format!("ERR Error running script: {}", e)),
+    }
+}
+
+fn run(
+    script: &str,
+    keys: Vec<Vec<u8>>,
+    args: Vec<Vec<u8>>,
+    storage: &Arc<Databases>,
+) -> mlua::Result<RespValue> {
+    let lua = Lua::new();
+    let globals = lua.globals();
+    // Lua strings are byte strings, so keys and arguments pass through as
+    // sent rather than as tables of bytes
+    let to_strings = |items: Vec<Vec<u8>>| {
+        items
+            .iter()
+            .map(|item| lua.create_string(item))
+            .collect::<mlua::Result<Vec<_>>>()
+    };
+    globals.set("KEYS", to_strings(keys)?)?;
+    globals.set("ARGV", to_strings(args)?)?;
+
+    let redis = lua.create_table()?;
+    let storage = storage.clone();
+    let call = lua.create_function(move |lua, args: Variadic<LuaValue>| {
+        let args = args
+            .iter()
+            .map(|arg| match arg {
+                LuaValue::String(s) => Ok(RespValue::BulkString(Some(s.as_bytes().to_vec()))),
+                LuaValue::Integer(i) => Ok(RespValue::BulkString(Some(i.to_string().into_bytes()))),
+                LuaValue::Number(n) => Ok(RespValue::BulkString(Some(n.to_string().into_bytes()))),
+                _ => Err(mlua::Error::runtime(
+                    "Lua redis lib command arguments must be strings or integers",
+                )),
+            })
+            .collect::<mlua::Result<Vec<_>>>()?;
+
+        let command = Command::try_from(RespValue::Array(Some(args)))
+            .map_err(|e| mlua::Error::runtime(e.to_string()))?;
+        let mut ctx = ConnectionContext {
+            in_script: true,
+            ..ConnectionContext::default()
+        };
+        match handle_command(command, &storage, &mut ctx) {
+            RespValue::Error(e) => Err(mlua::Error::runtime(e)),
+            reply => to_lua(lua, reply),
+        }
+    })?;
+    redis.set("call", call)?;
+    globals.set("redis", redis)?;
+
+    let value = lua.load(script).eval::<LuaValue>()?;
+    from_lua(value)
+}
+
+// Follows the Redis conversion rules: status and error replies become
+// tables with a single `ok` or `err` field, nil replies become false
+fn to_lua(lua: &Lua, value: RespValue) -> mlua::Result<LuaValue<'_>> {
+    match value {
+        RespValue::Integer(i) => Ok(LuaValue::Integer(i)),
+        RespValue::BulkString(Some(s)) => Ok(LuaValue::String(lua.create_string(&s)?)),
+        RespValue::BulkString(None) | RespValue::Array(None) => Ok(LuaValue::Boolean(false)),
+        RespValue::SimpleString(s) => {
+            let table = lua.create_table()?;
+            table.set("ok", s)?;
+            Ok(LuaValue::Table(table))
+        }
+        RespValue::Error(e) => {
+            let table = lua.create_table()?;
+            table.set("err", e)?;
+            Ok(LuaValue::Table(table))
+        }
+        RespValue::Array(Some(items)) => {
+            let table = lua.create_table()?;
+            for item in items {
+                table.push(to_lua(lua, item)?)?;
+            }
+            Ok(LuaValue::Table(table))
+        }
+    }
+}
+
+fn from_lua(value: LuaValue) -> mlua::Result<RespValue> {
+    match value {
+        LuaValue::Integer(i) => Ok(RespValue::Integer(i)),
+        LuaValue::Number(n) => Ok(RespValue::Integer(n as i64)),
+        LuaValue::String(s) => Ok(RespValue::BulkString(Some(s.as_bytes().to_vec()))),
+        LuaValue::Boolean(true) => Ok(RespValue::Integer(1)),
+        LuaValue::Table(table) => {
+            if let Some(ok) = table.get::<_, Option<String>>("ok")? {
+                return Ok(RespValue::SimpleString(ok));
+            }
+            if let Some(err) = table.get::<_, Option<String>>("err")? {
+                return Ok(RespValue::Error(err));
+            }
+            // Like Redis, an array reply stops at the first nil
+            let items = table
+                .sequence_values::<LuaValue>()
+                .map(|item| item.and_then(from_lua))
+                .collect::<mlua::Result<Vec<_>>>()?;
+            Ok(RespValue::Array(Some(items)))
+        }
+        _ => Ok(RespValue::BulkString(None)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<Vec<u8>> {
+        values.iter().map(|v| v.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_eval_redis_call_sets_key() {
+        let storage = Arc::new(Mutex::new(Storage::new()));
+
+        let reply = eval(
+            "return redis.call('SET', KEYS[1], ARGV[1])",
+            strings(&["k"]),
+            strings(&["v"]),
+            &storage,
+        );
+
+        assert_eq!(reply, RespValue::SimpleString("OK".to_string()));
+        assert_eq!(
+            storage.lock().unwrap().get("k".into()),
+            Ok(Some(b"v".to_vec()))
+        );
+    }
+
+    #[test]
+    fn test_script_load_then_evalsha() {
+        let storage = Arc::new(Mutex::new(Storage::new()));
+
+        let sha = load("return ARGV[1] .. KEYS[1]".to_string());
+        assert_eq!(sha, sha1_hex("return ARGV[1] .. KEYS[1]"));
+        assert_eq!(sha.len(), 40);
+
+        let reply = eval_sha(&sha, strings(&["b"]), strings(&["a"]), &storage);
+        assert_eq!(reply, RespValue::BulkString(Some("ab".into())));
+    }
+
+    #[test]
+    fn test_evalsha_unknown_hash() {
+        let storage = Arc::new(Mutex::new(Storage::new()));
+
+        let reply = eval_sha(&sha1_hex("return 'never loaded'"), vec![], vec![], &storage);
+
+        assert_eq!(
+            reply,
+            RespValue::Error("NOSCRIPT No matching script.".to_string())
+        );
+    }
+
+    #[test]
+    fn test_eval_converts_return_values() {
+        let storage = Arc::new(Mutex::new(Storage::new()));
+
+        let reply = eval("return {1, 'two', {3}, nil, 5}", vec![], vec![], &storage);
+
+        assert_eq!(
+            reply,
+            RespValue::Array(Some(vec![
+                RespValue::Integer(1),
+                RespValue::BulkString(Some("two".into())),
+                RespValue::Array(Some(vec![RespValue::Integer(3)])),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_eval_command_error_aborts_script() {
+        let storage = Arc::new(Mutex::new(Storage::new()));
+        storage.lock().unwrap().set("k".into(), b"v".to_vec());
+
+        let reply = eval(
+            "return redis.call('LPUSH', 'k', 'x')",
+            vec![],
+            vec![],
+            &storage,
+        );
+
+        let RespValue::Error(e) = reply else {
+            panic!("expected an error, got {:?}", reply);
+        };
+        assert!(e.starts_with("ERR Error running script"));
+        assert!(e.contains("WRONGTYPE"));
+        assert!(storage.lock().unwrap().has("k".into()));
+    }
+}
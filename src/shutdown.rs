@@ -0,0 +1,176 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use crate::logger::Logger;
+use crate::persistence;
+use crate::storage::Databases;
+
+/// How long a shutdown waits for the commands already running to finish.
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The server's shutdown state, shared by every connection.
+pub static SHUTDOWN: Coordinator = Coordinator::new();
+
+/// Tracks the commands in flight so that a shutdown can stop new ones from
+/// starting and wait for the rest.
+pub struct Coordinator {
+    requested: AtomicBool,
+    in_flight: Mutex<usize>,
+    idle: Condvar,
+}
+
+/// Marks a command as running until dropped.
+pub struct InFlight<'a>(&'a Coordinator);
+
+impl Coordinator {
+    pub const fn new() -> Self {
+        Self {
+            requested: AtomicBool::new(false),
+            in_flight: Mutex::new(0),
+            idle: Condvar::new(),
+        }
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Registers a command about to run, or returns `None` once a shutdown
+    /// has begun and the connection should close instead.
+    pub fn begin(&self) -> Option<InFlight<'_>> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if self.is_requested() {
+            return None;
+        }
+        *in_flight += 1;
+        Some(InFlight(self))
+    }
+
+    /// Stops new commands from starting and waits up to `timeout` for the
+    /// running ones, returning whether they all finished.
+    pub fn drain(&self, timeout: Duration) -> bool {
+        let in_flight = self.in_flight.lock().unwrap();
+        // Set under the lock so no command can slip in after the count is read
+        self.requested.store(true, Ordering::SeqCst);
+        let (in_flight, _) = self
+            .idle
+            .wait_timeout_while(in_flight, timeout, |count| *count > 0)
+            .unwrap();
+        *in_flight == 0
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        let mut in_flight = self.0.in_flight.lock().unwrap();
+        *in_flight -= 1;
+        if *in_flight == 0 {
+            self.0.idle.notify_all();
+        }
+    }
+}
+
+/// Stops the server gracefully: drains running commands, flushes the
+/// command log and, when `snapshot` names a file, saves every database to
+/// it. Returns the exit code for the process.
+pub fn shut_down(
+    coordinator: &Coordinator,
+    databases: &Databases,
+    logger: &Logger,
+    snapshot: Option<&str>,
+    timeout: Duration,
+) -> i32 {
+    if !coordinator.drain(timeout) {
+        eprintln!("Shutting down with commands still running");
+    }
+    logger.flush();
+
+    if let Some(path) = snapshot {
+        if let Err(e) = persistence::save(databases, path) {
+            eprintln!("Failed to save snapshot {} on shutdown: {}", path, e);
+            return 1;
+        }
+    }
+    0
+}
+
+/// Runs `on_signal` on a background thread when the process first receives
+/// SIGINT or SIGTERM.
+#[cfg(unix)]
+pub fn install_handlers(on_signal: impl FnOnce() + Send + 'static) -> std::io::Result<()> {
+    use signal_hook::consts::{SIGINT, SIGTERM};
+
+    let mut signals = signal_hook::iterator::Signals::new([SIGINT, SIGTERM])?;
+    std::thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            eprintln!("Received signal {}, shutting down", signal);
+            on_signal();
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{lock_storage, Storage};
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    #[test]
+    fn test_drain_waits_for_running_commands() {
+        let coordinator = Arc::new(Coordinator::new());
+        let running = Arc::clone(&coordinator);
+        let (started, wait_started) = std::sync::mpsc::channel();
+        let command = std::thread::spawn(move || {
+            let _in_flight = running.begin().unwrap();
+            started.send(()).unwrap();
+            std::thread::sleep(Duration::from_millis(50));
+        });
+        wait_started.recv().unwrap();
+        let start = Instant::now();
+
+        assert!(coordinator.drain(Duration::from_secs(5)));
+
+        assert!(start.elapsed() >= Duration::from_millis(25));
+        assert!(coordinator.begin().is_none());
+        command.join().unwrap();
+    }
+
+    #[test]
+    fn test_drain_gives_up_after_timeout() {
+        let coordinator = Coordinator::new();
+        let _in_flight = coordinator.begin().unwrap();
+
+        assert!(!coordinator.drain(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn test_shut_down_flushes_log_and_saves_snapshot() {
+        let dir = std::env::temp_dir();
+        let log_file = dir.join(format!("rrrr-shutdown-{}.log", std::process::id()));
+        let snapshot = dir.join(format!("rrrr-shutdown-{}.resp", std::process::id()));
+        let logger = Logger::new(log_file.to_string_lossy().into_owned());
+        let databases = Databases::new(Storage::new(), 2);
+        lock_storage(databases.get(1)).set("k".into(), b"v".to_vec());
+        logger.log("SET k v".to_string());
+
+        let code = shut_down(
+            &Coordinator::new(),
+            &databases,
+            &logger,
+            snapshot.to_str(),
+            DRAIN_TIMEOUT,
+        );
+
+        assert_eq!(code, 0);
+        assert!(std::fs::read_to_string(&log_file)
+            .unwrap()
+            .ends_with("] SET k v\n"));
+        let mut restored = persistence::load(&snapshot, 2).unwrap();
+        assert_eq!(restored[1].get("k".into()), Ok(Some(b"v".to_vec())));
+        std::fs::remove_file(log_file).unwrap();
+        std::fs::remove_file(snapshot).unwrap();
+    }
+}
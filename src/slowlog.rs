@@ -0,0 +1,105 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::resp::RespValue;
+
+struct SlowLogEntry {
+    id: u64,
+    timestamp: u64,
+    duration_us: u64,
+    args: Vec<String>,
+}
+
+/// Bounded log of commands that ran longer than the configured threshold,
+/// newest first.
+#[derive(Default)]
+pub struct SlowLog {
+    entries: Mutex<VecDeque<SlowLogEntry>>,
+    next_id: AtomicU64,
+}
+
+pub static SLOWLOG: LazyLock<SlowLog> = LazyLock::new(SlowLog::default);
+
+impl SlowLog {
+    pub fn record(&self, args: Vec<String>, duration: Duration, max_len: usize) {
+        let entry = SlowLogEntry {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            duration_us: duration.as_micros() as u64,
+            args,
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.push_front(entry);
+        entries.truncate(max_len);
+    }
+
+    /// Returns up to `count` of the most recent entries as
+    /// `[id, timestamp, duration, [args...]]` arrays.
+    pub fn get(&self, count: usize) -> RespValue {
+        let entries = self.entries.lock().unwrap();
+        RespValue::Array(Some(
+            entries
+                .iter()
+                .take(count)
+                .map(|entry| {
+                    RespValue::Array(Some(vec![
+                        RespValue::Integer(entry.id as i64),
+                        RespValue::Integer(entry.timestamp as i64),
+                        RespValue::Integer(entry.duration_us as i64),
+                        RespValue::Array(Some(
+                            entry
+                                .args
+                                .iter()
+                                .map(|arg| RespValue::BulkString(Some(arg.as_bytes().to_vec())))
+                                .collect(),
+                        )),
+                    ]))
+                })
+                .collect(),
+        ))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn reset(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slowlog_is_bounded_and_newest_first() {
+        let slowlog = SlowLog::default();
+
+        for i in 0..3 {
+            slowlog.record(vec![format!("cmd{}", i)], Duration::from_micros(i), 2);
+        }
+
+        assert_eq!(slowlog.len(), 2);
+        let RespValue::Array(Some(entries)) = slowlog.get(1) else {
+            panic!("SLOWLOG GET should return an array");
+        };
+        let RespValue::Array(Some(fields)) = &entries[0] else {
+            panic!("entries should be arrays");
+        };
+        assert_eq!(fields[0], RespValue::Integer(2));
+        assert_eq!(
+            fields[3],
+            RespValue::Array(Some(vec![RespValue::BulkString(Some("cmd2".into()))]))
+        );
+
+        slowlog.reset();
+        assert_eq!(slowlog.len(), 0);
+    }
+}
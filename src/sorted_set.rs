@@ -0,0 +1,267 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+
+/// A score with a total order so it can key a `BTreeSet`. NaN is never
+/// stored, so `total_cmp` agrees with numeric order.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Score(f64);
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Members ordered by score, then lexicographically for equal scores.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SortedSet {
+    scores: HashMap<Vec<u8>, f64>,
+    order: BTreeSet<(Score, Vec<u8>)>,
+}
+
+/// One end of a score range, as written in `ZRANGEBYSCORE`: a number, `(`
+/// before a number for an exclusive bound, or `-inf`/`+inf`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScoreBound {
+    pub value: f64,
+    pub exclusive: bool,
+}
+
+impl ScoreBound {
+    pub fn parse(bound: &str) -> Option<Self> {
+        let (value, exclusive) = match bound.strip_prefix('(') {
+            Some(value) => (value, true),
+            None => (bound, false),
+        };
+        let value = value.parse::<f64>().ok().filter(|value| !value.is_nan())?;
+        Some(Self { value, exclusive })
+    }
+
+    fn admits_above(&self, score: f64) -> bool {
+        if self.exclusive {
+            score > self.value
+        } else {
+            score >= self.value
+        }
+    }
+
+    fn admits_below(&self, score: f64) -> bool {
+        if self.exclusive {
+            score < self.value
+        } else {
+            score <= self.value
+        }
+    }
+}
+
+/// One end of a member range, as written in `ZRANGEBYLEX`: `[` or `(`
+/// before a member for an inclusive or exclusive bound, or `-`/`+` for the
+/// lowest and highest possible member.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LexBound {
+    Min,
+    Max,
+    Inclusive(Vec<u8>),
+    Exclusive(Vec<u8>),
+}
+
+impl LexBound {
+    pub fn parse(bound: &str) -> Option<Self> {
+        match bound {
+            "-" => Some(Self::Min),
+            "+" => Some(Self::Max),
+            _ => match bound.as_bytes().split_first() {
+                Some((b'[', member)) => Some(Self::Inclusive(member.to_vec())),
+                Some((b'(', member)) => Some(Self::Exclusive(member.to_vec())),
+                _ => None,
+            },
+        }
+    }
+
+    fn admits_above(&self, member: &[u8]) -> bool {
+        match self {
+            Self::Min => true,
+            Self::Max => false,
+            Self::Inclusive(bound) => member >= bound.as_slice(),
+            Self::Exclusive(bound) => member > bound.as_slice(),
+        }
+    }
+
+    fn admits_below(&self, member: &[u8]) -> bool {
+        match self {
+            Self::Min => false,
+            Self::Max => true,
+            Self::Inclusive(bound) => member <= bound.as_slice(),
+            Self::Exclusive(bound) => member < bound.as_slice(),
+        }
+    }
+}
+
+/// Formats a score the way replies show it: integral scores without a
+/// fractional part, infinities as `inf`/`-inf`.
+pub fn format_score(score: f64) -> String {
+    score.to_string()
+}
+
+impl SortedSet {
+    /// Sets `member`'s score, returning true if the member is new.
+    pub fn insert(&mut self, member: Vec<u8>, score: f64) -> bool {
+        let previous = self.scores.insert(member.clone(), score);
+        if let Some(previous) = previous {
+            self.order.remove(&(Score(previous), member.clone()));
+        }
+        self.order.insert((Score(score), member));
+        previous.is_none()
+    }
+
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => {
+                self.order.remove(&(Score(score), member.to_vec()));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes and returns the member with the lowest score, or the highest
+    /// when `lowest` is false.
+    pub fn pop(&mut self, lowest: bool) -> Option<(Vec<u8>, f64)> {
+        let (score, member) = if lowest {
+            self.order.pop_first()?
+        } else {
+            self.order.pop_last()?
+        };
+        self.scores.remove(&member);
+        Some((member, score.0))
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Zero-based position of `member` in ascending order.
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        let score = self.score(member)?;
+        Some(self.order.range(..(Score(score), member.to_vec())).count())
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// Members and scores in ascending order.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&[u8], f64)> {
+        self.order
+            .iter()
+            .map(|(score, member)| (member.as_slice(), score.0))
+    }
+
+    /// Members whose score lies between `min` and `max`, ascending.
+    pub fn range_by_score(
+        &self,
+        min: ScoreBound,
+        max: ScoreBound,
+    ) -> impl DoubleEndedIterator<Item = (&[u8], f64)> {
+        self.iter()
+            .filter(move |(_, score)| min.admits_above(*score) && max.admits_below(*score))
+    }
+
+    /// Members between `min` and `max` by byte order. Only meaningful when
+    /// every member has the same score, as the set is walked in score order.
+    pub fn range_by_lex<'a>(
+        &'a self,
+        min: &'a LexBound,
+        max: &'a LexBound,
+    ) -> impl Iterator<Item = (&'a [u8], f64)> {
+        self.iter()
+            .filter(move |(member, _)| min.admits_above(member) && max.admits_below(member))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zset(entries: &[(&str, f64)]) -> SortedSet {
+        let mut zset = SortedSet::default();
+        for (member, score) in entries {
+            zset.insert(member.as_bytes().to_vec(), *score);
+        }
+        zset
+    }
+
+    #[test]
+    fn test_orders_by_score_then_member() {
+        let zset = zset(&[("b", 1.0), ("c", 0.5), ("a", 1.0)]);
+
+        let members: Vec<&[u8]> = zset.iter().map(|(member, _)| member).collect();
+
+        assert_eq!(members, [&b"c"[..], b"a", b"b"]);
+    }
+
+    #[test]
+    fn test_rescoring_moves_member() {
+        let mut zset = zset(&[("a", 1.0), ("b", 2.0)]);
+
+        assert!(!zset.insert(b"a".to_vec(), 3.0));
+
+        assert_eq!(zset.len(), 2);
+        assert_eq!(zset.rank(b"a"), Some(1));
+        assert_eq!(zset.rank(b"b"), Some(0));
+    }
+
+    #[test]
+    fn test_pop_takes_from_either_end() {
+        let mut zset = zset(&[("b", 2.0), ("a", 1.0), ("c", 3.0)]);
+
+        assert_eq!(zset.pop(true), Some((b"a".to_vec(), 1.0)));
+        assert_eq!(zset.pop(false), Some((b"c".to_vec(), 3.0)));
+        assert_eq!(zset.score(b"a"), None);
+        assert_eq!(zset.len(), 1);
+    }
+
+    #[test]
+    fn test_score_bounds() {
+        assert_eq!(
+            ScoreBound::parse("(1.5"),
+            Some(ScoreBound {
+                value: 1.5,
+                exclusive: true
+            })
+        );
+        assert_eq!(
+            ScoreBound::parse("-inf").map(|bound| bound.value),
+            Some(f64::NEG_INFINITY)
+        );
+        assert_eq!(ScoreBound::parse("abc"), None);
+        assert_eq!(ScoreBound::parse("nan"), None);
+    }
+
+    #[test]
+    fn test_lex_bounds() {
+        assert_eq!(LexBound::parse("-"), Some(LexBound::Min));
+        assert_eq!(
+            LexBound::parse("[a"),
+            Some(LexBound::Inclusive(b"a".to_vec()))
+        );
+        assert_eq!(
+            LexBound::parse("(b"),
+            Some(LexBound::Exclusive(b"b".to_vec()))
+        );
+        assert_eq!(LexBound::parse("a"), None);
+        assert_eq!(LexBound::parse(""), None);
+    }
+}
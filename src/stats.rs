@@ -0,0 +1,250 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, RwLock};
+use std::time::Duration;
+
+#[derive(Default)]
+struct CommandStat {
+    calls: AtomicU64,
+    usec: AtomicU64,
+}
+
+/// Per-command call counts and cumulative execution time. Counters are
+/// atomics behind a read lock; the write lock is only taken the first time a
+/// command is seen.
+#[derive(Default)]
+pub struct CommandStats {
+    commands: RwLock<HashMap<&'static str, CommandStat>>,
+}
+
+pub static COMMAND_STATS: LazyLock<CommandStats> = LazyLock::new(CommandStats::default);
+
+/// Counts read lookups that found a live key (hits) or not (misses).
+#[derive(Default)]
+pub struct KeyspaceStats {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+pub static KEYSPACE_STATS: KeyspaceStats = KeyspaceStats {
+    hits: AtomicU64::new(0),
+    misses: AtomicU64::new(0),
+};
+
+impl KeyspaceStats {
+    pub fn record(&self, hit: bool) {
+        let counter = if hit { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Renders the `stats` INFO section.
+    pub fn info(&self) -> String {
+        format!(
+            "# Stats\r\nkeyspace_hits:{}\r\nkeyspace_misses:{}\r\n",
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed)
+        )
+    }
+}
+
+/// How often database locks were taken and how long callers queued for
+/// them. A lock counts as contended when it was already held on arrival.
+#[derive(Default)]
+pub struct LockStats {
+    acquisitions: AtomicU64,
+    contended: AtomicU64,
+    wait_usec: AtomicU64,
+    max_wait_usec: AtomicU64,
+    slow_waits: AtomicU64,
+}
+
+pub static LOCK_STATS: LockStats = LockStats {
+    acquisitions: AtomicU64::new(0),
+    contended: AtomicU64::new(0),
+    wait_usec: AtomicU64::new(0),
+    max_wait_usec: AtomicU64::new(0),
+    slow_waits: AtomicU64::new(0),
+};
+
+thread_local! {
+    // Lock wait accumulated by this thread since `take_lock_wait` last ran
+    static LOCK_WAIT: Cell<Duration> = const { Cell::new(Duration::ZERO) };
+}
+
+/// Returns and resets the time the current thread has spent waiting for
+/// database locks, so a command can be charged for its own waits.
+pub fn take_lock_wait() -> Duration {
+    LOCK_WAIT.take()
+}
+
+impl LockStats {
+    /// Records one acquisition, with `wait` set if the lock was contended.
+    pub fn record(&self, wait: Option<Duration>) {
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        let Some(wait) = wait else {
+            return;
+        };
+        let usec = wait.as_micros() as u64;
+        self.contended.fetch_add(1, Ordering::Relaxed);
+        self.wait_usec.fetch_add(usec, Ordering::Relaxed);
+        self.max_wait_usec.fetch_max(usec, Ordering::Relaxed);
+        LOCK_WAIT.set(LOCK_WAIT.get().saturating_add(wait));
+    }
+
+    /// Counts a command whose lock waits went over the configured threshold.
+    pub fn record_slow_wait(&self) {
+        self.slow_waits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Renders the lock lines of the `stats` INFO section.
+    pub fn info(&self) -> String {
+        format!(
+            "storage_lock_acquisitions:{}\r\nstorage_lock_contended:{}\r\n\
+             storage_lock_wait_usec:{}\r\nstorage_lock_max_wait_usec:{}\r\n\
+             storage_lock_slow_waits:{}\r\n",
+            self.acquisitions.load(Ordering::Relaxed),
+            self.contended.load(Ordering::Relaxed),
+            self.wait_usec.load(Ordering::Relaxed),
+            self.max_wait_usec.load(Ordering::Relaxed),
+            self.slow_waits.load(Ordering::Relaxed)
+        )
+    }
+}
+
+impl CommandStats {
+    pub fn record(&self, name: &'static str, elapsed: Duration) {
+        let usec = elapsed.as_micros() as u64;
+        if let Some(stat) = self.commands.read().unwrap().get(name) {
+            stat.calls.fetch_add(1, Ordering::Relaxed);
+            stat.usec.fetch_add(usec, Ordering::Relaxed);
+            return;
+        }
+
+        let mut commands = self.commands.write().unwrap();
+        let stat = commands.entry(name).or_default();
+        stat.calls.fetch_add(1, Ordering::Relaxed);
+        stat.usec.fetch_add(usec, Ordering::Relaxed);
+    }
+
+    /// Renders the `commandstats` INFO section, one line per command sorted
+    /// by name.
+    pub fn info(&self) -> String {
+        let commands = self.commands.read().unwrap();
+        let mut names: Vec<_> = commands.keys().copied().collect();
+        names.sort_unstable();
+
+        let mut info = String::from("# Commandstats\r\n");
+        for name in names {
+            let stat = &commands[name];
+            let calls = stat.calls.load(Ordering::Relaxed);
+            let usec = stat.usec.load(Ordering::Relaxed);
+            let per_call = if calls == 0 {
+                0.0
+            } else {
+                usec as f64 / calls as f64
+            };
+            let _ = write!(
+                info,
+                "cmdstat_{}:calls={},usec={},usec_per_call={:.2}\r\n",
+                name, calls, usec, per_call
+            );
+        }
+        info
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Command;
+    use crate::command_handler::handle_command;
+    use crate::connection::ConnectionContext;
+    use crate::resp::RespValue;
+    use crate::storage::{lock_storage, Databases, Storage};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_info_reports_calls_and_time() {
+        let stats = CommandStats::default();
+
+        stats.record("get", Duration::from_micros(10));
+        stats.record("get", Duration::from_micros(20));
+        stats.record("set", Duration::from_micros(5));
+
+        assert_eq!(
+            stats.info(),
+            "# Commandstats\r\n\
+             cmdstat_get:calls=2,usec=30,usec_per_call=15.00\r\n\
+             cmdstat_set:calls=1,usec=5,usec_per_call=5.00\r\n"
+        );
+    }
+
+    #[test]
+    fn test_keyspace_info_reports_hits_and_misses() {
+        let stats = KeyspaceStats::default();
+
+        stats.record(true);
+        stats.record(false);
+        stats.record(false);
+
+        assert_eq!(
+            stats.info(),
+            "# Stats\r\nkeyspace_hits:1\r\nkeyspace_misses:2\r\n"
+        );
+    }
+
+    #[test]
+    fn test_lock_stats_track_contended_waits() {
+        let stats = LockStats::default();
+        take_lock_wait();
+
+        stats.record(None);
+        stats.record(Some(Duration::from_micros(30)));
+        stats.record(Some(Duration::from_micros(10)));
+        stats.record_slow_wait();
+
+        assert_eq!(
+            stats.info(),
+            "storage_lock_acquisitions:3\r\nstorage_lock_contended:2\r\n\
+             storage_lock_wait_usec:40\r\nstorage_lock_max_wait_usec:30\r\n\
+             storage_lock_slow_waits:1\r\n"
+        );
+        assert_eq!(take_lock_wait(), Duration::from_micros(40));
+        assert_eq!(take_lock_wait(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_info_reports_contended_storage_locks() {
+        let storage = Arc::new(Mutex::new(Storage::new()));
+        let held = storage.lock().unwrap();
+        let waiter = {
+            let storage = storage.clone();
+            std::thread::spawn(move || drop(lock_storage(&storage)))
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        drop(held);
+        waiter.join().unwrap();
+
+        let info = handle_command(
+            Command::Info {
+                section: Some("stats".to_string()),
+            },
+            &Databases::new(Storage::new(), 1),
+            &mut ConnectionContext::default(),
+        );
+        let RespValue::BulkString(Some(info)) = info else {
+            panic!("INFO should return a bulk string");
+        };
+        let info = String::from_utf8(info).unwrap();
+        let field = |name: &str| {
+            info.lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+                .and_then(|value| value.parse::<u64>().ok())
+                .unwrap()
+        };
+        assert!(field("storage_lock_contended") > 0);
+        assert!(field("storage_lock_wait_usec") > 0);
+    }
+}
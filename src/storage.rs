@@ -1,50 +1,1197 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::mem::size_of;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, TryLockError};
+use std::time::Instant;
 
+use crate::clock::{Clock, SystemClock};
+use crate::sorted_set::SortedSet;
+use crate::stats::LOCK_STATS;
+use crate::util::{normalize_range, parse_int, scan_step};
+
+// A rough cost for each hash table entry beyond the bytes it holds: the
+// key's buffer header and a word for its hash
+const ENTRY_OVERHEAD: usize = size_of::<Vec<u8>>() + size_of::<usize>();
+
+/// A hash's fields and their values.
+pub type FieldMap = HashMap<Vec<u8>, Vec<u8>>;
+
+/// Sizes up to which collections report the compact encodings Redis uses for
+/// small values, as set by the `*-max-listpack-*` and `set-max-intset-entries`
+/// parameters. Only the reported encoding changes; the layout is the same.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EncodingLimits {
+    pub hash_max_listpack_entries: usize,
+    pub hash_max_listpack_value: usize,
+    pub set_max_intset_entries: usize,
+    pub zset_max_listpack_entries: usize,
+    pub zset_max_listpack_value: usize,
+    /// Positive values cap the number of items; -1 to -5 cap their total
+    /// size at 4, 8, 16, 32 or 64 KiB.
+    pub list_max_listpack_size: i64,
+}
+
+impl Default for EncodingLimits {
+    fn default() -> Self {
+        Self {
+            hash_max_listpack_entries: 128,
+            hash_max_listpack_value: 64,
+            set_max_intset_entries: 512,
+            zset_max_listpack_entries: 128,
+            zset_max_listpack_value: 64,
+            list_max_listpack_size: -2,
+        }
+    }
+}
+
+impl EncodingLimits {
+    fn list_fits(&self, list: &VecDeque<Vec<u8>>) -> bool {
+        match self.list_max_listpack_size {
+            entries if entries > 0 => list.len() <= entries as usize,
+            size_class => {
+                let max_bytes = 4096 << (size_class.unsigned_abs().clamp(1, 5) - 1);
+                list.iter().map(Vec::len).sum::<usize>() <= max_bytes
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(Vec<u8>),
+    /// A string that is the canonical decimal form of an integer. String
+    /// commands see the decimal bytes, materialized on access.
+    Int(i64),
+    Set(HashSet<Vec<u8>>),
+    List(VecDeque<Vec<u8>>),
+    Hash(FieldMap),
+    SortedSet(SortedSet),
+}
+
+impl Value {
+    /// Wraps string bytes, using the integer encoding when they round-trip
+    /// through an i64 unchanged (so "007" and "+1" stay plain strings).
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        match parse_int(&bytes) {
+            Some(n) => Value::Int(n),
+            None => Value::String(bytes),
+        }
+    }
+
+    /// The name Redis gives the internal representation of this value, as
+    /// reported by `OBJECT ENCODING` and `DEBUG OBJECT`. Collections within
+    /// `limits` report the compact encoding and the rest the general one.
+    pub fn encoding(&self, limits: &EncodingLimits) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            // Redis embeds short strings in the object header
+            Value::String(bytes) if bytes.len() <= 44 => "embstr",
+            Value::String(_) => "raw",
+            Value::Set(set)
+                if set.len() <= limits.set_max_intset_entries
+                    && set.iter().all(|member| parse_int(member).is_some()) =>
+            {
+                "intset"
+            }
+            Value::Hash(hash)
+                if hash.len() <= limits.hash_max_listpack_entries
+                    && hash.iter().all(|(field, value)| {
+                        field.len() <= limits.hash_max_listpack_value
+                            && value.len() <= limits.hash_max_listpack_value
+                    }) =>
+            {
+                "listpack"
+            }
+            Value::SortedSet(zset)
+                if zset.len() <= limits.zset_max_listpack_entries
+                    && zset
+                        .iter()
+                        .all(|(member, _)| member.len() <= limits.zset_max_listpack_value) =>
+            {
+                "listpack"
+            }
+            Value::List(list) if limits.list_fits(list) => "listpack",
+            Value::Set(_) | Value::Hash(_) => "hashtable",
+            Value::List(_) => "quicklist",
+            Value::SortedSet(_) => "skiplist",
+        }
+    }
+
+    /// Approximate bytes taken by the value: its elements' bytes plus the
+    /// bookkeeping each one needs in its container.
+    pub fn memory_usage(&self) -> usize {
+        self.sampled_memory_usage(0)
+    }
+
+    /// Like `memory_usage`, but a collection is estimated from the sizes of
+    /// its first `samples` elements scaled up to its length. Zero samples
+    /// every element.
+    pub fn sampled_memory_usage(&self, samples: usize) -> usize {
+        let (len, sizes): (usize, Box<dyn Iterator<Item = usize> + '_>) = match self {
+            Value::Int(_) => (0, Box::new(std::iter::empty())),
+            Value::String(bytes) => (1, Box::new(std::iter::once(bytes.len()))),
+            Value::Set(set) => (
+                set.len(),
+                Box::new(set.iter().map(|member| ENTRY_OVERHEAD + member.len())),
+            ),
+            Value::List(list) => (
+                list.len(),
+                Box::new(list.iter().map(|item| size_of::<Vec<u8>>() + item.len())),
+            ),
+            Value::Hash(hash) => (
+                hash.len(),
+                Box::new(
+                    hash.iter()
+                        .map(|(field, value)| ENTRY_OVERHEAD + field.len() + value.len()),
+                ),
+            ),
+            // Members are indexed both by name and by score
+            Value::SortedSet(zset) => (
+                zset.len(),
+                Box::new(
+                    zset.iter()
+                        .map(|(member, _)| 2 * ENTRY_OVERHEAD + member.len() + size_of::<f64>()),
+                ),
+            ),
+        };
+        let elements = if samples == 0 || samples >= len {
+            sizes.sum()
+        } else {
+            sizes.take(samples).sum::<usize>() * len / samples
+        };
+        size_of::<Value>() + elements
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StorageError {
+    WrongType,
+    NoSuchKey,
+    IndexOutOfRange,
+    HashValueNotInteger,
+    HashValueNotFloat,
+    Overflow,
+    NanOrInfinity,
+    NotANumber,
+}
+
+impl std::fmt::Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::WrongType => write!(
+                f,
+                "WRONGTYPE Operation against a key holding the wrong kind of value"
+            ),
+            StorageError::NoSuchKey => write!(f, "ERR no such key"),
+            StorageError::IndexOutOfRange => write!(f, "ERR index out of range"),
+            StorageError::HashValueNotInteger => write!(f, "ERR hash value is not an integer"),
+            StorageError::HashValueNotFloat => write!(f, "ERR hash value is not a float"),
+            StorageError::Overflow => write!(f, "ERR increment or decrement would overflow"),
+            StorageError::NotANumber => write!(f, "ERR resulting score is not a number (NaN)"),
+            StorageError::NanOrInfinity => {
+                write!(f, "ERR increment would produce NaN or Infinity")
+            }
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExpireOutcome {
+    Missing,
+    Set,
+    Deleted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SetOperation {
+    Inter,
+    Union,
+    Diff,
+}
+
+/// Locks a database, recovering the guard if a handler panicked while
+/// holding it. Handlers never leave a `Storage` half-updated across a panic
+/// point, so the data is still consistent. Time spent queueing behind
+/// another holder is recorded in `LOCK_STATS`.
+pub fn lock_storage(storage: &Mutex<Storage>) -> MutexGuard<'_, Storage> {
+    let guard = match storage.try_lock() {
+        Ok(guard) => guard,
+        Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+        Err(TryLockError::WouldBlock) => {
+            let start = Instant::now();
+            let guard = storage.lock().unwrap_or_else(PoisonError::into_inner);
+            LOCK_STATS.record(Some(start.elapsed()));
+            return guard;
+        }
+    };
+    LOCK_STATS.record(None);
+    guard
+}
+
+/// The numbered keyspaces a server holds. A connection addresses one of them
+/// through the `db` index of its `ConnectionContext`.
+pub struct Databases {
+    dbs: Vec<Arc<Mutex<Storage>>>,
+}
+
+impl Databases {
+    /// Creates `count` databases, the first of which holds `first`.
+    pub fn new(first: Storage, count: usize) -> Self {
+        let mut dbs = vec![Arc::new(Mutex::new(first))];
+        dbs.extend((1..count).map(|_| Arc::new(Mutex::new(Storage::new()))));
+        Self { dbs }
+    }
+
+    pub fn get(&self, index: usize) -> &Arc<Mutex<Storage>> {
+        &self.dbs[index]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<Mutex<Storage>>> {
+        self.dbs.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.dbs.len()
+    }
+}
+
+impl FromIterator<Storage> for Databases {
+    fn from_iter<I: IntoIterator<Item = Storage>>(iter: I) -> Self {
+        Self {
+            dbs: iter
+                .into_iter()
+                .map(|storage| Arc::new(Mutex::new(storage)))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Storage {
-    data: HashMap<String, String>,
-    expires: HashMap<String, u64>,
+    data: HashMap<Vec<u8>, Value>,
+    expires: HashMap<Vec<u8>, u64>,
+    // Where the active expiry sweep resumes, as a `scan_step` cursor
+    sweep_cursor: u64,
+    clock: Arc<dyn Clock>,
 }
 
 impl Storage {
     pub fn new() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+
+    /// An empty storage that reads the time for expiry from `clock`.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
         Self {
             data: HashMap::new(),
             expires: HashMap::new(),
+            sweep_cursor: 0,
+            clock,
         }
     }
 
-    pub fn get(&mut self, key: String) -> Option<String> {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        if self.expires.contains_key(&key) && self.expires[&key] < now {
-            self.data.remove(&key);
-            return None;
+    /// The current time in seconds, read once per operation so a command
+    /// sees a single consistent instant.
+    fn now_secs(&self) -> u64 {
+        self.clock.now_secs()
+    }
+
+    /// The current time in milliseconds, for expiries given relative to now.
+    pub fn now_millis(&self) -> u64 {
+        self.clock.now_millis()
+    }
+
+    fn expire_if_needed(&mut self, key: &[u8]) {
+        let now = self.now_secs();
+        self.expire_if_due(key, now);
+    }
+
+    fn expire_if_due(&mut self, key: &[u8], now: u64) {
+        if self.expires.get(key).is_some_and(|expire| *expire < now) {
+            self.data.remove(key);
+            self.expires.remove(key);
+        }
+    }
+
+    /// Checks up to `count` keys with a TTL, continuing from where the
+    /// previous call stopped, and deletes those that have expired. Returns
+    /// how many keys were sampled and how many of them were deleted.
+    pub fn expire_sample(&mut self, count: usize) -> (usize, usize) {
+        let (next_cursor, sampled) = scan_step(self.expires.keys(), self.sweep_cursor, count);
+        let sampled: Vec<Vec<u8>> = sampled.into_iter().cloned().collect();
+        self.sweep_cursor = next_cursor;
+
+        let before = self.data.len();
+        for key in &sampled {
+            self.expire_if_needed(key);
+        }
+        (sampled.len(), before - self.data.len())
+    }
+
+    pub fn get(&mut self, key: Vec<u8>) -> Result<Option<Vec<u8>>, StorageError> {
+        Ok(self.as_string(&key)?.map(Cow::into_owned))
+    }
+
+    /// Returns the string at `key`, failing with WRONGTYPE if the key holds
+    /// another type. Every read-only string command goes through here.
+    pub fn as_string(&mut self, key: &[u8]) -> Result<Option<Cow<'_, [u8]>>, StorageError> {
+        self.expire_if_needed(key);
+        match self.data.get(key) {
+            Some(Value::String(value)) => Ok(Some(Cow::Borrowed(value))),
+            Some(Value::Int(n)) => Ok(Some(Cow::Owned(n.to_string().into_bytes()))),
+            Some(_) => Err(StorageError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Like `get`, but treats a key holding another type as missing, for
+    /// commands such as MGET that never fail on type.
+    pub fn get_string(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        self.get(key.to_vec()).ok().flatten()
+    }
+
+    pub fn set(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.data.insert(key, Value::from_bytes(value));
+    }
+
+    pub fn set_int(&mut self, key: Vec<u8>, value: i64) {
+        self.data.insert(key, Value::Int(value));
+    }
+
+    /// Returns the string at `key` for in-place editing, creating an empty
+    /// one if the key is absent and materializing an integer encoding. The
+    /// writing counterpart of `as_string`.
+    pub fn as_string_mut(&mut self, key: Vec<u8>) -> Result<&mut Vec<u8>, StorageError> {
+        self.expire_if_needed(&key);
+        let value = self
+            .data
+            .entry(key)
+            .or_insert_with(|| Value::String(Vec::new()));
+        if let Value::Int(n) = value {
+            *value = Value::String(n.to_string().into_bytes());
+        }
+        match value {
+            Value::String(bytes) => Ok(bytes),
+            _ => Err(StorageError::WrongType),
+        }
+    }
+
+    /// Appends to the string at `key`, returning its new length.
+    pub fn append(&mut self, key: Vec<u8>, suffix: &[u8]) -> Result<usize, StorageError> {
+        let value = self.as_string_mut(key)?;
+        value.extend_from_slice(suffix);
+        Ok(value.len())
+    }
+
+    pub fn strlen(&mut self, key: Vec<u8>) -> Result<usize, StorageError> {
+        Ok(self.as_string(&key)?.map_or(0, |value| value.len()))
+    }
+
+    /// The bytes of the string at `key` between `start` and `end`
+    /// inclusive, with negative offsets counting from the end.
+    pub fn get_range(&mut self, key: &[u8], start: i64, end: i64) -> Result<Vec<u8>, StorageError> {
+        let Some(value) = self.as_string(key)? else {
+            return Ok(Vec::new());
+        };
+        match normalize_range(start, end, value.len()) {
+            Some((start, end)) => Ok(value[start..=end].to_vec()),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Overwrites the string at `key` starting at `offset`, zero-padding as
+    /// needed, and returns the new length. An empty write to a missing key
+    /// leaves it absent.
+    pub fn set_range(
+        &mut self,
+        key: Vec<u8>,
+        offset: usize,
+        bytes: &[u8],
+    ) -> Result<usize, StorageError> {
+        if bytes.is_empty() {
+            return self.strlen(key);
+        }
+
+        let value = self.as_string_mut(key)?;
+        let end = offset + bytes.len();
+        if value.len() < end {
+            value.resize(end, 0);
         }
-        self.data.get(&key).cloned()
+        value[offset..end].copy_from_slice(bytes);
+        Ok(value.len())
     }
 
-    pub fn set(&mut self, key: String, value: String) {
-        self.data.insert(key, value.to_string());
+    pub fn value(&mut self, key: &[u8]) -> Option<&Value> {
+        self.expire_if_needed(key);
+        self.data.get(key)
     }
 
-    pub fn set_expire(&mut self, key: String, expire: i64) -> Result<(), String> {
-        if expire < 0 {
+    pub fn set_value(&mut self, key: Vec<u8>, value: Value) {
+        self.data.insert(key, value);
+    }
+
+    /// Bits are addressed most-significant first, so offset 7 is the lowest
+    /// bit of the first byte.
+    pub fn get_bit(&mut self, key: Vec<u8>, offset: usize) -> Result<u8, StorageError> {
+        let Some(value) = self.as_string(&key)? else {
+            return Ok(0);
+        };
+        match value.get(offset / 8) {
+            Some(byte) => Ok((byte >> (7 - offset % 8)) & 1),
+            None => Ok(0),
+        }
+    }
+
+    /// Sets or clears a bit, zero-padding the value as needed, and returns
+    /// the bit's previous value.
+    pub fn set_bit(&mut self, key: Vec<u8>, offset: usize, bit: bool) -> Result<u8, StorageError> {
+        let value = self.as_string_mut(key)?;
+        let byte_index = offset / 8;
+        if value.len() <= byte_index {
+            value.resize(byte_index + 1, 0);
+        }
+
+        let mask = 1 << (7 - offset % 8);
+        let previous = (value[byte_index] & mask != 0) as u8;
+        if bit {
+            value[byte_index] |= mask;
+        } else {
+            value[byte_index] &= !mask;
+        }
+        Ok(previous)
+    }
+
+    /// Sets `key` to expire `expire` seconds from now. A TTL of zero or less
+    /// deletes the key instead, as Redis does.
+    pub fn set_expire(&mut self, key: Vec<u8>, expire: i64) -> ExpireOutcome {
+        let now = self.now_secs();
+        self.expire_if_due(&key, now);
+        if !self.data.contains_key(&key) {
+            return ExpireOutcome::Missing;
+        }
+
+        if expire <= 0 {
             self.data.remove(&key);
             self.expires.remove(&key);
-            return Ok(());
+            return ExpireOutcome::Deleted;
+        }
+        self.expires.insert(key, now + expire as u64);
+        ExpireOutcome::Set
+    }
+
+    pub fn set_expire_at(&mut self, key: Vec<u8>, expire_at: u64) {
+        self.expires.insert(key, expire_at);
+    }
+
+    pub fn bit_count(
+        &mut self,
+        key: Vec<u8>,
+        range: Option<(i64, i64)>,
+    ) -> Result<i64, StorageError> {
+        let Some(value) = self.as_string(&key)? else {
+            return Ok(0);
+        };
+
+        let bytes = match range {
+            Some((start, end)) => match normalize_range(start, end, value.len()) {
+                Some((start, end)) => &value[start..=end],
+                None => return Ok(0),
+            },
+            None => &value[..],
+        };
+        Ok(bytes.iter().map(|byte| byte.count_ones() as i64).sum())
+    }
+
+    /// Returns the position of the first bit equal to `bit` in the string at
+    /// `key`, searching the inclusive `range` of bytes, or of bits when
+    /// `in_bits`. A missing end means the rest of the value. When no clear
+    /// bit is found and no end was given, the value counts as padded with
+    /// zeros and the position just past the range is returned; otherwise a
+    /// failed search gives -1.
+    pub fn bit_pos(
+        &mut self,
+        key: &[u8],
+        bit: bool,
+        range: Option<(i64, Option<i64>)>,
+        in_bits: bool,
+    ) -> Result<i64, StorageError> {
+        let Some(value) = self.as_string(key)? else {
+            return Ok(if bit { -1 } else { 0 });
+        };
+
+        let len = value.len() as i64 * if in_bits { 8 } else { 1 };
+        let (start, end) = range.unwrap_or((0, None));
+        // Unlike GETRANGE, Redis clamps a too-negative end to the first
+        // position rather than treating the range as empty
+        let start = if start < 0 {
+            (len + start).max(0)
+        } else {
+            start
+        };
+        let end = match end {
+            Some(end) if end < 0 => (len + end).max(0),
+            Some(end) => end.min(len - 1),
+            None => len - 1,
+        };
+        if start > end {
+            return Ok(-1);
+        }
+
+        let (first, last) = if in_bits {
+            (start, end)
+        } else {
+            (start * 8, end * 8 + 7)
+        };
+        // Whole bytes holding none of the wanted bit are skipped at once
+        let skip = if bit { 0x00 } else { 0xff };
+        let mut pos = first;
+        while pos <= last {
+            let byte = value[(pos / 8) as usize];
+            if pos % 8 == 0 && pos + 7 <= last && byte == skip {
+                pos += 8;
+                continue;
+            }
+            if (byte >> (7 - pos % 8)) & 1 == bit as u8 {
+                return Ok(pos);
+            }
+            pos += 1;
+        }
+
+        let open_ended = range.is_none_or(|(_, end)| end.is_none());
+        Ok(if !bit && open_ended { last + 1 } else { -1 })
+    }
+
+    pub fn as_set(&mut self, key: &[u8]) -> Result<Option<&HashSet<Vec<u8>>>, StorageError> {
+        self.expire_if_needed(key);
+        match self.data.get(key) {
+            Some(Value::Set(set)) => Ok(Some(set)),
+            Some(_) => Err(StorageError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the set stored at `key`, creating an empty one if the key is
+    /// absent.
+    pub fn as_set_mut(&mut self, key: Vec<u8>) -> Result<&mut HashSet<Vec<u8>>, StorageError> {
+        self.expire_if_needed(&key);
+        match self
+            .data
+            .entry(key)
+            .or_insert_with(|| Value::Set(HashSet::new()))
+        {
+            Value::Set(set) => Ok(set),
+            _ => Err(StorageError::WrongType),
+        }
+    }
+
+    pub fn as_list(&mut self, key: &[u8]) -> Result<Option<&VecDeque<Vec<u8>>>, StorageError> {
+        self.expire_if_needed(key);
+        match self.data.get(key) {
+            Some(Value::List(list)) => Ok(Some(list)),
+            Some(_) => Err(StorageError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the list stored at `key`, creating an empty one if the key is
+    /// absent. A key holding any other type is left untouched.
+    pub fn as_list_mut(&mut self, key: Vec<u8>) -> Result<&mut VecDeque<Vec<u8>>, StorageError> {
+        self.expire_if_needed(&key);
+        match self
+            .data
+            .entry(key)
+            .or_insert_with(|| Value::List(VecDeque::new()))
+        {
+            Value::List(list) => Ok(list),
+            _ => Err(StorageError::WrongType),
+        }
+    }
+
+    /// Pushes each value in turn onto the head (`front`) or tail of the list,
+    /// returning the list's new length.
+    pub fn push(
+        &mut self,
+        key: Vec<u8>,
+        values: Vec<Vec<u8>>,
+        front: bool,
+    ) -> Result<usize, StorageError> {
+        let list = self.as_list_mut(key)?;
+        for value in values {
+            if front {
+                list.push_front(value);
+            } else {
+                list.push_back(value);
+            }
+        }
+        Ok(list.len())
+    }
+
+    pub fn lrange(
+        &mut self,
+        key: Vec<u8>,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<Vec<u8>>, StorageError> {
+        let Some(list) = self.as_list(&key)? else {
+            return Ok(Vec::new());
+        };
+        match normalize_range(start, stop, list.len()) {
+            Some((start, stop)) => Ok(list.range(start..=stop).cloned().collect()),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn llen(&mut self, key: Vec<u8>) -> Result<usize, StorageError> {
+        Ok(self.as_list(&key)?.map_or(0, |list| list.len()))
+    }
+
+    /// Returns the list at `key` for editing without creating it.
+    fn existing_list_mut(
+        &mut self,
+        key: &[u8],
+    ) -> Result<Option<&mut VecDeque<Vec<u8>>>, StorageError> {
+        self.expire_if_needed(key);
+        match self.data.get_mut(key) {
+            Some(Value::List(list)) => Ok(Some(list)),
+            Some(_) => Err(StorageError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Replaces the element at `index`, where negative indices count back
+    /// from the tail.
+    pub fn lset(&mut self, key: &[u8], index: i64, value: Vec<u8>) -> Result<(), StorageError> {
+        let list = self
+            .existing_list_mut(key)?
+            .ok_or(StorageError::NoSuchKey)?;
+        let index = if index < 0 {
+            index + list.len() as i64
         } else {
-            let now = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
-            self.expires.insert(key, now + expire as u64);
+            index
+        };
+        let slot = usize::try_from(index)
+            .ok()
+            .and_then(|index| list.get_mut(index))
+            .ok_or(StorageError::IndexOutOfRange)?;
+        *slot = value;
+        Ok(())
+    }
+
+    /// Inserts `value` before or after the first element equal to `pivot`.
+    /// Returns the new length, -1 if the pivot isn't found, or 0 if the key
+    /// doesn't exist.
+    pub fn linsert(
+        &mut self,
+        key: &[u8],
+        before: bool,
+        pivot: &[u8],
+        value: Vec<u8>,
+    ) -> Result<i64, StorageError> {
+        let Some(list) = self.existing_list_mut(key)? else {
+            return Ok(0);
+        };
+        let Some(position) = list.iter().position(|item| item == pivot) else {
+            return Ok(-1);
+        };
+        list.insert(if before { position } else { position + 1 }, value);
+        Ok(list.len() as i64)
+    }
+
+    /// Removes elements equal to `value`: the first `count` from the head
+    /// for a positive count, the last `-count` from the tail for a negative
+    /// one, or all of them for 0. Returns how many were removed.
+    pub fn lrem(&mut self, key: &[u8], count: i64, value: &[u8]) -> Result<usize, StorageError> {
+        let Some(list) = self.existing_list_mut(key)? else {
+            return Ok(0);
+        };
+        let limit = if count == 0 {
+            usize::MAX
+        } else {
+            count.unsigned_abs() as usize
+        };
+        let matches = list
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| item.as_slice() == value)
+            .map(|(index, _)| index);
+        let mut doomed: Vec<usize> = if count < 0 {
+            matches.rev().take(limit).collect()
+        } else {
+            matches.take(limit).collect()
+        };
+
+        // Remove from the back so earlier indices stay valid
+        doomed.sort_unstable_by(|a, b| b.cmp(a));
+        for index in &doomed {
+            list.remove(*index);
+        }
+        self.remove_if_empty(key);
+        Ok(doomed.len())
+    }
+
+    /// Keeps only the elements in the inclusive range `start..=stop`,
+    /// deleting the key when nothing is left.
+    pub fn ltrim(&mut self, key: &[u8], start: i64, stop: i64) -> Result<(), StorageError> {
+        let Some(list) = self.existing_list_mut(key)? else {
+            return Ok(());
+        };
+        match normalize_range(start, stop, list.len()) {
+            Some((start, stop)) => {
+                list.truncate(stop + 1);
+                list.drain(..start);
+            }
+            None => list.clear(),
         }
+        self.remove_if_empty(key);
         Ok(())
     }
 
-    pub fn remove_expire(&mut self, key: String) -> Result<(), String> {
+    /// Deletes `key` if it holds a collection that has become empty.
+    fn remove_if_empty(&mut self, key: &[u8]) {
+        let empty = match self.data.get(key) {
+            Some(Value::List(list)) => list.is_empty(),
+            Some(Value::Set(set)) => set.is_empty(),
+            Some(Value::SortedSet(zset)) => zset.is_empty(),
+            _ => false,
+        };
+        if empty {
+            self.data.remove(key);
+            self.expires.remove(key);
+        }
+    }
+
+    /// Removes and returns the element at the head (`front`) or tail of the
+    /// list, deleting the key once the list is empty.
+    pub fn pop(&mut self, key: &[u8], front: bool) -> Result<Option<Vec<u8>>, StorageError> {
+        self.expire_if_needed(key);
+        let list = match self.data.get_mut(key) {
+            Some(Value::List(list)) => list,
+            Some(_) => return Err(StorageError::WrongType),
+            None => return Ok(None),
+        };
+        let value = if front {
+            list.pop_front()
+        } else {
+            list.pop_back()
+        };
+        if list.is_empty() {
+            self.data.remove(key);
+            self.expires.remove(key);
+        }
+        Ok(value)
+    }
+
+    /// Pops an element from one end of `source` and pushes it onto one end of
+    /// `destination`, returning it, or None if `source` is empty. Both keys
+    /// are type-checked before anything moves, and they may be the same key.
+    pub fn lmove(
+        &mut self,
+        source: &[u8],
+        destination: Vec<u8>,
+        from_front: bool,
+        to_front: bool,
+    ) -> Result<Option<Vec<u8>>, StorageError> {
+        if self.as_list(source)?.is_none() {
+            return Ok(None);
+        }
+        self.as_list(&destination)?;
+
+        let list = self.as_list_mut(source.to_vec())?;
+        let Some(value) = (if from_front {
+            list.pop_front()
+        } else {
+            list.pop_back()
+        }) else {
+            return Ok(None);
+        };
+        self.push(destination, vec![value.clone()], to_front)?;
+
+        // Only drop the source once the push is done, so rotating a
+        // one-element list keeps its key (and TTL)
+        if self.as_list(source)?.is_some_and(|list| list.is_empty()) {
+            self.data.remove(source);
+            self.expires.remove(source);
+        }
+        Ok(Some(value))
+    }
+
+    pub fn as_hash(&mut self, key: &[u8]) -> Result<Option<&FieldMap>, StorageError> {
+        self.expire_if_needed(key);
+        match self.data.get(key) {
+            Some(Value::Hash(hash)) => Ok(Some(hash)),
+            Some(_) => Err(StorageError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the hash stored at `key`, creating an empty one if the key is
+    /// absent.
+    pub fn as_hash_mut(&mut self, key: Vec<u8>) -> Result<&mut FieldMap, StorageError> {
+        self.expire_if_needed(&key);
+        match self
+            .data
+            .entry(key)
+            .or_insert_with(|| Value::Hash(HashMap::new()))
+        {
+            Value::Hash(hash) => Ok(hash),
+            _ => Err(StorageError::WrongType),
+        }
+    }
+
+    /// Sets each field in turn, returning how many fields were newly added.
+    pub fn hset(
+        &mut self,
+        key: Vec<u8>,
+        pairs: Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> Result<usize, StorageError> {
+        let hash = self.as_hash_mut(key)?;
+        Ok(pairs
+            .into_iter()
+            .filter(|(field, value)| hash.insert(field.clone(), value.clone()).is_none())
+            .count())
+    }
+
+    pub fn hget(&mut self, key: &[u8], field: &[u8]) -> Result<Option<Vec<u8>>, StorageError> {
+        Ok(self.as_hash(key)?.and_then(|hash| hash.get(field).cloned()))
+    }
+
+    pub fn as_zset(&mut self, key: &[u8]) -> Result<Option<&SortedSet>, StorageError> {
+        self.expire_if_needed(key);
+        match self.data.get(key) {
+            Some(Value::SortedSet(zset)) => Ok(Some(zset)),
+            Some(_) => Err(StorageError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the sorted set stored at `key`, creating an empty one if the
+    /// key is absent.
+    pub fn as_zset_mut(&mut self, key: Vec<u8>) -> Result<&mut SortedSet, StorageError> {
+        self.expire_if_needed(&key);
+        match self
+            .data
+            .entry(key)
+            .or_insert_with(|| Value::SortedSet(SortedSet::default()))
+        {
+            Value::SortedSet(zset) => Ok(zset),
+            _ => Err(StorageError::WrongType),
+        }
+    }
+
+    /// Adds or rescores members, returning how many were new.
+    pub fn zadd(
+        &mut self,
+        key: Vec<u8>,
+        pairs: Vec<(f64, Vec<u8>)>,
+    ) -> Result<usize, StorageError> {
+        let zset = self.as_zset_mut(key)?;
+        Ok(pairs
+            .into_iter()
+            .filter(|(score, member)| zset.insert(member.clone(), *score))
+            .count())
+    }
+
+    /// Adds `increment` to a member's score, treating a missing member as
+    /// scored 0, and returns the new score.
+    pub fn zincr_by(
+        &mut self,
+        key: Vec<u8>,
+        increment: f64,
+        member: Vec<u8>,
+    ) -> Result<f64, StorageError> {
+        let zset = self.as_zset_mut(key.clone())?;
+        let score = zset.score(&member).unwrap_or(0.0) + increment;
+        if score.is_nan() {
+            // Don't leave behind the empty set as_zset_mut may have created
+            self.remove_if_empty(&key);
+            return Err(StorageError::NotANumber);
+        }
+        zset.insert(member, score);
+        Ok(score)
+    }
+
+    /// Removes members, deleting the key once the set is empty. Returns how
+    /// many were removed.
+    pub fn zrem(&mut self, key: &[u8], members: &[&[u8]]) -> Result<usize, StorageError> {
+        self.expire_if_needed(key);
+        let zset = match self.data.get_mut(key) {
+            Some(Value::SortedSet(zset)) => zset,
+            Some(_) => return Err(StorageError::WrongType),
+            None => return Ok(0),
+        };
+        let removed = members.iter().filter(|member| zset.remove(member)).count();
+        self.remove_if_empty(key);
+        Ok(removed)
+    }
+
+    /// Removes up to `count` members from the low (`lowest`) or high end of
+    /// the sorted set at `key`, deleting the key once it is empty.
+    pub fn zpop(
+        &mut self,
+        key: &[u8],
+        count: usize,
+        lowest: bool,
+    ) -> Result<Vec<(Vec<u8>, f64)>, StorageError> {
+        self.expire_if_needed(key);
+        let zset = match self.data.get_mut(key) {
+            Some(Value::SortedSet(zset)) => zset,
+            Some(_) => return Err(StorageError::WrongType),
+            None => return Ok(Vec::new()),
+        };
+        let popped = std::iter::from_fn(|| zset.pop(lowest))
+            .take(count)
+            .collect();
+        self.remove_if_empty(key);
+        Ok(popped)
+    }
+
+    /// Sets a hash field only if it is absent, returning whether it was set.
+    pub fn hsetnx(
+        &mut self,
+        key: Vec<u8>,
+        field: Vec<u8>,
+        value: Vec<u8>,
+    ) -> Result<bool, StorageError> {
+        let hash = self.as_hash_mut(key)?;
+        if hash.contains_key(&field) {
+            return Ok(false);
+        }
+        hash.insert(field, value);
+        Ok(true)
+    }
+
+    /// Looks up several fields at once; a missing key yields all `None`.
+    pub fn hmget(
+        &mut self,
+        key: &[u8],
+        fields: &[&[u8]],
+    ) -> Result<Vec<Option<Vec<u8>>>, StorageError> {
+        let hash = self.as_hash(key)?;
+        Ok(fields
+            .iter()
+            .map(|field| hash.and_then(|hash| hash.get(*field).cloned()))
+            .collect())
+    }
+
+    /// Adds `increment` to the integer in a hash field, treating a missing
+    /// field as 0, and returns the new value.
+    pub fn hincr_by(
+        &mut self,
+        key: Vec<u8>,
+        field: &[u8],
+        increment: i64,
+    ) -> Result<i64, StorageError> {
+        let hash = self.as_hash_mut(key)?;
+        let current = match hash.get(field) {
+            Some(value) => std::str::from_utf8(value)
+                .ok()
+                .and_then(|value| value.parse::<i64>().ok())
+                .ok_or(StorageError::HashValueNotInteger)?,
+            None => 0,
+        };
+        let updated = current
+            .checked_add(increment)
+            .ok_or(StorageError::Overflow)?;
+        hash.insert(field.to_vec(), updated.to_string().into_bytes());
+        Ok(updated)
+    }
+
+    /// Float counterpart of `hincr_by`; returns the new value as stored.
+    pub fn hincr_by_float(
+        &mut self,
+        key: Vec<u8>,
+        field: &[u8],
+        increment: f64,
+    ) -> Result<Vec<u8>, StorageError> {
+        let hash = self.as_hash_mut(key)?;
+        let current = match hash.get(field) {
+            Some(value) => std::str::from_utf8(value)
+                .ok()
+                .and_then(|value| value.parse::<f64>().ok())
+                .filter(|value| value.is_finite())
+                .ok_or(StorageError::HashValueNotFloat)?,
+            None => 0.0,
+        };
+        let updated = current + increment;
+        if !updated.is_finite() {
+            return Err(StorageError::NanOrInfinity);
+        }
+        let updated = updated.to_string().into_bytes();
+        hash.insert(field.to_vec(), updated.clone());
+        Ok(updated)
+    }
+
+    /// One SSCAN step over the members of the set at `key`.
+    pub fn sscan(
+        &mut self,
+        key: &[u8],
+        cursor: u64,
+        count: usize,
+    ) -> Result<(u64, Vec<Vec<u8>>), StorageError> {
+        let Some(set) = self.as_set(key)? else {
+            return Ok((0, Vec::new()));
+        };
+        let (next_cursor, members) = scan_step(set.iter(), cursor, count);
+        Ok((next_cursor, members.into_iter().cloned().collect()))
+    }
+
+    /// One HSCAN step over the hash at `key`. Fields alone decide the
+    /// iteration order, so updating a value never moves its field.
+    pub fn hscan(
+        &mut self,
+        key: &[u8],
+        cursor: u64,
+        count: usize,
+    ) -> Result<(u64, FieldMap), StorageError> {
+        let Some(hash) = self.as_hash(key)? else {
+            return Ok((0, FieldMap::new()));
+        };
+        let (next_cursor, fields) = scan_step(hash.keys(), cursor, count);
+        Ok((
+            next_cursor,
+            fields
+                .into_iter()
+                .map(|field| (field.clone(), hash[field].clone()))
+                .collect(),
+        ))
+    }
+
+    /// Returns the elements of the list or set at `key`; lists keep their
+    /// order, sets come back in arbitrary order.
+    pub fn elements(&mut self, key: &[u8]) -> Result<Vec<Vec<u8>>, StorageError> {
+        self.expire_if_needed(key);
+        match self.data.get(key) {
+            Some(Value::List(list)) => Ok(list.iter().cloned().collect()),
+            Some(Value::Set(set)) => Ok(set.iter().cloned().collect()),
+            Some(Value::SortedSet(zset)) => Ok(zset.iter().map(|(m, _)| m.to_vec()).collect()),
+            Some(_) => Err(StorageError::WrongType),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn sadd(&mut self, key: Vec<u8>, members: Vec<Vec<u8>>) -> Result<usize, StorageError> {
+        let set = self.as_set_mut(key)?;
+        Ok(members
+            .into_iter()
+            .filter(|member| set.insert(member.clone()))
+            .count())
+    }
+
+    /// Moves `member` from the set at `source` to the one at `destination`,
+    /// deleting `source` once it is empty. Both keys are type-checked first.
+    /// Returns whether `source` held the member.
+    pub fn smove(
+        &mut self,
+        source: &[u8],
+        destination: Vec<u8>,
+        member: &[u8],
+    ) -> Result<bool, StorageError> {
+        let present = self.as_set(source)?.is_some_and(|set| set.contains(member));
+        self.as_set(&destination)?;
+        if !present || source == destination {
+            return Ok(present);
+        }
+
+        if let Some(Value::Set(set)) = self.data.get_mut(source) {
+            set.remove(member);
+        }
+        self.remove_if_empty(source);
+        self.as_set_mut(destination)?.insert(member.to_vec());
+        Ok(true)
+    }
+
+    pub fn smembers(&mut self, key: Vec<u8>) -> Result<Vec<Vec<u8>>, StorageError> {
+        Ok(self
+            .as_set(&key)?
+            .map(|set| set.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    /// Counts the members common to the sets at `keys` without building the
+    /// intersection, stopping once `limit` are found; a zero limit counts
+    /// them all. Every key is type-checked first.
+    pub fn sintercard(&mut self, keys: &[Vec<u8>], limit: usize) -> Result<usize, StorageError> {
+        for key in keys {
+            self.expire_if_needed(key);
+        }
+
+        let empty = HashSet::new();
+        let mut sets = Vec::with_capacity(keys.len());
+        for key in keys {
+            match self.data.get(key) {
+                Some(Value::Set(set)) => sets.push(set),
+                Some(_) => return Err(StorageError::WrongType),
+                None => sets.push(&empty),
+            }
+        }
+        // Walking the smallest set bounds the work by its size
+        sets.sort_by_key(|set| set.len());
+        let Some((smallest, others)) = sets.split_first() else {
+            return Ok(0);
+        };
+
+        let common = smallest
+            .iter()
+            .filter(|member| others.iter().all(|set| set.contains(*member)));
+        Ok(match limit {
+            0 => common.count(),
+            limit => common.take(limit).count(),
+        })
+    }
+
+    /// Combines the sets at `keys` left to right. Missing keys count as empty
+    /// sets; every key is type-checked before any result is produced.
+    pub fn set_operation(
+        &mut self,
+        operation: SetOperation,
+        keys: &[Vec<u8>],
+    ) -> Result<HashSet<Vec<u8>>, StorageError> {
+        for key in keys {
+            self.expire_if_needed(key);
+        }
+
+        let empty = HashSet::new();
+        let sets = keys
+            .iter()
+            .map(|key| match self.data.get(key) {
+                Some(Value::Set(set)) => Ok(set),
+                Some(_) => Err(StorageError::WrongType),
+                None => Ok(&empty),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut sets = sets.into_iter();
+        let Some(first) = sets.next() else {
+            return Ok(HashSet::new());
+        };
+        let mut result = first.clone();
+        for set in sets {
+            match operation {
+                SetOperation::Inter => result.retain(|member| set.contains(member)),
+                SetOperation::Union => result.extend(set.iter().cloned()),
+                SetOperation::Diff => result.retain(|member| !set.contains(member)),
+            }
+        }
+        Ok(result)
+    }
+
+    /// Replaces `key` with the given set, deleting the key instead when the
+    /// set is empty. Returns the stored cardinality.
+    pub fn store_set(&mut self, key: Vec<u8>, members: HashSet<Vec<u8>>) -> usize {
+        self.expires.remove(&key);
+        if members.is_empty() {
+            self.data.remove(&key);
+            return 0;
+        }
+
+        let len = members.len();
+        self.data.insert(key, Value::Set(members));
+        len
+    }
+
+    pub fn remove_expire(&mut self, key: Vec<u8>) -> Result<(), String> {
         if !self.expires.contains_key(&key) || !self.has(key.clone()) {
             return Err("key does not exist".to_string());
         }
@@ -52,37 +1199,624 @@ impl Storage {
         Ok(())
     }
 
-    pub fn keys(&self, pattern: String) -> Vec<String> {
+    /// The live key count, how many of those keys have a TTL, and their
+    /// average remaining TTL in milliseconds, as reported by INFO keyspace.
+    pub fn keyspace_stats(&self) -> (usize, usize, u64) {
+        let now = self.now_secs();
+        let (mut keys, mut expires, mut total_ttl) = (0, 0, 0);
+        for (_, _, expire_at) in self.entries() {
+            keys += 1;
+            if let Some(at) = expire_at {
+                expires += 1;
+                total_ttl += at.saturating_sub(now) * 1000;
+            }
+        }
+        let avg_ttl = if expires == 0 {
+            0
+        } else {
+            total_ttl / expires as u64
+        };
+        (keys, expires, avg_ttl)
+    }
+
+    /// Approximate bytes held by this database's keys, values and expiry
+    /// times, as reported by INFO memory.
+    pub fn used_memory(&self) -> usize {
+        let data: usize = self
+            .data
+            .iter()
+            .map(|(key, value)| ENTRY_OVERHEAD + key.len() + value.memory_usage())
+            .sum();
+        let expires: usize = self
+            .expires
+            .keys()
+            .map(|key| ENTRY_OVERHEAD + key.len() + size_of::<u64>())
+            .sum();
+        data + expires
+    }
+
+    /// Approximate bytes used by `key`, its value and its expiry time, or
+    /// `None` if the key doesn't exist. See `Value::sampled_memory_usage`.
+    pub fn memory_usage(&mut self, key: &[u8], samples: usize) -> Option<usize> {
+        let expiry = if self.expires.contains_key(key) {
+            ENTRY_OVERHEAD + key.len() + size_of::<u64>()
+        } else {
+            0
+        };
+        let value = self.value(key)?.sampled_memory_usage(samples);
+        Some(ENTRY_OVERHEAD + key.len() + value + expiry)
+    }
+
+    pub fn value_type(&mut self, key: &[u8]) -> Option<&'static str> {
+        self.value(key).map(|value| match value {
+            Value::String(_) | Value::Int(_) => "string",
+            Value::Set(_) => "set",
+            Value::List(_) => "list",
+            Value::Hash(_) => "hash",
+            Value::SortedSet(_) => "zset",
+        })
+    }
+
+    /// Visits up to `count` live keys from `cursor`, returning the cursor to
+    /// continue from (0 once every key has been visited).
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<Vec<u8>>) {
+        let (next_cursor, keys) = scan_step(self.entries().map(|(key, _, _)| key), cursor, count);
+        (next_cursor, keys.into_iter().cloned().collect())
+    }
+
+    pub fn keys(&self, pattern: String) -> Vec<Vec<u8>> {
         self.data
             .keys()
-            .filter(|k| glob::Pattern::new(&pattern).unwrap().matches(k))
+            .filter(|k| {
+                glob::Pattern::new(&pattern)
+                    .unwrap()
+                    .matches(&String::from_utf8_lossy(k))
+            })
             .cloned()
             .collect()
     }
 
-    pub fn get_ttl(&self, key: String) -> i64 {
+    pub fn entries(&self) -> impl Iterator<Item = (&Vec<u8>, &Value, Option<u64>)> {
+        let now = self.now_secs();
+        self.data
+            .iter()
+            .map(|(key, value)| (key, value, self.expires.get(key).copied()))
+            .filter(move |(_, _, expire_at)| expire_at.is_none_or(|at| at >= now))
+    }
+
+    pub fn get_ttl(&self, key: Vec<u8>) -> i64 {
         if !self.has(key.clone()) {
             return -2;
         }
         if let Some(expire) = self.expires.get(&key) {
-            let now = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
-            return (*expire - now).try_into().unwrap();
+            // A key past its deadline that hasn't been lazily deleted yet
+            return match expire.checked_sub(self.now_secs()) {
+                Some(ttl) => ttl.try_into().unwrap_or(i64::MAX),
+                None => -2,
+            };
         }
         -1
     }
 
-    pub fn has(&self, key: String) -> bool {
+    /// The absolute Unix time in seconds at which `key` expires: `None` if
+    /// the key doesn't exist, `Some(None)` if it never expires.
+    pub fn expire_time(&mut self, key: &[u8]) -> Option<Option<u64>> {
+        self.value(key)?;
+        Some(self.expires.get(key).copied())
+    }
+
+    pub fn has(&self, key: Vec<u8>) -> bool {
         self.data.contains_key(&key)
     }
 
-    pub fn del(&mut self, key: String) {
+    /// Removes a live key, returning its value and absolute expiry.
+    pub fn take(&mut self, key: &[u8]) -> Option<(Value, Option<u64>)> {
+        self.expire_if_needed(key);
+        let value = self.data.remove(key)?;
+        Some((value, self.expires.remove(key)))
+    }
+
+    pub fn del(&mut self, key: Vec<u8>) {
         self.data.remove(&key);
     }
 
     pub fn clear(&mut self) {
         self.data.clear();
+        self.expires.clear();
+    }
+
+    /// Swaps in the keys and expiries of `other`, keeping this store's clock.
+    pub fn replace_contents(&mut self, other: Storage) {
+        self.data = other.data;
+        self.expires = other.expires;
+        self.sweep_cursor = 0;
+    }
+
+    /// Takes out the keys and expiries, leaving this store empty with its
+    /// clock in place.
+    pub fn take_contents(&mut self) -> Storage {
+        let mut taken = Storage::with_clock(self.clock.clone());
+        taken.data = std::mem::take(&mut self.data);
+        taken.expires = std::mem::take(&mut self.expires);
+        self.sweep_cursor = 0;
+        taken
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_bit_on_missing_key_grows_value() {
+        let mut storage = Storage::new();
+
+        assert_eq!(storage.set_bit("k".into(), 7, true), Ok(0));
+        assert_eq!(storage.get("k".into()), Ok(Some(vec![0x01])));
+    }
+
+    #[test]
+    fn test_set_bit_returns_previous_bit() {
+        let mut storage = Storage::new();
+        storage.set("k".into(), vec![0x80]);
+
+        assert_eq!(storage.set_bit("k".into(), 0, false), Ok(1));
+        assert_eq!(storage.set_bit("k".into(), 0, false), Ok(0));
+        assert_eq!(storage.get("k".into()), Ok(Some(vec![0x00])));
+    }
+
+    #[test]
+    fn test_set_bit_pads_with_zero_bytes() {
+        let mut storage = Storage::new();
+
+        storage.set_bit("k".into(), 17, true).unwrap();
+        assert_eq!(storage.get("k".into()), Ok(Some(vec![0x00, 0x00, 0x40])));
+    }
+
+    #[test]
+    fn test_bit_count_whole_value() {
+        let mut storage = Storage::new();
+        storage.set("k".into(), b"foobar".to_vec());
+
+        assert_eq!(storage.bit_count("k".into(), None), Ok(26));
+        assert_eq!(storage.bit_count("missing".into(), None), Ok(0));
+    }
+
+    #[test]
+    fn test_bit_count_range() {
+        let mut storage = Storage::new();
+        storage.set("k".into(), b"foobar".to_vec());
+
+        assert_eq!(storage.bit_count("k".into(), Some((0, 0))), Ok(4));
+        assert_eq!(storage.bit_count("k".into(), Some((1, 1))), Ok(6));
+        assert_eq!(storage.bit_count("k".into(), Some((-2, -1))), Ok(7));
+        // end past the value clamps to the last byte
+        assert_eq!(storage.bit_count("k".into(), Some((5, 100))), Ok(4));
+        assert_eq!(storage.bit_count("k".into(), Some((10, 20))), Ok(0));
+        assert_eq!(storage.bit_count("k".into(), Some((3, 1))), Ok(0));
+    }
+
+    #[test]
+    fn test_bit_pos_all_zeros_and_all_ones() {
+        let mut storage = Storage::new();
+        storage.set("zeros".into(), vec![0x00; 3]);
+        storage.set("ones".into(), vec![0xff; 3]);
+
+        assert_eq!(storage.bit_pos(b"zeros", true, None, false), Ok(-1));
+        assert_eq!(storage.bit_pos(b"zeros", false, None, false), Ok(0));
+        // Searching for a clear bit runs off the end of an all-ones value
+        assert_eq!(storage.bit_pos(b"ones", false, None, false), Ok(24));
+        assert_eq!(
+            storage.bit_pos(b"ones", false, Some((1, None)), false),
+            Ok(24)
+        );
+        assert_eq!(
+            storage.bit_pos(b"ones", false, Some((1, Some(-1))), false),
+            Ok(-1)
+        );
+        assert_eq!(
+            storage.bit_pos(b"ones", false, Some((3, Some(15))), true),
+            Ok(-1)
+        );
+        assert_eq!(
+            storage.bit_pos(b"ones", true, Some((-1, None)), false),
+            Ok(16)
+        );
+        assert_eq!(storage.bit_pos(b"missing", true, None, false), Ok(-1));
+        assert_eq!(storage.bit_pos(b"missing", false, None, false), Ok(0));
+    }
+
+    #[test]
+    fn test_bit_pos_ranges() {
+        let mut storage = Storage::new();
+        storage.set("k".into(), vec![0xff, 0xf0, 0x00]);
+
+        assert_eq!(storage.bit_pos(b"k", false, None, false), Ok(12));
+        assert_eq!(storage.bit_pos(b"k", true, Some((1, None)), false), Ok(8));
+        assert_eq!(
+            storage.bit_pos(b"k", true, Some((2, Some(2))), false),
+            Ok(-1)
+        );
+        assert_eq!(
+            storage.bit_pos(b"k", true, Some((9, Some(11))), true),
+            Ok(9)
+        );
+        assert_eq!(
+            storage.bit_pos(b"k", false, Some((5, Some(13))), true),
+            Ok(12)
+        );
+        assert_eq!(
+            storage.bit_pos(b"k", true, Some((3, Some(1))), false),
+            Ok(-1)
+        );
+        // An end far below zero clamps to the first byte
+        assert_eq!(
+            storage.bit_pos(b"k", true, Some((0, Some(-100))), false),
+            Ok(0)
+        );
+    }
+
+    #[test]
+    fn test_bit_operations_on_set_are_wrong_type() {
+        let mut storage = Storage::new();
+        storage.sadd("s".into(), vec![b"a".to_vec()]).unwrap();
+
+        assert_eq!(
+            storage.set_bit("s".into(), 0, true),
+            Err(StorageError::WrongType)
+        );
+        assert_eq!(storage.get_bit("s".into(), 0), Err(StorageError::WrongType));
+        assert_eq!(
+            storage.bit_count("s".into(), None),
+            Err(StorageError::WrongType)
+        );
+        assert_eq!(
+            storage.bit_pos(b"s", true, None, false),
+            Err(StorageError::WrongType)
+        );
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let mut storage = Storage::new();
+        storage
+            .sadd(
+                "a".into(),
+                vec![b"1".to_vec(), b"2".to_vec(), b"3".to_vec()],
+            )
+            .unwrap();
+        storage
+            .sadd(
+                "b".into(),
+                vec![b"2".to_vec(), b"3".to_vec(), b"4".to_vec()],
+            )
+            .unwrap();
+        let keys = [b"a".to_vec(), b"b".to_vec()];
+
+        let inter = storage.set_operation(SetOperation::Inter, &keys).unwrap();
+        assert_eq!(inter, HashSet::from([b"2".to_vec(), b"3".to_vec()]));
+
+        let union = storage.set_operation(SetOperation::Union, &keys).unwrap();
+        assert_eq!(union.len(), 4);
+
+        let diff = storage.set_operation(SetOperation::Diff, &keys).unwrap();
+        assert_eq!(diff, HashSet::from([b"1".to_vec()]));
+
+        let with_missing = [b"a".to_vec(), b"missing".to_vec()];
+        let inter = storage
+            .set_operation(SetOperation::Inter, &with_missing)
+            .unwrap();
+        assert!(inter.is_empty());
+    }
+
+    #[test]
+    fn test_sintercard_stops_at_limit() {
+        let mut storage = Storage::new();
+        let members = |range: std::ops::Range<u32>| range.map(|i| i.to_string().into_bytes());
+        storage.sadd("a".into(), members(0..100).collect()).unwrap();
+        storage
+            .sadd("b".into(), members(50..150).collect())
+            .unwrap();
+        let keys = [b"a".to_vec(), b"b".to_vec()];
+
+        assert_eq!(storage.sintercard(&keys, 0), Ok(50));
+        assert_eq!(storage.sintercard(&keys, 10), Ok(10));
+        assert_eq!(storage.sintercard(&keys, 500), Ok(50));
+        assert_eq!(
+            storage.sintercard(&["a".into(), "missing".into()], 0),
+            Ok(0)
+        );
+    }
+
+    #[test]
+    fn test_set_operation_rejects_wrong_type() {
+        let mut storage = Storage::new();
+        storage.sadd("a".into(), vec![b"1".to_vec()]).unwrap();
+        storage.set("s".into(), b"v".to_vec());
+
+        assert_eq!(
+            storage.set_operation(SetOperation::Union, &["a".into(), "s".into()]),
+            Err(StorageError::WrongType)
+        );
+    }
+
+    #[test]
+    fn test_push_onto_string_is_wrong_type() {
+        let mut storage = Storage::new();
+        storage.set("k".into(), b"v".to_vec());
+
+        assert_eq!(
+            storage.push("k".into(), vec![b"x".to_vec()], true),
+            Err(StorageError::WrongType)
+        );
+        assert_eq!(storage.get("k".into()), Ok(Some(b"v".to_vec())));
+    }
+
+    #[test]
+    fn test_lmove_rotates_same_list() {
+        let mut storage = Storage::new();
+        storage
+            .push("l".into(), vec![b"a".to_vec(), b"b".to_vec()], false)
+            .unwrap();
+
+        assert_eq!(
+            storage.lmove(b"l", b"l".to_vec(), false, true),
+            Ok(Some(b"b".to_vec()))
+        );
+        assert_eq!(
+            storage.lrange("l".into(), 0, -1),
+            Ok(vec![b"b".to_vec(), b"a".to_vec()])
+        );
+    }
+
+    #[test]
+    fn test_lmove_deletes_emptied_source() {
+        let mut storage = Storage::new();
+        storage
+            .push("src".into(), vec![b"a".to_vec()], false)
+            .unwrap();
+
+        assert_eq!(
+            storage.lmove(b"src", b"dst".to_vec(), true, false),
+            Ok(Some(b"a".to_vec()))
+        );
+        assert!(!storage.has("src".into()));
+        assert_eq!(
+            storage.lmove(b"src", b"dst".to_vec(), true, false),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn test_lmove_to_wrong_type_leaves_source_alone() {
+        let mut storage = Storage::new();
+        storage
+            .push("src".into(), vec![b"a".to_vec()], false)
+            .unwrap();
+        storage.set("dst".into(), b"v".to_vec());
+
+        assert_eq!(
+            storage.lmove(b"src", b"dst".to_vec(), true, false),
+            Err(StorageError::WrongType)
+        );
+        assert_eq!(storage.llen("src".into()), Ok(1));
+    }
+
+    #[test]
+    fn test_lset_errors() {
+        let mut storage = Storage::new();
+        storage
+            .push("l".into(), vec![b"a".to_vec(), b"b".to_vec()], false)
+            .unwrap();
+
+        assert_eq!(storage.lset(b"l", -1, b"z".to_vec()), Ok(()));
+        assert_eq!(
+            storage.lset(b"l", 2, b"z".to_vec()),
+            Err(StorageError::IndexOutOfRange)
+        );
+        assert_eq!(
+            storage.lset(b"l", -3, b"z".to_vec()),
+            Err(StorageError::IndexOutOfRange)
+        );
+        assert_eq!(
+            storage.lset(b"missing", 0, b"z".to_vec()),
+            Err(StorageError::NoSuchKey)
+        );
+        assert_eq!(
+            storage.lrange("l".into(), 0, -1),
+            Ok(vec![b"a".to_vec(), b"z".to_vec()])
+        );
+    }
+
+    #[test]
+    fn test_linsert() {
+        let mut storage = Storage::new();
+        storage
+            .push("l".into(), vec![b"a".to_vec(), b"c".to_vec()], false)
+            .unwrap();
+
+        assert_eq!(storage.linsert(b"l", true, b"c", b"b".to_vec()), Ok(3));
+        assert_eq!(storage.linsert(b"l", false, b"x", b"y".to_vec()), Ok(-1));
+        assert_eq!(
+            storage.linsert(b"missing", true, b"a", b"y".to_vec()),
+            Ok(0)
+        );
+        assert_eq!(
+            storage.lrange("l".into(), 0, -1),
+            Ok(vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()])
+        );
+    }
+
+    #[test]
+    fn test_lrem_negative_count_removes_from_tail() {
+        let mut storage = Storage::new();
+        let items = ["x", "a", "x", "b", "x"].map(|item| item.as_bytes().to_vec());
+        storage.push("l".into(), items.to_vec(), false).unwrap();
+
+        assert_eq!(storage.lrem(b"l", -1, b"x"), Ok(1));
+        assert_eq!(
+            storage.lrange("l".into(), 0, -1),
+            Ok(["x", "a", "x", "b"]
+                .map(|item| item.as_bytes().to_vec())
+                .to_vec())
+        );
+        assert_eq!(storage.lrem(b"l", 0, b"x"), Ok(2));
+        assert_eq!(storage.lrem(b"l", 5, b"a"), Ok(1));
+        assert_eq!(storage.lrem(b"l", 1, b"b"), Ok(1));
+        assert!(!storage.has("l".into()));
+    }
+
+    #[test]
+    fn test_ltrim() {
+        let mut storage = Storage::new();
+        let items = ["a", "b", "c", "d"].map(|item| item.as_bytes().to_vec());
+        storage.push("l".into(), items.to_vec(), false).unwrap();
+
+        storage.ltrim(b"l", 1, -2).unwrap();
+        assert_eq!(
+            storage.lrange("l".into(), 0, -1),
+            Ok(vec![b"b".to_vec(), b"c".to_vec()])
+        );
+
+        storage.ltrim(b"l", 5, 10).unwrap();
+        assert!(!storage.has("l".into()));
+    }
+
+    #[test]
+    fn test_push_and_lrange() {
+        let mut storage = Storage::new();
+
+        storage
+            .push("l".into(), vec![b"b".to_vec(), b"a".to_vec()], true)
+            .unwrap();
+        let len = storage
+            .push("l".into(), vec![b"c".to_vec()], false)
+            .unwrap();
+
+        assert_eq!(len, 3);
+        assert_eq!(
+            storage.lrange("l".into(), 0, -1),
+            Ok(vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()])
+        );
+        assert_eq!(
+            storage.lrange("l".into(), -2, 10),
+            Ok(vec![b"b".to_vec(), b"c".to_vec()])
+        );
+        assert_eq!(storage.lrange("missing".into(), 0, -1), Ok(vec![]));
+        assert_eq!(storage.get("l".into()), Err(StorageError::WrongType));
+    }
+
+    #[test]
+    fn test_set_expire_outcomes() {
+        let mut storage = Storage::new();
+        assert_eq!(storage.set_expire("k".into(), 10), ExpireOutcome::Missing);
+
+        storage.set("k".into(), b"v".to_vec());
+        assert_eq!(storage.set_expire("k".into(), 10), ExpireOutcome::Set);
+        assert_eq!(storage.get_ttl("k".into()), 10);
+
+        assert_eq!(storage.set_expire("k".into(), -1), ExpireOutcome::Deleted);
+        assert_eq!(storage.get("k".into()), Ok(None));
+        assert_eq!(storage.get_ttl("k".into()), -2);
+    }
+
+    #[test]
+    fn test_canonical_integers_use_int_encoding() {
+        let mut storage = Storage::new();
+
+        for (input, int) in [
+            ("42", true),
+            ("-7", true),
+            ("007", false),
+            ("+1", false),
+            ("1.5", false),
+        ] {
+            storage.set("k".into(), input.as_bytes().to_vec());
+            assert_eq!(
+                matches!(storage.value(b"k"), Some(Value::Int(_))),
+                int,
+                "{}",
+                input
+            );
+            assert_eq!(storage.get("k".into()), Ok(Some(input.as_bytes().to_vec())));
+        }
+    }
+
+    #[test]
+    fn test_append_materializes_int_encoding() {
+        let mut storage = Storage::new();
+        storage.set_int("k".into(), 12);
+
+        assert_eq!(storage.append("k".into(), b"ab"), Ok(4));
+        assert_eq!(storage.value(b"k"), Some(&Value::String(b"12ab".to_vec())));
+    }
+
+    #[test]
+    fn test_scan_visits_every_key_once() {
+        let mut storage = Storage::new();
+        for i in 0..50 {
+            storage.set(format!("key:{}", i).into_bytes(), b"v".to_vec());
+        }
+
+        let mut seen = Vec::new();
+        let mut cursor = 0;
+        loop {
+            let (next, keys) = storage.scan(cursor, 7);
+            assert!(keys.len() <= 7);
+            seen.extend(keys);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+
+        seen.sort();
+        let mut expected: Vec<Vec<u8>> =
+            (0..50).map(|i| format!("key:{}", i).into_bytes()).collect();
+        expected.sort();
+        assert_eq!(seen, expected);
+    }
+
+    #[test]
+    fn test_get_bit() {
+        let mut storage = Storage::new();
+        storage.set("k".into(), vec![0x01]);
+
+        assert_eq!(storage.get_bit("k".into(), 7), Ok(1));
+        assert_eq!(storage.get_bit("k".into(), 6), Ok(0));
+        assert_eq!(storage.get_bit("k".into(), 100), Ok(0));
+        assert_eq!(storage.get_bit("missing".into(), 0), Ok(0));
+    }
+
+    #[test]
+    fn test_keys_expire_when_the_clock_passes_their_deadline() {
+        let clock = Arc::new(crate::clock::MockClock::new(1_000));
+        let mut storage = Storage::with_clock(clock.clone());
+        storage.set("k".into(), b"v".to_vec());
+        storage.set_expire("k".into(), 10);
+
+        clock.advance(9);
+        assert_eq!(storage.get_ttl("k".into()), 1);
+        assert_eq!(storage.get("k".into()), Ok(Some(b"v".to_vec())));
+
+        // A key lives through the second its deadline falls in
+        clock.advance(1);
+        assert_eq!(storage.get_ttl("k".into()), 0);
+        assert_eq!(storage.get("k".into()), Ok(Some(b"v".to_vec())));
+
+        clock.advance(1);
+        assert_eq!(storage.get("k".into()), Ok(None));
+        assert_eq!(storage.get_ttl("k".into()), -2);
+    }
+
+    #[test]
+    fn test_ttl_of_a_key_past_its_deadline_is_missing() {
+        let clock = Arc::new(crate::clock::MockClock::new(1_000));
+        let mut storage = Storage::with_clock(clock.clone());
+        storage.set("k".into(), b"v".to_vec());
+        storage.set_expire("k".into(), 1);
+
+        clock.advance(5);
+        assert_eq!(storage.get_ttl("k".into()), -2);
     }
 }
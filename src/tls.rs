@@ -0,0 +1,141 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use rustls::{ServerConfig, ServerConnection};
+
+use crate::connection::ClientStream;
+
+/// Builds the TLS server settings from PEM files holding the certificate
+/// chain and its private key.
+pub fn server_config(cert_file: &str, key_file: &str) -> Result<Arc<ServerConfig>, String> {
+    let open = |path: &str| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|e| format!("can't open {}: {}", path, e))
+    };
+
+    let certs = rustls_pemfile::certs(&mut open(cert_file)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("can't read certificates from {}: {}", cert_file, e))?;
+    let key = rustls_pemfile::private_key(&mut open(key_file)?)
+        .map_err(|e| format!("can't read private key from {}: {}", key_file, e))?
+        .ok_or_else(|| format!("no private key in {}", key_file))?;
+
+    let config =
+        ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| e.to_string())?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|e| e.to_string())?;
+    Ok(Arc::new(config))
+}
+
+/// A client connection on the TLS port. The handshake runs on the first
+/// read or write.
+///
+/// The session state is shared between clones, so MONITOR and the
+/// subscribe commands can write to the client from other threads. Only the
+/// original handle reads, and it waits on the socket without holding the
+/// session lock so those writes aren't held up.
+pub struct TlsStream {
+    connection: Arc<Mutex<ServerConnection>>,
+    socket: TcpStream,
+}
+
+// Small enough that the plaintext it decrypts to always fits in the
+// session's receive buffer, which is empty whenever the socket is read
+const READ_CHUNK: usize = 4096;
+
+impl TlsStream {
+    pub fn new(config: Arc<ServerConfig>, socket: TcpStream) -> io::Result<Self> {
+        let connection = ServerConnection::new(config).map_err(io::Error::other)?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+            socket,
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ServerConnection> {
+        self.connection
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Sends any records the session has queued, such as handshake
+    /// messages or encrypted writes.
+    fn write_records(&self, connection: &mut ServerConnection) -> io::Result<()> {
+        while connection.wants_write() {
+            connection.write_tls(&mut &self.socket)?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut incoming = [0; READ_CHUNK];
+        loop {
+            {
+                let mut connection = self.lock();
+                self.write_records(&mut connection)?;
+                match connection.reader().read(buf) {
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    result => return result,
+                }
+            }
+
+            // A read of 0 bytes still goes to the session, which then
+            // reports the end of the stream
+            let read = (&self.socket).read(&mut incoming)?;
+            let mut connection = self.lock();
+            let mut records = &incoming[..read];
+            loop {
+                let consumed = connection.read_tls(&mut records)?;
+                if let Err(e) = connection.process_new_packets() {
+                    // Let the peer know why, if the session queued an alert
+                    let _ = self.write_records(&mut connection);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+                }
+                // Nothing more is taken once the peer has closed the session
+                if records.is_empty() || consumed == 0 {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut connection = self.lock();
+        let written = connection.writer().write(buf)?;
+        self.write_records(&mut connection)?;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut connection = self.lock();
+        connection.writer().flush()?;
+        self.write_records(&mut connection)
+    }
+}
+
+impl ClientStream for TlsStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            connection: self.connection.clone(),
+            socket: self.socket.try_clone()?,
+        })
+    }
+
+    fn peer_addr(&self) -> String {
+        ClientStream::peer_addr(&self.socket)
+    }
+
+    fn shutdown(&self) {
+        let _ = self.socket.shutdown(Shutdown::Both);
+    }
+}
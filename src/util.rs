@@ -1 +1,64 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
 
+/// Parses an integer the way Redis does: only the canonical decimal form is
+/// accepted, so signs other than a leading `-`, whitespace and leading zeros
+/// are all rejected.
+pub fn parse_int(bytes: &[u8]) -> Option<i64> {
+    std::str::from_utf8(bytes)
+        .ok()
+        .filter(|s| s.len() <= 20)
+        .and_then(|s| s.parse::<i64>().ok())
+        .filter(|n| n.to_string().as_bytes() == bytes)
+}
+
+/// Resolves a Redis-style inclusive `start..=end` range, where negative
+/// indices count back from the end, against a sequence of `len` elements.
+/// Returns `None` when the clamped range is empty.
+pub fn normalize_range(start: i64, end: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let end = if end < 0 { len + end } else { end.min(len - 1) };
+
+    if start > end || start >= len {
+        return None;
+    }
+    Some((start as usize, end as usize))
+}
+
+/// One step of a SCAN-style iteration. Items are visited in order of a fixed
+/// hash, and the cursor is the hash to resume from, so items that stay put
+/// for a whole iteration are returned at least once however the collection
+/// changes in between. Returns the next cursor (0 when done) and the items
+/// of this step.
+pub fn scan_step<T: Hash>(
+    items: impl Iterator<Item = T>,
+    cursor: u64,
+    count: usize,
+) -> (u64, Vec<T>) {
+    let mut remaining: Vec<(u64, T)> = items
+        .map(|item| (stable_hash(&item), item))
+        .filter(|(hash, _)| *hash >= cursor)
+        .collect();
+    remaining.sort_unstable_by_key(|(hash, _)| *hash);
+
+    let next_cursor = match remaining.get(count) {
+        Some((hash, _)) => *hash,
+        None => 0,
+    };
+    remaining.truncate(count);
+    (
+        next_cursor,
+        remaining.into_iter().map(|(_, item)| item).collect(),
+    )
+}
+
+// DefaultHasher::new() uses fixed keys, so hashes agree across calls
+fn stable_hash<T: Hash>(item: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    item.hash(&mut hasher);
+    hasher.finish()
+}